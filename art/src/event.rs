@@ -0,0 +1,322 @@
+//! NEP-297 events, see the `events` crate. Changes of art balances and supply use the
+//! NEP-141 events, the other operations of the contract are under the `art` standard.
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::Balance;
+
+use events::emit;
+pub(crate) use events::{ft_burn, ft_mint, ft_transfer};
+
+use crate::{ConversionPath, OrderSide};
+
+const ART_STANDARD: &str = "art";
+const ART_VERSION: &str = "1.0.0";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct AccountAmount<'a> {
+    account_id: &'a str,
+    amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct LiquidStake<'a> {
+    account_id: &'a str,
+    amount: U128,
+    start_amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct Vesting<'a> {
+    grant_id: U64,
+    account_id: &'a str,
+    amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct Snapshot<'a> {
+    name: &'a str,
+    block_height: U64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct PriceUpdate<'a> {
+    asset: &'a str,
+    price: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct AssetTrade<'a> {
+    account_id: &'a str,
+    asset: &'a str,
+    side: OrderSide,
+    asset_amount: U128,
+    ausd_amount: U128,
+    price: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct Liquidity<'a> {
+    account_id: &'a str,
+    art_amount: U128,
+    near_amount: U128,
+    shares: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct Swap<'a> {
+    account_id: &'a str,
+    path: &'a [String],
+    amount_in: U128,
+    amount_out: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct PlaceOrder<'a> {
+    order_id: U64,
+    account_id: &'a str,
+    side: OrderSide,
+    asset: &'a str,
+    amount: U128,
+    trigger_price: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct ExecuteOrder<'a> {
+    order_id: U64,
+    keeper_id: &'a str,
+    side: OrderSide,
+    asset: &'a str,
+    asset_amount: U128,
+    ausd_amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct Settlement<'a> {
+    account_id: &'a str,
+    asset: &'a str,
+    charge: U128,
+    rebate: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct Exchange<'a> {
+    account_id: &'a str,
+    path: ConversionPath,
+    amount_in: U128,
+    amount_out: U128,
+    fee: U128,
+}
+
+pub(crate) fn stake(account_id: &str, amount: Balance) {
+    let data = AccountAmount {
+        account_id,
+        amount: amount.into(),
+    };
+    emit(ART_STANDARD, ART_VERSION, "stake", data);
+}
+
+pub(crate) fn unstake(account_id: &str, amount: Balance) {
+    let data = AccountAmount {
+        account_id,
+        amount: amount.into(),
+    };
+    emit(ART_STANDARD, ART_VERSION, "unstake", data);
+}
+
+/// Staking reward added to the stake of `account_id`.
+pub(crate) fn reward(account_id: &str, amount: Balance) {
+    let data = AccountAmount {
+        account_id,
+        amount: amount.into(),
+    };
+    emit(ART_STANDARD, ART_VERSION, "reward", data);
+}
+
+/// `amount` of the stake of `account_id` moved to the liquid staking pool for `start_amount` stART.
+pub(crate) fn wrap_stake(account_id: &str, amount: Balance, start_amount: Balance) {
+    let data = LiquidStake {
+        account_id,
+        amount: amount.into(),
+        start_amount: start_amount.into(),
+    };
+    emit(ART_STANDARD, ART_VERSION, "wrap_stake", data);
+}
+
+/// `start_amount` stART redeemed for `amount` of staked art added to the stake of `account_id`.
+pub(crate) fn unwrap_stake(account_id: &str, amount: Balance, start_amount: Balance) {
+    let data = LiquidStake {
+        account_id,
+        amount: amount.into(),
+        start_amount: start_amount.into(),
+    };
+    emit(ART_STANDARD, ART_VERSION, "unwrap_stake", data);
+}
+
+/// New oracle price of `asset`, "art" for the art price.
+pub(crate) fn price_update(asset: &str, price: u128) {
+    let data = PriceUpdate {
+        asset,
+        price: price.into(),
+    };
+    emit(ART_STANDARD, ART_VERSION, "price_update", data);
+}
+
+pub(crate) fn asset_trade(
+    account_id: &str,
+    asset: &str,
+    side: OrderSide,
+    asset_amount: Balance,
+    ausd_amount: Balance,
+    price: u128,
+) {
+    let data = AssetTrade {
+        account_id,
+        asset,
+        side,
+        asset_amount: asset_amount.into(),
+        ausd_amount: ausd_amount.into(),
+        price: price.into(),
+    };
+    emit(ART_STANDARD, ART_VERSION, "asset_trade", data);
+}
+
+/// Conversion of `amount_in` on `path` for `amount_out`, after the `fee`.
+pub(crate) fn exchange(
+    account_id: &str,
+    path: ConversionPath,
+    amount_in: Balance,
+    amount_out: Balance,
+    fee: Balance,
+) {
+    let data = Exchange {
+        account_id,
+        path,
+        amount_in: amount_in.into(),
+        amount_out: amount_out.into(),
+        fee: fee.into(),
+    };
+    emit(ART_STANDARD, ART_VERSION, "exchange", data);
+}
+
+/// Snapshot `name` of the balances at the end of the block at `block_height`.
+pub(crate) fn snapshot(name: &str, block_height: u64) {
+    let data = Snapshot {
+        name,
+        block_height: block_height.into(),
+    };
+    emit(ART_STANDARD, ART_VERSION, "snapshot", data);
+}
+
+/// `event` of a vesting grant: `vesting_grant`, `vesting_claim`, `vesting_revoke`,
+/// `vesting_stake` or `vesting_unstake` of `amount`.
+pub(crate) fn vesting(event: &str, grant_id: u64, account_id: &str, amount: Balance) {
+    let data = Vesting {
+        grant_id: grant_id.into(),
+        account_id,
+        amount: amount.into(),
+    };
+    emit(ART_STANDARD, ART_VERSION, event, data);
+}
+
+/// `event` of the art/NEAR pool: `add_liquidity` or `remove_liquidity` of `art_amount` and
+/// `near_amount` for `shares`.
+pub(crate) fn liquidity(
+    event: &str,
+    account_id: &str,
+    art_amount: Balance,
+    near_amount: Balance,
+    shares: Balance,
+) {
+    let data = Liquidity {
+        account_id,
+        art_amount: art_amount.into(),
+        near_amount: near_amount.into(),
+        shares: shares.into(),
+    };
+    emit(ART_STANDARD, ART_VERSION, event, data);
+}
+
+/// Swap of `amount_in` of the first token of `path` for `amount_out` of the last one, through
+/// the art/NEAR pool or a route of conversions.
+pub(crate) fn swap(account_id: &str, path: &[String], amount_in: Balance, amount_out: Balance) {
+    let data = Swap {
+        account_id,
+        path,
+        amount_in: amount_in.into(),
+        amount_out: amount_out.into(),
+    };
+    emit(ART_STANDARD, ART_VERSION, "swap", data);
+}
+
+/// `asset` delisted at its final `price`.
+pub(crate) fn delist_asset(asset: &str, price: u128) {
+    let data = PriceUpdate {
+        asset,
+        price: price.into(),
+    };
+    emit(ART_STANDARD, ART_VERSION, "delist_asset", data);
+}
+
+/// Order escrowing `amount`, aUSD for buy orders and the asset for sell orders.
+pub(crate) fn place_order(
+    order_id: u64,
+    account_id: &str,
+    side: OrderSide,
+    asset: &str,
+    amount: Balance,
+    trigger_price: u128,
+) {
+    let data = PlaceOrder {
+        order_id: order_id.into(),
+        account_id,
+        side,
+        asset,
+        amount: amount.into(),
+        trigger_price: trigger_price.into(),
+    };
+    emit(ART_STANDARD, ART_VERSION, "place_order", data);
+}
+
+/// Order executed by `keeper_id` for `asset_amount` of `asset` and `ausd_amount` aUSD, after
+/// the keeper's bounty.
+pub(crate) fn execute_order(
+    order_id: u64,
+    keeper_id: &str,
+    side: OrderSide,
+    asset: &str,
+    asset_amount: Balance,
+    ausd_amount: Balance,
+) {
+    let data = ExecuteOrder {
+        order_id: order_id.into(),
+        keeper_id,
+        side,
+        asset,
+        asset_amount: asset_amount.into(),
+        ausd_amount: ausd_amount.into(),
+    };
+    emit(ART_STANDARD, ART_VERSION, "execute_order", data);
+}
+
+/// Delayed settlement of the trades of `asset` by `account_id`, `charge` and `rebate` in aUSD.
+pub(crate) fn settle_asset_trades(account_id: &str, asset: &str, charge: Balance, rebate: Balance) {
+    let data = Settlement {
+        account_id,
+        asset,
+        charge: charge.into(),
+        rebate: rebate.into(),
+    };
+    emit(ART_STANDARD, ART_VERSION, "settle_asset_trades", data);
+}
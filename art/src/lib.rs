@@ -1,3 +1,4 @@
+use batch_transfer::{assert_at_least_one_yocto, refund_deposit, BatchTransfer, FailedTransfer};
 use near_contract_standards::fungible_token::core::FungibleTokenCore;
 use near_contract_standards::fungible_token::metadata::{
     FungibleTokenMetadata, FungibleTokenMetadataProvider, FT_METADATA_SPEC,
@@ -5,17 +6,21 @@ use near_contract_standards::fungible_token::metadata::{
 use near_contract_standards::storage_management::{
     StorageBalance, StorageBalanceBounds, StorageManagement,
 };
-use near_sdk::collections::UnorderedMap;
+use near_sdk::collections::{LookupMap, UnorderedMap, Vector};
+use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
     assert_one_yocto,
     borsh::{self, BorshDeserialize, BorshSerialize},
-    json_types::{ValidAccountId, U128},
+    json_types::{Base58PublicKey, Base64VecU8, ValidAccountId, U128, U64},
     Gas, StorageUsage,
 };
 use near_sdk::{env, ext_contract, log, near_bindgen, AccountId, Balance, Promise, PromiseOrValue, PromiseResult};
 use num_bigint::BigInt;
 use num_rational::Ratio;
 use num_traits::cast::ToPrimitive;
+use permit::PermitMessage;
+
+mod event;
 use std::collections::HashMap;
 use std::str::FromStr;
 
@@ -207,6 +212,15 @@ impl Account {
     pub fn total_balance(&self) -> Balance {
         self.balance + self.staked_balance
     }
+
+    /// Returns the storage in bytes the allowances and assets take on top of an empty account.
+    pub fn extra_storage_usage(&self) -> StorageUsage {
+        self.allowances
+            .keys()
+            .chain(self.assets.keys())
+            .map(|key| (4 + key.len() + 16) as StorageUsage)
+            .sum()
+    }
 }
 
 #[ext_contract(ext_usd)]
@@ -218,15 +232,124 @@ pub trait ExtAUSDContract {
         burn_amount: u128,
         unstake_amount: u128,
     ) -> Promise;
-    fn burn_to_buy_asset(
-        &mut self,
-        account_id: String,
-        burn_amount: u128,
-        asset: String,
-        asset_amount: u128,
-    ) -> Promise;
     fn buy_ausd(&mut self, new_owner_id: AccountId, amount: U128);
     fn sell_ausd(&mut self, seller_id: AccountId, amount: U128);
+    fn burn(&mut self, account_id: String, burn_amount: u128);
+}
+
+#[ext_contract(ext_start)]
+pub trait ExtStartContract {
+    fn mint(&mut self, account_id: AccountId, amount: U128);
+    fn burn(&mut self, account_id: AccountId, amount: U128);
+}
+
+#[ext_contract(ext_art)]
+pub trait ExtArtCallbacks {
+    fn on_asset_purchased(
+        &mut self,
+        account_id: AccountId,
+        asset: String,
+        asset_amount: U128,
+        asset_price: U128,
+        round: U64,
+    );
+    fn on_settlement_charged(&mut self, account_id: AccountId, asset: String, asset_charge: U128);
+    fn on_settlement_rebated(&mut self, account_id: AccountId, asset: String);
+    fn on_delisted_asset_settled(
+        &mut self,
+        account_id: AccountId,
+        asset: String,
+        asset_amount: U128,
+    );
+    fn on_asset_sold(
+        &mut self,
+        account_id: AccountId,
+        asset: String,
+        asset_amount: U128,
+        ausd_amount: U128,
+        asset_price: U128,
+    );
+    fn on_sell_order_paid(
+        &mut self,
+        order: OrderView,
+        keeper_id: AccountId,
+        bounty: U128,
+        price: U128,
+    );
+    fn on_order_refunded(&mut self, order: OrderView);
+    fn on_art_sold_for_near(
+        &mut self,
+        account_id: AccountId,
+        amount: U128,
+        near_amount: U128,
+        fee: U128,
+    );
+    fn on_ausd_sold_for_near(
+        &mut self,
+        account_id: AccountId,
+        ausd_amount: U128,
+        near_amount: U128,
+        fee: U128,
+    );
+    fn on_near_sent_for_ausd(
+        &mut self,
+        account_id: AccountId,
+        ausd_amount: U128,
+        near_amount: U128,
+        fee: U128,
+    );
+    fn on_exchange_settled(
+        &mut self,
+        account_id: AccountId,
+        path: ConversionPath,
+        amount_in: U128,
+        amount_out: U128,
+        fee: U128,
+    );
+    fn on_fee_paid(&mut self, path: ConversionPath, fee: U128);
+    fn on_pool_near_sent(
+        &mut self,
+        account_id: AccountId,
+        art_amount: U128,
+        near_amount: U128,
+        shares: U128,
+    );
+    fn on_reserve_ausd_minted(
+        &mut self,
+        account_id: AccountId,
+        amount: U128,
+        ausd_amount: U128,
+        fee: U128,
+    );
+    fn on_reserve_ausd_burned(
+        &mut self,
+        account_id: AccountId,
+        amount: U128,
+        ausd_amount: U128,
+        fee: U128,
+    );
+    fn on_swap_input_taken(&mut self, route: SwapRoute) -> PromiseOrValue<U128>;
+    fn on_swap_output_sent(&mut self, route: SwapRoute) -> U128;
+    fn on_transfer_ausd_burned(
+        &mut self,
+        sender_id: AccountId,
+        action: AusdTransferAction,
+        amount: U128,
+        burn_amount: U128,
+        asset_price: U128,
+        round: U64,
+    ) -> U128;
+    fn on_buy_order_escrowed(
+        &mut self,
+        account_id: AccountId,
+        asset: String,
+        ausd_amount: U128,
+        trigger_price: U128,
+        direction: TriggerDirection,
+    ) -> Option<u64>;
+    fn on_start_minted(&mut self, account_id: AccountId, amount: U128, shares: U128);
+    fn on_stake_minted(&mut self, account_id: AccountId, stake_amount: U128, liquid: bool);
+    fn on_start_burned(&mut self, account_id: AccountId, shares: U128, amount: U128);
 }
 
 #[near_bindgen]
@@ -261,1018 +384,9261 @@ pub struct Art {
 
     /// The storage size in bytes for one account.
     pub account_storage_usage: StorageUsage,
-}
 
-impl Default for Art {
-    fn default() -> Self {
-        panic!("Fun token should be initialized before usage")
-    }
-}
+    /// Delisted asset -> final price the remaining balances are settled at
+    pub delisted_assets: UnorderedMap<String, u128>,
 
-#[near_bindgen]
-impl Art {
-    #[init(ignore_state)]
-    pub fn new(owner_id: AccountId, total_supply: String, ausd_token: AccountId) -> Self {
-        let total_supply = u128::from_str(&total_supply).expect("Failed to parse total supply");
-        let mut ft = Self {
-            accounts: UnorderedMap::new(b"a".to_vec()),
-            asset_prices: UnorderedMap::new(b"b".to_vec()),
-            total_supply,
-            price: 0,
-            owner: owner_id.clone(),
-            ausd_token,
-            total_staked: 0,
-            reward_paid_at: UnorderedMap::new(b"d".to_vec()),
-            staking_reward_enabled_at: env::block_timestamp(),
-            account_storage_usage: 0,
-        };
-        let mut account = ft.get_account(&owner_id);
-        account.balance = total_supply;
-        ft.accounts.insert(&owner_id, &account);
-        ft.measure_account_storage_usage();
-        ft
-    }
+    /// Asset -> total amount held by all accounts
+    pub asset_supplies: UnorderedMap<String, Balance>,
 
-    pub fn set_account_storage_usage(&mut self) {
-        if env::predecessor_account_id() != self.owner {
-            env::panic(b"Only owner can set account storage");
-        }
-        self.measure_account_storage_usage();
-    }
+    /// Asset -> maximum total amount that can be bought
+    pub asset_supply_caps: UnorderedMap<String, Balance>,
 
-    fn measure_account_storage_usage(&mut self) {
-        let initial_storage_usage = env::storage_usage();
-        let tmp_account_id = "a".repeat(64);
-        self.accounts.insert(&tmp_account_id, &Default::default());
-        self.account_storage_usage = env::storage_usage() - initial_storage_usage;
-        self.accounts.remove(&tmp_account_id);
-    }
+    /// Maximum USD value, in aUSD, of all synthetic assets in the system
+    pub max_total_asset_exposure: Option<Balance>,
 
-    pub fn refresh_reward(&mut self) -> bool {
-        log!("refresh_reward");
-        let account_id = env::predecessor_account_id();
-        let staked = self.get_staked_balance(account_id.clone());
-        let staked = u128::from_str(&staked).unwrap();
-        let mut account = self.get_account(&account_id);
+    /// Order ID -> open limit or stop order
+    pub orders: UnorderedMap<u64, Order>,
 
-        if staked == 0 {
-            env::log(b"no token was staked");
-            return false;
-        }
-        let mut reward_paid_at = self
-            .reward_paid_at
-            .get(&account_id)
-            .unwrap_or(self.staking_reward_enabled_at);
-        let now = env::block_timestamp();
-        let mut new_staked = staked;
-        let mut days = (now - reward_paid_at) / (24 * 60 * 60 * 1000000000);
-        if days == 0 {
-            env::log(b"not been a day since last reward_paid_at");
-            return false;
-        }
-        reward_paid_at += days * (24 * 60 * 60 * 1000000000);
-        if days > 365 {
-            let mut r = Ratio::new(new_staked, 1);
-            r *= YEAR_INTEREST[(days / 365) as usize];
-            new_staked = r.to_integer();
-            days %= 365;
-        }
-        if days > 30 {
-            let mut r = Ratio::new(new_staked, 1);
-            r *= MONTH_INTEREST[(days / 30) as usize];
-            new_staked = r.to_integer();
-            days %= 30;
-        }
-        if days > 0 {
-            let mut r = Ratio::new(new_staked, 1);
-            r *= DAY_INTEREST[days as usize];
-            new_staked = r.to_integer();
-        }
-        log!("refresh_reward {} {}", account.staked_balance, new_staked);
-        account.staked_balance = new_staked;
-        self.total_supply += new_staked - staked;
-        self.total_staked += new_staked - staked;
-        self.accounts.insert(&account_id, &account);
-        self.reward_paid_at.insert(&account_id, &reward_paid_at);
-        return true;
-    }
+    /// ID of the next placed order
+    pub next_order_id: u64,
 
-    #[payable]
-    pub fn buy_art_with_near(&mut self) {
-        let attached_deposit = env::attached_deposit();
-        if attached_deposit == 0 {
-            env::panic(b"Can't buy with 0 NEAR");
-        }
-        let account_id = env::predecessor_account_id();
-        let mut account = self.get_account(&account_id);
-        let near_price = self._get_asset_price(&"aNEAR".to_string());
-        if near_price == 0 {
-            env::panic(b"No NEAR price data from oracle");
-        }
-        let art_price = self.price;
-        if art_price == 0 {
-            env::panic(b"No price data from oracle");
-        }
-        let attached_deposit: BigInt = attached_deposit.into();
-        let art_amount: Ratio<BigInt> =
-            Ratio::<BigInt>::new(near_price.into(), art_price.into()) * attached_deposit;
-        let art_amount = art_amount.to_integer().to_u128().unwrap();
-        let mut owner = self.get_account(&self.owner);
+    /// Share of an executed order paid to the keeper, in basis points
+    pub order_bounty_bps: u32,
 
-        account.balance = account.balance.checked_add(art_amount).unwrap();
-        owner.balance = owner.balance.checked_sub(art_amount).unwrap();
+    /// Time in nanoseconds after an asset purchase before it's re-settled at the next oracle
+    /// price. 0 disables delayed settlement
+    pub settlement_waiting_period: u64,
 
-        self.accounts.insert(&self.owner, &owner);
-        self.accounts.insert(&account_id, &account);
-    }
+    /// Asset -> number of prices submitted for it
+    pub asset_price_rounds: LookupMap<String, u64>,
 
-    pub fn exchange_art_to_ausd(&mut self, amount: String) -> Promise {
-        if self.price == 0 {
-            // Not received any data from oracle
-            env::panic(b"No price data from oracle");
-        }
-        let amount = u128::from_str(&amount).expect("Failed to parse amount");
+    /// (Asset, round) -> price submitted while delayed settlement is enabled
+    pub asset_round_prices: LookupMap<(String, u64), PriceRound>,
 
-        let unit_price = Ratio::<BigInt>::new(self.price.into(), 100_000_000.into());
-        let amount_b: BigInt = amount.into();
+    /// (Account, asset) -> purchases waiting to be settled
+    pub pending_trades: LookupMap<(AccountId, String), Vec<PendingTrade>>,
 
-        let ausd_amount = unit_price * amount_b * Ratio::<BigInt>::new(997.into(), 1000.into());
-        let ausd_amount = ausd_amount.to_integer().to_u128().unwrap();
+    /// Index asset -> registered assets it's a weighted basket of
+    pub index_assets: UnorderedMap<String, Vec<IndexComponent>>,
 
-        let account_id = env::predecessor_account_id();
-        let mut owner = self.get_account(&self.owner);
-        let mut account = self.get_account(&account_id);
-        account.balance = account.balance.checked_sub(amount).unwrap();
-        owner.balance = owner.balance.checked_add(amount).unwrap();
-        self.accounts.insert(&self.owner, &owner);
-        self.accounts.insert(&account_id, &account);
-        ext_usd::buy_ausd(
-            account_id,
-            U128(ausd_amount),
-            &self.ausd_token,
-            0,
-            env::prepaid_gas() / 2,
-        )
-    }
+    /// Asset -> timestamp of its last submitted price
+    pub asset_prices_updated_at: LookupMap<String, u64>,
 
-    pub fn exchange_ausd_to_art(&mut self, ausd_amount: String) -> Promise {
-        if self.price == 0 {
-            // Not received any data from oracle
-            env::panic(b"No price data from oracle");
-        }
-        let unit_price = Ratio::<BigInt>::new(self.price.into(), 100_000_000.into());
-        let ausd_amount = u128::from_str(&ausd_amount).expect("Failed to parse ausd_amount");
-        let ausd_amount_b: BigInt = ausd_amount.into();
+    /// Maximum age in nanoseconds of component prices an index price is computed from
+    pub max_index_price_age: u64,
 
-        let amount = Ratio::<BigInt>::new(997.into(), 1000.into()) * ausd_amount_b / unit_price;
-        let amount = amount.to_integer().to_u128().unwrap();
+    /// art/NEAR constant-product pool
+    pub pool: LiquidityPool,
 
-        let account_id = env::predecessor_account_id();
-        let mut owner = self.get_account(&self.owner);
-        let mut account = self.get_account(&account_id);
-        account.balance = account.balance.checked_add(amount).unwrap();
-        owner.balance = owner.balance.checked_sub(amount).unwrap();
-        self.accounts.insert(&self.owner, &owner);
-        self.accounts.insert(&account_id, &account);
+    /// Whether exchanges between art and aUSD go through owner balances or the art reserve
+    pub conversion_mode: ConversionMode,
 
-        ext_usd::sell_ausd(
-            account_id,
-            U128(ausd_amount),
-            &self.ausd_token,
-            0,
-            env::prepaid_gas() / 2,
-        )
-    }
+    /// Art paid in for aUSD minted in `ConversionMode::Reserve`, not owned by any account
+    pub art_reserve: Balance,
 
-    #[payable]
-    pub fn buy_ausd_with_near(&mut self) -> Promise {
-        let attached_deposit = env::attached_deposit();
-        if attached_deposit == 0 {
-            env::panic(b"Can't buy with 0 NEAR");
-        }
-        let account_id = env::predecessor_account_id();
-        let near_price = self._get_asset_price(&"aNEAR".to_string());
-        if near_price == 0 {
-            env::panic(b"No NEAR price data from oracle");
-        }
-        let attached_deposit: BigInt = attached_deposit.into();
-        let ausd_amount: Ratio<BigInt> =
-            Ratio::<BigInt>::new(near_price.into(), 100000000.into()) * attached_deposit;
-        let ausd_amount = ausd_amount.to_integer().to_u128().unwrap();
-        ext_usd::buy_ausd(
-            account_id,
-            U128(ausd_amount),
-            &self.ausd_token,
-            0,
-            env::prepaid_gas() / 2,
-        )
-    }
+    /// Art the reserve can't be drawn below by `exchange_ausd_to_art` or `withdraw_art_reserve`
+    pub min_art_reserve: Balance,
 
-    pub fn sell_art_to_near(&mut self) {}
+    /// Maximum art the reserve can hold, `exchange_art_to_ausd` panics above it
+    pub max_art_reserve: Option<Balance>,
 
-    pub fn sell_ausd_to_near(&mut self) {}
+    /// Conversion path -> fee in basis points, `ConversionPath::default_fee_bps` if not set
+    pub conversion_fee_bps: LookupMap<ConversionPath, u32>,
 
-    /// Sets amount allowed to spent by `escrow_account_id` on behalf of the caller of the function
-    /// (`predecessor_id`) who is considered the balance owner to the new `allowance`.
-    pub fn set_allowance(&mut self, escrow_account_id: AccountId, allowance: String) {
-        let allowance = u128::from_str(&allowance).expect("Failed to parse allowance");
-        let owner_id = env::predecessor_account_id();
-        if escrow_account_id == owner_id {
-            env::panic(b"Can't set allowance for yourself");
-        }
-        let mut account = self.get_account(&owner_id);
+    /// Conversion path -> total fees paid to the treasury
+    pub collected_fees: LookupMap<ConversionPath, Balance>,
 
-        account.set_allowance(&escrow_account_id, allowance);
-        self.accounts.insert(&owner_id, &account);
-    }
+    /// Receives the conversion fees
+    pub treasury: AccountId,
 
-    pub fn submit_price(&mut self, price: String) {
-        if env::predecessor_account_id() != self.owner {
-            env::panic(b"Only owner can submit price data");
-        }
-        let price = u128::from_str(&price).expect("Failed to parse price");
-        // we completely trust owner for now
-        self.price = price;
-    }
+    /// Account allowed to set conversion fees and treasury besides owner
+    pub governance: Option<AccountId>,
 
-    pub fn submit_asset_price(&mut self, asset: String, price: String) {
-        if env::predecessor_account_id() != self.owner {
-            env::panic(b"Only owner can submit price data");
-        }
-        let price = u128::from_str(&price).expect("Failed to parse price");
-        self.asset_prices.insert(&asset, &price);
-    }
+    /// Conversion path -> volume limits, unlimited if not set
+    pub rate_limits: LookupMap<ConversionPath, RateLimit>,
 
-    pub fn stake_and_mint(&mut self, stake: String) -> Promise {
-        if self.price == 0 {
-            // Not received any data from oracle
-            env::panic(b"No price data from oracle");
-        }
-        let stake_amount = self.stake(stake);
+    /// Conversion path -> volume of all accounts in the current window
+    pub global_volumes: LookupMap<ConversionPath, WindowVolume>,
 
-        let unit_price = Ratio::<BigInt>::new(self.price.into(), 100_000_000.into());
+    /// (Account, conversion path) -> volume of the account in the current window
+    pub account_volumes: LookupMap<(AccountId, ConversionPath), WindowVolume>,
 
-        let mint_amount = Ratio::<BigInt>::new(stake_amount.into(), 5.into()) * unit_price;
-        let mint_amount = mint_amount.to_integer().to_u128().unwrap();
+    /// Staked art backing the stART liquid staking token
+    pub liquid_staking: LiquidStaking,
 
-        let account_id = env::predecessor_account_id();
-        ext_usd::mint(
-            account_id,
-            mint_amount,
-            &self.ausd_token,
-            0,
-            env::prepaid_gas() / 2,
-        )
-    }
+    /// AccountID -> ed25519 key that signs its permits
+    pub permit_keys: LookupMap<AccountId, Vec<u8>>,
 
-    pub fn burn_to_unstake(&mut self, unstake_amount: String) -> Promise {
-        if self.price == 0 {
-            // Not received any data from oracle
-            env::panic(b"No price data from oracle");
-        }
-        let unstake_amount =
-            u128::from_str(&unstake_amount).expect("Failed to parse unstake_amount");
+    /// AccountID -> nonce of its next permit
+    pub permit_nonces: LookupMap<AccountId, u64>,
 
-        let account_id = env::predecessor_account_id();
+    /// (Account, index) -> checkpoint of the total balance of the account, by increasing height
+    pub balance_checkpoints: LookupMap<(AccountId, u64), Checkpoint>,
 
-        let unit_price = Ratio::<BigInt>::new(self.price.into(), 100_000_000.into());
+    /// AccountID -> number of its balance checkpoints
+    pub balance_checkpoint_counts: LookupMap<AccountId, u64>,
 
-        let burn_amount = Ratio::<BigInt>::new(unstake_amount.into(), 5.into()) * unit_price;
-        let burn_amount = burn_amount.to_integer().to_u128().unwrap();
+    /// Checkpoints of the total supply, by increasing height
+    pub supply_checkpoints: Vector<Checkpoint>,
 
-        ext_usd::burn_to_unstake(
-            account_id,
-            burn_amount,
-            unstake_amount,
-            &self.ausd_token,
-            0,
-            env::prepaid_gas() / 3,
-        )
-    }
+    /// Snapshot name -> block height it was taken at
+    pub snapshots: UnorderedMap<String, u64>,
 
-    pub fn sell_asset_to_ausd(&mut self, asset: String, asset_amount: String) -> Promise {
-        let asset_price = self._get_asset_price(&asset);
-        if asset_price == 0 {
-            env::panic(b"No price data from oracle");
-        }
-        let asset_amount = u128::from_str(&asset_amount).expect("Failed to parse asset_amount");
+    /// Grant ID -> vesting grant
+    pub vesting_grants: UnorderedMap<u64, VestingGrant>,
 
-        let account_id = env::predecessor_account_id();
-        let mut account = self.get_account(&account_id);
-        let balance = self._get_asset_balance(&account_id, &asset);
-        let new_balance = balance.checked_sub(asset_amount).unwrap();
-        account.assets.insert(asset.clone(), new_balance);
-        self.accounts.insert(&account_id, &account);
+    /// ID of the next vesting grant
+    pub next_vesting_grant_id: u64,
 
-        let unit_price = Ratio::<BigInt>::new(asset_price.into(), 100_000_000.into());
-        let asset_amount: BigInt = asset_amount.into();
+    /// Account -> NEAR deposited for its storage with `storage_deposit`
+    pub storage_deposits: LookupMap<AccountId, Balance>,
 
-        let mint_amount: Ratio<BigInt> = unit_price * asset_amount;
-        let mint_amount = mint_amount.to_integer().to_u128().unwrap();
+    /// Asset -> (price, USD value in aUSD of its supply at that price) counted in
+    /// `total_asset_exposure`. An index keeps its last price while it can't be computed
+    pub asset_exposures: LookupMap<String, (u128, Balance)>,
 
-        ext_usd::mint(
-            account_id,
-            mint_amount,
-            &self.ausd_token,
-            0,
-            env::prepaid_gas() / 2,
-        )
-    }
+    /// USD value, in aUSD, of all synthetic assets at their last known prices
+    pub total_asset_exposure: Balance,
 
-    pub fn buy_asset_with_ausd(&mut self, asset: String, asset_amount: String) -> Promise {
-        let asset_price = self._get_asset_price(&asset);
-        if asset_price == 0 {
-            env::panic(b"No price data from oracle");
-        }
-        let asset_amount = u128::from_str(&asset_amount).expect("Failed to parse asset_amount");
-        let unit_price = Ratio::<BigInt>::new(asset_price.into(), 100_000_000.into());
-        let asset_amount_b: BigInt = asset_amount.into();
+    /// Asset -> amount bought but not credited yet, counted against its supply cap
+    pub reserved_asset_supplies: LookupMap<String, Balance>,
 
-        let burn_amount: Ratio<BigInt> = unit_price * asset_amount_b;
-        let burn_amount = burn_amount.to_integer().to_u128().unwrap();
+    /// USD value, in aUSD, of the reserved asset supplies counted against the exposure cap
+    pub reserved_asset_exposure: Balance,
 
-        let account_id = env::predecessor_account_id();
-        ext_usd::burn_to_buy_asset(
-            account_id,
-            burn_amount,
-            asset.clone(),
-            asset_amount,
-            &self.ausd_token,
-            0,
-            env::prepaid_gas() / 3,
-        )
-    }
+    /// (Account, asset) -> settled purchases whose aUSD charge is being burnt
+    pub settling_trades: LookupMap<(AccountId, String), Vec<PendingTrade>>,
 
-    pub fn buy_asset_callback(&mut self, account_id: String, asset: String, asset_amount: u128) {
-        assert!(
-            env::predecessor_account_id() == self.ausd_token,
-            "Only allow unstake originated from ausd token"
-        );
+    /// Asset -> last price round removed from `asset_round_prices`
+    pub pruned_price_rounds: LookupMap<String, u64>,
 
-        let mut account = self.get_account(&account_id);
-        let balance = self._get_asset_balance(&account_id, &asset);
-        let new_balance = balance.checked_add(asset_amount).unwrap();
-        account.assets.insert(asset.clone(), new_balance);
-        self.accounts.insert(&account_id, &account);
-    }
+    /// AccountID -> storage in bytes the account takes outside of `accounts`, e.g. its conversion
+    /// volumes, charged to its storage balance
+    pub storage_usages: LookupMap<AccountId, StorageUsage>,
 
-    /// Stakes an additional `stake_amount` to the signer
-    /// Requirements:
-    /// * The signer should have enough unstaked balance.
-    fn stake(&mut self, stake_amount: String) -> u128 {
-        let stake_amount = u128::from_str(&stake_amount).expect("Failed to parse stake_amount");
-        if stake_amount == 0 {
-            env::panic(b"Can't stake 0 tokens");
-        }
-        self.refresh_reward();
-        let account_id = env::predecessor_account_id();
-        let mut account = self.get_account(&account_id);
+    /// AccountID -> number of its open orders, counted until their callbacks are done
+    pub order_counts: LookupMap<AccountId, u64>,
 
-        // Checking and updating unstaked balance
-        if account.balance < stake_amount {
-            env::panic(b"Not enough unstaked balance");
-        }
-        account.balance -= stake_amount;
+    /// AccountID -> number of its vesting grants
+    pub vesting_grant_counts: LookupMap<AccountId, u64>,
 
-        // Updating total stake balance
-        let staked_balance = account.get_staked_balance();
-        account.set_staked_balance(staked_balance + stake_amount);
-        self.total_staked += stake_amount;
-        self.reward_paid_at
-            .insert(&account_id, &env::block_timestamp());
+    /// Accounts of the state before `migrate` still to be backfilled by `migrate_accounts`
+    pub account_migration: Option<AccountMigration>,
+}
 
-        self.accounts.insert(&account_id, &account);
-        stake_amount
-    }
+/// Weights of index components are scaled by this, so the index price is
+/// `sum(weight * component price) / INDEX_WEIGHT_DENOMINATOR`.
+pub const INDEX_WEIGHT_DENOMINATOR: u128 = 100_000_000;
 
-    /// Unstakes the `unstake_amount` from the owner
-    pub fn unstake(&mut self, account_id: String, unstake_amount: u128) {
-        assert!(
-            env::predecessor_account_id() == self.ausd_token,
-            "Only allow unstake originated from ausd token"
-        );
-        if unstake_amount == 0 {
-            env::panic(b"Can't unstake 0 tokens");
-        }
-        self.refresh_reward();
+/// Price rounds older than this, in nanoseconds, are pruned. Trades not settled by then settle at
+/// the oldest kept round.
+pub const MAX_PRICE_ROUND_AGE: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
 
-        let mut account = self.get_account(&account_id);
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct IndexComponent {
+    pub asset: String,
+    /// Amount of the component in one unit of the index, times `INDEX_WEIGHT_DENOMINATOR`
+    pub weight: U128,
+}
 
-        // Checking and updating staked balance
-        let staked_balance = account.get_staked_balance();
-        if staked_balance < unstake_amount {
-            env::panic(b"Not enough staked tokens");
-        }
-        account.set_staked_balance(staked_balance - unstake_amount);
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct PriceRound {
+    pub price: u128,
+    pub timestamp: u64,
+}
 
-        // Updating unstaked balance
-        account.balance += unstake_amount;
-        self.total_staked -= unstake_amount;
-        self.accounts.insert(&account_id, &account);
-    }
+/// Asset purchase that's re-settled at the first oracle price after the waiting period.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingTrade {
+    pub asset_amount: U128,
+    /// Price the purchase was executed at
+    pub asset_price: U128,
+    /// Price round the purchase was executed at
+    pub round: U64,
+    pub created_at: U64,
+}
 
-    /// Transfers unstaked `amount` of tokens from `owner_id` to the `new_owner_id`.
-    /// Requirements:
-    /// * The caller of the function (`predecessor_id`) should have at least `amount` of allowance tokens.
-    /// * The balance owner should have at least `amount` of unstaked (by `predecessor_id`) tokens
-    pub fn transfer_from(&mut self, owner_id: AccountId, new_owner_id: AccountId, amount: String) {
-        let amount = u128::from_str(&amount).expect("Failed to parse allow amount");
-        if amount == 0 {
-            env::panic(b"Can't transfer 0 tokens");
-        }
-        let escrow_account_id = env::predecessor_account_id();
-        let mut account = self.get_account(&owner_id);
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum OrderSide {
+    /// Escrows aUSD to buy the asset
+    Buy,
+    /// Escrows the asset to sell it for aUSD
+    Sell,
+}
 
-        // Checking and updating unstaked balance
-        if account.balance < amount {
-            env::panic(b"Not enough unstaked balance");
-        }
-        account.balance -= amount;
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerDirection {
+    /// Executable once the asset price is at or above the trigger price
+    Above,
+    /// Executable once the asset price is at or below the trigger price
+    Below,
+}
 
-        // If transferring by escrow, need to check and update allowance.
-        if escrow_account_id != owner_id {
-            let allowance = account.get_allowance(&escrow_account_id);
-            // Checking and updating unstaked balance
-            if allowance < amount {
-                env::panic(b"Not enough allowance");
-            }
-            account.set_allowance(&escrow_account_id, allowance - amount);
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Order {
+    pub account_id: AccountId,
+    pub side: OrderSide,
+    pub asset: String,
+    /// Escrowed amount, aUSD for buy orders and the asset for sell orders
+    pub amount: Balance,
+    /// Price per 10^8 units of the asset in USD
+    pub trigger_price: u128,
+    pub direction: TriggerDirection,
+}
+
+impl Order {
+    pub fn is_triggered(&self, price: u128) -> bool {
+        match self.direction {
+            TriggerDirection::Above => price >= self.trigger_price,
+            TriggerDirection::Below => price <= self.trigger_price,
         }
+    }
+}
 
-        self.accounts.insert(&owner_id, &account);
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OrderView {
+    pub id: u64,
+    pub account_id: AccountId,
+    pub side: OrderSide,
+    pub asset: String,
+    pub amount: U128,
+    pub trigger_price: U128,
+    pub direction: TriggerDirection,
+}
 
-        // Stake amount to the new owner
-        let mut new_account = self.get_account(&new_owner_id);
-        new_account.balance += amount;
-        self.accounts.insert(&new_owner_id, &new_account);
+impl OrderView {
+    fn new(id: u64, order: Order) -> Self {
+        Self {
+            id,
+            account_id: order.account_id,
+            side: order.side,
+            asset: order.asset,
+            amount: order.amount.into(),
+            trigger_price: order.trigger_price.into(),
+            direction: order.direction,
+        }
     }
+}
 
-    /// Same as `transfer_from` with `owner_id` `predecessor_id`.
-    pub fn transfer(&mut self, new_owner_id: AccountId, amount: String) {
-        self.transfer_from(env::predecessor_account_id(), new_owner_id, amount);
+impl From<OrderView> for Order {
+    fn from(view: OrderView) -> Self {
+        Self {
+            account_id: view.account_id,
+            side: view.side,
+            asset: view.asset,
+            amount: view.amount.into(),
+            trigger_price: view.trigger_price.into(),
+            direction: view.direction,
+        }
     }
+}
 
-    /// Returns total supply of tokens.
-    pub fn get_total_supply(&self) -> String {
-        self.total_supply.to_string()
-    }
+/// Open interest of one synthetic asset against its cap.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OpenInterest {
+    pub asset: String,
+    pub supply: U128,
+    pub cap: Option<U128>,
+    /// USD value of `supply` in aUSD at the current price
+    pub value: U128,
+    /// Set if the price of an index asset can't be computed
+    pub price_error: Option<String>,
+}
 
-    /// Returns total balance for the `owner_id` account. Including all staked and unstaked tokens.
-    pub fn get_total_balance(&self, owner_id: AccountId) -> String {
-        self.get_account(&owner_id).total_balance().to_string()
+/// Constant-product (x * y = k) pool of art and NEAR. Swap fees stay in the reserves, so they
+/// accrue to the liquidity providers.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LiquidityPool {
+    /// Art deposited to the pool, not part of any account balance
+    pub art_reserve: Balance,
+    /// NEAR deposited to the pool, part of the contract balance
+    pub near_reserve: Balance,
+    /// Total LP shares issued
+    pub total_shares: Balance,
+    /// AccountID -> LP shares
+    pub shares: LookupMap<AccountId, Balance>,
+    /// Swap fee in basis points
+    pub fee_bps: u32,
+}
+
+impl LiquidityPool {
+    fn new(prefix: Vec<u8>) -> Self {
+        Self {
+            art_reserve: 0,
+            near_reserve: 0,
+            total_shares: 0,
+            shares: LookupMap::new(prefix),
+            fee_bps: 30,
+        }
     }
 
-    /// Returns unstaked token balance for the `owner_id`.
-    pub fn get_unstaked_balance(&self, owner_id: AccountId) -> String {
-        self.get_account(&owner_id).balance.to_string()
+    fn shares_of(&self, account_id: &AccountId) -> Balance {
+        self.shares.get(account_id).unwrap_or(0)
     }
 
-    /// Returns current allowance for the `owner_id` to be able to use by `escrow_account_id`.
-    pub fn get_allowance(&self, owner_id: AccountId, escrow_account_id: AccountId) -> String {
-        self.get_account(&owner_id)
-            .get_allowance(&escrow_account_id)
-            .to_string()
+    /// Returns the output of swapping `amount_in` against the reserves, after the fee.
+    fn amount_out(&self, amount_in: Balance, reserve_in: Balance, reserve_out: Balance) -> Balance {
+        if reserve_in == 0 || reserve_out == 0 {
+            env::panic(b"The pool has no liquidity");
+        }
+        let amount_in_with_fee: BigInt = BigInt::from(amount_in) * (10_000 - self.fee_bps);
+        let numerator: BigInt = &amount_in_with_fee * reserve_out;
+        let denominator: BigInt = BigInt::from(reserve_in) * 10_000 + amount_in_with_fee;
+        (numerator / denominator).to_u128().unwrap()
     }
+}
 
-    /// Returns current staked balance for the `owner_id` staked by `escrow_account_id`.
-    pub fn get_staked_balance(&self, account_id: AccountId) -> String {
-        self.get_account(&account_id)
-            .get_staked_balance()
-            .to_string()
+/// Reserves of the art/NEAR pool.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolView {
+    pub art_reserve: U128,
+    pub near_reserve: U128,
+    pub total_shares: U128,
+    pub fee_bps: u32,
+}
+
+/// Art granted to `account_id` by owner, held by the contract until it's claimed. It vests linearly
+/// from `start` to `end`, but none of it before `cliff`.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct VestingGrant {
+    pub account_id: AccountId,
+    /// Art granted, only the vested art is left after the grant is revoked
+    pub amount: Balance,
+    /// Vested art claimed to the account balance
+    pub claimed: Balance,
+    /// Timestamps in nanoseconds
+    pub start: u64,
+    pub cliff: u64,
+    pub end: u64,
+    /// Whether owner can take back the art that isn't vested yet
+    pub revocable: bool,
+    /// Whether the art that isn't claimed yet can be staked
+    pub stakeable: bool,
+    /// Art of the grant that's staked, counted in `total_staked`
+    pub staked: Balance,
+    /// Staking reward not claimed yet, it isn't locked
+    pub reward: Balance,
+    /// Timestamp the staking reward is paid until
+    pub reward_paid_at: u64,
+}
+
+impl VestingGrant {
+    /// Returns the art vested at `timestamp`.
+    fn vested_at(&self, timestamp: u64) -> Balance {
+        if timestamp < self.cliff {
+            0
+        } else if timestamp >= self.end {
+            self.amount
+        } else {
+            mul_div(
+                self.amount,
+                (timestamp - self.start).into(),
+                (self.end - self.start).into(),
+            )
+        }
     }
 
-    pub fn get_price(&self) -> String {
-        self.price.to_string()
+    /// Returns the vested art that can be claimed at `timestamp`, staked art has to be unstaked
+    /// first.
+    fn claimable_at(&self, timestamp: u64) -> Balance {
+        std::cmp::min(
+            self.vested_at(timestamp) - self.claimed,
+            self.amount - self.claimed - self.staked,
+        )
     }
+}
 
-    pub fn get_asset_price(&self, asset: String) -> String {
-        self._get_asset_price(&asset).to_string()
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VestingGrantView {
+    pub id: u64,
+    pub account_id: AccountId,
+    pub amount: U128,
+    pub vested: U128,
+    pub claimed: U128,
+    /// Vested art and staking reward that can be claimed now
+    pub claimable: U128,
+    pub start: U64,
+    pub cliff: U64,
+    pub end: U64,
+    pub revocable: bool,
+    pub stakeable: bool,
+    pub staked: U128,
+    pub reward: U128,
+}
+
+impl VestingGrantView {
+    fn new(id: u64, grant: VestingGrant) -> Self {
+        let now = env::block_timestamp();
+        Self {
+            id,
+            vested: grant.vested_at(now).into(),
+            claimable: (grant.claimable_at(now) + grant.reward).into(),
+            account_id: grant.account_id,
+            amount: grant.amount.into(),
+            claimed: grant.claimed.into(),
+            start: grant.start.into(),
+            cliff: grant.cliff.into(),
+            end: grant.end.into(),
+            revocable: grant.revocable,
+            stakeable: grant.stakeable,
+            staked: grant.staked.into(),
+            reward: grant.reward.into(),
+        }
     }
+}
 
-    pub fn get_asset_balance(&self, account_id: AccountId, asset: String) -> String {
-        self._get_asset_balance(&account_id, &asset).to_string()
+/// Balance, staked and unstaked, from `block_height` until the next checkpoint.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Checkpoint {
+    pub block_height: u64,
+    pub balance: Balance,
+}
+
+/// Pool of staked art that stART is a share of. The pool earns the staking reward like any stake,
+/// so the art one stART is worth grows over time.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LiquidStaking {
+    /// stART contract, liquid staking is disabled until it's set
+    pub start_token: Option<AccountId>,
+    /// Art staked by the pool, not part of any account balance but counted in `total_staked`
+    pub staked: Balance,
+    /// Total stART minted for the pool
+    pub shares: Balance,
+    /// Timestamp the staking reward of the pool is paid until
+    pub reward_paid_at: u64,
+}
+
+impl LiquidStaking {
+    /// Returns the stART worth `amount` of staked art.
+    fn shares_for(&self, amount: Balance) -> Balance {
+        if self.shares == 0 || self.staked == 0 {
+            amount
+        } else {
+            mul_div(amount, self.shares, self.staked)
+        }
     }
 
-    pub fn get_reward_paid_at(&self, account_id: AccountId) -> u64 {
-        self.reward_paid_at
-            .get(&account_id)
-            .unwrap_or(self.staking_reward_enabled_at)
+    /// Returns the staked art `shares` of stART is worth.
+    fn amount_for(&self, shares: Balance) -> Balance {
+        if self.shares == 0 {
+            0
+        } else {
+            mul_div(shares, self.staked, self.shares)
+        }
     }
 }
 
-impl Art {
-    /// Helper method to get the account details for `owner_id`.
-    fn get_account(&self, owner_id: &AccountId) -> Account {
-        self.accounts.get(owner_id).unwrap_or_default()
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LiquidStakingView {
+    pub start_token: Option<AccountId>,
+    pub staked: U128,
+    pub start_supply: U128,
+    pub reward_paid_at: U64,
+}
+
+/// Where art and aUSD come from and go to in `exchange_art_to_ausd` and `exchange_ausd_to_art`.
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum ConversionMode {
+    /// Art is paid to and taken from owner, aUSD is moved from and to the aUSD owner
+    OwnerInventory,
+    /// Art is paid to and taken from the protocol art reserve, aUSD is minted and burned
+    Reserve,
+}
+
+/// Protocol art reserve backing aUSD in `ConversionMode::Reserve`.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ArtReserveView {
+    pub conversion_mode: ConversionMode,
+    pub art_reserve: U128,
+    pub min_art_reserve: U128,
+    pub max_art_reserve: Option<U128>,
+    /// Art that can currently be bought out of the reserve
+    pub available_art: U128,
+}
+
+/// Oracle-priced conversion between art, aUSD and NEAR. Each path has its own fee, charged in the
+/// token received and paid to the treasury.
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum ConversionPath {
+    /// `exchange_art_to_ausd`
+    ArtToAusd,
+    /// `exchange_ausd_to_art`
+    AusdToArt,
+    /// `buy_art_with_near`
+    NearToArt,
+    /// `buy_ausd_with_near`
+    NearToAusd,
+    /// `sell_art_to_near`
+    ArtToNear,
+    /// `sell_ausd_to_near`
+    AusdToNear,
+}
+
+impl ConversionPath {
+    pub const ALL: [ConversionPath; 6] = [
+        ConversionPath::ArtToAusd,
+        ConversionPath::AusdToArt,
+        ConversionPath::NearToArt,
+        ConversionPath::NearToAusd,
+        ConversionPath::ArtToNear,
+        ConversionPath::AusdToNear,
+    ];
+
+    /// Fee of the path until owner or governance sets one.
+    pub fn default_fee_bps(self) -> u32 {
+        match self {
+            ConversionPath::NearToArt | ConversionPath::NearToAusd => 0,
+            _ => 30,
+        }
     }
 
-    fn _get_asset_price(&self, asset: &String) -> u128 {
-        self.asset_prices.get(asset).unwrap_or_default()
+    /// Token the fee is charged in.
+    pub fn fee_token(self) -> &'static str {
+        match self {
+            ConversionPath::ArtToAusd | ConversionPath::NearToAusd => "aUSD",
+            ConversionPath::AusdToArt | ConversionPath::NearToArt => "art",
+            ConversionPath::ArtToNear | ConversionPath::AusdToNear => "NEAR",
+        }
     }
 
-    fn _get_asset_balance(&self, account_id: &AccountId, asset: &String) -> Balance {
-        *self
-            .get_account(&account_id)
-            .assets
-            .get(asset)
-            .unwrap_or(&0)
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ConversionPath::ArtToAusd => "art_to_ausd",
+            ConversionPath::AusdToArt => "ausd_to_art",
+            ConversionPath::NearToArt => "near_to_art",
+            ConversionPath::NearToAusd => "near_to_ausd",
+            ConversionPath::ArtToNear => "art_to_near",
+            ConversionPath::AusdToNear => "ausd_to_near",
+        }
     }
 }
 
-#[ext_contract(ext_fungible_token_receiver)]
-pub trait FungibleTokenReceiver {
-    fn ft_on_transfer(
-        &mut self,
-        sender_id: AccountId,
-        amount: U128,
-        msg: String,
-    ) -> PromiseOrValue<U128>;
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConversionFeeView {
+    pub path: ConversionPath,
+    pub fee_bps: u32,
+    pub fee_token: String,
+    /// Fees paid to the treasury on this path so far, in `fee_token`
+    pub collected: U128,
 }
 
-#[ext_contract(ext_fungible_token)]
-pub trait FungibleTokenContract {
-    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+/// Volume limits of a conversion path, in the token paid, per window of `window` nanoseconds.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct RateLimit {
+    pub window: u64,
+    pub account_limit: Option<Balance>,
+    pub global_limit: Option<Balance>,
+}
 
-    fn ft_transfer_call(
-        &mut self,
-        receiver_id: AccountId,
-        amount: U128,
-        memo: Option<String>,
-        msg: String,
-    ) -> PromiseOrValue<U128>;
+/// Volume converted in the window starting at `window_start`.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct WindowVolume {
+    pub window_start: u64,
+    pub volume: Balance,
+}
 
-    /// Returns the total supply of the token in a decimal string representation.
-    fn ft_total_supply(&self) -> U128;
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RateLimitView {
+    pub window: U64,
+    pub account_limit: Option<U128>,
+    pub global_limit: Option<U128>,
+}
 
-    /// Returns the balance of the account. If the account doesn't exist must returns `"0"`.
-    fn ft_balance_of(&self, account_id: AccountId) -> U128;
+/// Volume that can still be converted on a path in the current window, `None` if unlimited.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConversionAllowance {
+    pub account_remaining: Option<U128>,
+    pub global_remaining: Option<U128>,
+    /// Timestamp the current window ends and the volumes reset at
+    pub window_ends_at: U64,
 }
 
-const GAS_FOR_RESOLVE_TRANSFER: Gas = 5_000_000_000_000;
-const GAS_FOR_FT_TRANSFER_CALL: Gas = 30_000_000_000_000 + GAS_FOR_RESOLVE_TRANSFER;
+/// Expected result of a conversion at the current prices, computed by the same code that executes
+/// it.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Quote {
+    /// Amount paid
+    pub amount_in: U128,
+    /// Amount received, after the fee
+    pub amount_out: U128,
+    /// Fee taken from the received amount
+    pub fee: U128,
+    /// Price of 10^8 units of the converted art, NEAR or asset in the other token of the
+    /// conversion (aUSD, or NEAR for art/NEAR conversions)
+    pub price: U128,
+}
 
-const NO_DEPOSIT: Balance = 0;
+impl Quote {
+    fn new(amount_in: Balance, amount_out: Balance, fee: Balance, price: u128) -> Self {
+        Self {
+            amount_in: amount_in.into(),
+            amount_out: amount_out.into(),
+            fee: fee.into(),
+            price: price.into(),
+        }
+    }
+}
 
-#[near_bindgen]
-impl FungibleTokenMetadataProvider for Art {
-    fn ft_metadata(&self) -> FungibleTokenMetadata {
-        FungibleTokenMetadata {
-            spec: FT_METADATA_SPEC.to_string(),
-            name: "Artificial Coin".to_string(),
-            symbol: "art".to_string(),
-            icon: Some("https://artcoin.network/static/media/logo192.c7f41b7c.png".to_string()),
-            decimals: 24,
-            reference: None,
-            reference_hash: None,
+/// Token of a `swap` path: "NEAR", "art", "aUSD" or a registered asset.
+#[derive(Clone, PartialEq)]
+enum SwapToken {
+    Near,
+    Art,
+    Ausd,
+    Asset(String),
+}
+
+impl SwapToken {
+    fn parse(token: &str) -> Self {
+        match token {
+            "NEAR" => SwapToken::Near,
+            "art" => SwapToken::Art,
+            "aUSD" => SwapToken::Ausd,
+            asset => SwapToken::Asset(asset.to_string()),
         }
     }
 }
 
-#[near_bindgen]
-impl StorageManagement for Art {
-    // `registration_only` doesn't affect the implementation for vanilla fungible token.
-    #[allow(unused_variables)]
-    #[payable]
-    fn storage_deposit(
-        &mut self,
-        account_id: Option<ValidAccountId>,
-        registration_only: Option<bool>,
-    ) -> StorageBalance {
-        let amount: Balance = env::attached_deposit();
-        let account_id = account_id
-            .map(|a| a.into())
-            .unwrap_or_else(|| env::predecessor_account_id());
-        if self.accounts.get(&account_id).is_some() {
-            log!("The account is already registered, refunding the deposit");
-            if amount > 0 {
-                Promise::new(env::predecessor_account_id()).transfer(amount);
-            }
-        } else {
-            let min_balance = self.storage_balance_bounds().min.0;
-            if amount < min_balance {
-                env::panic(b"The attached deposit is less than the mimimum storage balance");
-            }
+/// Fee taken on one leg of a swap.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapFee {
+    pub path: ConversionPath,
+    /// Amount converted on the leg
+    pub amount_in: U128,
+    /// Amount received on the leg, after the fee
+    pub amount_out: U128,
+    pub amount: U128,
+}
 
-            self.internal_register_account(&account_id);
-            let refund = amount - min_balance;
-            if refund > 0 {
-                Promise::new(env::predecessor_account_id()).transfer(refund);
-            }
-        }
-        self.internal_storage_balance_of(&account_id).unwrap()
-    }
+/// Expected result of a `swap`, computed by the same code that executes it.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapQuote {
+    pub amount_in: U128,
+    pub amount_out: U128,
+    /// Fees of the legs, each in the token received on its leg
+    pub fees: Vec<SwapFee>,
+    /// Price of the asset bought, if the swap ends with one
+    pub asset_price: U128,
+}
 
-    /// While storage_withdraw normally allows the caller to retrieve `available` balance, the basic
-    /// Fungible Token implementation sets storage_balance_bounds.min == storage_balance_bounds.max,
-    /// which means available balance will always be 0. So this implementation:
-    /// * panics if `amount > 0`
-    /// * never transfers Ⓝ to caller
-    /// * returns a `storage_balance` struct if `amount` is 0
-    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
-        assert_one_yocto();
-        let predecessor_account_id = env::predecessor_account_id();
-        if let Some(storage_balance) = self.internal_storage_balance_of(&predecessor_account_id) {
-            match amount {
-                Some(amount) if amount.0 > 0 => {
-                    env::panic(b"The amount is greater than the available storage balance");
-                }
-                _ => storage_balance,
-            }
-        } else {
-            env::panic(
-                format!("The account {} is not registered", &predecessor_account_id).as_bytes(),
-            );
-        }
-    }
+/// A `swap` in progress, passed to its callbacks to finish or roll it back.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapRoute {
+    pub account_id: AccountId,
+    pub path: Vec<String>,
+    pub amount_in: U128,
+    pub amount_out: U128,
+    pub fees: Vec<SwapFee>,
+    pub asset_price: U128,
+    /// Price round of the asset bought, if the swap ends with one
+    pub round: U64,
+    /// Whether art paid for or received from aUSD goes to or comes from the art reserve, and
+    /// aUSD is minted or burnt for it
+    pub uses_art_reserve: bool,
+}
 
-    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
-        self.internal_storage_unregister(force).is_some()
-    }
+/// Operation requested by the `msg` of an aUSD `ft_transfer_call` to the art contract, e.g.
+/// `{"action":"burn_to_unstake","amount":"100"}`.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum AusdTransferAction {
+    /// Unstakes `amount` of art for the aUSD that `burn_to_unstake` burns
+    BurnToUnstake { amount: U128 },
+    /// Buys `asset_amount` of `asset` at the oracle price
+    BuyAsset { asset: String, asset_amount: U128 },
+}
 
-    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
-        let required_storage_balance =
-            Balance::from(self.account_storage_usage) * env::storage_byte_cost();
-        StorageBalanceBounds {
-            min: required_storage_balance.into(),
-            max: Some(required_storage_balance.into()),
-        }
-    }
+/// State of the first version of the contract, read by `migrate`.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct OldArt {
+    pub accounts: UnorderedMap<AccountId, Account>,
+    pub total_supply: Balance,
+    pub price: u128,
+    pub owner: AccountId,
+    pub ausd_token: AccountId,
+    pub total_staked: Balance,
+    pub asset_prices: UnorderedMap<String, u128>,
+    pub reward_paid_at: UnorderedMap<AccountId, u64>,
+    pub staking_reward_enabled_at: u64,
+    pub account_storage_usage: StorageUsage,
+}
 
-    fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance> {
-        self.internal_storage_balance_of(account_id.as_ref())
+/// Range of the indexes in `accounts` of the accounts `migrate_accounts` hasn't backfilled yet.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AccountMigration {
+    pub next_index: u64,
+    pub end_index: u64,
+}
+
+impl Default for Art {
+    fn default() -> Self {
+        panic!("Fun token should be initialized before usage")
     }
 }
 
+#[near_bindgen]
 impl Art {
-    /// Internal method that returns the Account ID and the balance in case the account was
-    /// unregistered.
-    pub fn internal_storage_unregister(
-        &mut self,
-        force: Option<bool>,
-    ) -> Option<(AccountId, Balance)> {
-        assert_one_yocto();
-        let account_id = env::predecessor_account_id();
-        let force = force.unwrap_or(false);
-        if let Some(account) = self.accounts.get(&account_id) {
-            let balance = account.balance.checked_add(account.staked_balance).unwrap();
-            if balance == 0 || force {
-                self.accounts.remove(&account_id);
-                self.total_supply -= balance;
-                Promise::new(account_id.clone()).transfer(self.storage_balance_bounds().min.0 + 1);
-                Some((account_id, balance))
-            } else {
-                env::panic(b"Can't unregister the account with the positive balance without force")
-            }
-        } else {
-            log!("The account {} is not registered", &account_id);
-            None
+    #[init(ignore_state)]
+    pub fn new(owner_id: AccountId, total_supply: String, ausd_token: AccountId) -> Self {
+        let total_supply = u128::from_str(&total_supply).expect("Failed to parse total supply");
+        let mut ft = Self {
+            total_supply,
+            ..Self::empty(owner_id.clone(), ausd_token)
+        };
+        ft.internal_register_account(&owner_id);
+        let mut account = ft.get_account(&owner_id);
+        account.balance = total_supply;
+        ft.internal_save_account(&owner_id, &account);
+        ft.internal_checkpoint_total_supply();
+        ft.measure_account_storage_usage();
+        // The owner's minimum storage balance is paid by the contract
+        let min_balance = ft.storage_balance_bounds().min.0;
+        ft.storage_deposits.insert(&owner_id, &min_balance);
+        if total_supply > 0 {
+            event::ft_mint(&owner_id, total_supply, None);
         }
+        ft
     }
 
-    fn internal_storage_balance_of(&self, account_id: &AccountId) -> Option<StorageBalance> {
-        if self.accounts.get(account_id).is_some() {
-            Some(StorageBalance {
-                total: self.storage_balance_bounds().min,
-                available: 0.into(),
-            })
-        } else {
-            None
+    /// Migrates the state of the first version of the contract. The accounts are backfilled by
+    /// `migrate_accounts` afterwards, asset trades and unregistering are paused until then. Only
+    /// the contract itself can call it.
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "Method is private"
+        );
+        let old: OldArt = env::state_read().expect("Failed to read the old state");
+        let mut ft = Self {
+            accounts: old.accounts,
+            total_supply: old.total_supply,
+            price: old.price,
+            total_staked: old.total_staked,
+            asset_prices: old.asset_prices,
+            reward_paid_at: old.reward_paid_at,
+            staking_reward_enabled_at: old.staking_reward_enabled_at,
+            ..Self::empty(old.owner, old.ausd_token)
+        };
+        ft.measure_account_storage_usage();
+        ft.internal_checkpoint_total_supply();
+        if !ft.accounts.is_empty() {
+            ft.account_migration = Some(AccountMigration {
+                next_index: 0,
+                end_index: ft.accounts.len(),
+            });
         }
+        ft
     }
 
-    pub fn internal_register_account(&mut self, account_id: &AccountId) {
-        if self
-            .accounts
-            .insert(&account_id, &Default::default())
-            .is_some()
-        {
-            env::panic(b"The account is already registered");
+    /// Backfills up to `limit` accounts of the state migrated by `migrate`: the supplies of their
+    /// assets are counted, their balances checkpointed and they get the minimum storage balance.
+    /// Returns whether accounts are left. Only owner can call it.
+    pub fn migrate_accounts(&mut self, limit: u64) -> bool {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can migrate accounts");
+        }
+        let mut migration = self
+            .account_migration
+            .take()
+            .unwrap_or_else(|| env::panic(b"No accounts to migrate"));
+        let end_index = std::cmp::min(migration.next_index + limit, migration.end_index);
+        for index in migration.next_index..end_index {
+            let account_id = self.accounts.keys_as_vector().get(index).unwrap();
+            let account = self.accounts.values_as_vector().get(index).unwrap();
+            self.internal_migrate_account(&account_id, &account);
+        }
+        migration.next_index = end_index;
+        if end_index < migration.end_index {
+            self.account_migration = Some(migration);
+            return true;
+        }
+        let assets: Vec<String> = self.asset_supplies.keys().collect();
+        for asset in assets.iter() {
+            self.internal_update_asset_exposure(asset);
         }
+        false
     }
 
-    pub fn internal_unwrap_balance_of(&self, account_id: &AccountId) -> Balance {
-        match self.accounts.get(&account_id) {
-            Some(account) => account.balance,
-            None => env::panic(format!("The account {} is not registered", &account_id).as_bytes()),
+    /// Returns the state of a contract without accounts or supply, with the defaults of the
+    /// settings.
+    fn empty(owner_id: AccountId, ausd_token: AccountId) -> Self {
+        Self {
+            accounts: UnorderedMap::new(b"a".to_vec()),
+            asset_prices: UnorderedMap::new(b"b".to_vec()),
+            total_supply: 0,
+            price: 0,
+            owner: owner_id.clone(),
+            ausd_token,
+            total_staked: 0,
+            reward_paid_at: UnorderedMap::new(b"d".to_vec()),
+            staking_reward_enabled_at: env::block_timestamp(),
+            account_storage_usage: 0,
+            delisted_assets: UnorderedMap::new(b"e".to_vec()),
+            asset_supplies: UnorderedMap::new(b"f".to_vec()),
+            asset_supply_caps: UnorderedMap::new(b"g".to_vec()),
+            max_total_asset_exposure: None,
+            orders: UnorderedMap::new(b"h".to_vec()),
+            next_order_id: 0,
+            order_bounty_bps: 10,
+            settlement_waiting_period: 0,
+            asset_price_rounds: LookupMap::new(b"i".to_vec()),
+            asset_round_prices: LookupMap::new(b"j".to_vec()),
+            pending_trades: LookupMap::new(b"k".to_vec()),
+            index_assets: UnorderedMap::new(b"l".to_vec()),
+            asset_prices_updated_at: LookupMap::new(b"m".to_vec()),
+            max_index_price_age: 60 * 60 * 1_000_000_000,
+            pool: LiquidityPool::new(b"n".to_vec()),
+            conversion_mode: ConversionMode::OwnerInventory,
+            art_reserve: 0,
+            min_art_reserve: 0,
+            max_art_reserve: None,
+            conversion_fee_bps: LookupMap::new(b"o".to_vec()),
+            collected_fees: LookupMap::new(b"p".to_vec()),
+            treasury: owner_id,
+            governance: None,
+            rate_limits: LookupMap::new(b"q".to_vec()),
+            global_volumes: LookupMap::new(b"r".to_vec()),
+            account_volumes: LookupMap::new(b"s".to_vec()),
+            liquid_staking: LiquidStaking {
+                start_token: None,
+                staked: 0,
+                shares: 0,
+                reward_paid_at: env::block_timestamp(),
+            },
+            permit_keys: LookupMap::new(b"t".to_vec()),
+            permit_nonces: LookupMap::new(b"u".to_vec()),
+            balance_checkpoints: LookupMap::new(b"v".to_vec()),
+            balance_checkpoint_counts: LookupMap::new(b"w".to_vec()),
+            supply_checkpoints: Vector::new(b"x".to_vec()),
+            snapshots: UnorderedMap::new(b"y".to_vec()),
+            vesting_grants: UnorderedMap::new(b"z".to_vec()),
+            next_vesting_grant_id: 0,
+            storage_deposits: LookupMap::new(b"c".to_vec()),
+            asset_exposures: LookupMap::new(b"A".to_vec()),
+            total_asset_exposure: 0,
+            reserved_asset_supplies: LookupMap::new(b"B".to_vec()),
+            reserved_asset_exposure: 0,
+            settling_trades: LookupMap::new(b"C".to_vec()),
+            pruned_price_rounds: LookupMap::new(b"D".to_vec()),
+            storage_usages: LookupMap::new(b"E".to_vec()),
+            order_counts: LookupMap::new(b"F".to_vec()),
+            vesting_grant_counts: LookupMap::new(b"G".to_vec()),
+            account_migration: None,
         }
     }
 
-    pub fn internal_deposit(&mut self, account_id: &AccountId, amount: Balance) {
-        let mut account = self.accounts.get(&account_id).unwrap();
-        let balance = self.internal_unwrap_balance_of(account_id);
-        if let Some(new_balance) = balance.checked_add(amount) {
-            account.balance = new_balance;
-            self.accounts.insert(&account_id, &account);
-            self.total_supply = self
-                .total_supply
-                .checked_add(amount)
-                .expect("Total supply overflow");
-        } else {
-            env::panic(b"Balance overflow");
+    pub fn set_account_storage_usage(&mut self) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can set account storage");
         }
+        self.measure_account_storage_usage();
     }
 
-    pub fn internal_withdraw(&mut self, account_id: &AccountId, amount: Balance) {
-        let mut account = self.accounts.get(&account_id).unwrap();
-        let balance = self.internal_unwrap_balance_of(account_id);
-        if let Some(new_balance) = balance.checked_sub(amount) {
-            account.balance = new_balance;
-            self.accounts.insert(&account_id, &account);
-            self.total_supply = self
-                .total_supply
-                .checked_sub(amount)
-                .expect("Total supply overflow");
-        } else {
-            env::panic(b"The account doesn't have enough balance");
-        }
+    /// Measures the storage of an empty account, including its reward timestamp, storage deposit
+    /// and storage usage. Allowances, assets and `storage_usages` are charged per byte on top of
+    /// it.
+    fn measure_account_storage_usage(&mut self) {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = "a".repeat(64);
+        self.accounts.insert(&tmp_account_id, &Default::default());
+        self.reward_paid_at.insert(&tmp_account_id, &0);
+        self.storage_deposits.insert(&tmp_account_id, &0);
+        self.storage_usages.insert(&tmp_account_id, &0);
+        self.account_storage_usage = env::storage_usage() - initial_storage_usage;
+        self.accounts.remove(&tmp_account_id);
+        self.reward_paid_at.remove(&tmp_account_id);
+        self.storage_deposits.remove(&tmp_account_id);
+        self.storage_usages.remove(&tmp_account_id);
     }
 
-    pub fn internal_transfer(
-        &mut self,
-        sender_id: &AccountId,
-        receiver_id: &AccountId,
-        amount: Balance,
-        memo: Option<String>,
-    ) {
-        assert_ne!(
-            sender_id, receiver_id,
-            "Sender and receiver should be different"
-        );
-        assert!(amount > 0, "The amount should be a positive number");
-        self.internal_withdraw(sender_id, amount);
-        self.internal_deposit(receiver_id, amount);
-        log!("Transfer {} from {} to {}", amount, sender_id, receiver_id);
-        if let Some(memo) = memo {
-            log!("Memo: {}", memo);
-        }
+    pub fn refresh_reward(&mut self) -> bool {
+        self.internal_refresh_reward(&env::predecessor_account_id())
     }
-}
 
-#[near_bindgen]
-impl FungibleTokenCore for Art {
-    #[payable]
-    fn ft_transfer(&mut self, receiver_id: ValidAccountId, amount: U128, memo: Option<String>) {
-        assert_one_yocto();
-        let sender_id = env::predecessor_account_id();
-        let amount: Balance = amount.into();
-        self.internal_transfer(&sender_id, receiver_id.as_ref(), amount, memo);
+    /// Pays the staking reward of the liquid staking pool. Returns false if it hasn't been a day
+    /// since the last reward.
+    pub fn refresh_liquid_reward(&mut self) -> bool {
+        self.internal_refresh_liquid_reward()
     }
 
+    /// Buys art from owner with the attached NEAR at the oracle price. Panics if less than
+    /// `min_amount_out` art would be received or the block timestamp is past `deadline`.
     #[payable]
-    fn ft_transfer_call(
+    pub fn buy_art_with_near(&mut self, min_amount_out: Option<String>, deadline: Option<U64>) {
+        assert_deadline(deadline);
+        let attached_deposit = env::attached_deposit();
+        if attached_deposit == 0 {
+            env::panic(b"Can't buy with 0 NEAR");
+        }
+        let quote = self.internal_quote_near_to_art(attached_deposit);
+        let art_amount = quote.amount_out.0;
+        assert_min_amount_out(art_amount, min_amount_out);
+        let account_id = env::predecessor_account_id();
+        self.internal_use_conversion_allowance(
+            ConversionPath::NearToArt,
+            &account_id,
+            attached_deposit,
+        );
+        let mut account = self.get_account(&account_id);
+        let mut owner = self.get_account(&self.owner);
+
+        account.balance = account.balance.checked_add(art_amount).unwrap();
+        owner.balance = owner.balance.checked_sub(art_amount + quote.fee.0).unwrap();
+
+        self.internal_save_account(&self.owner.clone(), &owner);
+        self.internal_save_account(&account_id, &account);
+        event::ft_transfer(&self.owner, &account_id, art_amount, None);
+        event::exchange(
+            &account_id,
+            ConversionPath::NearToArt,
+            attached_deposit,
+            art_amount,
+            quote.fee.0,
+        );
+        self.internal_pay_art_fee(ConversionPath::NearToArt, quote.fee.0);
+    }
+
+    /// Sells `amount` of art to owner for aUSD at the oracle price, minus the `art_to_ausd` fee.
+    /// Panics if less than `min_amount_out` aUSD would be received or the block timestamp is past
+    /// `deadline`.
+    pub fn exchange_art_to_ausd(
         &mut self,
-        receiver_id: ValidAccountId,
-        amount: U128,
-        memo: Option<String>,
-        msg: String,
-    ) -> PromiseOrValue<U128> {
-        assert_one_yocto();
-        let sender_id = env::predecessor_account_id();
-        let amount: Balance = amount.into();
-        self.internal_transfer(&sender_id, receiver_id.as_ref(), amount, memo);
-        // Initiating receiver's call and the callback
-        ext_fungible_token_receiver::ft_on_transfer(
-            sender_id.clone(),
-            amount.into(),
-            msg,
-            receiver_id.as_ref(),
-            NO_DEPOSIT,
-            env::prepaid_gas() - GAS_FOR_FT_TRANSFER_CALL,
+        amount: String,
+        min_amount_out: Option<String>,
+        deadline: Option<U64>,
+    ) -> Promise {
+        assert_deadline(deadline);
+        let amount = u128::from_str(&amount).expect("Failed to parse amount");
+        let quote = self.internal_quote_art_to_ausd(amount);
+        let ausd_amount = quote.amount_out.0;
+        assert_min_amount_out(ausd_amount, min_amount_out);
+
+        let account_id = env::predecessor_account_id();
+        self.internal_use_conversion_allowance(ConversionPath::ArtToAusd, &account_id, amount);
+        if self.conversion_mode == ConversionMode::Reserve {
+            let art_reserve = self.art_reserve.checked_add(amount).unwrap();
+            if let Some(max_art_reserve) = self.max_art_reserve {
+                if art_reserve > max_art_reserve {
+                    env::panic(b"Exchange would take the art reserve above its maximum");
+                }
+            }
+            let mut account = self.get_account(&account_id);
+            account.balance = account.balance.checked_sub(amount).unwrap();
+            self.internal_save_account(&account_id, &account);
+            self.art_reserve = art_reserve;
+            return ext_usd::mint(
+                account_id.clone(),
+                ausd_amount,
+                &self.ausd_token,
+                0,
+                env::prepaid_gas() / 3,
+            )
+            .then(ext_art::on_reserve_ausd_minted(
+                account_id,
+                U128(amount),
+                U128(ausd_amount),
+                quote.fee,
+                &env::current_account_id(),
+                0,
+                env::prepaid_gas() / 3,
+            ));
+        }
+
+        // Held until the aUSD is bought, then moved to owner in `on_exchange_settled`
+        self.internal_debit_art(&account_id, amount);
+        ext_usd::buy_ausd(
+            account_id.clone(),
+            U128(ausd_amount),
+            &self.ausd_token,
+            0,
+            env::prepaid_gas() / 3,
         )
-        .then(ext_self::ft_resolve_transfer(
-            sender_id,
-            receiver_id.into(),
-            amount.into(),
+        .then(ext_art::on_exchange_settled(
+            account_id,
+            ConversionPath::ArtToAusd,
+            U128(amount),
+            U128(ausd_amount),
+            quote.fee,
             &env::current_account_id(),
             NO_DEPOSIT,
-            GAS_FOR_RESOLVE_TRANSFER,
+            env::prepaid_gas() / 3,
         ))
-        .into()
     }
 
-    fn ft_total_supply(&self) -> U128 {
-        self.total_supply.into()
-    }
+    /// Buys art from owner with `ausd_amount` of aUSD at the oracle price, minus the `ausd_to_art`
+    /// fee. Panics if less than `min_amount_out` art would be received or the block timestamp is
+    /// past `deadline`.
+    pub fn exchange_ausd_to_art(
+        &mut self,
+        ausd_amount: String,
+        min_amount_out: Option<String>,
+        deadline: Option<U64>,
+    ) -> Promise {
+        self.assert_registered(&env::predecessor_account_id());
+        assert_deadline(deadline);
+        let ausd_amount = u128::from_str(&ausd_amount).expect("Failed to parse ausd_amount");
+        let quote = self.internal_quote_ausd_to_art(ausd_amount);
+        let amount = quote.amount_out.0;
+        assert_min_amount_out(amount, min_amount_out);
 
-    fn ft_balance_of(&self, account_id: ValidAccountId) -> U128 {
-        self.accounts
-            .get(account_id.as_ref())
-            .unwrap_or(Default::default())
-            .balance
-            .into()
+        let account_id = env::predecessor_account_id();
+        self.internal_use_conversion_allowance(ConversionPath::AusdToArt, &account_id, ausd_amount);
+        if self.conversion_mode == ConversionMode::Reserve {
+            if amount + quote.fee.0 > self.available_art_reserve() {
+                env::panic(b"Not enough art in the reserve");
+            }
+            // Taken out of the reserve before the burn so concurrent exchanges can't overdraw it,
+            // credited to the account and treasury once the burn succeeds
+            self.art_reserve -= amount + quote.fee.0;
+            return ext_usd::burn(
+                account_id.clone(),
+                ausd_amount,
+                &self.ausd_token,
+                0,
+                env::prepaid_gas() / 3,
+            )
+            .then(ext_art::on_reserve_ausd_burned(
+                account_id,
+                U128(amount),
+                U128(ausd_amount),
+                quote.fee,
+                &env::current_account_id(),
+                0,
+                env::prepaid_gas() / 3,
+            ));
+        }
+
+        // Taken from owner so it can't be spent meanwhile, credited to the account and treasury
+        // once the aUSD is sold
+        let owner = self.owner.clone();
+        self.internal_debit_art(&owner, amount + quote.fee.0);
+        ext_usd::sell_ausd(
+            account_id.clone(),
+            U128(ausd_amount),
+            &self.ausd_token,
+            0,
+            env::prepaid_gas() / 3,
+        )
+        .then(ext_art::on_exchange_settled(
+            account_id,
+            ConversionPath::AusdToArt,
+            U128(ausd_amount),
+            U128(amount),
+            quote.fee,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            env::prepaid_gas() / 3,
+        ))
     }
-}
 
-#[ext_contract(ext_self)]
-trait FungibleTokenResolver {
-    fn ft_resolve_transfer(
+    /// Buys aUSD from owner with the attached NEAR at the oracle price. Panics if less than
+    /// `min_amount_out` aUSD would be received or the block timestamp is past `deadline`.
+    #[payable]
+    pub fn buy_ausd_with_near(
         &mut self,
-        sender_id: AccountId,
-        receiver_id: AccountId,
-        amount: U128,
-    ) -> U128;
-}
+        min_amount_out: Option<String>,
+        deadline: Option<U64>,
+    ) -> Promise {
+        assert_deadline(deadline);
+        let attached_deposit = env::attached_deposit();
+        if attached_deposit == 0 {
+            env::panic(b"Can't buy with 0 NEAR");
+        }
+        let account_id = env::predecessor_account_id();
+        let quote = self.internal_quote_near_to_ausd(attached_deposit);
+        let ausd_amount = quote.amount_out.0;
+        assert_min_amount_out(ausd_amount, min_amount_out);
+        self.internal_use_conversion_allowance(
+            ConversionPath::NearToAusd,
+            &account_id,
+            attached_deposit,
+        );
+        ext_usd::buy_ausd(
+            account_id.clone(),
+            U128(ausd_amount),
+            &self.ausd_token,
+            0,
+            env::prepaid_gas() / 3,
+        )
+        .then(ext_art::on_exchange_settled(
+            account_id,
+            ConversionPath::NearToAusd,
+            U128(attached_deposit),
+            U128(ausd_amount),
+            quote.fee,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            env::prepaid_gas() / 3,
+        ))
+    }
 
-trait FungibleTokenResolver {
-    fn ft_resolve_transfer(
+    /// Sells `amount` of unstaked art to owner for NEAR from the contract reserve at the oracle
+    /// price, minus the `art_to_near` fee.
+    pub fn sell_art_to_near(&mut self, amount: String) -> Promise {
+        let amount = u128::from_str(&amount).expect("Failed to parse amount");
+        if amount == 0 {
+            env::panic(b"Can't sell 0 tokens");
+        }
+        let quote = self.internal_quote_art_to_near(amount);
+        let near_amount = quote.amount_out.0;
+        self.assert_near_reserve(near_amount + quote.fee.0);
+
+        let account_id = env::predecessor_account_id();
+        self.internal_use_conversion_allowance(ConversionPath::ArtToNear, &account_id, amount);
+        // The art is held by the contract until the NEAR transfer resolves, so it can always be
+        // returned to the seller.
+        let mut account = self.get_account(&account_id);
+        account.balance = account.balance.checked_sub(amount).unwrap();
+        self.internal_save_account(&account_id, &account);
+
+        Promise::new(account_id.clone())
+            .transfer(near_amount)
+            .then(ext_art::on_art_sold_for_near(
+                account_id,
+                amount.into(),
+                near_amount.into(),
+                quote.fee,
+                &env::current_account_id(),
+                0,
+                GAS_FOR_NEAR_CALLBACK,
+            ))
+    }
+
+    /// Gives the sold art to owner and pays the NEAR `fee` to the treasury, or returns the art to
+    /// the seller if the NEAR transfer failed.
+    pub fn on_art_sold_for_near(
         &mut self,
-        sender_id: AccountId,
-        receiver_id: AccountId,
+        account_id: AccountId,
         amount: U128,
-    ) -> U128;
-}
+        near_amount: U128,
+        fee: U128,
+    ) {
+        assert_self();
+        if is_promise_success() {
+            let mut owner = self.get_account(&self.owner);
+            owner.balance = owner.balance.checked_add(amount.0).unwrap();
+            self.internal_save_account(&self.owner.clone(), &owner);
+            event::ft_transfer(&account_id, &self.owner, amount.0, None);
+            event::exchange(
+                &account_id,
+                ConversionPath::ArtToNear,
+                amount.0,
+                near_amount.0,
+                fee.0,
+            );
+            self.internal_pay_near_fee(ConversionPath::ArtToNear, fee.0);
+        } else {
+            log!(
+                "Failed to send NEAR, refunding {} art to {}",
+                amount.0,
+                account_id
+            );
+            let mut account = self.get_account(&account_id);
+            account.balance = account.balance.checked_add(amount.0).unwrap();
+            self.internal_save_account(&account_id, &account);
+            self.internal_refund_conversion_allowance(
+                ConversionPath::ArtToNear,
+                &account_id,
+                amount.0,
+            );
+        }
+    }
 
-pub(crate) fn assert_self() {
-    assert_eq!(
-        env::predecessor_account_id(),
-        env::current_account_id(),
-        "Method is private"
-    );
-}
+    /// Sells `ausd_amount` of aUSD to owner for NEAR from the contract reserve at the oracle
+    /// price, minus the `ausd_to_near` fee.
+    pub fn sell_ausd_to_near(&mut self, ausd_amount: String) -> Promise {
+        let ausd_amount = u128::from_str(&ausd_amount).expect("Failed to parse ausd_amount");
+        if ausd_amount == 0 {
+            env::panic(b"Can't sell 0 tokens");
+        }
+        let quote = self.internal_quote_ausd_to_near(ausd_amount);
+        let near_amount = quote.amount_out.0;
+        self.assert_near_reserve(near_amount + quote.fee.0);
 
-#[near_bindgen]
-impl FungibleTokenResolver for Art {
-    fn ft_resolve_transfer(
+        let account_id = env::predecessor_account_id();
+        self.internal_use_conversion_allowance(
+            ConversionPath::AusdToNear,
+            &account_id,
+            ausd_amount,
+        );
+        ext_usd::sell_ausd(
+            account_id.clone(),
+            U128(ausd_amount),
+            &self.ausd_token,
+            0,
+            env::prepaid_gas() / 3,
+        )
+        .then(ext_art::on_ausd_sold_for_near(
+            account_id,
+            ausd_amount.into(),
+            near_amount.into(),
+            quote.fee,
+            &env::current_account_id(),
+            0,
+            env::prepaid_gas() / 3,
+        ))
+    }
+
+    /// Sends NEAR for the sold aUSD, or returns the aUSD if the reserve ran out meanwhile.
+    pub fn on_ausd_sold_for_near(
         &mut self,
-        sender_id: AccountId,
-        receiver_id: AccountId,
-        amount: U128,
-    ) -> U128 {
+        account_id: AccountId,
+        ausd_amount: U128,
+        near_amount: U128,
+        fee: U128,
+    ) -> PromiseOrValue<()> {
         assert_self();
-        let amount: Balance = amount.into();
+        if !is_promise_success() {
+            log!("Failed to sell aUSD");
+            self.internal_refund_conversion_allowance(
+                ConversionPath::AusdToNear,
+                &account_id,
+                ausd_amount.0,
+            );
+            return PromiseOrValue::Value(());
+        }
+        if self.available_near_reserve() < near_amount.0 + fee.0 {
+            log!(
+                "Not enough NEAR in reserve, refunding {} aUSD",
+                ausd_amount.0
+            );
+            self.internal_refund_conversion_allowance(
+                ConversionPath::AusdToNear,
+                &account_id,
+                ausd_amount.0,
+            );
+            return PromiseOrValue::Promise(ext_usd::buy_ausd(
+                account_id,
+                ausd_amount,
+                &self.ausd_token,
+                0,
+                env::prepaid_gas() / 2,
+            ));
+        }
+        PromiseOrValue::Promise(
+            Promise::new(account_id.clone())
+                .transfer(near_amount.0)
+                .then(ext_art::on_near_sent_for_ausd(
+                    account_id,
+                    ausd_amount,
+                    near_amount,
+                    fee,
+                    &env::current_account_id(),
+                    0,
+                    env::prepaid_gas() / 3,
+                )),
+        )
+    }
 
-        // Get the unused amount from the `ft_on_transfer` call result.
-        let unused_amount = match env::promise_result(0) {
-            PromiseResult::NotReady => unreachable!(),
-            PromiseResult::Successful(value) => {
-                if let Ok(unused_amount) = near_sdk::serde_json::from_slice::<U128>(&value) {
-                    std::cmp::min(amount, unused_amount.0)
-                } else {
-                    amount
+    /// Pays the NEAR `fee` to the treasury, or returns the aUSD to the seller if the NEAR transfer
+    /// failed.
+    pub fn on_near_sent_for_ausd(
+        &mut self,
+        account_id: AccountId,
+        ausd_amount: U128,
+        near_amount: U128,
+        fee: U128,
+    ) {
+        assert_self();
+        if is_promise_success() {
+            event::exchange(
+                &account_id,
+                ConversionPath::AusdToNear,
+                ausd_amount.0,
+                near_amount.0,
+                fee.0,
+            );
+            self.internal_pay_near_fee(ConversionPath::AusdToNear, fee.0);
+        } else {
+            log!("Failed to send NEAR, refunding {} aUSD", ausd_amount.0);
+            self.internal_refund_conversion_allowance(
+                ConversionPath::AusdToNear,
+                &account_id,
+                ausd_amount.0,
+            );
+            ext_usd::buy_ausd(
+                account_id,
+                ausd_amount,
+                &self.ausd_token,
+                0,
+                env::prepaid_gas() / 2,
+            );
+        }
+    }
+
+    /// Finishes a conversion with owner once its aUSD is moved: the art held for it is credited
+    /// and the fee is paid. If moving the aUSD failed, the art or NEAR paid in is returned.
+    pub fn on_exchange_settled(
+        &mut self,
+        account_id: AccountId,
+        path: ConversionPath,
+        amount_in: U128,
+        amount_out: U128,
+        fee: U128,
+    ) {
+        assert_self();
+        if !is_promise_success() {
+            log!(
+                "Failed to move the aUSD of {} on {}, reverting",
+                account_id,
+                path.as_str()
+            );
+            match path {
+                ConversionPath::ArtToAusd => self.internal_credit_art(&account_id, amount_in.0),
+                ConversionPath::AusdToArt => {
+                    let owner = self.owner.clone();
+                    self.internal_credit_art(&owner, amount_out.0 + fee.0);
+                }
+                _ => {
+                    Promise::new(account_id.clone()).transfer(amount_in.0);
                 }
             }
-            PromiseResult::Failed => amount,
+            self.internal_refund_conversion_allowance(path, &account_id, amount_in.0);
+            return;
+        }
+        match path {
+            ConversionPath::ArtToAusd => {
+                let owner = self.owner.clone();
+                self.internal_credit_art(&owner, amount_in.0);
+                event::ft_transfer(&account_id, &owner, amount_in.0, None);
+                self.internal_pay_ausd_fee(path, fee.0, false);
+            }
+            ConversionPath::AusdToArt => {
+                self.internal_credit_art(&account_id, amount_out.0);
+                event::ft_transfer(&self.owner, &account_id, amount_out.0, None);
+                self.internal_pay_art_fee(path, fee.0);
+            }
+            _ => self.internal_pay_ausd_fee(path, fee.0, false),
+        }
+        event::exchange(&account_id, path, amount_in.0, amount_out.0, fee.0);
+    }
+
+    /// Records the NEAR or aUSD `fee` of `path` as collected once it's paid to the treasury. A
+    /// fee that failed to be paid stays in the NEAR reserve, or with owner for aUSD.
+    pub fn on_fee_paid(&mut self, path: ConversionPath, fee: U128) {
+        assert_self();
+        if is_promise_success() {
+            self.internal_record_fee(path, fee.0);
+        } else {
+            log!(
+                "Failed to pay {} {} fee on {} to {}",
+                fee.0,
+                path.fee_token(),
+                path.as_str(),
+                self.treasury
+            );
+        }
+    }
+
+    /// Converts `amount_in` of the first token of `path` to its last token in one call. Tokens are
+    /// "NEAR", "art", "aUSD" or a registered asset, and a path either converts between two of
+    /// them directly or goes through "aUSD", e.g. `["NEAR", "aUSD", "aBTC"]`. aUSD in the middle
+    /// of a path is only used for pricing, it's not minted or moved. Swapping from NEAR takes the
+    /// attached deposit, which must equal `amount_in`. Panics if less than `min_out` would be
+    /// received. If sending the output fails the input is refunded. Returns the amount received.
+    #[payable]
+    pub fn swap(
+        &mut self,
+        path: Vec<String>,
+        amount_in: String,
+        min_out: String,
+    ) -> PromiseOrValue<U128> {
+        self.assert_registered(&env::predecessor_account_id());
+        let tokens = parse_swap_path(&path);
+        let amount_in = u128::from_str(&amount_in).expect("Failed to parse amount_in");
+        if amount_in == 0 {
+            env::panic(b"Can't swap 0 tokens");
+        }
+        let min_out = u128::from_str(&min_out).expect("Failed to parse min_out");
+        if tokens[0] == SwapToken::Near && env::attached_deposit() != amount_in {
+            env::panic(b"Attached deposit must equal amount_in");
+        }
+        let quote = self.internal_quote_swap(&tokens, amount_in);
+        if quote.amount_out.0 < min_out {
+            env::panic(b"Output amount is less than min_out");
+        }
+        let round = match &tokens[tokens.len() - 1] {
+            SwapToken::Asset(asset) => self.asset_price_rounds.get(asset).unwrap_or(0),
+            _ => 0,
+        };
+        let route = SwapRoute {
+            account_id: env::predecessor_account_id(),
+            path,
+            amount_in: quote.amount_in,
+            amount_out: quote.amount_out,
+            fees: quote.fees,
+            asset_price: quote.asset_price,
+            round: round.into(),
+            uses_art_reserve: self.conversion_mode == ConversionMode::Reserve,
         };
 
-        if unused_amount > 0 {
-            let mut receiver = self.get_account(&receiver_id);
-            let receiver_balance = receiver.balance;
-            if receiver_balance > 0 {
-                let refund_amount = std::cmp::min(receiver_balance, unused_amount);
-                receiver.balance -= refund_amount;
-                self.accounts.insert(&receiver_id, &receiver);
+        for fee in route.fees.iter() {
+            self.internal_use_conversion_allowance(fee.path, &route.account_id, fee.amount_in.0);
+        }
+        self.internal_swap_reserve_output(&route, &tokens);
+        match self.internal_swap_take_input(&route, &tokens) {
+            Some(promise) => PromiseOrValue::Promise(promise.then(ext_art::on_swap_input_taken(
+                route,
+                &env::current_account_id(),
+                0,
+                env::prepaid_gas() / 2,
+            ))),
+            None => self.internal_swap_give_output(route, &tokens),
+        }
+    }
+
+    /// Gives the output of a swap once its aUSD is taken, or returns the output set aside for it
+    /// if taking the aUSD failed.
+    pub fn on_swap_input_taken(&mut self, route: SwapRoute) -> PromiseOrValue<U128> {
+        assert_self();
+        let tokens = parse_swap_path(&route.path);
+        if !is_promise_success() {
+            log!(
+                "Failed to take {} aUSD from {}, cancelling swap",
+                route.amount_in.0,
+                route.account_id
+            );
+            self.internal_swap_release_output(&route, &tokens);
+            self.internal_swap_refund_allowances(&route);
+            return PromiseOrValue::Value(U128(0));
+        }
+        if tokens[tokens.len() - 1] == SwapToken::Near
+            && self.available_near_reserve() < route.amount_out.0 + swap_output_fee(&route)
+        {
+            log!("Not enough NEAR in reserve");
+            self.internal_swap_refund_input(&route, &tokens);
+            return PromiseOrValue::Value(U128(0));
+        }
+        self.internal_swap_give_output(route, &tokens)
+    }
+
+    /// Finishes a swap once its NEAR or aUSD output is sent, or refunds its input if sending
+    /// failed.
+    pub fn on_swap_output_sent(&mut self, route: SwapRoute) -> U128 {
+        assert_self();
+        let tokens = parse_swap_path(&route.path);
+        if is_promise_success() {
+            self.internal_swap_finish(&route, &tokens)
+        } else {
+            self.internal_swap_refund_input(&route, &tokens);
+            U128(0)
+        }
+    }
+
+    /// Deposits the attached NEAR and the matching amount of art, at most `max_art_amount`, to
+    /// the art/NEAR pool. The first deposit sets the pool price. Returns the minted LP shares.
+    #[payable]
+    pub fn add_liquidity(&mut self, max_art_amount: String) -> String {
+        let near_amount = env::attached_deposit();
+        if near_amount == 0 {
+            env::panic(b"Can't add liquidity with 0 NEAR");
+        }
+        let max_art_amount =
+            u128::from_str(&max_art_amount).expect("Failed to parse max_art_amount");
+        let (art_amount, shares) = if self.pool.total_shares == 0 {
+            (max_art_amount, near_amount)
+        } else {
+            // Rounds up, so a deposit never dilutes the existing shares
+            (
+                mul_div_round_up(near_amount, self.pool.art_reserve, self.pool.near_reserve),
+                mul_div(near_amount, self.pool.total_shares, self.pool.near_reserve),
+            )
+        };
+        if art_amount == 0 {
+            env::panic(b"Can't add liquidity with 0 art");
+        }
+        if art_amount > max_art_amount {
+            env::panic(b"The required art amount exceeds max_art_amount");
+        }
+
+        let account_id = env::predecessor_account_id();
+        let mut account = self.get_account(&account_id);
+        if account.balance < art_amount {
+            env::panic(b"Not enough unstaked balance");
+        }
+        account.balance -= art_amount;
+        self.internal_save_account(&account_id, &account);
+
+        self.pool.art_reserve += art_amount;
+        self.pool.near_reserve += near_amount;
+        self.pool.total_shares += shares;
+        let account_shares = self.pool.shares_of(&account_id) + shares;
+        self.pool.shares.insert(&account_id, &account_shares);
+        event::liquidity(
+            "add_liquidity",
+            &account_id,
+            art_amount,
+            near_amount,
+            shares,
+        );
+        shares.to_string()
+    }
+
+    /// Burns `shares` of the caller's LP shares for their part of both pool reserves.
+    pub fn remove_liquidity(&mut self, shares: String) -> Promise {
+        let shares = u128::from_str(&shares).expect("Failed to parse shares");
+        if shares == 0 {
+            env::panic(b"Can't remove 0 shares");
+        }
+        let account_id = env::predecessor_account_id();
+        let account_shares = self.pool.shares_of(&account_id);
+        if account_shares < shares {
+            env::panic(b"Not enough shares");
+        }
+        let art_amount = mul_div(shares, self.pool.art_reserve, self.pool.total_shares);
+        let near_amount = mul_div(shares, self.pool.near_reserve, self.pool.total_shares);
+
+        if account_shares == shares {
+            self.pool.shares.remove(&account_id);
+        } else {
+            self.pool
+                .shares
+                .insert(&account_id, &(account_shares - shares));
+        }
+        self.pool.total_shares -= shares;
+        self.pool.art_reserve -= art_amount;
+        self.pool.near_reserve -= near_amount;
+
+        // The art is credited once the NEAR is sent
+        self.internal_send_pool_near(account_id, art_amount, near_amount, shares)
+    }
+
+    /// Swaps the attached NEAR for art from the pool. Panics if less than `min_art_out` would be
+    /// received. Returns the received art amount.
+    #[payable]
+    pub fn swap_near_to_art(&mut self, min_art_out: String) -> String {
+        let near_amount = env::attached_deposit();
+        if near_amount == 0 {
+            env::panic(b"Can't swap 0 NEAR");
+        }
+        let min_art_out = u128::from_str(&min_art_out).expect("Failed to parse min_art_out");
+        let art_amount =
+            self.pool
+                .amount_out(near_amount, self.pool.near_reserve, self.pool.art_reserve);
+        if art_amount == 0 {
+            env::panic(b"Output amount is 0");
+        }
+        if art_amount < min_art_out {
+            env::panic(b"Output amount is less than min_art_out");
+        }
+        self.pool.near_reserve += near_amount;
+        self.pool.art_reserve -= art_amount;
+
+        let account_id = env::predecessor_account_id();
+        let mut account = self.get_account(&account_id);
+        account.balance += art_amount;
+        self.internal_save_account(&account_id, &account);
+        event::swap(
+            &account_id,
+            &["NEAR".to_string(), "art".to_string()],
+            near_amount,
+            art_amount,
+        );
+        art_amount.to_string()
+    }
+
+    /// Swaps `art_amount` of the caller's unstaked art for NEAR from the pool. Panics if less than
+    /// `min_near_out` would be received.
+    pub fn swap_art_to_near(&mut self, art_amount: String, min_near_out: String) -> Promise {
+        let art_amount = u128::from_str(&art_amount).expect("Failed to parse art_amount");
+        if art_amount == 0 {
+            env::panic(b"Can't swap 0 tokens");
+        }
+        let min_near_out = u128::from_str(&min_near_out).expect("Failed to parse min_near_out");
+        let near_amount =
+            self.pool
+                .amount_out(art_amount, self.pool.art_reserve, self.pool.near_reserve);
+        if near_amount == 0 {
+            env::panic(b"Output amount is 0");
+        }
+        if near_amount < min_near_out {
+            env::panic(b"Output amount is less than min_near_out");
+        }
+
+        let account_id = env::predecessor_account_id();
+        let mut account = self.get_account(&account_id);
+        if account.balance < art_amount {
+            env::panic(b"Not enough unstaked balance");
+        }
+        account.balance -= art_amount;
+        self.internal_save_account(&account_id, &account);
+
+        // The art is added to the pool once the NEAR is sent
+        self.pool.near_reserve -= near_amount;
+        self.internal_send_pool_near(account_id, art_amount, near_amount, 0)
+    }
+
+    /// Completes a pool withdrawal of `shares`, or a swap if `shares` is 0, once its NEAR is sent:
+    /// the withdrawn art goes to the account, or the swapped art to the pool. If sending the NEAR
+    /// failed, the amounts taken out are put back instead. Both cases only add to balances, so
+    /// the revert can't fail.
+    pub fn on_pool_near_sent(
+        &mut self,
+        account_id: AccountId,
+        art_amount: U128,
+        near_amount: U128,
+        shares: U128,
+    ) {
+        assert_self();
+        let success = is_promise_success();
+        if !success {
+            log!(
+                "Failed to send {} NEAR from the pool to {}, reverting",
+                near_amount.0,
+                account_id
+            );
+        }
+        if shares.0 == 0 {
+            if success {
+                self.pool.art_reserve += art_amount.0;
+                event::swap(
+                    &account_id,
+                    &["art".to_string(), "NEAR".to_string()],
+                    art_amount.0,
+                    near_amount.0,
+                );
+            } else {
+                self.pool.near_reserve += near_amount.0;
+                let mut account = self.get_account(&account_id);
+                account.balance += art_amount.0;
+                self.internal_save_account(&account_id, &account);
+            }
+        } else if success {
+            let mut account = self.get_account(&account_id);
+            account.balance += art_amount.0;
+            self.internal_save_account(&account_id, &account);
+            event::liquidity(
+                "remove_liquidity",
+                &account_id,
+                art_amount.0,
+                near_amount.0,
+                shares.0,
+            );
+        } else {
+            self.pool.art_reserve += art_amount.0;
+            self.pool.near_reserve += near_amount.0;
+            self.pool.total_shares += shares.0;
+            let account_shares = self.pool.shares_of(&account_id) + shares.0;
+            self.pool.shares.insert(&account_id, &account_shares);
+        }
+    }
+
+    /// Mints the aUSD `fee` to the treasury, or returns the art paid in to the reserve if minting
+    /// its aUSD failed.
+    pub fn on_reserve_ausd_minted(
+        &mut self,
+        account_id: AccountId,
+        amount: U128,
+        ausd_amount: U128,
+        fee: U128,
+    ) {
+        assert_self();
+        if is_promise_success() {
+            event::exchange(
+                &account_id,
+                ConversionPath::ArtToAusd,
+                amount.0,
+                ausd_amount.0,
+                fee.0,
+            );
+            self.internal_pay_ausd_fee(ConversionPath::ArtToAusd, fee.0, true);
+        } else {
+            log!(
+                "Failed to mint {} aUSD to {}, reverting",
+                ausd_amount.0,
+                account_id
+            );
+            self.art_reserve -= amount.0;
+            let mut account = self.get_account(&account_id);
+            account.balance = account.balance.checked_add(amount.0).unwrap();
+            self.internal_save_account(&account_id, &account);
+            self.internal_refund_conversion_allowance(
+                ConversionPath::ArtToAusd,
+                &account_id,
+                amount.0,
+            );
+        }
+    }
+
+    /// Credits the art taken out of the reserve to the account and the art `fee` to the treasury
+    /// once its aUSD is burnt, or puts it back if the burn failed.
+    pub fn on_reserve_ausd_burned(
+        &mut self,
+        account_id: AccountId,
+        amount: U128,
+        ausd_amount: U128,
+        fee: U128,
+    ) {
+        assert_self();
+        if is_promise_success() {
+            let mut account = self.get_account(&account_id);
+            account.balance = account.balance.checked_add(amount.0).unwrap();
+            self.internal_save_account(&account_id, &account);
+            event::exchange(
+                &account_id,
+                ConversionPath::AusdToArt,
+                ausd_amount.0,
+                amount.0,
+                fee.0,
+            );
+            self.internal_pay_art_fee(ConversionPath::AusdToArt, fee.0);
+        } else {
+            log!(
+                "Failed to burn {} aUSD of {}, reverting",
+                ausd_amount.0,
+                account_id
+            );
+            self.art_reserve += amount.0 + fee.0;
+            self.internal_refund_conversion_allowance(
+                ConversionPath::AusdToArt,
+                &account_id,
+                ausd_amount.0,
+            );
+        }
+    }
+
+    pub fn set_conversion_fee_bps(&mut self, path: ConversionPath, fee_bps: u32) {
+        self.assert_owner_or_governance();
+        if fee_bps >= 10_000 {
+            env::panic(b"Fee must be less than 10000 basis points");
+        }
+        self.conversion_fee_bps.insert(&path, &fee_bps);
+    }
+
+    /// Limits the volume converted on `path`, in the token paid, per account and for all accounts
+    /// in each window of `window` nanoseconds. `None` limits are unlimited.
+    pub fn set_rate_limit(
+        &mut self,
+        path: ConversionPath,
+        window: U64,
+        account_limit: Option<U128>,
+        global_limit: Option<U128>,
+    ) {
+        self.assert_owner_or_governance();
+        if account_limit.is_none() && global_limit.is_none() {
+            self.rate_limits.remove(&path);
+            return;
+        }
+        if window.0 == 0 {
+            env::panic(b"Window must be more than 0");
+        }
+        self.rate_limits.insert(
+            &path,
+            &RateLimit {
+                window: window.0,
+                account_limit: account_limit.map(|limit| limit.0),
+                global_limit: global_limit.map(|limit| limit.0),
+            },
+        );
+    }
+
+    pub fn set_treasury(&mut self, treasury: ValidAccountId) {
+        self.assert_owner_or_governance();
+        self.assert_registered(treasury.as_ref());
+        self.treasury = treasury.into();
+    }
+
+    pub fn set_governance(&mut self, governance: Option<ValidAccountId>) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can set governance");
+        }
+        self.governance = governance.map(|governance| governance.into());
+    }
+
+    pub fn set_conversion_mode(&mut self, mode: ConversionMode) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can set conversion mode");
+        }
+        self.conversion_mode = mode;
+    }
+
+    /// Sets the art the reserve can't be drawn below and the maximum art it can hold.
+    pub fn set_art_reserve_bounds(
+        &mut self,
+        min_art_reserve: String,
+        max_art_reserve: Option<String>,
+    ) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can set art reserve bounds");
+        }
+        let min_art_reserve =
+            u128::from_str(&min_art_reserve).expect("Failed to parse min_art_reserve");
+        let max_art_reserve = max_art_reserve
+            .map(|max| u128::from_str(&max).expect("Failed to parse max_art_reserve"));
+        if let Some(max_art_reserve) = max_art_reserve {
+            if max_art_reserve < min_art_reserve {
+                env::panic(b"max_art_reserve must not be less than min_art_reserve");
+            }
+        }
+        self.min_art_reserve = min_art_reserve;
+        self.max_art_reserve = max_art_reserve;
+    }
+
+    /// Moves `amount` art of the caller into the reserve, without minting aUSD for it.
+    pub fn fund_art_reserve(&mut self, amount: String) {
+        let amount = u128::from_str(&amount).expect("Failed to parse amount");
+        if amount == 0 {
+            env::panic(b"Can't fund 0 art");
+        }
+        let art_reserve = self.art_reserve.checked_add(amount).unwrap();
+        if let Some(max_art_reserve) = self.max_art_reserve {
+            if art_reserve > max_art_reserve {
+                env::panic(b"Funding would take the art reserve above its maximum");
+            }
+        }
+        let account_id = env::predecessor_account_id();
+        let mut account = self.get_account(&account_id);
+        if account.balance < amount {
+            env::panic(b"Not enough balance to fund");
+        }
+        account.balance -= amount;
+        self.internal_save_account(&account_id, &account);
+        self.art_reserve = art_reserve;
+    }
+
+    /// Moves `amount` art out of the reserve to owner, down to `min_art_reserve`.
+    pub fn withdraw_art_reserve(&mut self, amount: String) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can withdraw art reserve");
+        }
+        let amount = u128::from_str(&amount).expect("Failed to parse amount");
+        if amount > self.available_art_reserve() {
+            env::panic(b"Not enough art in the reserve");
+        }
+        self.art_reserve -= amount;
+        let mut owner = self.get_account(&self.owner);
+        owner.balance = owner.balance.checked_add(amount).unwrap();
+        self.internal_save_account(&self.owner.clone(), &owner);
+    }
+
+    pub fn set_pool_fee_bps(&mut self, fee_bps: u32) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can set the pool fee");
+        }
+        if fee_bps >= 10_000 {
+            env::panic(b"Fee must be less than 10000 basis points");
+        }
+        self.pool.fee_bps = fee_bps;
+    }
+
+    /// Sets the stART contract, initialized with this contract as its art token. Can't be changed
+    /// while there is stART.
+    pub fn set_start_token(&mut self, start_token: ValidAccountId) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can set the stART token");
+        }
+        if self.liquid_staking.shares > 0 {
+            env::panic(b"Can't change the stART token while there is stART");
+        }
+        self.liquid_staking.start_token = Some(start_token.into());
+    }
+
+    /// Takes a snapshot named `name` of all balances at the end of the current block, e.g. for a
+    /// vote or an airdrop. Returns the block height of the snapshot. Can't be taken while accounts
+    /// without balance checkpoints are left to migrate.
+    pub fn create_snapshot(&mut self, name: String) -> U64 {
+        self.assert_owner_or_governance();
+        self.assert_accounts_migrated();
+        let block_height = env::block_index();
+        if self.snapshots.insert(&name, &block_height).is_some() {
+            env::panic(b"The snapshot already exists");
+        }
+        event::snapshot(&name, block_height);
+        block_height.into()
+    }
+
+    /// Sets amount allowed to spent by `escrow_account_id` on behalf of the caller of the function
+    /// (`predecessor_id`) who is considered the balance owner to the new `allowance`.
+    pub fn set_allowance(&mut self, escrow_account_id: AccountId, allowance: String) {
+        let allowance = u128::from_str(&allowance).expect("Failed to parse allowance");
+        let owner_id = env::predecessor_account_id();
+        if escrow_account_id == owner_id {
+            env::panic(b"Can't set allowance for yourself");
+        }
+        let mut account = self.get_account(&owner_id);
+
+        account.set_allowance(&escrow_account_id, allowance);
+        self.internal_save_account(&owner_id, &account);
+    }
+
+    /// Registers the ed25519 key that signs the permits of the caller, or removes it with `None`.
+    /// The key is charged to the storage balance of the caller.
+    pub fn set_permit_key(&mut self, public_key: Option<Base58PublicKey>) {
+        let initial_storage = env::storage_usage();
+        let owner_id = env::predecessor_account_id();
+        match public_key {
+            Some(public_key) => {
+                self.permit_keys
+                    .insert(&owner_id, &permit::ed25519_key_bytes(public_key));
+            }
+            None => {
+                self.permit_keys.remove(&owner_id);
+            }
+        }
+        self.internal_track_storage(&owner_id, initial_storage);
+    }
+
+    /// Sets the allowance of `spender_id` on the balance of `owner_id` to `amount`, same as
+    /// `set_allowance` called by `owner_id`, with a `PermitMessage` signed by the permit key of
+    /// `owner_id`. Anyone can submit the permit, e.g. a relayer.
+    /// Requirements:
+    /// * `nonce` should be the current permit nonce of `owner_id`, the permit increments it.
+    /// * The block timestamp should be at most `deadline`.
+    /// * The storage balance of `owner_id` should cover its nonce and the allowance.
+    pub fn permit(
+        &mut self,
+        owner_id: AccountId,
+        spender_id: AccountId,
+        amount: String,
+        nonce: U64,
+        deadline: U64,
+        signature: Base64VecU8,
+    ) {
+        let amount = u128::from_str(&amount).expect("Failed to parse amount");
+        if spender_id == owner_id {
+            env::panic(b"Can't set allowance for yourself");
+        }
+        assert_deadline(Some(deadline));
+        let public_key = self
+            .permit_keys
+            .get(&owner_id)
+            .unwrap_or_else(|| env::panic(b"The owner has no permit key"));
+        let current_nonce = self.permit_nonces.get(&owner_id).unwrap_or(0);
+        if nonce.0 != current_nonce {
+            env::panic(b"Invalid permit nonce");
+        }
+        let message = PermitMessage {
+            contract_id: env::current_account_id(),
+            owner_id: owner_id.clone(),
+            spender_id: spender_id.clone(),
+            amount,
+            nonce: nonce.0,
+            deadline: deadline.0,
+        };
+        message.assert_signed(&public_key, &signature.0);
+        let initial_storage = env::storage_usage();
+        self.permit_nonces.insert(&owner_id, &(current_nonce + 1));
+        self.internal_track_storage(&owner_id, initial_storage);
+
+        let mut account = self.get_account(&owner_id);
+        account.set_allowance(&spender_id, amount);
+        self.internal_save_account(&owner_id, &account);
+    }
+
+    pub fn submit_price(&mut self, price: String) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can submit price data");
+        }
+        let price = u128::from_str(&price).expect("Failed to parse price");
+        // we completely trust owner for now
+        self.price = price;
+        event::price_update("art", price);
+    }
+
+    pub fn submit_asset_price(&mut self, asset: String, price: String) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can submit price data");
+        }
+        if self.delisted_assets.get(&asset).is_some() {
+            env::panic(b"Asset is delisted");
+        }
+        if self.index_assets.get(&asset).is_some() {
+            env::panic(b"Index price is computed from its components");
+        }
+        let price = u128::from_str(&price).expect("Failed to parse price");
+        self.asset_prices.insert(&asset, &price);
+        event::price_update(&asset, price);
+        self.asset_prices_updated_at
+            .insert(&asset, &env::block_timestamp());
+        self.internal_update_asset_exposure(&asset);
+        let indexes: Vec<String> = self
+            .index_assets
+            .iter()
+            .filter(|(_, components)| components.iter().any(|c| c.asset == asset))
+            .map(|(index, _)| index)
+            .collect();
+        for index in indexes.iter() {
+            self.internal_update_asset_exposure(index);
+        }
+        let round = self.asset_price_rounds.get(&asset).unwrap_or(0) + 1;
+        self.asset_price_rounds.insert(&asset, &round);
+        if self.settlement_waiting_period > 0 {
+            let price_round = PriceRound {
+                price,
+                timestamp: env::block_timestamp(),
+            };
+            self.asset_round_prices.insert(&(asset.clone(), round), &price_round);
+        }
+        self.internal_prune_price_rounds(&asset, round);
+    }
+
+    /// Defines `asset` as an index priced from the weighted prices of registered `components`.
+    /// It can be traded with `buy_asset_with_ausd` and `sell_asset_to_ausd` like any asset.
+    pub fn define_index_asset(&mut self, asset: String, components: Vec<IndexComponent>) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can define index asset");
+        }
+        if self.asset_prices.get(&asset).is_some() {
+            env::panic(b"Asset already has an oracle price");
+        }
+        if self.asset_supplies.get(&asset).unwrap_or(0) > 0 {
+            env::panic(b"Can't redefine an index with open interest");
+        }
+        if components.is_empty() {
+            env::panic(b"Index should have at least one component");
+        }
+        for (i, component) in components.iter().enumerate() {
+            if component.weight.0 == 0 {
+                env::panic(b"Component weight should be positive");
+            }
+            if self._get_asset_price(&component.asset) == 0
+                || self.index_assets.get(&component.asset).is_some()
+                || self.delisted_assets.get(&component.asset).is_some()
+            {
+                env::panic(
+                    format!("{} is not a registered asset", component.asset).as_bytes(),
+                );
+            }
+            if components[..i].iter().any(|c| c.asset == component.asset) {
+                env::panic(format!("Duplicate component {}", component.asset).as_bytes());
+            }
+        }
+        self.index_assets.insert(&asset, &components);
+    }
+
+    pub fn set_max_index_price_age(&mut self, max_index_price_age: u64) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can set max index price age");
+        }
+        self.max_index_price_age = max_index_price_age;
+    }
+
+    /// Sets the time in nanoseconds after an asset purchase before it's re-settled at the next
+    /// oracle price. Sales of the asset are blocked until then. 0 disables delayed settlement.
+    pub fn set_settlement_waiting_period(&mut self, waiting_period: u64) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can set settlement waiting period");
+        }
+        self.settlement_waiting_period = waiting_period;
+    }
+
+    /// Re-settles purchases of `asset` by `account_id` whose waiting period is over at the
+    /// first oracle price submitted after it. The difference to the purchase price is charged
+    /// or rebated in aUSD. Trades stay pending until the charge is burnt or the rebate minted. A
+    /// charge that can't be burnt is taken from the asset balance instead, a rebate that can't be
+    /// minted is retried by the next call. Anyone can call it.
+    pub fn settle_asset_trades(&mut self, account_id: AccountId, asset: String) -> Option<Promise> {
+        let key = (account_id.clone(), asset.clone());
+        if self.settling_trades.get(&key).is_some() {
+            env::panic(b"Trades are already being settled");
+        }
+        let trades = self.pending_trades.get(&key).unwrap_or_default();
+        let mut remaining = vec![];
+        let mut settled = vec![];
+        let mut owed_to_account: Balance = 0;
+        let mut owed_by_account: Balance = 0;
+        // The same amounts in the asset, at each trade's settlement price
+        let mut asset_owed_to_account: Balance = 0;
+        let mut asset_owed_by_account: Balance = 0;
+        for trade in trades.into_iter() {
+            match self.settlement_price(&asset, &trade) {
+                Some(settlement_price) => {
+                    let paid = value_in_ausd(trade.asset_price.0, trade.asset_amount.0);
+                    let value = value_in_ausd(settlement_price, trade.asset_amount.0);
+                    if value > paid {
+                        owed_by_account += value - paid;
+                        asset_owed_by_account += ausd_to_asset(settlement_price, value - paid);
+                    } else {
+                        owed_to_account += paid - value;
+                        asset_owed_to_account += ausd_to_asset(settlement_price, paid - value);
+                    }
+                    settled.push(trade);
+                }
+                None => remaining.push(trade),
+            }
+        }
+        if settled.is_empty() {
+            return None;
+        }
+        event::settle_asset_trades(&account_id, &asset, owed_by_account, owed_to_account);
+
+        let initial_storage = env::storage_usage();
+        if owed_by_account != owed_to_account {
+            self.settling_trades.insert(&key, &settled);
+        }
+        if remaining.is_empty() {
+            self.pending_trades.remove(&key);
+        } else {
+            self.pending_trades.insert(&key, &remaining);
+        }
+        self.internal_charge_storage(&account_id, initial_storage);
+        if owed_by_account > owed_to_account {
+            Some(
+                ext_usd::burn(
+                    account_id.clone(),
+                    owed_by_account - owed_to_account,
+                    &self.ausd_token,
+                    0,
+                    env::prepaid_gas() / 3,
+                )
+                .then(ext_art::on_settlement_charged(
+                    account_id,
+                    asset,
+                    asset_owed_by_account
+                        .saturating_sub(asset_owed_to_account)
+                        .into(),
+                    &env::current_account_id(),
+                    0,
+                    env::prepaid_gas() / 3,
+                )),
+            )
+        } else if owed_to_account > owed_by_account {
+            Some(
+                ext_usd::mint(
+                    account_id.clone(),
+                    owed_to_account - owed_by_account,
+                    &self.ausd_token,
+                    0,
+                    env::prepaid_gas() / 3,
+                )
+                .then(ext_art::on_settlement_rebated(
+                    account_id,
+                    asset,
+                    &env::current_account_id(),
+                    0,
+                    env::prepaid_gas() / 3,
+                )),
+            )
+        } else {
+            None
+        }
+    }
+
+    /// Completes the settlement of the trades once their charge is burnt. If it couldn't be
+    /// burnt, `asset_charge` is taken from the asset balance instead.
+    pub fn on_settlement_charged(
+        &mut self,
+        account_id: AccountId,
+        asset: String,
+        asset_charge: U128,
+    ) {
+        assert_self();
+        let key = (account_id.clone(), asset.clone());
+        let initial_storage = env::storage_usage();
+        self.settling_trades.remove(&key);
+        self.internal_charge_storage(&account_id, initial_storage);
+        if !is_promise_success() {
+            let mut account = self.get_account(&account_id);
+            let balance = self._get_asset_balance(&account_id, &asset);
+            let charge = std::cmp::min(balance, asset_charge.0);
+            log!(
+                "Failed to burn the settlement charge of {}, taking {} {} instead",
+                account_id,
+                charge,
+                asset
+            );
+            account.assets.insert(asset.clone(), balance - charge);
+            self.internal_save_account(&account_id, &account);
+            self.internal_sub_asset_supply(&asset, charge);
+        }
+    }
+
+    /// Completes the settlement of the trades once their rebate is minted. If it couldn't be
+    /// minted, the trades are pending again so the next settlement retries it.
+    pub fn on_settlement_rebated(&mut self, account_id: AccountId, asset: String) {
+        assert_self();
+        let key = (account_id.clone(), asset);
+        let initial_storage = env::storage_usage();
+        let settled = self.settling_trades.remove(&key).unwrap_or_default();
+        if !is_promise_success() {
+            log!("Failed to mint the settlement rebate of {}", account_id);
+            let mut trades = settled;
+            trades.extend(self.pending_trades.get(&key).unwrap_or_default());
+            self.pending_trades.insert(&key, &trades);
+        }
+        self.internal_charge_storage(&account_id, initial_storage);
+    }
+
+    /// Sets the maximum total amount of `asset` that can be bought. `None` removes the cap.
+    pub fn set_asset_supply_cap(&mut self, asset: String, cap: Option<String>) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can set asset supply cap");
+        }
+        match cap {
+            Some(cap) => {
+                let cap = u128::from_str(&cap).expect("Failed to parse cap");
+                self.asset_supply_caps.insert(&asset, &cap);
+            }
+            None => {
+                self.asset_supply_caps.remove(&asset);
+            }
+        }
+    }
+
+    /// Sets the maximum USD value, in aUSD, of all synthetic assets. `None` removes the cap.
+    pub fn set_max_total_asset_exposure(&mut self, cap: Option<String>) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can set total asset exposure cap");
+        }
+        self.max_total_asset_exposure =
+            cap.map(|cap| u128::from_str(&cap).expect("Failed to parse cap"));
+    }
+
+    /// Freezes the price of `asset` and blocks new purchases of it. Remaining balances can be
+    /// converted to aUSD at the final price with `sell_asset_to_ausd`, `settle_delisted_asset`
+    /// or `settle_delisted_asset_for`, and escrowed sell orders with `settle_delisted_orders`.
+    /// The asset is removed once all balances are settled.
+    pub fn delist_asset(&mut self, asset: String) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can delist asset");
+        }
+        if self.delisted_assets.get(&asset).is_some() {
+            env::panic(b"Asset is already delisted");
+        }
+        for (index, components) in self.index_assets.iter() {
+            if index != asset && components.iter().any(|c| c.asset == asset) {
+                env::panic(format!("{} is a component of {}", asset, index).as_bytes());
+            }
+        }
+        let final_price = self._get_asset_price(&asset);
+        if final_price == 0 {
+            env::panic(b"No price data from oracle");
+        }
+        self.delisted_assets.insert(&asset, &final_price);
+        event::delist_asset(&asset, final_price);
+        self.clear_delisted_asset_if_settled(&asset);
+    }
+
+    /// Converts the whole `asset` balance of the caller to aUSD at the final price.
+    pub fn settle_delisted_asset(&mut self, asset: String) -> Promise {
+        let account_id = env::predecessor_account_id();
+        match self.internal_settle_delisted_asset(&account_id, &asset) {
+            Some(promise) => promise,
+            None => env::panic(b"No asset balance to settle"),
+        }
+    }
+
+    /// Keeper entry point that settles the `asset` balances of a batch of `account_ids` at the
+    /// final price. Accounts without balance are skipped.
+    pub fn settle_delisted_asset_for(&mut self, asset: String, account_ids: Vec<AccountId>) {
+        for account_id in account_ids.iter() {
+            self.internal_settle_delisted_asset(account_id, &asset);
+        }
+    }
+
+    /// Keeper entry point that settles the assets escrowed in sell orders of delisted assets at
+    /// the final price. Other orders are skipped.
+    pub fn settle_delisted_orders(&mut self, order_ids: Vec<u64>) {
+        for order_id in order_ids.iter() {
+            let order = match self.orders.get(order_id) {
+                Some(order) => order,
+                None => continue,
+            };
+            if order.side != OrderSide::Sell || self.delisted_assets.get(&order.asset).is_none() {
+                continue;
+            }
+            self.orders.remove(order_id);
+            decrement_count(&mut self.order_counts, &order.account_id);
+            log!("settle_delisted_order {}", order_id);
+            self.internal_settle_delisted_amount(&order.account_id, &order.asset, order.amount);
+        }
+    }
+
+    /// Removes the settled amount from the asset supply, or returns it to the account if the
+    /// aUSD mint failed. The account keeps its asset key until the mint succeeded, so returning
+    /// the amount doesn't take more storage.
+    pub fn on_delisted_asset_settled(
+        &mut self,
+        account_id: AccountId,
+        asset: String,
+        asset_amount: U128,
+    ) {
+        assert_self();
+        if is_promise_success() {
+            let final_price = self.delisted_assets.get(&asset).unwrap();
+            event::asset_trade(
+                &account_id,
+                &asset,
+                OrderSide::Sell,
+                asset_amount.0,
+                value_in_ausd(final_price, asset_amount.0),
+                final_price,
+            );
+            self.internal_sub_asset_supply(&asset, asset_amount.0);
+            self.clear_delisted_asset_if_settled(&asset);
+            // Open orders may still be settled back to the key
+            if let Some(mut account) = self.accounts.get(&account_id) {
+                if account.assets.get(&asset) == Some(&0)
+                    && self.order_counts.get(&account_id).is_none()
+                {
+                    account.assets.remove(&asset);
+                    self.internal_save_account(&account_id, &account);
+                }
+            }
+        } else {
+            log!(
+                "Failed to mint aUSD, returning {} {} to {}",
+                asset_amount.0,
+                asset,
+                account_id
+            );
+            let mut account = self.get_account(&account_id);
+            let balance = self._get_asset_balance(&account_id, &asset);
+            account
+                .assets
+                .insert(asset, balance.checked_add(asset_amount.0).unwrap());
+            self.internal_save_account(&account_id, &account);
+        }
+    }
+
+    pub fn stake_and_mint(&mut self, stake: String) -> Promise {
+        if self.price == 0 {
+            // Not received any data from oracle
+            env::panic(b"No price data from oracle");
+        }
+        let stake_amount = self.stake(stake);
+        let mint_amount = self
+            .internal_quote_stake_and_mint(stake_amount)
+            .amount_out
+            .0;
+
+        let account_id = env::predecessor_account_id();
+        ext_usd::mint(
+            account_id.clone(),
+            mint_amount,
+            &self.ausd_token,
+            0,
+            env::prepaid_gas() / 3,
+        )
+        .then(ext_art::on_stake_minted(
+            account_id,
+            stake_amount.into(),
+            false,
+            &env::current_account_id(),
+            0,
+            env::prepaid_gas() / 3,
+        ))
+    }
+
+    pub fn burn_to_unstake(&mut self, unstake_amount: String) -> Promise {
+        if self.price == 0 {
+            // Not received any data from oracle
+            env::panic(b"No price data from oracle");
+        }
+        let unstake_amount =
+            u128::from_str(&unstake_amount).expect("Failed to parse unstake_amount");
+
+        let account_id = env::predecessor_account_id();
+        let burn_amount = self
+            .internal_quote_burn_to_unstake(unstake_amount)
+            .amount_in
+            .0;
+
+        ext_usd::burn_to_unstake(
+            account_id,
+            burn_amount,
+            unstake_amount,
+            &self.ausd_token,
+            0,
+            env::prepaid_gas() / 3,
+        )
+    }
+
+    /// Same as `stake_and_mint`, but the stake goes to the liquid staking pool and the caller
+    /// receives stART for it, see `wrap_stake`. The stake is wrapped once the aUSD is minted.
+    pub fn stake_and_mint_liquid(&mut self, stake: String) -> Promise {
+        if self.price == 0 {
+            // Not received any data from oracle
+            env::panic(b"No price data from oracle");
+        }
+        self.assert_start_token();
+        let stake_amount = self.stake(stake);
+        self.internal_refresh_liquid_reward();
+        if self.liquid_staking.shares_for(stake_amount) == 0 {
+            env::panic(b"The stake is too small to mint stART");
+        }
+        let mint_amount = self
+            .internal_quote_stake_and_mint(stake_amount)
+            .amount_out
+            .0;
+
+        let account_id = env::predecessor_account_id();
+        ext_usd::mint(
+            account_id.clone(),
+            mint_amount,
+            &self.ausd_token,
+            0,
+            env::prepaid_gas() / 4,
+        )
+        .then(ext_art::on_stake_minted(
+            account_id,
+            stake_amount.into(),
+            true,
+            &env::current_account_id(),
+            0,
+            env::prepaid_gas() / 2,
+        ))
+    }
+
+    /// Unstakes the stake of `stake_and_mint` or `stake_and_mint_liquid` if minting its aUSD
+    /// failed, otherwise wraps it for `stake_and_mint_liquid`.
+    pub fn on_stake_minted(
+        &mut self,
+        account_id: AccountId,
+        stake_amount: U128,
+        liquid: bool,
+    ) -> PromiseOrValue<()> {
+        assert_self();
+        if is_promise_success() {
+            if liquid {
+                return PromiseOrValue::Promise(
+                    self.internal_wrap_stake(&account_id, stake_amount.0),
+                );
+            }
+            return PromiseOrValue::Value(());
+        }
+        log!("Failed to mint aUSD for the stake of {}", account_id);
+        // Unless the stake moved since, e.g. with `wrap_stake`
+        let staked = self.get_account(&account_id).get_staked_balance();
+        let amount = std::cmp::min(staked, stake_amount.0);
+        if amount > 0 {
+            self.internal_unstake(&account_id, amount);
+        }
+        PromiseOrValue::Value(())
+    }
+
+    /// Moves `amount` of the caller's stake to the liquid staking pool and mints the stART it's
+    /// worth to the caller, who has to be registered with the stART contract. The aUSD minted for
+    /// the stake isn't touched: the stake moves with the stART, and whoever redeems the stART with
+    /// `unwrap_stake` has to burn aUSD to unstake it.
+    pub fn wrap_stake(&mut self, amount: String) -> Promise {
+        let amount = u128::from_str(&amount).expect("Failed to parse amount");
+        self.refresh_reward();
+        let account_id = env::predecessor_account_id();
+        self.internal_wrap_stake(&account_id, amount)
+    }
+
+    /// Burns `start_amount` of the caller's stART and adds the staked art it's worth, with the
+    /// rewards of the pool, to the caller's stake. It's then unstaked with `burn_to_unstake` like
+    /// any stake, so the aUSD debt of the art goes to whoever holds the stART.
+    pub fn unwrap_stake(&mut self, start_amount: String) -> Promise {
+        self.assert_registered(&env::predecessor_account_id());
+        let shares = u128::from_str(&start_amount).expect("Failed to parse start_amount");
+        if shares == 0 {
+            env::panic(b"Can't unwrap 0 stART");
+        }
+        let start_token = self.assert_start_token();
+        self.internal_refresh_liquid_reward();
+        if shares > self.liquid_staking.shares {
+            env::panic(b"Not enough stART minted");
+        }
+        // Taken from the pool until the stART is burnt
+        let amount = self.liquid_staking.amount_for(shares);
+        self.liquid_staking.staked -= amount;
+        self.liquid_staking.shares -= shares;
+
+        let account_id = env::predecessor_account_id();
+        ext_start::burn(
+            account_id.clone(),
+            shares.into(),
+            &start_token,
+            0,
+            env::prepaid_gas() / 3,
+        )
+        .then(ext_art::on_start_burned(
+            account_id,
+            shares.into(),
+            amount.into(),
+            &env::current_account_id(),
+            0,
+            env::prepaid_gas() / 3,
+        ))
+    }
+
+    /// Returns the stake moved by `wrap_stake` if minting the stART failed.
+    pub fn on_start_minted(&mut self, account_id: AccountId, amount: U128, shares: U128) {
+        assert_self();
+        if is_promise_success() {
+            event::wrap_stake(&account_id, amount.0, shares.0);
+        } else {
+            self.liquid_staking.staked -= amount.0;
+            self.liquid_staking.shares -= shares.0;
+            let mut account = self.get_account(&account_id);
+            account.staked_balance += amount.0;
+            self.internal_save_account(&account_id, &account);
+            log!("Failed to mint stART for {}", account_id);
+        }
+    }
+
+    /// Adds the art taken from the pool by `unwrap_stake` to the stake of `account_id` once its
+    /// stART is burnt, or returns it to the pool.
+    pub fn on_start_burned(&mut self, account_id: AccountId, shares: U128, amount: U128) {
+        assert_self();
+        if is_promise_success() {
+            self.internal_refresh_reward(&account_id);
+            let mut account = self.get_account(&account_id);
+            // An existing stake keeps its reward period, the part of a day since its last reward
+            // isn't lost. A new stake starts earning now.
+            if account.get_staked_balance() == 0 {
+                self.reward_paid_at
+                    .insert(&account_id, &env::block_timestamp());
+            }
+            account.staked_balance += amount.0;
+            self.internal_save_account(&account_id, &account);
+            event::unwrap_stake(&account_id, amount.0, shares.0);
+        } else {
+            self.liquid_staking.staked += amount.0;
+            self.liquid_staking.shares += shares.0;
+            log!("Failed to burn stART of {}", account_id);
+        }
+    }
+
+    /// Performs the `AusdTransferAction` in `msg` with aUSD sent by `ft_transfer_call`, so the
+    /// operation is atomic with the transfer. The aUSD it needs is burnt and the rest is refunded
+    /// by the aUSD contract, panicking here refunds the whole transfer.
+    pub fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        assert!(
+            env::predecessor_account_id() == self.ausd_token,
+            "Only aUSD can be transferred to the art contract"
+        );
+        let action: AusdTransferAction = near_sdk::serde_json::from_str(&msg)
+            .unwrap_or_else(|_| env::panic(b"Failed to parse the transfer msg"));
+        self.assert_registered(&sender_id);
+        let (burn_amount, asset_price, round) = match &action {
+            AusdTransferAction::BurnToUnstake {
+                amount: unstake_amount,
+            } => {
+                if unstake_amount.0 == 0 {
+                    env::panic(b"Can't unstake 0 tokens");
+                }
+                let burn_amount = self
+                    .internal_quote_burn_to_unstake(unstake_amount.0)
+                    .amount_in
+                    .0;
+                // Unstaked once the aUSD is burnt, checked here to not burn it for nothing
+                if self.get_account(&sender_id).get_staked_balance() < unstake_amount.0 {
+                    env::panic(b"Not enough staked tokens");
+                }
+                (burn_amount, 0, 0)
+            }
+            AusdTransferAction::BuyAsset {
+                asset,
+                asset_amount,
+            } => {
+                let quote = self.internal_quote_asset_trade(asset, OrderSide::Buy, asset_amount.0);
+                self.internal_reserve_asset_supply(
+                    asset,
+                    asset_amount.0,
+                    value_in_ausd(quote.price.0, asset_amount.0),
+                );
+                self.internal_reserve_storage(
+                    &sender_id,
+                    purchase_storage_usage(&sender_id, asset),
+                );
+                let round = self.asset_price_rounds.get(asset).unwrap_or(0);
+                (quote.amount_in.0, quote.price.0, round)
+            }
+        };
+        if burn_amount > amount.0 {
+            env::panic(b"Not enough aUSD transferred for the action");
+        }
+
+        ext_usd::burn(
+            env::current_account_id(),
+            burn_amount,
+            &self.ausd_token,
+            0,
+            env::prepaid_gas() / 3,
+        )
+        .then(ext_art::on_transfer_ausd_burned(
+            sender_id,
+            action,
+            amount,
+            burn_amount.into(),
+            asset_price.into(),
+            round.into(),
+            &env::current_account_id(),
+            0,
+            env::prepaid_gas() / 3,
+        ))
+        .into()
+    }
+
+    /// Finishes the action of an aUSD transfer once its aUSD is burnt, or rolls it back. Returns
+    /// the transferred aUSD the action didn't use, for the aUSD contract to refund.
+    pub fn on_transfer_ausd_burned(
+        &mut self,
+        sender_id: AccountId,
+        action: AusdTransferAction,
+        amount: U128,
+        burn_amount: U128,
+        asset_price: U128,
+        round: U64,
+    ) -> U128 {
+        assert_self();
+        let burned = is_promise_success();
+        match action {
+            AusdTransferAction::BurnToUnstake {
+                amount: unstake_amount,
+            } => {
+                if burned {
+                    let staked = self.get_account(&sender_id).get_staked_balance();
+                    if staked >= unstake_amount.0 {
+                        self.internal_unstake(&sender_id, unstake_amount.0);
+                    } else {
+                        // The stake moved since the transfer, e.g. with `wrap_stake`
+                        log!(
+                            "Not enough staked tokens of {}, minting back {} aUSD",
+                            sender_id,
+                            burn_amount.0
+                        );
+                        ext_usd::mint(
+                            sender_id.clone(),
+                            burn_amount.0,
+                            &self.ausd_token,
+                            0,
+                            GAS_FOR_SETTLEMENT_MINT,
+                        );
+                    }
+                }
+            }
+            AusdTransferAction::BuyAsset {
+                asset,
+                asset_amount,
+            } => {
+                self.internal_release_asset_supply(
+                    &asset,
+                    asset_amount.0,
+                    value_in_ausd(asset_price.0, asset_amount.0),
+                );
+                self.internal_release_storage(
+                    &sender_id,
+                    purchase_storage_usage(&sender_id, &asset),
+                );
+                if burned {
+                    self.internal_purchase_asset(
+                        &sender_id,
+                        &asset,
+                        asset_amount.0,
+                        asset_price.0,
+                        round.0,
+                    );
+                }
+            }
+        }
+        if !burned {
+            log!("Failed to burn aUSD transferred by {}", sender_id);
+            return amount;
+        }
+        (amount.0 - burn_amount.0).into()
+    }
+
+    /// Sells `asset_amount` of `asset` for newly minted aUSD at the oracle price. Panics if less
+    /// than `min_amount_out` aUSD would be received or the block timestamp is past `deadline`.
+    /// The asset is returned if the mint fails.
+    pub fn sell_asset_to_ausd(
+        &mut self,
+        asset: String,
+        asset_amount: String,
+        min_amount_out: Option<String>,
+        deadline: Option<U64>,
+    ) -> Promise {
+        assert_deadline(deadline);
+        let asset_amount = u128::from_str(&asset_amount).expect("Failed to parse asset_amount");
+        let quote = self.internal_quote_asset_trade(&asset, OrderSide::Sell, asset_amount);
+        let mint_amount = quote.amount_out.0;
+        assert_min_amount_out(mint_amount, min_amount_out);
+
+        let account_id = env::predecessor_account_id();
+        self.assert_no_pending_trades(&account_id, &asset);
+        let mut account = self.get_account(&account_id);
+        let balance = self._get_asset_balance(&account_id, &asset);
+        let new_balance = balance.checked_sub(asset_amount).unwrap();
+        account.assets.insert(asset.clone(), new_balance);
+        self.internal_save_account(&account_id, &account);
+
+        ext_usd::mint(
+            account_id.clone(),
+            mint_amount,
+            &self.ausd_token,
+            0,
+            GAS_FOR_SETTLEMENT_MINT,
+        )
+        .then(ext_art::on_asset_sold(
+            account_id,
+            asset,
+            asset_amount.into(),
+            mint_amount.into(),
+            quote.price,
+            &env::current_account_id(),
+            0,
+            GAS_FOR_SETTLEMENT_CALLBACK,
+        ))
+    }
+
+    /// Removes the sold amount from the asset supply, or returns it to the account if the aUSD
+    /// mint failed. The account keeps its asset key while the mint is pending, so returning it
+    /// doesn't take more storage.
+    pub fn on_asset_sold(
+        &mut self,
+        account_id: AccountId,
+        asset: String,
+        asset_amount: U128,
+        ausd_amount: U128,
+        asset_price: U128,
+    ) {
+        assert_self();
+        if is_promise_success() {
+            event::asset_trade(
+                &account_id,
+                &asset,
+                OrderSide::Sell,
+                asset_amount.0,
+                ausd_amount.0,
+                asset_price.0,
+            );
+            self.internal_sub_asset_supply(&asset, asset_amount.0);
+            self.clear_delisted_asset_if_settled(&asset);
+        } else {
+            log!(
+                "Failed to mint aUSD, returning {} {} to {}",
+                asset_amount.0,
+                asset,
+                account_id
+            );
+            let mut account = self.get_account(&account_id);
+            let balance = self._get_asset_balance(&account_id, &asset);
+            account
+                .assets
+                .insert(asset, balance.checked_add(asset_amount.0).unwrap());
+            self.internal_save_account(&account_id, &account);
+        }
+    }
+
+    /// Buys `asset_amount` of `asset` for aUSD at the oracle price. As the bought amount is fixed,
+    /// the price is bounded by `max_ausd_amount` to burn instead. Panics if more than
+    /// `max_ausd_amount` aUSD would be burnt or the block timestamp is past `deadline`.
+    pub fn buy_asset_with_ausd(
+        &mut self,
+        asset: String,
+        asset_amount: String,
+        max_ausd_amount: Option<String>,
+        deadline: Option<U64>,
+    ) -> Promise {
+        self.assert_registered(&env::predecessor_account_id());
+        assert_deadline(deadline);
+        let asset_amount = u128::from_str(&asset_amount).expect("Failed to parse asset_amount");
+        let quote = self.internal_quote_asset_trade(&asset, OrderSide::Buy, asset_amount);
+        let burn_amount = quote.amount_in.0;
+        let asset_price = quote.price.0;
+        if let Some(max_ausd_amount) = max_ausd_amount {
+            let max_ausd_amount =
+                u128::from_str(&max_ausd_amount).expect("Failed to parse max_ausd_amount");
+            if burn_amount > max_ausd_amount {
+                env::panic(b"Input amount is more than max_ausd_amount");
+            }
+        }
+        self.internal_reserve_asset_supply(
+            &asset,
+            asset_amount,
+            value_in_ausd(asset_price, asset_amount),
+        );
+
+        let account_id = env::predecessor_account_id();
+        self.internal_reserve_storage(&account_id, purchase_storage_usage(&account_id, &asset));
+        let round = self.asset_price_rounds.get(&asset).unwrap_or(0);
+        ext_usd::burn(
+            account_id.clone(),
+            burn_amount,
+            &self.ausd_token,
+            0,
+            env::prepaid_gas() / 3,
+        )
+        .then(ext_art::on_asset_purchased(
+            account_id,
+            asset,
+            asset_amount.into(),
+            asset_price.into(),
+            round.into(),
+            &env::current_account_id(),
+            0,
+            env::prepaid_gas() / 3,
+        ))
+    }
+
+    /// Credits the asset once the aUSD for it is burnt, or releases its reserved supply if the
+    /// burn failed. The storage reserved for it is released either way.
+    pub fn on_asset_purchased(
+        &mut self,
+        account_id: AccountId,
+        asset: String,
+        asset_amount: U128,
+        asset_price: U128,
+        round: U64,
+    ) {
+        assert_self();
+        self.internal_release_asset_supply(
+            &asset,
+            asset_amount.0,
+            value_in_ausd(asset_price.0, asset_amount.0),
+        );
+        self.internal_release_storage(&account_id, purchase_storage_usage(&account_id, &asset));
+        if !is_promise_success() {
+            log!("Failed to burn aUSD to buy {}", asset);
+            return;
+        }
+        self.internal_purchase_asset(&account_id, &asset, asset_amount.0, asset_price.0, round.0);
+    }
+
+    /// Places an order to buy `asset` with `ausd_amount` of aUSD once its price crosses
+    /// `trigger_price` in `direction`. The aUSD is burnt as escrow until the order is executed
+    /// or cancelled, and the storage of the purchase is reserved until then.
+    pub fn place_buy_order(
+        &mut self,
+        asset: String,
+        ausd_amount: String,
+        trigger_price: String,
+        direction: TriggerDirection,
+    ) -> Promise {
+        self.assert_registered(&env::predecessor_account_id());
+        if self.delisted_assets.get(&asset).is_some() {
+            env::panic(b"Can't buy a delisted asset");
+        }
+        if self._get_asset_price(&asset) == 0 {
+            env::panic(b"No price data from oracle");
+        }
+        let ausd_amount = u128::from_str(&ausd_amount).expect("Failed to parse ausd_amount");
+        if ausd_amount == 0 {
+            env::panic(b"Can't place an order of 0 tokens");
+        }
+        let trigger_price = u128::from_str(&trigger_price).expect("Failed to parse trigger_price");
+
+        let account_id = env::predecessor_account_id();
+        self.internal_reserve_storage(&account_id, purchase_storage_usage(&account_id, &asset));
+        ext_usd::burn(
+            account_id.clone(),
+            ausd_amount,
+            &self.ausd_token,
+            0,
+            env::prepaid_gas() / 3,
+        )
+        .then(ext_art::on_buy_order_escrowed(
+            account_id,
+            asset,
+            ausd_amount.into(),
+            trigger_price.into(),
+            direction,
+            &env::current_account_id(),
+            0,
+            env::prepaid_gas() / 3,
+        ))
+    }
+
+    pub fn on_buy_order_escrowed(
+        &mut self,
+        account_id: AccountId,
+        asset: String,
+        ausd_amount: U128,
+        trigger_price: U128,
+        direction: TriggerDirection,
+    ) -> Option<u64> {
+        assert_self();
+        if !is_promise_success() {
+            log!("Failed to escrow aUSD for the order");
+            self.internal_release_storage(&account_id, purchase_storage_usage(&account_id, &asset));
+            return None;
+        }
+        Some(self.internal_add_order(Order {
+            account_id,
+            side: OrderSide::Buy,
+            asset,
+            amount: ausd_amount.into(),
+            trigger_price: trigger_price.into(),
+            direction,
+        }))
+    }
+
+    /// Places an order to sell `asset_amount` of `asset` for aUSD once its price crosses
+    /// `trigger_price` in `direction`. The asset is escrowed until the order is executed or
+    /// cancelled.
+    pub fn place_sell_order(
+        &mut self,
+        asset: String,
+        asset_amount: String,
+        trigger_price: String,
+        direction: TriggerDirection,
+    ) -> u64 {
+        let asset_amount = u128::from_str(&asset_amount).expect("Failed to parse asset_amount");
+        if asset_amount == 0 {
+            env::panic(b"Can't place an order of 0 tokens");
+        }
+        let trigger_price = u128::from_str(&trigger_price).expect("Failed to parse trigger_price");
+        if self.delisted_assets.get(&asset).is_some() {
+            env::panic(b"Can't place orders of a delisted asset");
+        }
+
+        let account_id = env::predecessor_account_id();
+        self.assert_no_pending_trades(&account_id, &asset);
+        let mut account = self.get_account(&account_id);
+        let balance = self._get_asset_balance(&account_id, &asset);
+        if balance < asset_amount {
+            env::panic(b"Not enough asset balance");
+        }
+        account.assets.insert(asset.clone(), balance - asset_amount);
+        self.internal_save_account(&account_id, &account);
+
+        self.internal_add_order(Order {
+            account_id,
+            side: OrderSide::Sell,
+            asset,
+            amount: asset_amount,
+            trigger_price,
+            direction,
+        })
+    }
+
+    /// Executes an order whose trigger price has been crossed. The caller receives
+    /// `order_bounty_bps` of the order value in aUSD. A sell order is restored if minting its
+    /// aUSD fails, and the keeper is only paid once it succeeded.
+    pub fn execute_order(&mut self, order_id: u64) -> Promise {
+        self.assert_accounts_migrated();
+        let order = self.orders.get(&order_id).expect("Order doesn't exist");
+        let price = self._get_asset_price(&order.asset);
+        if price == 0 {
+            env::panic(b"No price data from oracle");
+        }
+        if !order.is_triggered(price) {
+            env::panic(b"Order is not triggered at current price");
+        }
+        self.orders.remove(&order_id);
+        let keeper_id = env::predecessor_account_id();
+
+        match order.side {
+            OrderSide::Buy => {
+                if self.delisted_assets.get(&order.asset).is_some() {
+                    env::panic(b"Can't buy a delisted asset");
+                }
+                let bounty = self.order_bounty(order.amount);
+                let ausd_amount = order.amount - bounty;
+                let asset_amount = ausd_to_asset(price, ausd_amount);
+                self.assert_within_exposure_limits(
+                    &order.asset,
+                    asset_amount,
+                    value_in_ausd(price, asset_amount),
+                );
+                let round = self.asset_price_rounds.get(&order.asset).unwrap_or(0);
+                self.internal_release_storage(
+                    &order.account_id,
+                    purchase_storage_usage(&order.account_id, &order.asset),
+                );
+                self.internal_purchase_asset(
+                    &order.account_id,
+                    &order.asset,
+                    asset_amount,
+                    price,
+                    round,
+                );
+                decrement_count(&mut self.order_counts, &order.account_id);
+                event::execute_order(
+                    order_id,
+                    &keeper_id,
+                    OrderSide::Buy,
+                    &order.asset,
+                    asset_amount,
+                    ausd_amount,
+                );
+                // The order is filled already, a failed bounty mint only loses the keeper's bounty
+                ext_usd::mint(keeper_id, bounty, &self.ausd_token, 0, GAS_FOR_ORDER_MINT)
+            }
+            OrderSide::Sell => {
+                let value = value_in_ausd(price, order.amount);
+                let bounty = self.order_bounty(value);
+                // Logged once the aUSD is minted
+                ext_usd::mint(
+                    order.account_id.clone(),
+                    value - bounty,
+                    &self.ausd_token,
+                    0,
+                    GAS_FOR_ORDER_MINT,
+                )
+                .then(ext_art::on_sell_order_paid(
+                    OrderView::new(order_id, order),
+                    keeper_id,
+                    bounty.into(),
+                    price.into(),
+                    &env::current_account_id(),
+                    0,
+                    GAS_FOR_ORDER_MINT + GAS_FOR_ORDER_CALLBACK,
+                ))
+            }
+        }
+    }
+
+    /// Removes the sold asset from the supply and pays the keeper `bounty`, or restores the order
+    /// if the aUSD mint failed.
+    pub fn on_sell_order_paid(
+        &mut self,
+        order: OrderView,
+        keeper_id: AccountId,
+        bounty: U128,
+        price: U128,
+    ) {
+        assert_self();
+        if !is_promise_success() {
+            log!("Failed to mint aUSD, restoring order {}", order.id);
+            let order_id = order.id;
+            self.orders.insert(&order_id, &order.into());
+            return;
+        }
+        let value = value_in_ausd(price.0, order.amount.0);
+        event::execute_order(
+            order.id,
+            &keeper_id,
+            OrderSide::Sell,
+            &order.asset,
+            order.amount.0,
+            value - bounty.0,
+        );
+        event::asset_trade(
+            &order.account_id,
+            &order.asset,
+            OrderSide::Sell,
+            order.amount.0,
+            value,
+            price.0,
+        );
+        self.internal_sub_asset_supply(&order.asset, order.amount.0);
+        self.clear_delisted_asset_if_settled(&order.asset);
+        decrement_count(&mut self.order_counts, &order.account_id);
+        ext_usd::mint(keeper_id, bounty.0, &self.ausd_token, 0, GAS_FOR_ORDER_MINT);
+    }
+
+    /// Restores a cancelled buy order if returning its escrowed aUSD failed, or releases the
+    /// storage reserved for it.
+    pub fn on_order_refunded(&mut self, order: OrderView) {
+        assert_self();
+        if !is_promise_success() {
+            log!("Failed to refund aUSD, restoring order {}", order.id);
+            let order_id = order.id;
+            self.orders.insert(&order_id, &order.into());
+        } else {
+            self.internal_release_storage(
+                &order.account_id,
+                purchase_storage_usage(&order.account_id, &order.asset),
+            );
+            decrement_count(&mut self.order_counts, &order.account_id);
+        }
+    }
+
+    /// Cancels an open order of the caller and returns the escrowed aUSD or asset.
+    pub fn cancel_order(&mut self, order_id: u64) {
+        let order = self.orders.get(&order_id).expect("Order doesn't exist");
+        if order.account_id != env::predecessor_account_id() {
+            env::panic(b"Only the order owner can cancel it");
+        }
+        self.orders.remove(&order_id);
+        match order.side {
+            OrderSide::Buy => {
+                ext_usd::mint(
+                    order.account_id.clone(),
+                    order.amount,
+                    &self.ausd_token,
+                    0,
+                    GAS_FOR_ORDER_MINT,
+                )
+                .then(ext_art::on_order_refunded(
+                    OrderView::new(order_id, order),
+                    &env::current_account_id(),
+                    0,
+                    GAS_FOR_ORDER_CALLBACK,
+                ));
+            }
+            OrderSide::Sell => {
+                let mut account = self.get_account(&order.account_id);
+                let balance = self._get_asset_balance(&order.account_id, &order.asset);
+                account
+                    .assets
+                    .insert(order.asset.clone(), balance.checked_add(order.amount).unwrap());
+                self.internal_save_account(&order.account_id, &account);
+                decrement_count(&mut self.order_counts, &order.account_id);
+            }
+        }
+    }
+
+    pub fn set_order_bounty_bps(&mut self, order_bounty_bps: u32) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can set order bounty");
+        }
+        if order_bounty_bps > 10_000 {
+            env::panic(b"Order bounty can't exceed 10000 basis points");
+        }
+        self.order_bounty_bps = order_bounty_bps;
+    }
+
+    /// Grants `amount` of owner's unstaked art to `account_id`, vesting linearly over `duration`
+    /// nanoseconds from `start`. Nothing vests until `cliff_duration` after `start`. Returns the
+    /// grant ID. An unregistered `account_id` is registered with the attached deposit.
+    #[allow(clippy::too_many_arguments)]
+    #[payable]
+    pub fn create_vesting_grant(
+        &mut self,
+        account_id: ValidAccountId,
+        amount: String,
+        start: U64,
+        cliff_duration: U64,
+        duration: U64,
+        revocable: bool,
+        stakeable: bool,
+    ) -> u64 {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can create vesting grants");
+        }
+        let amount = u128::from_str(&amount).expect("Failed to parse amount");
+        if amount == 0 {
+            env::panic(b"Can't grant 0 tokens");
+        }
+        if duration.0 == 0 {
+            env::panic(b"Vesting duration must be positive");
+        }
+        if cliff_duration.0 > duration.0 {
+            env::panic(b"Cliff can't be longer than the vesting duration");
+        }
+        let mut owner = self.get_account(&self.owner);
+        if owner.balance < amount {
+            env::panic(b"Not enough unstaked balance");
+        }
+        owner.balance -= amount;
+        self.internal_save_account(&self.owner.clone(), &owner);
+
+        let grant_id = self.next_vesting_grant_id;
+        self.next_vesting_grant_id += 1;
+        let account_id: AccountId = account_id.into();
+        let refund = self.internal_register_receiver(&account_id, env::attached_deposit());
+        self.vesting_grants.insert(
+            &grant_id,
+            &VestingGrant {
+                account_id: account_id.clone(),
+                amount,
+                claimed: 0,
+                start: start.0,
+                cliff: start.0 + cliff_duration.0,
+                end: start.0 + duration.0,
+                revocable,
+                stakeable,
+                staked: 0,
+                reward: 0,
+                reward_paid_at: env::block_timestamp(),
+            },
+        );
+        increment_count(&mut self.vesting_grant_counts, &account_id);
+        event::vesting("vesting_grant", grant_id, &account_id, amount);
+        refund_deposit(refund);
+        grant_id
+    }
+
+    /// Takes back the art of a revocable grant that isn't vested yet to owner's balance. Staked
+    /// art beyond the vested art that isn't claimed is unstaked.
+    pub fn revoke_vesting_grant(&mut self, grant_id: u64) {
+        if env::predecessor_account_id() != self.owner {
+            env::panic(b"Only owner can revoke vesting grants");
+        }
+        let mut grant = self.internal_unwrap_vesting_grant(grant_id);
+        if !grant.revocable {
+            env::panic(b"The vesting grant isn't revocable");
+        }
+        self.internal_refresh_vesting_reward(&mut grant);
+        let now = env::block_timestamp();
+        let vested = grant.vested_at(now);
+        let unvested = grant.amount - vested;
+        let max_staked = vested - grant.claimed;
+        if grant.staked > max_staked {
+            self.total_staked -= grant.staked - max_staked;
+            grant.staked = max_staked;
+        }
+        grant.amount = vested;
+        grant.cliff = std::cmp::min(grant.cliff, now);
+        grant.end = std::cmp::min(grant.end, now);
+        grant.revocable = false;
+        if grant.claimed == grant.amount && grant.staked == 0 && grant.reward == 0 {
+            self.vesting_grants.remove(&grant_id);
+            decrement_count(&mut self.vesting_grant_counts, &grant.account_id);
+        } else {
+            self.vesting_grants.insert(&grant_id, &grant);
+        }
+
+        let mut owner = self.get_account(&self.owner);
+        owner.balance += unvested;
+        self.internal_save_account(&self.owner.clone(), &owner);
+        event::vesting("vesting_revoke", grant_id, &grant.account_id, unvested);
+    }
+
+    /// Claims the vested art of the grant that isn't staked, and its staking reward, to the
+    /// caller's unstaked balance. Returns the claimed amount.
+    pub fn claim_vesting(&mut self, grant_id: u64) -> String {
+        let mut grant = self.internal_unwrap_vesting_grant(grant_id);
+        self.assert_vesting_grant_account(&grant);
+        self.internal_refresh_vesting_reward(&mut grant);
+        let vested = grant.claimable_at(env::block_timestamp());
+        let amount = vested + grant.reward;
+        if amount == 0 {
+            env::panic(b"Nothing to claim");
+        }
+        grant.claimed += vested;
+        grant.reward = 0;
+        if grant.claimed == grant.amount && grant.staked == 0 {
+            self.vesting_grants.remove(&grant_id);
+            decrement_count(&mut self.vesting_grant_counts, &grant.account_id);
+        } else {
+            self.vesting_grants.insert(&grant_id, &grant);
+        }
+
+        let mut account = self.get_account(&grant.account_id);
+        account.balance += amount;
+        self.internal_save_account(&grant.account_id, &account);
+        event::vesting("vesting_claim", grant_id, &grant.account_id, amount);
+        amount.to_string()
+    }
+
+    /// Stakes `amount` of the art of the grant that isn't claimed, vested or not, to earn the
+    /// staking reward. The staked art stays in the grant, it's not minted aUSD for.
+    pub fn stake_vesting(&mut self, grant_id: u64, amount: String) {
+        let amount = u128::from_str(&amount).expect("Failed to parse amount");
+        if amount == 0 {
+            env::panic(b"Can't stake 0 tokens");
+        }
+        let mut grant = self.internal_unwrap_vesting_grant(grant_id);
+        self.assert_vesting_grant_account(&grant);
+        if !grant.stakeable {
+            env::panic(b"The vesting grant can't be staked");
+        }
+        self.internal_refresh_vesting_reward(&mut grant);
+        if grant.amount - grant.claimed - grant.staked < amount {
+            env::panic(b"Not enough unstaked art in the vesting grant");
+        }
+        grant.staked += amount;
+        self.total_staked += amount;
+        self.vesting_grants.insert(&grant_id, &grant);
+        event::vesting("vesting_stake", grant_id, &grant.account_id, amount);
+    }
+
+    /// Unstakes `amount` of the staked art of the grant, so it can be claimed once it's vested.
+    pub fn unstake_vesting(&mut self, grant_id: u64, amount: String) {
+        let amount = u128::from_str(&amount).expect("Failed to parse amount");
+        if amount == 0 {
+            env::panic(b"Can't unstake 0 tokens");
+        }
+        let mut grant = self.internal_unwrap_vesting_grant(grant_id);
+        self.assert_vesting_grant_account(&grant);
+        self.internal_refresh_vesting_reward(&mut grant);
+        if grant.staked < amount {
+            env::panic(b"Not enough staked art in the vesting grant");
+        }
+        grant.staked -= amount;
+        self.total_staked -= amount;
+        self.vesting_grants.insert(&grant_id, &grant);
+        event::vesting("vesting_unstake", grant_id, &grant.account_id, amount);
+    }
+
+    /// Stakes an additional `stake_amount` to the signer
+    /// Requirements:
+    /// * The signer should have enough unstaked balance.
+    fn stake(&mut self, stake_amount: String) -> u128 {
+        let stake_amount = u128::from_str(&stake_amount).expect("Failed to parse stake_amount");
+        if stake_amount == 0 {
+            env::panic(b"Can't stake 0 tokens");
+        }
+        self.refresh_reward();
+        let account_id = env::predecessor_account_id();
+        let mut account = self.get_account(&account_id);
+
+        // Checking and updating unstaked balance
+        if account.balance < stake_amount {
+            env::panic(b"Not enough unstaked balance");
+        }
+        account.balance -= stake_amount;
+
+        // Updating total stake balance
+        let staked_balance = account.get_staked_balance();
+        account.set_staked_balance(staked_balance + stake_amount);
+        self.total_staked += stake_amount;
+        self.reward_paid_at
+            .insert(&account_id, &env::block_timestamp());
+
+        self.internal_save_account(&account_id, &account);
+        event::stake(&account_id, stake_amount);
+        stake_amount
+    }
+
+    /// Unstakes the `unstake_amount` from the owner
+    pub fn unstake(&mut self, account_id: String, unstake_amount: u128) {
+        assert!(
+            env::predecessor_account_id() == self.ausd_token,
+            "Only allow unstake originated from ausd token"
+        );
+        self.internal_unstake(&account_id, unstake_amount);
+    }
+
+    /// Transfers unstaked `amount` of tokens from `owner_id` to the `new_owner_id`.
+    /// Requirements:
+    /// * The caller of the function (`predecessor_id`) should have at least `amount` of allowance tokens.
+    /// * The balance owner should have at least `amount` of unstaked (by `predecessor_id`) tokens
+    /// * `new_owner_id` should be registered, or the attached deposit should cover its minimum
+    ///   storage balance. The rest of the deposit is refunded.
+    #[payable]
+    pub fn transfer_from(&mut self, owner_id: AccountId, new_owner_id: AccountId, amount: String) {
+        let amount = u128::from_str(&amount).expect("Failed to parse allow amount");
+        if amount == 0 {
+            env::panic(b"Can't transfer 0 tokens");
+        }
+        let refund = self.internal_register_receiver(&new_owner_id, env::attached_deposit());
+        let escrow_account_id = env::predecessor_account_id();
+        let mut account = self.get_account(&owner_id);
+
+        // Checking and updating unstaked balance
+        if account.balance < amount {
+            env::panic(b"Not enough unstaked balance");
+        }
+        account.balance -= amount;
+
+        // If transferring by escrow, need to check and update allowance.
+        if escrow_account_id != owner_id {
+            let allowance = account.get_allowance(&escrow_account_id);
+            // Checking and updating unstaked balance
+            if allowance < amount {
+                env::panic(b"Not enough allowance");
+            }
+            account.set_allowance(&escrow_account_id, allowance - amount);
+        }
+
+        self.internal_save_account(&owner_id, &account);
+
+        // Stake amount to the new owner
+        let mut new_account = self.get_account(&new_owner_id);
+        new_account.balance += amount;
+        self.internal_save_account(&new_owner_id, &new_account);
+        event::ft_transfer(&owner_id, &new_owner_id, amount, None);
+        refund_deposit(refund);
+    }
+
+    /// Same as `transfer_from` with `owner_id` `predecessor_id`.
+    #[payable]
+    pub fn transfer(&mut self, new_owner_id: AccountId, amount: String) {
+        self.transfer_from(env::predecessor_account_id(), new_owner_id, amount);
+    }
+
+    /// Transfers the amount of each leg of `transfers` from the caller to its receiver, like
+    /// `ft_transfer`, logging an `ft_transfer` event per leg. The caller's balance has to cover
+    /// the total of all legs. Unregistered receivers are registered with the attached deposit
+    /// beyond 1 yoctoNEAR, the rest is refunded. A leg fails if its receiver isn't registered and
+    /// the deposit left doesn't cover it, is the caller, or its amount is 0. If `partial` is set,
+    /// failed legs are skipped and returned, otherwise the first one reverts the whole batch.
+    #[payable]
+    pub fn batch_transfer(
+        &mut self,
+        transfers: Vec<(ValidAccountId, U128)>,
+        memo: Option<String>,
+        partial: Option<bool>,
+    ) -> Vec<FailedTransfer> {
+        let deposit = assert_at_least_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let (failed, refund) =
+            batch_transfer::batch_transfer(self, &sender_id, transfers, memo, partial, deposit);
+        refund_deposit(refund);
+        failed
+    }
+
+    /// Returns total supply of tokens.
+    pub fn get_total_supply(&self) -> String {
+        self.total_supply.to_string()
+    }
+
+    /// Returns total balance for the `owner_id` account. Including all staked and unstaked tokens.
+    pub fn get_total_balance(&self, owner_id: AccountId) -> String {
+        self.get_account(&owner_id).total_balance().to_string()
+    }
+
+    /// Returns unstaked token balance for the `owner_id`.
+    pub fn get_unstaked_balance(&self, owner_id: AccountId) -> String {
+        self.get_account(&owner_id).balance.to_string()
+    }
+
+    /// Returns current allowance for the `owner_id` to be able to use by `escrow_account_id`.
+    pub fn get_allowance(&self, owner_id: AccountId, escrow_account_id: AccountId) -> String {
+        self.get_account(&owner_id)
+            .get_allowance(&escrow_account_id)
+            .to_string()
+    }
+
+    /// Returns the total balance, staked and unstaked, of `account_id` at the end of the block at
+    /// `height`.
+    pub fn get_balance_at(&self, account_id: AccountId, height: U64) -> String {
+        assert_past_height(height.0);
+        let count = self.balance_checkpoint_counts.get(&account_id).unwrap_or(0);
+        balance_at(count, height.0, |i| {
+            self.balance_checkpoints
+                .get(&(account_id.clone(), i))
+                .unwrap()
+        })
+        .to_string()
+    }
+
+    /// Returns the total supply at the end of the block at `height`. It includes the art staked in
+    /// the liquid staking pool and the unvested art of vesting grants, which aren't in the balance
+    /// of any account.
+    pub fn get_total_supply_at(&self, height: U64) -> String {
+        assert_past_height(height.0);
+        balance_at(self.supply_checkpoints.len(), height.0, |i| {
+            self.supply_checkpoints.get(i).unwrap()
+        })
+        .to_string()
+    }
+
+    /// Returns the block height of the snapshot `name`.
+    pub fn get_snapshot(&self, name: String) -> Option<U64> {
+        self.snapshots.get(&name).map(|height| height.into())
+    }
+
+    pub fn get_balance_at_snapshot(&self, account_id: AccountId, name: String) -> String {
+        self.get_balance_at(account_id, self.internal_unwrap_snapshot(&name))
+    }
+
+    pub fn get_total_supply_at_snapshot(&self, name: String) -> String {
+        self.get_total_supply_at(self.internal_unwrap_snapshot(&name))
+    }
+
+    pub fn get_permit_key(&self, owner_id: AccountId) -> Option<Base58PublicKey> {
+        self.permit_keys
+            .get(&owner_id)
+            .map(permit::ed25519_public_key)
+    }
+
+    /// Returns the nonce the next permit of `owner_id` has to be signed with.
+    pub fn get_permit_nonce(&self, owner_id: AccountId) -> U64 {
+        self.permit_nonces.get(&owner_id).unwrap_or(0).into()
+    }
+
+    /// Returns current staked balance for the `owner_id` staked by `escrow_account_id`.
+    pub fn get_staked_balance(&self, account_id: AccountId) -> String {
+        self.get_account(&account_id)
+            .get_staked_balance()
+            .to_string()
+    }
+
+    pub fn get_price(&self) -> String {
+        self.price.to_string()
+    }
+
+    pub fn get_asset_price(&self, asset: String) -> String {
+        self._get_asset_price(&asset).to_string()
+    }
+
+    pub fn get_asset_balance(&self, account_id: AccountId, asset: String) -> String {
+        self._get_asset_balance(&account_id, &asset).to_string()
+    }
+
+    /// Returns the total amount of `asset` held by all accounts.
+    pub fn get_asset_supply(&self, asset: String) -> String {
+        self.asset_supplies.get(&asset).unwrap_or(0).to_string()
+    }
+
+    /// Returns current open interest of `asset` against its cap. If the price of an index asset
+    /// can't be computed, `value` is 0 and `price_error` tells why.
+    pub fn get_open_interest(&self, asset: String) -> OpenInterest {
+        let supply = self.asset_supplies.get(&asset).unwrap_or(0);
+        let (value, price_error) = match self.internal_asset_price(&asset) {
+            Ok(price) => (value_in_ausd(price, supply), None),
+            Err(err) => (0, Some(err)),
+        };
+        OpenInterest {
+            cap: self.asset_supply_caps.get(&asset).map(|cap| cap.into()),
+            asset,
+            supply: supply.into(),
+            value: value.into(),
+            price_error,
+        }
+    }
+
+    /// Returns current open interest of every asset with a price.
+    pub fn get_open_interests(&self) -> Vec<OpenInterest> {
+        self.asset_prices
+            .keys()
+            .chain(self.index_assets.keys())
+            .map(|asset| self.get_open_interest(asset))
+            .collect()
+    }
+
+    /// Returns the USD value, in aUSD, of all synthetic assets in the system. Index assets whose
+    /// price can't be computed are valued at their last price.
+    pub fn get_total_asset_exposure(&self) -> String {
+        self.total_asset_exposure.to_string()
+    }
+
+    pub fn get_max_total_asset_exposure(&self) -> Option<String> {
+        self.max_total_asset_exposure.map(|cap| cap.to_string())
+    }
+
+    pub fn get_index_asset(&self, asset: String) -> Option<Vec<IndexComponent>> {
+        self.index_assets.get(&asset)
+    }
+
+    pub fn get_index_assets(&self) -> Vec<String> {
+        self.index_assets.keys().collect()
+    }
+
+    pub fn get_max_index_price_age(&self) -> u64 {
+        self.max_index_price_age
+    }
+
+    /// Returns purchases of `asset` by `account_id` waiting to be settled.
+    pub fn get_pending_trades(&self, account_id: AccountId, asset: String) -> Vec<PendingTrade> {
+        let key = (account_id, asset);
+        let mut trades = self.settling_trades.get(&key).unwrap_or_default();
+        trades.extend(self.pending_trades.get(&key).unwrap_or_default());
+        trades
+    }
+
+    pub fn get_settlement_waiting_period(&self) -> u64 {
+        self.settlement_waiting_period
+    }
+
+    pub fn get_order(&self, order_id: u64) -> Option<OrderView> {
+        self.orders
+            .get(&order_id)
+            .map(|order| OrderView::new(order_id, order))
+    }
+
+    /// Returns open orders of `account_id` among the `limit` orders from `from_index`.
+    pub fn get_orders(&self, account_id: AccountId, from_index: u64, limit: u64) -> Vec<OrderView> {
+        let keys = self.orders.keys_as_vector();
+        let values = self.orders.values_as_vector();
+        (from_index..std::cmp::min(from_index + limit, keys.len()))
+            .filter_map(|index| {
+                let order = values.get(index).unwrap();
+                if order.account_id == account_id {
+                    Some(OrderView::new(keys.get(index).unwrap(), order))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn get_number_of_orders(&self) -> u64 {
+        self.orders.len()
+    }
+
+    /// Returns orders that can be executed at current prices, for keepers. Orders of index assets
+    /// without a current price are skipped.
+    pub fn get_executable_orders(&self, from_index: u64, limit: u64) -> Vec<OrderView> {
+        let keys = self.orders.keys_as_vector();
+        let values = self.orders.values_as_vector();
+        (from_index..std::cmp::min(from_index + limit, keys.len()))
+            .filter_map(|index| {
+                let order = values.get(index).unwrap();
+                let price = self.internal_asset_price(&order.asset).unwrap_or(0);
+                if price > 0 && order.is_triggered(price) {
+                    Some(OrderView::new(keys.get(index).unwrap(), order))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn get_vesting_grant(&self, grant_id: u64) -> Option<VestingGrantView> {
+        self.vesting_grants
+            .get(&grant_id)
+            .map(|grant| VestingGrantView::new(grant_id, grant))
+    }
+
+    /// Returns vesting grants of `account_id` among the `limit` grants from `from_index`.
+    pub fn get_vesting_grants(
+        &self,
+        account_id: AccountId,
+        from_index: u64,
+        limit: u64,
+    ) -> Vec<VestingGrantView> {
+        let keys = self.vesting_grants.keys_as_vector();
+        let values = self.vesting_grants.values_as_vector();
+        (from_index..std::cmp::min(from_index + limit, keys.len()))
+            .filter_map(|index| {
+                let grant = values.get(index).unwrap();
+                if grant.account_id == account_id {
+                    Some(VestingGrantView::new(keys.get(index).unwrap(), grant))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn get_number_of_vesting_grants(&self) -> u64 {
+        self.vesting_grants.len()
+    }
+
+    pub fn get_order_bounty_bps(&self) -> u32 {
+        self.order_bounty_bps
+    }
+
+    /// Returns the final price of a delisted `asset`, or `None` if it's not delisted.
+    pub fn get_delisted_price(&self, asset: String) -> Option<String> {
+        self.delisted_assets.get(&asset).map(|price| price.to_string())
+    }
+
+    pub fn get_pool(&self) -> PoolView {
+        PoolView {
+            art_reserve: self.pool.art_reserve.into(),
+            near_reserve: self.pool.near_reserve.into(),
+            total_shares: self.pool.total_shares.into(),
+            fee_bps: self.pool.fee_bps,
+        }
+    }
+
+    pub fn get_pool_shares(&self, account_id: AccountId) -> String {
+        self.pool.shares_of(&account_id).to_string()
+    }
+
+    /// Returns the liquid staking pool, one stART is worth `staked / start_supply` staked art.
+    pub fn get_liquid_staking(&self) -> LiquidStakingView {
+        LiquidStakingView {
+            start_token: self.liquid_staking.start_token.clone(),
+            staked: self.liquid_staking.staked.into(),
+            start_supply: self.liquid_staking.shares.into(),
+            reward_paid_at: self.liquid_staking.reward_paid_at.into(),
+        }
+    }
+
+    /// Returns fee and total fees collected of each conversion path.
+    pub fn get_conversion_fees(&self) -> Vec<ConversionFeeView> {
+        ConversionPath::ALL
+            .iter()
+            .map(|path| ConversionFeeView {
+                path: *path,
+                fee_bps: self.internal_fee_bps(*path),
+                fee_token: path.fee_token().to_string(),
+                collected: self.collected_fees.get(path).unwrap_or(0).into(),
+            })
+            .collect()
+    }
+
+    pub fn get_rate_limit(&self, path: ConversionPath) -> Option<RateLimitView> {
+        self.rate_limits.get(&path).map(|rate_limit| RateLimitView {
+            window: rate_limit.window.into(),
+            account_limit: rate_limit.account_limit.map(U128),
+            global_limit: rate_limit.global_limit.map(U128),
+        })
+    }
+
+    /// Returns the volume `account_id` can still convert on `path` in the current window.
+    pub fn get_conversion_allowance(
+        &self,
+        path: ConversionPath,
+        account_id: AccountId,
+    ) -> ConversionAllowance {
+        let rate_limit = match self.rate_limits.get(&path) {
+            Some(rate_limit) => rate_limit,
+            None => {
+                return ConversionAllowance {
+                    account_remaining: None,
+                    global_remaining: None,
+                    window_ends_at: 0.into(),
+                }
+            }
+        };
+        let window_start = env::block_timestamp() - env::block_timestamp() % rate_limit.window;
+        let account_volume =
+            current_volume(self.account_volumes.get(&(account_id, path)), window_start);
+        let global_volume = current_volume(self.global_volumes.get(&path), window_start);
+        ConversionAllowance {
+            account_remaining: rate_limit
+                .account_limit
+                .map(|limit| U128(limit.saturating_sub(account_volume.volume))),
+            global_remaining: rate_limit
+                .global_limit
+                .map(|limit| U128(limit.saturating_sub(global_volume.volume))),
+            window_ends_at: (window_start + rate_limit.window).into(),
+        }
+    }
+
+    pub fn get_treasury(&self) -> AccountId {
+        self.treasury.clone()
+    }
+
+    pub fn get_governance(&self) -> Option<AccountId> {
+        self.governance.clone()
+    }
+
+    pub fn get_art_reserve(&self) -> ArtReserveView {
+        ArtReserveView {
+            conversion_mode: self.conversion_mode,
+            art_reserve: self.art_reserve.into(),
+            min_art_reserve: self.min_art_reserve.into(),
+            max_art_reserve: self.max_art_reserve.map(U128),
+            available_art: self.available_art_reserve().into(),
+        }
+    }
+
+    /// Returns the aUSD minted for staking `stake` art.
+    pub fn quote_stake_and_mint(&self, stake: String) -> Quote {
+        let stake = u128::from_str(&stake).expect("Failed to parse stake");
+        self.internal_quote_stake_and_mint(stake)
+    }
+
+    /// Returns the aUSD burnt to unstake `unstake_amount` art.
+    pub fn quote_burn_to_unstake(&self, unstake_amount: String) -> Quote {
+        let unstake_amount =
+            u128::from_str(&unstake_amount).expect("Failed to parse unstake_amount");
+        self.internal_quote_burn_to_unstake(unstake_amount)
+    }
+
+    /// Returns the result of exchanging `amount_in` of `token_in`, "art" or "aUSD", for the other
+    /// with `exchange_art_to_ausd` or `exchange_ausd_to_art`.
+    pub fn quote_exchange(&self, token_in: String, amount_in: String) -> Quote {
+        let amount_in = u128::from_str(&amount_in).expect("Failed to parse amount_in");
+        match token_in.as_str() {
+            "art" => self.internal_quote_art_to_ausd(amount_in),
+            "aUSD" => self.internal_quote_ausd_to_art(amount_in),
+            _ => env::panic(b"token_in must be art or aUSD"),
+        }
+    }
+
+    /// Returns the result of buying `token_out`, "art" or "aUSD", with `near_amount` NEAR.
+    pub fn quote_buy_with_near(&self, token_out: String, near_amount: String) -> Quote {
+        let near_amount = u128::from_str(&near_amount).expect("Failed to parse near_amount");
+        match token_out.as_str() {
+            "art" => self.internal_quote_near_to_art(near_amount),
+            "aUSD" => self.internal_quote_near_to_ausd(near_amount),
+            _ => env::panic(b"token_out must be art or aUSD"),
+        }
+    }
+
+    /// Returns the result of selling `amount_in` of `token_in`, "art" or "aUSD", for NEAR.
+    pub fn quote_sell_for_near(&self, token_in: String, amount_in: String) -> Quote {
+        let amount_in = u128::from_str(&amount_in).expect("Failed to parse amount_in");
+        match token_in.as_str() {
+            "art" => self.internal_quote_art_to_near(amount_in),
+            "aUSD" => self.internal_quote_ausd_to_near(amount_in),
+            _ => env::panic(b"token_in must be art or aUSD"),
+        }
+    }
+
+    /// Returns the result of `swap` along `path`.
+    pub fn quote_swap(&self, path: Vec<String>, amount_in: String) -> SwapQuote {
+        let amount_in = u128::from_str(&amount_in).expect("Failed to parse amount_in");
+        self.internal_quote_swap(&parse_swap_path(&path), amount_in)
+    }
+
+    /// Returns the aUSD paid for buying, or received for selling, `asset_amount` of `asset`.
+    pub fn quote_asset_trade(&self, asset: String, side: OrderSide, asset_amount: String) -> Quote {
+        let asset_amount = u128::from_str(&asset_amount).expect("Failed to parse asset_amount");
+        self.internal_quote_asset_trade(&asset, side, asset_amount)
+    }
+
+    pub fn get_reward_paid_at(&self, account_id: AccountId) -> u64 {
+        self.reward_paid_at
+            .get(&account_id)
+            .unwrap_or(self.staking_reward_enabled_at)
+    }
+}
+
+impl Art {
+    /// Helper method to get the account details for `owner_id`.
+    fn get_account(&self, owner_id: &AccountId) -> Account {
+        self.accounts.get(owner_id).unwrap_or_default()
+    }
+
+    fn internal_unwrap_vesting_grant(&self, grant_id: u64) -> VestingGrant {
+        self.vesting_grants
+            .get(&grant_id)
+            .unwrap_or_else(|| env::panic(b"The vesting grant doesn't exist"))
+    }
+
+    fn assert_vesting_grant_account(&self, grant: &VestingGrant) {
+        if env::predecessor_account_id() != grant.account_id {
+            env::panic(b"Only the account of the vesting grant can call this method");
+        }
+    }
+
+    /// Adds the staking reward of the staked art of `grant` since it was last paid to the grant.
+    fn internal_refresh_vesting_reward(&mut self, grant: &mut VestingGrant) {
+        let now = env::block_timestamp();
+        if grant.staked == 0 {
+            grant.reward_paid_at = now;
+            return;
+        }
+        let days = (now - grant.reward_paid_at) / (24 * 60 * 60 * 1000000000);
+        if days == 0 {
+            return;
+        }
+        grant.reward_paid_at += days * (24 * 60 * 60 * 1000000000);
+        let reward = compound_stake(grant.staked, days) - grant.staked;
+        grant.reward += reward;
+        self.total_supply += reward;
+        self.internal_checkpoint_total_supply();
+        event::ft_mint(
+            &env::current_account_id(),
+            reward,
+            Some("vesting staking reward"),
+        );
+        event::reward(&grant.account_id, reward);
+    }
+
+    fn internal_unwrap_snapshot(&self, name: &String) -> U64 {
+        self.snapshots
+            .get(name)
+            .unwrap_or_else(|| env::panic(b"The snapshot doesn't exist"))
+            .into()
+    }
+
+    /// Saves `account` and checkpoints its total balance. The account has to be registered, so
+    /// art is never credited to an account that didn't pay for its storage. If the account takes
+    /// more storage than before, its storage balance has to cover it. Callbacks release the
+    /// storage reserved for them first, so it's covered once tokens are burnt.
+    fn internal_save_account(&mut self, account_id: &AccountId, account: &Account) {
+        let old_extra_storage_usage = self
+            .accounts
+            .insert(account_id, account)
+            .unwrap_or_else(|| {
+                env::panic(format!("The account {} is not registered", account_id).as_bytes())
+            })
+            .extra_storage_usage();
+        if account.extra_storage_usage() > old_extra_storage_usage {
+            self.assert_storage_balance(account_id, account);
+        }
+        self.internal_checkpoint_balance(account_id, account.total_balance());
+    }
+
+    /// Panics if the storage deposit of `account_id` doesn't cover the storage `account` takes.
+    fn assert_storage_balance(&self, account_id: &AccountId, account: &Account) {
+        let required = self.internal_storage_cost(account_id, account);
+        let deposit = self.internal_storage_deposit_of(account_id);
+        if deposit < required {
+            env::panic(
+                format!(
+                    "The account {} needs {} more storage balance, attach it with storage_deposit",
+                    account_id,
+                    required - deposit
+                )
+                .as_bytes(),
+            );
+        }
+    }
+
+    /// Returns the cost of the storage `account` of `account_id` takes, with `storage_usages`.
+    fn internal_storage_cost(&self, account_id: &AccountId, account: &Account) -> Balance {
+        Balance::from(
+            self.account_storage_usage
+                + account.extra_storage_usage()
+                + self.storage_usages.get(account_id).unwrap_or(0),
+        ) * env::storage_byte_cost()
+    }
+
+    /// Adds the storage used since `initial_storage` to the `storage_usages` of `account_id`, or
+    /// takes off what was released. Panics if its storage balance doesn't cover the increase.
+    fn internal_track_storage(&mut self, account_id: &AccountId, initial_storage: StorageUsage) {
+        if self.internal_charge_storage(account_id, initial_storage) {
+            let account = self.accounts.get(account_id).unwrap_or_else(|| {
+                env::panic(format!("The account {} is not registered", account_id).as_bytes())
+            });
+            self.assert_storage_balance(account_id, &account);
+        }
+    }
+
+    /// Same as `internal_track_storage` without checking the storage balance, for storage the
+    /// account can't refuse, e.g. the checkpoint of art it receives. It can't withdraw its storage
+    /// balance until it covers it again. Returns whether the storage increased.
+    fn internal_charge_storage(
+        &mut self,
+        account_id: &AccountId,
+        initial_storage: StorageUsage,
+    ) -> bool {
+        let current_storage = env::storage_usage();
+        if current_storage == initial_storage {
+            return false;
+        }
+        let usage = (self.storage_usages.get(account_id).unwrap_or(0) + current_storage)
+            .saturating_sub(initial_storage);
+        self.storage_usages.insert(account_id, &usage);
+        current_storage > initial_storage
+    }
+
+    /// Reserves `bytes` of the storage balance of `account_id` for a write in a callback, so the
+    /// callback doesn't fail once tokens are burnt. Panics if the storage balance doesn't cover it.
+    fn internal_reserve_storage(&mut self, account_id: &AccountId, bytes: StorageUsage) {
+        let usage = self.storage_usages.get(account_id).unwrap_or(0) + bytes;
+        self.storage_usages.insert(account_id, &usage);
+        let account = self.accounts.get(account_id).unwrap_or_else(|| {
+            env::panic(format!("The account {} is not registered", account_id).as_bytes())
+        });
+        self.assert_storage_balance(account_id, &account);
+    }
+
+    /// Releases `bytes` reserved with `internal_reserve_storage`, before the write they're for.
+    fn internal_release_storage(&mut self, account_id: &AccountId, bytes: StorageUsage) {
+        if let Some(usage) = self.storage_usages.get(account_id) {
+            self.storage_usages
+                .insert(account_id, &usage.saturating_sub(bytes));
+        }
+    }
+
+    fn internal_storage_deposit_of(&self, account_id: &AccountId) -> Balance {
+        self.storage_deposits.get(account_id).unwrap_or(0)
+    }
+
+    fn assert_registered(&self, account_id: &AccountId) {
+        if self.accounts.get(account_id).is_none() {
+            env::panic(format!("The account {} is not registered", account_id).as_bytes());
+        }
+    }
+
+    /// Registers `account_id` with the minimum storage balance paid from `deposit`, unless it's
+    /// registered already. Returns what's left of `deposit`.
+    fn internal_register_receiver(&mut self, account_id: &AccountId, deposit: Balance) -> Balance {
+        if self.accounts.get(account_id).is_some() {
+            return deposit;
+        }
+        let min_balance = self.storage_balance_bounds().min.0;
+        if deposit < min_balance {
+            env::panic(format!("The account {} is not registered", account_id).as_bytes());
+        }
+        self.internal_register_account(account_id);
+        self.storage_deposits.insert(account_id, &min_balance);
+        deposit - min_balance
+    }
+
+    /// Records `balance` as the total balance of `account_id` from the current block on, one
+    /// checkpoint per block. The storage of the checkpoints is charged to `account_id`.
+    fn internal_checkpoint_balance(&mut self, account_id: &AccountId, balance: Balance) {
+        let initial_storage = env::storage_usage();
+        self.internal_write_balance_checkpoint(account_id, balance);
+        self.internal_charge_storage(account_id, initial_storage);
+    }
+
+    /// Writes the checkpoint of `internal_checkpoint_balance`.
+    fn internal_write_balance_checkpoint(&mut self, account_id: &AccountId, balance: Balance) {
+        let block_height = env::block_index();
+        let count = self.balance_checkpoint_counts.get(account_id).unwrap_or(0);
+        if count == 0 {
+            if balance == 0 {
+                return;
+            }
+        } else {
+            let key = (account_id.clone(), count - 1);
+            let last = self.balance_checkpoints.get(&key).unwrap();
+            if last.balance == balance {
+                return;
+            }
+            if last.block_height == block_height {
+                let checkpoint = Checkpoint {
+                    block_height,
+                    balance,
+                };
+                self.balance_checkpoints.insert(&key, &checkpoint);
+                return;
+            }
+        }
+        let checkpoint = Checkpoint {
+            block_height,
+            balance,
+        };
+        self.balance_checkpoints
+            .insert(&(account_id.clone(), count), &checkpoint);
+        self.balance_checkpoint_counts
+            .insert(account_id, &(count + 1));
+    }
+
+    /// Records the current total supply from the current block on.
+    fn internal_checkpoint_total_supply(&mut self) {
+        let checkpoint = Checkpoint {
+            block_height: env::block_index(),
+            balance: self.total_supply,
+        };
+        let count = self.supply_checkpoints.len();
+        if let Some(last) = count
+            .checked_sub(1)
+            .and_then(|i| self.supply_checkpoints.get(i))
+        {
+            if last.balance == checkpoint.balance {
+                return;
+            }
+            if last.block_height == checkpoint.block_height {
+                self.supply_checkpoints.replace(count - 1, &checkpoint);
+                return;
+            }
+        }
+        self.supply_checkpoints.push(&checkpoint);
+    }
+
+    /// Pays the staking reward of `account_id` for the whole days since it was last paid.
+    fn internal_refresh_reward(&mut self, account_id: &AccountId) -> bool {
+        let mut account = self.get_account(account_id);
+        let staked = account.get_staked_balance();
+
+        if staked == 0 {
+            env::log(b"no token was staked");
+            return false;
+        }
+        let mut reward_paid_at = self
+            .reward_paid_at
+            .get(account_id)
+            .unwrap_or(self.staking_reward_enabled_at);
+        let now = env::block_timestamp();
+        let days = (now - reward_paid_at) / (24 * 60 * 60 * 1000000000);
+        if days == 0 {
+            env::log(b"not been a day since last reward_paid_at");
+            return false;
+        }
+        reward_paid_at += days * (24 * 60 * 60 * 1000000000);
+        let new_staked = compound_stake(staked, days);
+        account.staked_balance = new_staked;
+        self.total_supply += new_staked - staked;
+        self.internal_checkpoint_total_supply();
+        self.total_staked += new_staked - staked;
+        self.internal_save_account(account_id, &account);
+        self.reward_paid_at.insert(account_id, &reward_paid_at);
+        event::ft_mint(account_id, new_staked - staked, Some("staking reward"));
+        event::reward(account_id, new_staked - staked);
+        true
+    }
+
+    /// Same as `internal_refresh_reward` for the liquid staking pool, the reward is added to the
+    /// pool so it's shared by all stART.
+    fn internal_refresh_liquid_reward(&mut self) -> bool {
+        let now = env::block_timestamp();
+        let staked = self.liquid_staking.staked;
+        if staked == 0 {
+            // Nothing earned the reward until now
+            self.liquid_staking.reward_paid_at = now;
+            return false;
+        }
+        let days = (now - self.liquid_staking.reward_paid_at) / (24 * 60 * 60 * 1000000000);
+        if days == 0 {
+            return false;
+        }
+        self.liquid_staking.reward_paid_at += days * (24 * 60 * 60 * 1000000000);
+        let reward = compound_stake(staked, days) - staked;
+        self.liquid_staking.staked += reward;
+        self.total_supply += reward;
+        self.internal_checkpoint_total_supply();
+        self.total_staked += reward;
+        let pool_id = env::current_account_id();
+        event::ft_mint(&pool_id, reward, Some("liquid staking reward"));
+        event::reward(&pool_id, reward);
+        true
+    }
+
+    fn assert_start_token(&self) -> AccountId {
+        self.liquid_staking
+            .start_token
+            .clone()
+            .unwrap_or_else(|| env::panic(b"Liquid staking is not enabled"))
+    }
+
+    /// Moves `amount` of the stake of `account_id` to the liquid staking pool and mints stART for
+    /// it. The stake is returned by `on_start_minted` if the mint fails.
+    fn internal_wrap_stake(&mut self, account_id: &AccountId, amount: Balance) -> Promise {
+        if amount == 0 {
+            env::panic(b"Can't wrap 0 tokens");
+        }
+        let start_token = self.assert_start_token();
+        self.internal_refresh_liquid_reward();
+        let shares = self.liquid_staking.shares_for(amount);
+        if shares == 0 {
+            env::panic(b"The stake is too small to mint stART");
+        }
+        let mut account = self.get_account(account_id);
+        let staked_balance = account.get_staked_balance();
+        if staked_balance < amount {
+            env::panic(b"Not enough staked tokens");
+        }
+        account.set_staked_balance(staked_balance - amount);
+        self.internal_save_account(account_id, &account);
+        self.liquid_staking.staked += amount;
+        self.liquid_staking.shares += shares;
+
+        ext_start::mint(
+            account_id.clone(),
+            shares.into(),
+            &start_token,
+            0,
+            env::prepaid_gas() / 4,
+        )
+        .then(ext_art::on_start_minted(
+            account_id.clone(),
+            amount.into(),
+            shares.into(),
+            &env::current_account_id(),
+            0,
+            env::prepaid_gas() / 4,
+        ))
+    }
+
+    /// Panics if the price of an index asset can't be computed.
+    fn _get_asset_price(&self, asset: &String) -> u128 {
+        self.internal_asset_price(asset)
+            .unwrap_or_else(|err| env::panic(err.as_bytes()))
+    }
+
+    /// Returns the price of `asset`, or 0 if it has none yet. Fails if it's an index with a
+    /// missing or stale component price.
+    fn internal_asset_price(&self, asset: &String) -> Result<u128, String> {
+        if let Some(final_price) = self.delisted_assets.get(asset) {
+            return Ok(final_price);
+        }
+        if let Some(components) = self.index_assets.get(asset) {
+            return self.internal_index_price(&components);
+        }
+        Ok(self.asset_prices.get(asset).unwrap_or_default())
+    }
+
+    /// Computes an index price from its component prices. Fails if any of them is missing or
+    /// older than `max_index_price_age`.
+    fn internal_index_price(&self, components: &[IndexComponent]) -> Result<u128, String> {
+        let now = env::block_timestamp();
+        let mut value = BigInt::from(0);
+        for component in components {
+            let price = self.asset_prices.get(&component.asset).unwrap_or(0);
+            if price == 0 {
+                return Err(format!("No price data for {}", component.asset));
+            }
+            let updated_at = self
+                .asset_prices_updated_at
+                .get(&component.asset)
+                .unwrap_or(0);
+            if now.saturating_sub(updated_at) > self.max_index_price_age {
+                return Err(format!("Price of {} is stale", component.asset));
+            }
+            value += BigInt::from(component.weight.0) * BigInt::from(price);
+        }
+        Ok((value / BigInt::from(INDEX_WEIGHT_DENOMINATOR))
+            .to_u128()
+            .unwrap())
+    }
+
+    fn _get_asset_balance(&self, account_id: &AccountId, asset: &String) -> Balance {
+        *self
+            .get_account(&account_id)
+            .assets
+            .get(asset)
+            .unwrap_or(&0)
+    }
+
+    fn assert_art_price(&self) -> u128 {
+        if self.price == 0 {
+            // Not received any data from oracle
+            env::panic(b"No price data from oracle");
+        }
+        self.price
+    }
+
+    fn assert_near_price(&self) -> u128 {
+        let near_price = self._get_asset_price(&"aNEAR".to_string());
+        if near_price == 0 {
+            env::panic(b"No NEAR price data from oracle");
+        }
+        near_price
+    }
+
+    fn internal_fee_bps(&self, path: ConversionPath) -> u32 {
+        self.conversion_fee_bps
+            .get(&path)
+            .unwrap_or_else(|| path.default_fee_bps())
+    }
+
+    /// Share of the converted amount that's left after the fee of `path`.
+    fn internal_fee_rate(&self, path: ConversionPath) -> Ratio<BigInt> {
+        let fee_bps = self.internal_fee_bps(path);
+        Ratio::<BigInt>::new((10_000 - fee_bps).into(), 10_000.into())
+    }
+
+    fn internal_record_fee(&mut self, path: ConversionPath, fee: Balance) {
+        let collected = self.collected_fees.get(&path).unwrap_or(0) + fee;
+        self.collected_fees.insert(&path, &collected);
+        log!(
+            "Collected {} {} fee on {} for {}",
+            fee,
+            path.fee_token(),
+            path.as_str(),
+            self.treasury
+        );
+    }
+
+    /// Credits the art `fee` of `path` to the treasury.
+    fn internal_pay_art_fee(&mut self, path: ConversionPath, fee: Balance) {
+        if fee == 0 {
+            return;
+        }
+        let mut treasury = self.get_account(&self.treasury);
+        treasury.balance = treasury.balance.checked_add(fee).unwrap();
+        self.internal_save_account(&self.treasury.clone(), &treasury);
+        self.internal_record_fee(path, fee);
+    }
+
+    /// Sends the NEAR `fee` of `path` to the treasury, or keeps it in the reserve if the reserve
+    /// no longer covers it. `on_fee_paid` records it once sent.
+    fn internal_pay_near_fee(&mut self, path: ConversionPath, fee: Balance) {
+        if fee == 0 {
+            return;
+        }
+        if self.available_near_reserve() < fee {
+            log!("Not enough NEAR in reserve to pay {} NEAR fee", fee);
+            return;
+        }
+        Promise::new(self.treasury.clone())
+            .transfer(fee)
+            .then(ext_art::on_fee_paid(
+                path,
+                U128(fee),
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_FEE_CALLBACK,
+            ));
+    }
+
+    /// Pays the aUSD `fee` of `path` to the treasury, minted if the aUSD of the conversion was
+    /// minted, otherwise moved from the aUSD owner. `on_fee_paid` records it once paid.
+    fn internal_pay_ausd_fee(&mut self, path: ConversionPath, fee: Balance, mint: bool) {
+        if fee == 0 {
+            return;
+        }
+        let payment = if mint {
+            ext_usd::mint(
+                self.treasury.clone(),
+                fee,
+                &self.ausd_token,
+                0,
+                env::prepaid_gas() / 4,
+            )
+        } else {
+            ext_usd::buy_ausd(
+                self.treasury.clone(),
+                U128(fee),
+                &self.ausd_token,
+                0,
+                env::prepaid_gas() / 4,
+            )
+        };
+        payment.then(ext_art::on_fee_paid(
+            path,
+            U128(fee),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_FEE_CALLBACK,
+        ));
+    }
+
+    /// Pays the `fee` of `path` to the treasury in the token received on it.
+    fn internal_pay_fee(&mut self, path: ConversionPath, fee: Balance, uses_art_reserve: bool) {
+        match path {
+            ConversionPath::AusdToArt | ConversionPath::NearToArt => {
+                self.internal_pay_art_fee(path, fee)
+            }
+            ConversionPath::ArtToNear | ConversionPath::AusdToNear => {
+                self.internal_pay_near_fee(path, fee)
+            }
+            ConversionPath::ArtToAusd => self.internal_pay_ausd_fee(path, fee, uses_art_reserve),
+            ConversionPath::NearToAusd => self.internal_pay_ausd_fee(path, fee, false),
+        }
+    }
+
+    /// Adds `amount` to the volumes of `account_id` and all accounts on `path` in the current
+    /// window. Panics if either goes over its limit.
+    fn internal_use_conversion_allowance(
+        &mut self,
+        path: ConversionPath,
+        account_id: &AccountId,
+        amount: Balance,
+    ) {
+        let rate_limit = match self.rate_limits.get(&path) {
+            Some(rate_limit) => rate_limit,
+            None => return,
+        };
+        let window_start = env::block_timestamp() - env::block_timestamp() % rate_limit.window;
+        let key = (account_id.clone(), path);
+
+        let mut account_volume = current_volume(self.account_volumes.get(&key), window_start);
+        if let Some(limit) = rate_limit.account_limit {
+            if account_volume.volume + amount > limit {
+                env::panic(
+                    format!(
+                        "Volume limit of {} per account is reached, {} left in this window",
+                        path.as_str(),
+                        limit.saturating_sub(account_volume.volume)
+                    )
+                    .as_bytes(),
+                );
+            }
+        }
+        let mut global_volume = current_volume(self.global_volumes.get(&path), window_start);
+        if let Some(limit) = rate_limit.global_limit {
+            if global_volume.volume + amount > limit {
+                env::panic(
+                    format!(
+                        "Volume limit of {} for all accounts is reached, {} left in this window",
+                        path.as_str(),
+                        limit.saturating_sub(global_volume.volume)
+                    )
+                    .as_bytes(),
+                );
+            }
+        }
+        account_volume.volume += amount;
+        global_volume.volume += amount;
+        let initial_storage = env::storage_usage();
+        self.account_volumes.insert(&key, &account_volume);
+        self.internal_track_storage(account_id, initial_storage);
+        self.global_volumes.insert(&path, &global_volume);
+    }
+
+    /// Takes `amount` of a conversion that was reverted back off the volumes
+    /// `internal_use_conversion_allowance` added it to, unless their window has ended since.
+    fn internal_refund_conversion_allowance(
+        &mut self,
+        path: ConversionPath,
+        account_id: &AccountId,
+        amount: Balance,
+    ) {
+        let rate_limit = match self.rate_limits.get(&path) {
+            Some(rate_limit) => rate_limit,
+            None => return,
+        };
+        let window_start = env::block_timestamp() - env::block_timestamp() % rate_limit.window;
+        let key = (account_id.clone(), path);
+        if let Some(mut account_volume) = self.account_volumes.get(&key) {
+            if account_volume.window_start == window_start {
+                account_volume.volume = account_volume.volume.saturating_sub(amount);
+                self.account_volumes.insert(&key, &account_volume);
+            }
+        }
+        if let Some(mut global_volume) = self.global_volumes.get(&path) {
+            if global_volume.window_start == window_start {
+                global_volume.volume = global_volume.volume.saturating_sub(amount);
+                self.global_volumes.insert(&path, &global_volume);
+            }
+        }
+    }
+
+    fn assert_owner_or_governance(&self) {
+        let predecessor = env::predecessor_account_id();
+        if predecessor != self.owner && Some(predecessor) != self.governance {
+            env::panic(b"Only owner or governance can call this method");
+        }
+    }
+
+    fn internal_quote_stake_and_mint(&self, stake_amount: Balance) -> Quote {
+        let price = self.assert_art_price();
+        let unit_price = Ratio::<BigInt>::new(price.into(), 100_000_000.into());
+        let mint_amount = Ratio::<BigInt>::new(stake_amount.into(), 5.into()) * unit_price;
+        let mint_amount = mint_amount.to_integer().to_u128().unwrap();
+        Quote::new(stake_amount, mint_amount, 0, price)
+    }
+
+    fn internal_quote_burn_to_unstake(&self, unstake_amount: Balance) -> Quote {
+        let price = self.assert_art_price();
+        let unit_price = Ratio::<BigInt>::new(price.into(), 100_000_000.into());
+        let burn_amount = Ratio::<BigInt>::new(unstake_amount.into(), 5.into()) * unit_price;
+        let burn_amount = burn_amount.to_integer().to_u128().unwrap();
+        Quote::new(burn_amount, unstake_amount, 0, price)
+    }
+
+    fn internal_quote_art_to_ausd(&self, amount: Balance) -> Quote {
+        let price = self.assert_art_price();
+        let unit_price = Ratio::<BigInt>::new(price.into(), 100_000_000.into());
+        let amount_b: BigInt = amount.into();
+        let gross = unit_price * amount_b;
+        let ausd_amount = &gross * self.internal_fee_rate(ConversionPath::ArtToAusd);
+        let gross = gross.to_integer().to_u128().unwrap();
+        let ausd_amount = ausd_amount.to_integer().to_u128().unwrap();
+        Quote::new(amount, ausd_amount, gross - ausd_amount, price)
+    }
+
+    fn internal_quote_ausd_to_art(&self, ausd_amount: Balance) -> Quote {
+        let price = self.assert_art_price();
+        let unit_price = Ratio::<BigInt>::new(price.into(), 100_000_000.into());
+        let ausd_amount_b: BigInt = ausd_amount.into();
+        let gross = Ratio::from_integer(ausd_amount_b.clone()) / &unit_price;
+        let amount = self.internal_fee_rate(ConversionPath::AusdToArt) * ausd_amount_b / unit_price;
+        let gross = gross.to_integer().to_u128().unwrap();
+        let amount = amount.to_integer().to_u128().unwrap();
+        Quote::new(ausd_amount, amount, gross - amount, price)
+    }
+
+    fn internal_quote_near_to_art(&self, near_amount: Balance) -> Quote {
+        let near_price = self.assert_near_price();
+        let art_price = self.assert_art_price();
+        let near_amount_b: BigInt = near_amount.into();
+        let gross = Ratio::<BigInt>::new(near_price.into(), art_price.into()) * near_amount_b;
+        let art_amount = &gross * self.internal_fee_rate(ConversionPath::NearToArt);
+        let gross = gross.to_integer().to_u128().unwrap();
+        let art_amount = art_amount.to_integer().to_u128().unwrap();
+        Quote::new(
+            near_amount,
+            art_amount,
+            gross - art_amount,
+            mul_div(art_price, 100_000_000, near_price),
+        )
+    }
+
+    fn internal_quote_near_to_ausd(&self, near_amount: Balance) -> Quote {
+        let near_price = self.assert_near_price();
+        let near_amount_b: BigInt = near_amount.into();
+        let gross = Ratio::<BigInt>::new(near_price.into(), 100000000.into()) * near_amount_b;
+        let ausd_amount = &gross * self.internal_fee_rate(ConversionPath::NearToAusd);
+        let gross = gross.to_integer().to_u128().unwrap();
+        let ausd_amount = ausd_amount.to_integer().to_u128().unwrap();
+        Quote::new(near_amount, ausd_amount, gross - ausd_amount, near_price)
+    }
+
+    fn internal_quote_art_to_near(&self, amount: Balance) -> Quote {
+        let near_price = self.assert_near_price();
+        let art_price = self.assert_art_price();
+        let amount_b: BigInt = amount.into();
+        let gross = Ratio::<BigInt>::new(art_price.into(), near_price.into()) * amount_b;
+        let near_amount = &gross * self.internal_fee_rate(ConversionPath::ArtToNear);
+        let gross = gross.to_integer().to_u128().unwrap();
+        let near_amount = near_amount.to_integer().to_u128().unwrap();
+        Quote::new(
+            amount,
+            near_amount,
+            gross - near_amount,
+            mul_div(art_price, 100_000_000, near_price),
+        )
+    }
+
+    fn internal_quote_ausd_to_near(&self, ausd_amount: Balance) -> Quote {
+        let near_price = self.assert_near_price();
+        let ausd_amount_b: BigInt = ausd_amount.into();
+        let gross = Ratio::<BigInt>::new(100000000.into(), near_price.into()) * ausd_amount_b;
+        let near_amount = &gross * self.internal_fee_rate(ConversionPath::AusdToNear);
+        let gross = gross.to_integer().to_u128().unwrap();
+        let near_amount = near_amount.to_integer().to_u128().unwrap();
+        Quote::new(ausd_amount, near_amount, gross - near_amount, near_price)
+    }
+
+    fn internal_quote_asset_trade(
+        &self,
+        asset: &String,
+        side: OrderSide,
+        asset_amount: Balance,
+    ) -> Quote {
+        self.assert_accounts_migrated();
+        if side == OrderSide::Buy && self.delisted_assets.get(asset).is_some() {
+            env::panic(b"Can't buy a delisted asset");
+        }
+        let asset_price = self._get_asset_price(asset);
+        if asset_price == 0 {
+            env::panic(b"No price data from oracle");
+        }
+        let ausd_amount = value_in_ausd(asset_price, asset_amount);
+        match side {
+            OrderSide::Buy => Quote::new(ausd_amount, asset_amount, 0, asset_price),
+            OrderSide::Sell => Quote::new(asset_amount, ausd_amount, 0, asset_price),
+        }
+    }
+
+    fn internal_quote_ausd_to_asset(&self, asset: &String, ausd_amount: Balance) -> Quote {
+        if self.delisted_assets.get(asset).is_some() {
+            env::panic(b"Can't buy a delisted asset");
+        }
+        let asset_price = self._get_asset_price(asset);
+        if asset_price == 0 {
+            env::panic(b"No price data from oracle");
+        }
+        let asset_amount = mul_div(ausd_amount, 100_000_000, asset_price);
+        Quote::new(ausd_amount, asset_amount, 0, asset_price)
+    }
+
+    /// Returns the result of converting `amount_in` of `from` to `to`, and the conversion path
+    /// whose fee is taken.
+    fn internal_quote_swap_leg(
+        &self,
+        from: &SwapToken,
+        to: &SwapToken,
+        amount_in: Balance,
+    ) -> (Quote, Option<ConversionPath>) {
+        match (from, to) {
+            (SwapToken::Near, SwapToken::Art) => (
+                self.internal_quote_near_to_art(amount_in),
+                Some(ConversionPath::NearToArt),
+            ),
+            (SwapToken::Near, SwapToken::Ausd) => (
+                self.internal_quote_near_to_ausd(amount_in),
+                Some(ConversionPath::NearToAusd),
+            ),
+            (SwapToken::Art, SwapToken::Ausd) => (
+                self.internal_quote_art_to_ausd(amount_in),
+                Some(ConversionPath::ArtToAusd),
+            ),
+            (SwapToken::Ausd, SwapToken::Art) => (
+                self.internal_quote_ausd_to_art(amount_in),
+                Some(ConversionPath::AusdToArt),
+            ),
+            (SwapToken::Art, SwapToken::Near) => (
+                self.internal_quote_art_to_near(amount_in),
+                Some(ConversionPath::ArtToNear),
+            ),
+            (SwapToken::Ausd, SwapToken::Near) => (
+                self.internal_quote_ausd_to_near(amount_in),
+                Some(ConversionPath::AusdToNear),
+            ),
+            (SwapToken::Ausd, SwapToken::Asset(asset)) => {
+                (self.internal_quote_ausd_to_asset(asset, amount_in), None)
+            }
+            (SwapToken::Asset(asset), SwapToken::Ausd) => (
+                self.internal_quote_asset_trade(asset, OrderSide::Sell, amount_in),
+                None,
+            ),
+            _ => env::panic(b"Unsupported swap leg"),
+        }
+    }
+
+    fn internal_quote_swap(&self, tokens: &[SwapToken], amount_in: Balance) -> SwapQuote {
+        let mut amount = amount_in;
+        let mut fees = vec![];
+        let mut asset_price = 0;
+        for leg in tokens.windows(2) {
+            let (quote, path) = self.internal_quote_swap_leg(&leg[0], &leg[1], amount);
+            if let Some(path) = path {
+                fees.push(SwapFee {
+                    path,
+                    amount_in: quote.amount_in,
+                    amount_out: quote.amount_out,
+                    amount: quote.fee,
+                });
+            }
+            if let SwapToken::Asset(_) = leg[1] {
+                asset_price = quote.price.0;
+            }
+            amount = quote.amount_out.0;
+        }
+        SwapQuote {
+            amount_in: amount_in.into(),
+            amount_out: amount.into(),
+            fees,
+            asset_price: asset_price.into(),
+        }
+    }
+
+    fn internal_credit_art(&mut self, account_id: &AccountId, amount: Balance) {
+        let mut account = self.get_account(account_id);
+        account.balance = account.balance.checked_add(amount).unwrap();
+        self.internal_save_account(account_id, &account);
+    }
+
+    fn internal_debit_art(&mut self, account_id: &AccountId, amount: Balance) {
+        let mut account = self.get_account(account_id);
+        account.balance = account.balance.checked_sub(amount).unwrap();
+        self.internal_save_account(account_id, &account);
+    }
+
+    /// Checks the output of `route` can be paid and sets aside the art paying it, out of owner's
+    /// balance or the art reserve, or reserves the asset bought against its caps and the storage
+    /// of its purchase.
+    fn internal_swap_reserve_output(&mut self, route: &SwapRoute, tokens: &[SwapToken]) {
+        let amount = route.amount_out.0;
+        match &tokens[tokens.len() - 1] {
+            SwapToken::Art => {
+                let gross = amount + swap_output_fee(route);
+                if tokens[tokens.len() - 2] == SwapToken::Ausd && route.uses_art_reserve {
+                    if gross > self.available_art_reserve() {
+                        env::panic(b"Not enough art in the reserve");
+                    }
+                    self.art_reserve -= gross;
+                } else {
+                    let owner = self.owner.clone();
+                    self.internal_debit_art(&owner, gross);
+                }
+            }
+            SwapToken::Asset(asset) => {
+                let ausd_amount = value_in_ausd(route.asset_price.0, amount);
+                self.internal_reserve_asset_supply(asset, amount, ausd_amount);
+                let bytes = purchase_storage_usage(&route.account_id, asset);
+                self.internal_reserve_storage(&route.account_id, bytes);
+            }
+            SwapToken::Near => self.assert_near_reserve(amount + swap_output_fee(route)),
+            SwapToken::Ausd => {}
+        }
+    }
+
+    /// Returns the art set aside by `internal_swap_reserve_output`, or releases the asset and
+    /// storage it reserved.
+    fn internal_swap_release_output(&mut self, route: &SwapRoute, tokens: &[SwapToken]) {
+        match &tokens[tokens.len() - 1] {
+            SwapToken::Art => {
+                let gross = route.amount_out.0 + swap_output_fee(route);
+                if tokens[tokens.len() - 2] == SwapToken::Ausd && route.uses_art_reserve {
+                    self.art_reserve += gross;
+                } else {
+                    let owner = self.owner.clone();
+                    self.internal_credit_art(&owner, gross);
+                }
+            }
+            SwapToken::Asset(asset) => {
+                let ausd_amount = value_in_ausd(route.asset_price.0, route.amount_out.0);
+                self.internal_release_asset_supply(asset, route.amount_out.0, ausd_amount);
+                let bytes = purchase_storage_usage(&route.account_id, asset);
+                self.internal_release_storage(&route.account_id, bytes);
+            }
+            SwapToken::Near | SwapToken::Ausd => {}
+        }
+    }
+
+    /// Takes the input of `route` from the account. aUSD is taken by the returned call to the
+    /// aUSD contract, NEAR is the attached deposit.
+    fn internal_swap_take_input(
+        &mut self,
+        route: &SwapRoute,
+        tokens: &[SwapToken],
+    ) -> Option<Promise> {
+        let account_id = &route.account_id;
+        let amount = route.amount_in.0;
+        match &tokens[0] {
+            SwapToken::Near => None,
+            SwapToken::Art => {
+                self.internal_debit_art(account_id, amount);
+                if tokens[1] == SwapToken::Ausd && route.uses_art_reserve {
+                    let art_reserve = self.art_reserve.checked_add(amount).unwrap();
+                    if let Some(max_art_reserve) = self.max_art_reserve {
+                        if art_reserve > max_art_reserve {
+                            env::panic(b"Swap would take the art reserve above its maximum");
+                        }
+                    }
+                    self.art_reserve = art_reserve;
+                } else {
+                    let owner = self.owner.clone();
+                    self.internal_credit_art(&owner, amount);
+                    event::ft_transfer(account_id, &owner, amount, None);
+                }
+                None
+            }
+            SwapToken::Asset(asset) => {
+                self.assert_no_pending_trades(account_id, asset);
+                let balance = self._get_asset_balance(account_id, asset);
+                let mut account = self.get_account(account_id);
+                account
+                    .assets
+                    .insert(asset.clone(), balance.checked_sub(amount).unwrap());
+                self.internal_save_account(account_id, &account);
+                self.internal_sub_asset_supply(asset, amount);
+                let asset_price = self._get_asset_price(asset);
+                event::asset_trade(
+                    account_id,
+                    asset,
+                    OrderSide::Sell,
+                    amount,
+                    value_in_ausd(asset_price, amount),
+                    asset_price,
+                );
+                None
+            }
+            SwapToken::Ausd => Some(if swap_burns_ausd(&tokens[1], route.uses_art_reserve) {
+                ext_usd::burn(
+                    account_id.clone(),
+                    amount,
+                    &self.ausd_token,
+                    0,
+                    env::prepaid_gas() / 4,
+                )
+            } else {
+                ext_usd::sell_ausd(
+                    account_id.clone(),
+                    U128(amount),
+                    &self.ausd_token,
+                    0,
+                    env::prepaid_gas() / 4,
+                )
+            }),
+        }
+    }
+
+    /// Takes the legs of a reverted swap back off the conversion volumes.
+    fn internal_swap_refund_allowances(&mut self, route: &SwapRoute) {
+        for fee in route.fees.iter() {
+            self.internal_refund_conversion_allowance(fee.path, &route.account_id, fee.amount_in.0);
+        }
+    }
+
+    /// Gives back the input taken by `internal_swap_take_input`.
+    fn internal_swap_refund_input(&mut self, route: &SwapRoute, tokens: &[SwapToken]) {
+        self.internal_swap_refund_allowances(route);
+        let account_id = &route.account_id;
+        let amount = route.amount_in.0;
+        log!(
+            "Swap failed, refunding {} {} to {}",
+            amount,
+            route.path[0],
+            account_id
+        );
+        match &tokens[0] {
+            SwapToken::Near => {
+                Promise::new(account_id.clone()).transfer(amount);
+            }
+            SwapToken::Art => {
+                if tokens[1] == SwapToken::Ausd && route.uses_art_reserve {
+                    self.art_reserve -= amount;
+                } else {
+                    let owner = self.owner.clone();
+                    self.internal_debit_art(&owner, amount);
+                    event::ft_transfer(&owner, account_id, amount, Some("refund"));
+                }
+                self.internal_credit_art(account_id, amount);
+            }
+            SwapToken::Asset(asset) => self.internal_add_asset(account_id, asset, amount),
+            SwapToken::Ausd => {
+                if swap_burns_ausd(&tokens[1], route.uses_art_reserve) {
+                    ext_usd::mint(
+                        account_id.clone(),
+                        amount,
+                        &self.ausd_token,
+                        0,
+                        env::prepaid_gas() / 4,
+                    );
+                } else {
+                    ext_usd::buy_ausd(
+                        account_id.clone(),
+                        U128(amount),
+                        &self.ausd_token,
+                        0,
+                        env::prepaid_gas() / 4,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Gives the output of `route` to the account, then finishes the swap. NEAR and aUSD are
+    /// sent by a promise that finishes it in `on_swap_output_sent`.
+    fn internal_swap_give_output(
+        &mut self,
+        route: SwapRoute,
+        tokens: &[SwapToken],
+    ) -> PromiseOrValue<U128> {
+        let account_id = route.account_id.clone();
+        let amount = route.amount_out.0;
+        let promise = match &tokens[tokens.len() - 1] {
+            SwapToken::Art => {
+                self.internal_credit_art(&account_id, amount);
+                if tokens[tokens.len() - 2] != SwapToken::Ausd || !route.uses_art_reserve {
+                    event::ft_transfer(&self.owner, &account_id, amount, None);
+                }
+                return PromiseOrValue::Value(self.internal_swap_finish(&route, tokens));
+            }
+            SwapToken::Asset(asset) => {
+                self.internal_swap_release_output(&route, tokens);
+                self.internal_purchase_asset(
+                    &account_id,
+                    asset,
+                    amount,
+                    route.asset_price.0,
+                    route.round.0,
+                );
+                return PromiseOrValue::Value(self.internal_swap_finish(&route, tokens));
+            }
+            SwapToken::Near => Promise::new(account_id).transfer(amount),
+            SwapToken::Ausd => {
+                if swap_mints_ausd(&tokens[tokens.len() - 2], route.uses_art_reserve) {
+                    ext_usd::mint(
+                        account_id,
+                        amount,
+                        &self.ausd_token,
+                        0,
+                        env::prepaid_gas() / 4,
+                    )
+                } else {
+                    ext_usd::buy_ausd(
+                        account_id,
+                        U128(amount),
+                        &self.ausd_token,
+                        0,
+                        env::prepaid_gas() / 4,
+                    )
+                }
+            }
+        };
+        PromiseOrValue::Promise(promise.then(ext_art::on_swap_output_sent(
+            route,
+            &env::current_account_id(),
+            0,
+            env::prepaid_gas() / 4,
+        )))
+    }
+
+    /// Logs the legs of a completed swap and pays their fees to the treasury.
+    fn internal_swap_finish(&mut self, route: &SwapRoute, tokens: &[SwapToken]) -> U128 {
+        for fee in route.fees.iter() {
+            event::exchange(
+                &route.account_id,
+                fee.path,
+                fee.amount_in.0,
+                fee.amount_out.0,
+                fee.amount.0,
+            );
+            self.internal_pay_fee(fee.path, fee.amount.0, route.uses_art_reserve);
+        }
+        if let SwapToken::Asset(asset) = &tokens[0] {
+            self.clear_delisted_asset_if_settled(asset);
+        }
+        event::swap(
+            &route.account_id,
+            &route.path,
+            route.amount_in.0,
+            route.amount_out.0,
+        );
+        route.amount_out
+    }
+
+    /// NEAR held by the contract that's not needed to cover its storage or owned by the pool.
+    fn available_near_reserve(&self) -> Balance {
+        let storage_cost = Balance::from(env::storage_usage()) * env::storage_byte_cost();
+        env::account_balance()
+            .saturating_sub(storage_cost)
+            .saturating_sub(self.pool.near_reserve)
+    }
+
+    /// Art in the reserve above `min_art_reserve`.
+    fn available_art_reserve(&self) -> Balance {
+        self.art_reserve.saturating_sub(self.min_art_reserve)
+    }
+
+    fn assert_near_reserve(&self, near_amount: Balance) {
+        if self.available_near_reserve() < near_amount {
+            env::panic(b"Not enough NEAR in reserve");
+        }
+    }
+
+    fn internal_send_pool_near(
+        &mut self,
+        account_id: AccountId,
+        art_amount: Balance,
+        near_amount: Balance,
+        shares: Balance,
+    ) -> Promise {
+        Promise::new(account_id.clone())
+            .transfer(near_amount)
+            .then(ext_art::on_pool_near_sent(
+                account_id,
+                art_amount.into(),
+                near_amount.into(),
+                shares.into(),
+                &env::current_account_id(),
+                0,
+                GAS_FOR_NEAR_CALLBACK,
+            ))
+    }
+
+    fn internal_add_asset(&mut self, account_id: &AccountId, asset: &String, amount: Balance) {
+        let mut account = self.get_account(account_id);
+        let balance = self._get_asset_balance(account_id, asset);
+        let new_balance = balance.checked_add(amount).unwrap();
+        account.assets.insert(asset.clone(), new_balance);
+        self.internal_save_account(account_id, &account);
+        let supply = self.asset_supplies.get(asset).unwrap_or(0);
+        self.asset_supplies
+            .insert(asset, &supply.checked_add(amount).unwrap());
+        self.internal_update_asset_exposure(asset);
+    }
+
+    /// Moves `unstake_amount` of the stake of `account_id` to its unstaked balance, once the aUSD
+    /// for it is burnt.
+    fn internal_unstake(&mut self, account_id: &AccountId, unstake_amount: u128) {
+        if unstake_amount == 0 {
+            env::panic(b"Can't unstake 0 tokens");
+        }
+        self.refresh_reward();
+
+        let mut account = self.get_account(account_id);
+
+        // Checking and updating staked balance
+        let staked_balance = account.get_staked_balance();
+        if staked_balance < unstake_amount {
+            env::panic(b"Not enough staked tokens");
+        }
+        account.set_staked_balance(staked_balance - unstake_amount);
+
+        // Updating unstaked balance
+        account.balance += unstake_amount;
+        self.total_staked -= unstake_amount;
+        self.internal_save_account(account_id, &account);
+        event::unstake(account_id, unstake_amount);
+    }
+
+    /// Credits a purchased asset and, if delayed settlement is enabled, records the trade to be
+    /// re-settled at the next oracle price. The storage of the trade is charged to `account_id`.
+    fn internal_purchase_asset(
+        &mut self,
+        account_id: &AccountId,
+        asset: &String,
+        asset_amount: Balance,
+        asset_price: u128,
+        round: u64,
+    ) {
+        self.internal_add_asset(account_id, asset, asset_amount);
+        event::asset_trade(
+            account_id,
+            asset,
+            OrderSide::Buy,
+            asset_amount,
+            value_in_ausd(asset_price, asset_amount),
+            asset_price,
+        );
+        if self.settlement_waiting_period > 0 {
+            let initial_storage = env::storage_usage();
+            let key = (account_id.clone(), asset.clone());
+            let mut trades = self.pending_trades.get(&key).unwrap_or_default();
+            trades.push(PendingTrade {
+                asset_amount: asset_amount.into(),
+                asset_price: asset_price.into(),
+                round: round.into(),
+                created_at: env::block_timestamp().into(),
+            });
+            self.pending_trades.insert(&key, &trades);
+            self.internal_track_storage(account_id, initial_storage);
+        }
+    }
+
+    /// Returns the price `trade` is settled at, or `None` if it's still waiting for the first
+    /// oracle price after its waiting period. Delisted assets settle at their final price, and
+    /// trades settle at their own price once delayed settlement is disabled.
+    fn settlement_price(&self, asset: &String, trade: &PendingTrade) -> Option<u128> {
+        if let Some(final_price) = self.delisted_assets.get(asset) {
+            return Some(final_price);
+        }
+        if self.settlement_waiting_period == 0 {
+            return Some(trade.asset_price.0);
+        }
+        let settle_after = trade.created_at.0 + self.settlement_waiting_period;
+        if let Some(components) = self.index_assets.get(asset) {
+            // An index settles once all of its components got a price after the waiting period
+            let all_updated = components.iter().all(|component| {
+                self.asset_prices_updated_at
+                    .get(&component.asset)
+                    .unwrap_or(0)
+                    >= settle_after
+            });
+            return if all_updated {
+                self.internal_index_price(&components).ok()
+            } else {
+                None
+            };
+        }
+        let last_round = self.asset_price_rounds.get(asset).unwrap_or(0);
+        let first_round = std::cmp::max(
+            trade.round.0,
+            self.pruned_price_rounds.get(asset).unwrap_or(0),
+        ) + 1;
+        for round in first_round..=last_round {
+            if let Some(price_round) = self.asset_round_prices.get(&(asset.clone(), round)) {
+                if price_round.timestamp >= settle_after {
+                    return Some(price_round.price);
+                }
+            }
+        }
+        None
+    }
+
+    /// Removes up to two price rounds of `asset` older than `MAX_PRICE_ROUND_AGE`, so pruning
+    /// keeps up with the one round each submitted price adds.
+    fn internal_prune_price_rounds(&mut self, asset: &String, last_round: u64) {
+        let last_pruned = self.pruned_price_rounds.get(asset).unwrap_or(0);
+        let mut pruned = last_pruned;
+        for _ in 0..2 {
+            let round = pruned + 1;
+            if round >= last_round {
+                break;
+            }
+            let key = (asset.clone(), round);
+            if let Some(price_round) = self.asset_round_prices.get(&key) {
+                if env::block_timestamp().saturating_sub(price_round.timestamp)
+                    <= MAX_PRICE_ROUND_AGE
+                {
+                    break;
+                }
+                self.asset_round_prices.remove(&key);
+            }
+            pruned = round;
+        }
+        if pruned != last_pruned {
+            self.pruned_price_rounds.insert(asset, &pruned);
+        }
+    }
+
+    /// Whether `account_id` has purchases of `asset` waiting for settlement or for its charge.
+    fn has_pending_trades(&self, account_id: &AccountId, asset: &str) -> bool {
+        let key = (account_id.clone(), asset.to_string());
+        self.pending_trades.get(&key).is_some() || self.settling_trades.get(&key).is_some()
+    }
+
+    fn assert_no_pending_trades(&self, account_id: &AccountId, asset: &String) {
+        if self.has_pending_trades(account_id, asset) {
+            env::panic(
+                format!(
+                    "{} has unsettled trades, call settle_asset_trades first",
+                    asset
+                )
+                .as_bytes(),
+            );
+        }
+    }
+
+    fn internal_add_order(&mut self, order: Order) -> u64 {
+        let order_id = self.next_order_id;
+        self.next_order_id += 1;
+        event::place_order(
+            order_id,
+            &order.account_id,
+            order.side,
+            &order.asset,
+            order.amount,
+            order.trigger_price,
+        );
+        increment_count(&mut self.order_counts, &order.account_id);
+        self.orders.insert(&order_id, &order);
+        order_id
+    }
+
+    fn order_bounty(&self, amount: Balance) -> Balance {
+        let bounty = Ratio::<BigInt>::new(self.order_bounty_bps.into(), 10_000.into())
+            * BigInt::from(amount);
+        bounty.to_integer().to_u128().unwrap()
+    }
+
+    fn internal_sub_asset_supply(&mut self, asset: &String, amount: Balance) {
+        let supply = self.asset_supplies.get(asset).unwrap_or(0);
+        if supply < amount {
+            // Balances bought before supplies were tracked aren't counted in the supply
+            log!(
+                "Supply of {} is {}, less than the removed {}",
+                asset,
+                supply,
+                amount
+            );
+        }
+        self.asset_supplies
+            .insert(asset, &supply.saturating_sub(amount));
+        self.internal_update_asset_exposure(asset);
+    }
+
+    /// Removes the whole `asset` balance of `account_id` and mints aUSD for it at the final
+    /// price. Returns `None` if the account holds none of the asset.
+    fn internal_settle_delisted_asset(
+        &mut self,
+        account_id: &AccountId,
+        asset: &String,
+    ) -> Option<Promise> {
+        if self.delisted_assets.get(asset).is_none() {
+            env::panic(b"Asset is not delisted");
+        }
+        if self.has_pending_trades(account_id, asset) {
+            log!("{} has unsettled trades of {}", account_id, asset);
+            return None;
+        }
+        let mut account = self.get_account(account_id);
+        let asset_amount = match account.assets.get(asset) {
+            Some(amount) if *amount > 0 => *amount,
+            _ => return None,
+        };
+        account.assets.insert(asset.clone(), 0);
+        self.internal_save_account(account_id, &account);
+        Some(self.internal_settle_delisted_amount(account_id, asset, asset_amount))
+    }
+
+    /// Mints aUSD at the final price for `asset_amount` of a delisted `asset` already taken from
+    /// `account_id`. The supply is only reduced once the mint succeeded, so the asset isn't
+    /// cleared while a settlement can still be returned.
+    fn internal_settle_delisted_amount(
+        &mut self,
+        account_id: &AccountId,
+        asset: &String,
+        asset_amount: Balance,
+    ) -> Promise {
+        let final_price = self.delisted_assets.get(asset).unwrap();
+        ext_usd::mint(
+            account_id.clone(),
+            value_in_ausd(final_price, asset_amount),
+            &self.ausd_token,
+            0,
+            GAS_FOR_SETTLEMENT_MINT,
+        )
+        .then(ext_art::on_delisted_asset_settled(
+            account_id.clone(),
+            asset.clone(),
+            asset_amount.into(),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_SETTLEMENT_CALLBACK,
+        ))
+    }
+
+    /// Revalues the supply of `asset` at its current price in `total_asset_exposure`. An index
+    /// whose price can't be computed keeps its last price.
+    fn internal_update_asset_exposure(&mut self, asset: &String) {
+        let (last_price, last_value) = self.asset_exposures.get(asset).unwrap_or((0, 0));
+        let price = self.internal_asset_price(asset).unwrap_or(last_price);
+        let value = value_in_ausd(price, self.asset_supplies.get(asset).unwrap_or(0));
+        self.total_asset_exposure = self.total_asset_exposure - last_value + value;
+        if value == 0 {
+            self.asset_exposures.remove(asset);
+        } else {
+            self.asset_exposures.insert(asset, &(price, value));
+        }
+    }
+
+    /// Panics if buying `asset_amount` of `asset` worth `ausd_amount` would exceed the asset
+    /// supply cap or the total exposure cap, including purchases not credited yet.
+    fn assert_within_exposure_limits(
+        &self,
+        asset: &String,
+        asset_amount: Balance,
+        ausd_amount: Balance,
+    ) {
+        self.assert_accounts_migrated();
+        if let Some(cap) = self.asset_supply_caps.get(asset) {
+            let supply = self.asset_supplies.get(asset).unwrap_or(0)
+                + self.reserved_asset_supplies.get(asset).unwrap_or(0);
+            if supply.checked_add(asset_amount).unwrap() > cap {
+                env::panic(format!("Supply cap of {} exceeded", asset).as_bytes());
+            }
+        }
+        if let Some(cap) = self.max_total_asset_exposure {
+            let exposure = self.total_asset_exposure + self.reserved_asset_exposure;
+            if exposure.checked_add(ausd_amount).unwrap() > cap {
+                env::panic(b"Total asset exposure cap exceeded");
+            }
+        }
+    }
+
+    /// Reserves `asset_amount` of `asset` worth `ausd_amount` against the supply and exposure
+    /// caps until the purchase is credited or fails. Panics if a cap would be exceeded.
+    fn internal_reserve_asset_supply(
+        &mut self,
+        asset: &String,
+        asset_amount: Balance,
+        ausd_amount: Balance,
+    ) {
+        self.assert_within_exposure_limits(asset, asset_amount, ausd_amount);
+        let reserved = self.reserved_asset_supplies.get(asset).unwrap_or(0);
+        self.reserved_asset_supplies
+            .insert(asset, &(reserved + asset_amount));
+        self.reserved_asset_exposure += ausd_amount;
+    }
+
+    /// Releases a reservation made by `internal_reserve_asset_supply`.
+    fn internal_release_asset_supply(
+        &mut self,
+        asset: &String,
+        asset_amount: Balance,
+        ausd_amount: Balance,
+    ) {
+        let reserved = self
+            .reserved_asset_supplies
+            .get(asset)
+            .unwrap_or(0)
+            .saturating_sub(asset_amount);
+        if reserved == 0 {
+            self.reserved_asset_supplies.remove(asset);
+        } else {
+            self.reserved_asset_supplies.insert(asset, &reserved);
+        }
+        self.reserved_asset_exposure = self.reserved_asset_exposure.saturating_sub(ausd_amount);
+    }
+
+    /// Clears a delisted `asset` from storage once no account holds it anymore, including its
+    /// supply cap and price rounds.
+    fn clear_delisted_asset_if_settled(&mut self, asset: &String) {
+        if self.delisted_assets.get(asset).is_none() {
+            return;
+        }
+        if self.asset_supplies.get(asset).unwrap_or(0) == 0 {
+            self.delisted_assets.remove(asset);
+            self.asset_supplies.remove(asset);
+            self.asset_supply_caps.remove(asset);
+            self.asset_exposures.remove(asset);
+            self.asset_prices.remove(asset);
+            self.asset_prices_updated_at.remove(asset);
+            self.index_assets.remove(asset);
+            let last_round = self.asset_price_rounds.remove(asset).unwrap_or(0);
+            let pruned = self.pruned_price_rounds.remove(asset).unwrap_or(0);
+            for round in pruned + 1..=last_round {
+                self.asset_round_prices.remove(&(asset.clone(), round));
+            }
+            log!("delisted asset {} cleared", asset);
+        }
+    }
+}
+
+/// Returns the USD value, in aUSD, of `amount` at `price` per 10^8 units.
+fn value_in_ausd(price: u128, amount: Balance) -> Balance {
+    let unit_price = Ratio::<BigInt>::new(price.into(), 100_000_000.into());
+    let amount: BigInt = amount.into();
+    let value: Ratio<BigInt> = unit_price * amount;
+    value.to_integer().to_u128().unwrap()
+}
+
+/// Returns the amount of an asset priced at `price` per 10^8 units worth `ausd_amount`.
+fn ausd_to_asset(price: u128, ausd_amount: Balance) -> Balance {
+    let unit_price = Ratio::<BigInt>::new(price.into(), 100_000_000.into());
+    let ausd_amount: BigInt = ausd_amount.into();
+    let amount: Ratio<BigInt> = Ratio::from_integer(ausd_amount) / unit_price;
+    amount.to_integer().to_u128().unwrap()
+}
+
+/// Panics if the block timestamp is past `deadline`, in nanoseconds.
+fn assert_deadline(deadline: Option<U64>) {
+    if let Some(deadline) = deadline {
+        if env::block_timestamp() > deadline.0 {
+            env::panic(b"Deadline has passed");
+        }
+    }
+}
+
+/// Panics if `amount_out` is less than `min_amount_out`.
+fn assert_min_amount_out(amount_out: Balance, min_amount_out: Option<String>) {
+    if let Some(min_amount_out) = min_amount_out {
+        let min_amount_out =
+            u128::from_str(&min_amount_out).expect("Failed to parse min_amount_out");
+        if amount_out < min_amount_out {
+            env::panic(b"Output amount is less than min_amount_out");
+        }
+    }
+}
+
+/// Counts one more open order or vesting grant of `account_id` in `counts`.
+fn increment_count(counts: &mut LookupMap<AccountId, u64>, account_id: &AccountId) {
+    let count = counts.get(account_id).unwrap_or(0);
+    counts.insert(account_id, &(count + 1));
+}
+
+/// Counts one less open order or vesting grant of `account_id`, removing the count at 0.
+fn decrement_count(counts: &mut LookupMap<AccountId, u64>, account_id: &AccountId) {
+    match counts.get(account_id).unwrap_or(0) {
+        0 | 1 => counts.remove(account_id),
+        count => counts.insert(account_id, &(count - 1)),
+    };
+}
+
+/// Returns `volume` if it's of the window starting at `window_start`, otherwise an empty volume
+/// of that window.
+fn current_volume(volume: Option<WindowVolume>, window_start: u64) -> WindowVolume {
+    match volume {
+        Some(volume) if volume.window_start == window_start => volume,
+        _ => WindowVolume {
+            window_start,
+            volume: 0,
+        },
+    }
+}
+
+/// Parses a `swap` path, which converts between two tokens directly or through aUSD.
+fn parse_swap_path(path: &[String]) -> Vec<SwapToken> {
+    if path.len() < 2 || path.len() > 3 {
+        env::panic(b"Swap path must have 2 or 3 tokens");
+    }
+    let tokens: Vec<SwapToken> = path.iter().map(|token| SwapToken::parse(token)).collect();
+    if tokens.len() == 3 && tokens[1] != SwapToken::Ausd {
+        env::panic(b"Only aUSD can be in the middle of a swap path");
+    }
+    if tokens[0] == tokens[tokens.len() - 1] {
+        env::panic(b"Swap path must end with a different token");
+    }
+    tokens
+}
+
+/// Fee of the last leg of `route`, taken from its output.
+fn swap_output_fee(route: &SwapRoute) -> Balance {
+    match route.fees.last() {
+        Some(fee) if fee.path.fee_token() == route.path[route.path.len() - 1] => fee.amount.0,
+        _ => 0,
+    }
+}
+
+/// Whether aUSD paid for `to` is burnt, rather than moved to the aUSD owner.
+fn swap_burns_ausd(to: &SwapToken, uses_art_reserve: bool) -> bool {
+    match to {
+        SwapToken::Asset(_) => true,
+        SwapToken::Art => uses_art_reserve,
+        _ => false,
+    }
+}
+
+/// Whether aUSD received for `from` is minted, rather than moved from the aUSD owner.
+fn swap_mints_ausd(from: &SwapToken, uses_art_reserve: bool) -> bool {
+    match from {
+        SwapToken::Asset(_) => true,
+        SwapToken::Art => uses_art_reserve,
+        _ => false,
+    }
+}
+
+/// Returns the balance of the last of `count` checkpoints at or before `block_height`, 0 if there
+/// is none. `checkpoint(i)` returns the i-th checkpoint, by increasing height.
+fn balance_at(count: u64, block_height: u64, checkpoint: impl Fn(u64) -> Checkpoint) -> Balance {
+    // Checkpoints before `low` are at or before `block_height`, the ones from `high` on after it
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = (low + high) / 2;
+        if checkpoint(mid).block_height <= block_height {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    if low == 0 {
+        0
+    } else {
+        checkpoint(low - 1).balance
+    }
+}
+
+/// Returns the storage in bytes a purchase of `asset` by `account_id` can take once its aUSD is
+/// burnt: the asset in the account and a new record of its pending trades.
+fn purchase_storage_usage(account_id: &str, asset: &str) -> StorageUsage {
+    let asset_key = 4 + asset.len() as StorageUsage + 16;
+    let pending_trades_key =
+        1 + 4 + account_id.len() as StorageUsage + 4 + asset.len() as StorageUsage;
+    // A vector of one trade of two `U128` and two `U64`
+    let pending_trades_value = 4 + 48;
+    asset_key + STORAGE_RECORD_EXTRA_BYTES + pending_trades_key + pending_trades_value
+}
+
+/// Panics if the balances at the end of the block at `height` can still change.
+fn assert_past_height(height: u64) {
+    if height >= env::block_index() {
+        env::panic(b"The block height isn't in the past yet");
+    }
+}
+
+/// Returns `staked` with the staking reward of `days` days added.
+fn compound_stake(staked: Balance, mut days: u64) -> Balance {
+    let mut new_staked = staked;
+    if days > 365 {
+        let mut r = Ratio::new(new_staked, 1);
+        r *= YEAR_INTEREST[(days / 365) as usize];
+        new_staked = r.to_integer();
+        days %= 365;
+    }
+    if days > 30 {
+        let mut r = Ratio::new(new_staked, 1);
+        r *= MONTH_INTEREST[(days / 30) as usize];
+        new_staked = r.to_integer();
+        days %= 30;
+    }
+    if days > 0 {
+        let mut r = Ratio::new(new_staked, 1);
+        r *= DAY_INTEREST[days as usize];
+        new_staked = r.to_integer();
+    }
+    new_staked
+}
+
+/// Returns `a * b / c` rounded down.
+fn mul_div(a: u128, b: u128, c: u128) -> u128 {
+    (BigInt::from(a) * b / c).to_u128().unwrap()
+}
+
+/// Returns `a * b / c` rounded up.
+fn mul_div_round_up(a: u128, b: u128, c: u128) -> u128 {
+    ((BigInt::from(a) * b + c - 1u32) / c).to_u128().unwrap()
+}
+
+fn is_promise_success() -> bool {
+    assert_eq!(
+        env::promise_results_count(),
+        1,
+        "Contract expected a result on the callback"
+    );
+    match env::promise_result(0) {
+        PromiseResult::Successful(_) => true,
+        _ => false,
+    }
+}
+
+#[ext_contract(ext_fungible_token_receiver)]
+pub trait FungibleTokenReceiver {
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128>;
+}
+
+#[ext_contract(ext_fungible_token)]
+pub trait FungibleTokenContract {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128>;
+
+    /// Returns the total supply of the token in a decimal string representation.
+    fn ft_total_supply(&self) -> U128;
+
+    /// Returns the balance of the account. If the account doesn't exist must returns `"0"`.
+    fn ft_balance_of(&self, account_id: AccountId) -> U128;
+}
+
+const GAS_FOR_SETTLEMENT_MINT: Gas = 10_000_000_000_000;
+const GAS_FOR_SETTLEMENT_CALLBACK: Gas = 10_000_000_000_000;
+const GAS_FOR_ORDER_MINT: Gas = 10_000_000_000_000;
+const GAS_FOR_ORDER_CALLBACK: Gas = 10_000_000_000_000;
+const GAS_FOR_NEAR_CALLBACK: Gas = 25_000_000_000_000;
+const GAS_FOR_FEE_CALLBACK: Gas = 5_000_000_000_000;
+const GAS_FOR_RESOLVE_TRANSFER: Gas = 5_000_000_000_000;
+const GAS_FOR_FT_TRANSFER_CALL: Gas = 30_000_000_000_000 + GAS_FOR_RESOLVE_TRANSFER;
+
+const NO_DEPOSIT: Balance = 0;
+
+/// Bytes NEAR charges on top of the key and value of every storage record.
+const STORAGE_RECORD_EXTRA_BYTES: StorageUsage = 40;
+
+#[near_bindgen]
+impl FungibleTokenMetadataProvider for Art {
+    fn ft_metadata(&self) -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: FT_METADATA_SPEC.to_string(),
+            name: "Artificial Coin".to_string(),
+            symbol: "art".to_string(),
+            icon: Some("https://artcoin.network/static/media/logo192.c7f41b7c.png".to_string()),
+            decimals: 24,
+            reference: None,
+            reference_hash: None,
+        }
+    }
+}
+
+#[near_bindgen]
+impl StorageManagement for Art {
+    /// Registers the account with at least the minimum storage balance, or adds the deposit to the
+    /// storage balance of a registered account. With `registration_only` only the minimum storage
+    /// balance is kept and the rest is refunded.
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<ValidAccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let amount: Balance = env::attached_deposit();
+        let account_id = account_id
+            .map(|a| a.into())
+            .unwrap_or_else(|| env::predecessor_account_id());
+        let registration_only = registration_only.unwrap_or(false);
+        if self.accounts.get(&account_id).is_some() {
+            if registration_only {
+                log!("The account is already registered, refunding the deposit");
+                if amount > 0 {
+                    Promise::new(env::predecessor_account_id()).transfer(amount);
+                }
+            } else {
+                let deposit = self.internal_storage_deposit_of(&account_id) + amount;
+                self.storage_deposits.insert(&account_id, &deposit);
+            }
+        } else {
+            let min_balance = self.storage_balance_bounds().min.0;
+            if amount < min_balance {
+                env::panic(b"The attached deposit is less than the mimimum storage balance");
+            }
+
+            self.internal_register_account(&account_id);
+            let deposit = if registration_only {
+                min_balance
+            } else {
+                amount
+            };
+            self.storage_deposits.insert(&account_id, &deposit);
+            let refund = amount - deposit;
+            if refund > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(refund);
+            }
+        }
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+
+    /// Withdraws `amount`, or all of the available storage balance, to the caller. The available
+    /// storage balance is the storage deposit not used by the account's allowances and assets.
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let predecessor_account_id = env::predecessor_account_id();
+        if let Some(storage_balance) = self.internal_storage_balance_of(&predecessor_account_id) {
+            let amount = amount.unwrap_or(storage_balance.available).0;
+            if amount > storage_balance.available.0 {
+                env::panic(b"The amount is greater than the available storage balance");
+            }
+            if amount > 0 {
+                self.storage_deposits
+                    .insert(&predecessor_account_id, &(storage_balance.total.0 - amount));
+                Promise::new(predecessor_account_id.clone()).transfer(amount);
+            }
+            self.internal_storage_balance_of(&predecessor_account_id)
+                .unwrap()
+        } else {
+            env::panic(
+                format!("The account {} is not registered", &predecessor_account_id).as_bytes(),
+            );
+        }
+    }
+
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        self.internal_storage_unregister(force).is_some()
+    }
+
+    /// There's no maximum, allowances and assets take more storage.
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        let required_storage_balance =
+            Balance::from(self.account_storage_usage) * env::storage_byte_cost();
+        StorageBalanceBounds {
+            min: required_storage_balance.into(),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance> {
+        self.internal_storage_balance_of(account_id.as_ref())
+    }
+}
+
+impl BatchTransfer for Art {
+    fn transferable_balance(&self, account_id: &AccountId) -> Balance {
+        self.internal_unwrap_balance_of(account_id)
+    }
+
+    fn register_receiver(&mut self, account_id: &AccountId, deposit: Balance) -> Balance {
+        self.internal_register_receiver(account_id, deposit)
+    }
+
+    fn transfer_leg(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        memo: Option<String>,
+    ) {
+        self.internal_transfer(sender_id, receiver_id, amount, memo);
+    }
+}
+
+impl Art {
+    /// Internal method that returns the Account ID and the balance in case the account was
+    /// unregistered. Accounts holding assets, pool shares, open orders, pending trades or vesting
+    /// grants can't unregister, even with `force`. The permit nonce is kept so permits signed
+    /// before can't be replayed if the account registers again.
+    pub fn internal_storage_unregister(
+        &mut self,
+        force: Option<bool>,
+    ) -> Option<(AccountId, Balance)> {
+        assert_one_yocto();
+        // Removing an account moves another one in `accounts`
+        self.assert_accounts_migrated();
+        let account_id = env::predecessor_account_id();
+        let force = force.unwrap_or(false);
+        if let Some(account) = self.accounts.get(&account_id) {
+            self.assert_nothing_held(&account_id, &account);
+            let balance = account.balance.checked_add(account.staked_balance).unwrap();
+            if balance == 0 || force {
+                let storage_deposit = self.internal_storage_deposit_of(&account_id);
+                self.accounts.remove(&account_id);
+                self.storage_deposits.remove(&account_id);
+                self.storage_usages.remove(&account_id);
+                for path in ConversionPath::ALL.iter() {
+                    self.account_volumes.remove(&(account_id.clone(), *path));
+                }
+                let count = self
+                    .balance_checkpoint_counts
+                    .remove(&account_id)
+                    .unwrap_or(0);
+                for index in 0..count {
+                    self.balance_checkpoints
+                        .remove(&(account_id.clone(), index));
+                }
+                self.reward_paid_at.remove(&account_id);
+                self.permit_keys.remove(&account_id);
+                self.pool.shares.remove(&account_id);
+                self.total_staked -= account.staked_balance;
+                self.total_supply -= balance;
+                self.internal_checkpoint_total_supply();
+                if balance > 0 {
+                    event::ft_burn(&account_id, balance, Some("storage unregister"));
+                }
+                Promise::new(account_id.clone()).transfer(storage_deposit + 1);
+                Some((account_id, balance))
+            } else {
+                env::panic(b"Can't unregister the account with the positive balance without force")
+            }
+        } else {
+            log!("The account {} is not registered", &account_id);
+            None
+        }
+    }
+
+    /// Panics while `migrate_accounts` hasn't backfilled all accounts, as asset supplies and
+    /// balance checkpoints are backfilled from the balances and the accounts are walked by index.
+    fn assert_accounts_migrated(&self) {
+        if self.account_migration.is_some() {
+            env::panic(b"Accounts are still being migrated, call migrate_accounts");
+        }
+    }
+
+    /// Backfills the state of an account from before `migrate`.
+    fn internal_migrate_account(&mut self, account_id: &AccountId, account: &Account) {
+        // The minimum storage balance of accounts registered before the deposit was tracked is
+        // paid by the contract
+        let min_balance = self.storage_balance_bounds().min.0;
+        self.storage_deposits.insert(account_id, &min_balance);
+        self.internal_checkpoint_balance(account_id, account.total_balance());
+        for (asset, balance) in account.assets.iter() {
+            let supply = self.asset_supplies.get(asset).unwrap_or(0);
+            self.asset_supplies.insert(asset, &(supply + balance));
+        }
+    }
+
+    fn assert_nothing_held(&self, account_id: &AccountId, account: &Account) {
+        if account.assets.values().any(|balance| *balance > 0) {
+            env::panic(b"Can't unregister the account holding assets");
+        }
+        if self.pool.shares_of(account_id) > 0 {
+            env::panic(b"Can't unregister the account with pool shares");
+        }
+        if account
+            .assets
+            .keys()
+            .any(|asset| self.has_pending_trades(account_id, asset))
+        {
+            env::panic(b"Can't unregister the account with pending trades");
+        }
+        if self.order_counts.get(account_id).is_some() {
+            env::panic(b"Can't unregister the account with open orders");
+        }
+        if self.vesting_grant_counts.get(account_id).is_some() {
+            env::panic(b"Can't unregister the account with vesting grants");
+        }
+    }
+
+    fn internal_storage_balance_of(&self, account_id: &AccountId) -> Option<StorageBalance> {
+        self.accounts.get(account_id).map(|account| {
+            let total = self.internal_storage_deposit_of(account_id);
+            StorageBalance {
+                total: total.into(),
+                available: total
+                    .saturating_sub(self.internal_storage_cost(account_id, &account))
+                    .into(),
+            }
+        })
+    }
+
+    pub fn internal_register_account(&mut self, account_id: &AccountId) {
+        if self
+            .accounts
+            .insert(&account_id, &Default::default())
+            .is_some()
+        {
+            env::panic(b"The account is already registered");
+        }
+    }
+
+    pub fn internal_unwrap_balance_of(&self, account_id: &AccountId) -> Balance {
+        match self.accounts.get(&account_id) {
+            Some(account) => account.balance,
+            None => env::panic(format!("The account {} is not registered", &account_id).as_bytes()),
+        }
+    }
+
+    pub fn internal_deposit(&mut self, account_id: &AccountId, amount: Balance) {
+        let mut account = self.accounts.get(&account_id).unwrap();
+        let balance = self.internal_unwrap_balance_of(account_id);
+        if let Some(new_balance) = balance.checked_add(amount) {
+            account.balance = new_balance;
+            self.internal_save_account(&account_id, &account);
+            self.total_supply = self
+                .total_supply
+                .checked_add(amount)
+                .expect("Total supply overflow");
+            self.internal_checkpoint_total_supply();
+        } else {
+            env::panic(b"Balance overflow");
+        }
+    }
+
+    pub fn internal_withdraw(&mut self, account_id: &AccountId, amount: Balance) {
+        let mut account = self.accounts.get(&account_id).unwrap();
+        let balance = self.internal_unwrap_balance_of(account_id);
+        if let Some(new_balance) = balance.checked_sub(amount) {
+            account.balance = new_balance;
+            self.internal_save_account(&account_id, &account);
+            self.total_supply = self
+                .total_supply
+                .checked_sub(amount)
+                .expect("Total supply overflow");
+            self.internal_checkpoint_total_supply();
+        } else {
+            env::panic(b"The account doesn't have enough balance");
+        }
+    }
+
+    pub fn internal_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        memo: Option<String>,
+    ) {
+        assert_ne!(
+            sender_id, receiver_id,
+            "Sender and receiver should be different"
+        );
+        assert!(amount > 0, "The amount should be a positive number");
+        self.internal_withdraw(sender_id, amount);
+        self.internal_deposit(receiver_id, amount);
+        event::ft_transfer(sender_id, receiver_id, amount, memo.as_deref());
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenCore for Art {
+    /// Unregistered receivers are registered with the attached deposit beyond 1 yoctoNEAR, the
+    /// rest is refunded.
+    #[payable]
+    fn ft_transfer(&mut self, receiver_id: ValidAccountId, amount: U128, memo: Option<String>) {
+        let deposit = assert_at_least_one_yocto();
+        let refund = self.internal_register_receiver(receiver_id.as_ref(), deposit);
+        let sender_id = env::predecessor_account_id();
+        let amount: Balance = amount.into();
+        self.internal_transfer(&sender_id, receiver_id.as_ref(), amount, memo);
+        refund_deposit(refund);
+    }
+
+    #[payable]
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: ValidAccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        let deposit = assert_at_least_one_yocto();
+        let refund = self.internal_register_receiver(receiver_id.as_ref(), deposit);
+        let sender_id = env::predecessor_account_id();
+        let amount: Balance = amount.into();
+        self.internal_transfer(&sender_id, receiver_id.as_ref(), amount, memo);
+        refund_deposit(refund);
+        // Initiating receiver's call and the callback
+        ext_fungible_token_receiver::ft_on_transfer(
+            sender_id.clone(),
+            amount.into(),
+            msg,
+            receiver_id.as_ref(),
+            NO_DEPOSIT,
+            env::prepaid_gas() - GAS_FOR_FT_TRANSFER_CALL,
+        )
+        .then(ext_self::ft_resolve_transfer(
+            sender_id,
+            receiver_id.into(),
+            amount.into(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+        .into()
+    }
+
+    fn ft_total_supply(&self) -> U128 {
+        self.total_supply.into()
+    }
+
+    fn ft_balance_of(&self, account_id: ValidAccountId) -> U128 {
+        self.accounts
+            .get(account_id.as_ref())
+            .unwrap_or(Default::default())
+            .balance
+            .into()
+    }
+}
+
+#[ext_contract(ext_self)]
+trait FungibleTokenResolver {
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128;
+}
+
+trait FungibleTokenResolver {
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128;
+}
+
+pub(crate) fn assert_self() {
+    assert_eq!(
+        env::predecessor_account_id(),
+        env::current_account_id(),
+        "Method is private"
+    );
+}
+
+#[near_bindgen]
+impl FungibleTokenResolver for Art {
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        assert_self();
+        let amount: Balance = amount.into();
+
+        // Get the unused amount from the `ft_on_transfer` call result.
+        let unused_amount = match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(value) => {
+                if let Ok(unused_amount) = near_sdk::serde_json::from_slice::<U128>(&value) {
+                    std::cmp::min(amount, unused_amount.0)
+                } else {
+                    amount
+                }
+            }
+            PromiseResult::Failed => amount,
+        };
+
+        if unused_amount > 0 {
+            let mut receiver = self.get_account(&receiver_id);
+            let receiver_balance = receiver.balance;
+            if receiver_balance > 0 {
+                let refund_amount = std::cmp::min(receiver_balance, unused_amount);
+                receiver.balance -= refund_amount;
+                self.internal_save_account(&receiver_id, &receiver);
+
+                let mut sender = self.get_account(&sender_id);
+                sender.balance += refund_amount;
+                self.internal_save_account(&sender_id, &sender);
+
+                event::ft_transfer(&receiver_id, &sender_id, refund_amount, Some("refund"));
+                return (amount - refund_amount).into();
+            }
+        }
+        amount.into()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use near_sdk::env::STORAGE_PRICE_PER_BYTE;
+    use near_sdk::test_utils::get_logs;
+    use near_sdk::MockedBlockchain;
+    use near_sdk::{testing_env, VMContext};
+    use permit::test_utils::{permit_keypair, permit_public_key, sign_permit};
+
+    use super::*;
+
+    fn alice() -> AccountId {
+        "alice.near".to_string()
+    }
+    fn bob() -> AccountId {
+        "bob.near".to_string()
+    }
+    fn carol() -> AccountId {
+        "carol.near".to_string()
+    }
+
+    fn get_context(predecessor_account_id: AccountId) -> VMContext {
+        VMContext {
+            current_account_id: alice(),
+            signer_account_id: bob(),
+            signer_account_pk: vec![0, 1, 2],
+            predecessor_account_id,
+            input: vec![],
+            block_index: 0,
+            block_timestamp: 0,
+            account_balance: 0,
+            account_locked_balance: 0,
+            storage_usage: 10u64.pow(6),
+            attached_deposit: 0,
+            prepaid_gas: 10u64.pow(18),
+            random_seed: vec![0, 1, 2],
+            is_view: false,
+            output_data_receivers: vec![],
+            epoch_height: 0,
+        }
+    }
+
+    /// Registers `account_id` with `storage_deposit`, or tops up its storage balance, so it
+    /// covers 1000 bytes of allowances, assets and checkpoints. The current predecessor pays.
+    fn register(contract: &mut Art, account_id: &AccountId) {
+        let attached_deposit = env::attached_deposit();
+        let mut context = get_context(env::predecessor_account_id());
+        context.block_index = env::block_index();
+        context.block_timestamp = env::block_timestamp();
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+        context.attached_deposit =
+            contract.storage_balance_bounds().min.0 + 1000 * STORAGE_PRICE_PER_BYTE;
+        testing_env!(context.clone());
+        contract.storage_deposit(Some(account_id.clone().try_into().unwrap()), None);
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance() - attached_deposit;
+        context.attached_deposit = attached_deposit;
+        testing_env!(context);
+    }
+
+    /// Credits `asset_amount` of `asset` to `account_id` as if it was bought at the current
+    /// oracle price.
+    fn buy_asset(contract: &mut Art, account_id: &AccountId, asset: &String, asset_amount: u128) {
+        let asset_price = contract._get_asset_price(asset);
+        let round = contract.asset_price_rounds.get(asset).unwrap_or(0);
+        contract.internal_purchase_asset(account_id, asset, asset_amount, asset_price, round);
+    }
+
+    #[test]
+    fn test_new() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let total_supply = 1_000_000_000_000_000u128;
+        let contract = Art::new(bob(), total_supply.to_string(), "ausd".to_string());
+        assert_eq!(contract.get_total_supply(), total_supply.to_string());
+        assert_eq!(
+            contract.get_unstaked_balance(bob()),
+            total_supply.to_string()
+        );
+        assert_eq!(contract.get_total_balance(bob()), total_supply.to_string());
+    }
+
+    #[test]
+    fn test_transfer() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        let transfer_amount = total_supply / 3;
+        contract.transfer(bob(), transfer_amount.to_string());
+        assert_eq!(
+            contract.get_unstaked_balance(carol()),
+            (total_supply - transfer_amount).to_string()
+        );
+        assert_eq!(
+            contract.get_unstaked_balance(bob()),
+            transfer_amount.to_string()
+        );
+    }
+
+    #[test]
+    fn test_stake_fail() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        let transfer_amount = total_supply / 3;
+        let context = get_context(bob());
+        testing_env!(context);
+        std::panic::catch_unwind(move || {
+            contract.stake(transfer_amount.to_string());
+        })
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_self_allowance_fail() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        std::panic::catch_unwind(move || {
+            contract.set_allowance(carol(), format!("{}", total_supply / 2));
+        })
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_carol_escrows_to_bob_transfers_to_alice() {
+        // Acting as carol
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &alice());
+        register(&mut contract, &carol());
+        assert_eq!(contract.get_total_supply(), total_supply.to_string());
+        let allowance = total_supply / 3;
+        let transfer_amount = allowance / 3;
+        contract.set_allowance(bob(), format!("{}", allowance));
+        assert_eq!(
+            contract.get_allowance(carol(), bob()),
+            format!("{}", allowance)
+        );
+        // Acting as bob now
+        testing_env!(get_context(bob()));
+        contract.transfer_from(carol(), alice(), transfer_amount.to_string());
+        assert_eq!(
+            contract.get_total_balance(carol()),
+            (total_supply - transfer_amount).to_string()
+        );
+        assert_eq!(
+            contract.get_unstaked_balance(alice()),
+            transfer_amount.to_string()
+        );
+        assert_eq!(
+            contract.get_allowance(carol(), bob()),
+            format!("{}", allowance - transfer_amount)
+        );
+    }
+
+    #[test]
+    fn test_delist_asset_and_settle() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &alice());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        testing_env!(get_context("ausd".to_string()));
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 100);
+        buy_asset(&mut contract, &alice(), &"aBTC".to_string(), 50);
+        assert_eq!(contract.get_asset_supply("aBTC".to_string()), "150");
+
+        testing_env!(get_context(carol()));
+        contract.delist_asset("aBTC".to_string());
+        assert_eq!(
+            contract.get_delisted_price("aBTC".to_string()),
+            Some("3000000000000".to_string())
+        );
+
+        testing_env!(get_context(bob()));
+        contract.settle_delisted_asset("aBTC".to_string());
+        assert_eq!(contract.get_asset_balance(bob(), "aBTC".to_string()), "0");
+        // the supply is only reduced once the aUSD is minted
+        assert_eq!(contract.get_asset_supply("aBTC".to_string()), "150");
+        testing_env!(
+            get_context(alice()),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_delisted_asset_settled(bob(), "aBTC".to_string(), U128(100));
+        assert_eq!(contract.get_asset_supply("aBTC".to_string()), "50");
+
+        // a keeper settles the rest, which clears the asset
+        testing_env!(get_context(carol()));
+        contract.settle_delisted_asset_for("aBTC".to_string(), vec![alice(), bob()]);
+        assert_eq!(contract.get_asset_balance(alice(), "aBTC".to_string()), "0");
+        assert_eq!(
+            contract.get_delisted_price("aBTC".to_string()),
+            Some("3000000000000".to_string())
+        );
+        testing_env!(
+            get_context(alice()),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_delisted_asset_settled(alice(), "aBTC".to_string(), U128(50));
+        assert_eq!(contract.get_delisted_price("aBTC".to_string()), None);
+        assert_eq!(contract.get_asset_price("aBTC".to_string()), "0");
+        assert!(contract
+            .asset_price_rounds
+            .get(&"aBTC".to_string())
+            .is_none());
+        assert!(contract
+            .asset_prices_updated_at
+            .get(&"aBTC".to_string())
+            .is_none());
+        assert!(!contract.get_account(&bob()).assets.contains_key("aBTC"));
+    }
+
+    #[test]
+    fn test_delisted_settlement_mint_failed() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        testing_env!(get_context("ausd".to_string()));
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 100);
+        testing_env!(get_context(carol()));
+        contract.delist_asset("aBTC".to_string());
+
+        testing_env!(get_context(bob()));
+        contract.settle_delisted_asset("aBTC".to_string());
+        // the key stays until the mint succeeded, so returning the asset takes no storage
+        let storage_usage = env::storage_usage();
+        testing_env!(
+            get_context(alice()),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_delisted_asset_settled(bob(), "aBTC".to_string(), U128(100));
+        assert_eq!(contract.get_asset_balance(bob(), "aBTC".to_string()), "100");
+        assert_eq!(env::storage_usage(), storage_usage);
+        assert_eq!(contract.get_asset_supply("aBTC".to_string()), "100");
+        assert_eq!(
+            contract.get_delisted_price("aBTC".to_string()),
+            Some("3000000000000".to_string())
+        );
+    }
+
+    #[test]
+    fn test_settle_delisted_orders() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        testing_env!(get_context("ausd".to_string()));
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 100);
+        testing_env!(get_context(bob()));
+        let order_id = contract.place_sell_order(
+            "aBTC".to_string(),
+            "40".to_string(),
+            "4000000000000".to_string(),
+            TriggerDirection::Above,
+        );
+        testing_env!(get_context(carol()));
+        contract.delist_asset("aBTC".to_string());
+
+        contract.settle_delisted_asset_for("aBTC".to_string(), vec![bob()]);
+        contract.settle_delisted_orders(vec![order_id, order_id + 1]);
+        assert_eq!(contract.get_orders(bob(), 0, 10).len(), 0);
+        testing_env!(
+            get_context(alice()),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_delisted_asset_settled(bob(), "aBTC".to_string(), U128(60));
+        contract.on_delisted_asset_settled(bob(), "aBTC".to_string(), U128(40));
+        assert_eq!(contract.get_delisted_price("aBTC".to_string()), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't buy a delisted asset")]
+    fn test_buy_delisted_asset_fail() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &alice());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        testing_env!(get_context("ausd".to_string()));
+        buy_asset(&mut contract, &alice(), &"aBTC".to_string(), 50);
+        testing_env!(get_context(carol()));
+        contract.delist_asset("aBTC".to_string());
+        testing_env!(get_context(bob()));
+        contract.buy_asset_with_ausd("aBTC".to_string(), "1".to_string(), None, None);
+    }
+
+    #[test]
+    fn test_open_interest() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        contract.set_asset_supply_cap("aBTC".to_string(), Some("100".to_string()));
+        testing_env!(get_context("ausd".to_string()));
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 40);
+
+        let open_interest = contract.get_open_interest("aBTC".to_string());
+        assert_eq!(open_interest.supply.0, 40);
+        assert_eq!(open_interest.cap, Some(100.into()));
+        assert_eq!(open_interest.value.0, 40 * 30000);
+        assert_eq!(contract.get_total_asset_exposure(), (40 * 30000).to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Supply cap of aBTC exceeded")]
+    fn test_asset_supply_cap_exceeded() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        contract.set_asset_supply_cap("aBTC".to_string(), Some("100".to_string()));
+        testing_env!(get_context("ausd".to_string()));
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 40);
+        testing_env!(get_context(bob()));
+        contract.buy_asset_with_ausd("aBTC".to_string(), "61".to_string(), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Supply cap of aBTC exceeded")]
+    fn test_asset_supply_cap_counts_pending_purchases() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        contract.set_asset_supply_cap("aBTC".to_string(), Some("100".to_string()));
+        testing_env!(get_context(bob()));
+        contract.buy_asset_with_ausd("aBTC".to_string(), "60".to_string(), None, None);
+        // the first purchase isn't credited yet but still counts against the cap
+        contract.buy_asset_with_ausd("aBTC".to_string(), "41".to_string(), None, None);
+    }
+
+    #[test]
+    fn test_asset_supply_reservation_released() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        contract.set_asset_supply_cap("aBTC".to_string(), Some("100".to_string()));
+        contract.set_max_total_asset_exposure(Some((100 * 30000).to_string()));
+        testing_env!(get_context(bob()));
+        contract.buy_asset_with_ausd("aBTC".to_string(), "60".to_string(), None, None);
+        testing_env!(
+            get_context(alice()),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_asset_purchased(
+            bob(),
+            "aBTC".to_string(),
+            U128(60),
+            U128(3000000000000),
+            U64(1),
+        );
+        assert_eq!(
+            contract.reserved_asset_supplies.get(&"aBTC".to_string()),
+            None
+        );
+        assert_eq!(contract.reserved_asset_exposure, 0);
+
+        testing_env!(get_context(bob()));
+        contract.buy_asset_with_ausd("aBTC".to_string(), "100".to_string(), None, None);
+        testing_env!(
+            get_context(alice()),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_asset_purchased(
+            bob(),
+            "aBTC".to_string(),
+            U128(100),
+            U128(3000000000000),
+            U64(1),
+        );
+        assert_eq!(contract.get_asset_supply("aBTC".to_string()), "100");
+        assert_eq!(contract.reserved_asset_exposure, 0);
+    }
+
+    #[test]
+    fn test_asset_purchase_storage_reserved() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        contract.set_settlement_waiting_period(1000);
+        testing_env!(get_context(bob()));
+        contract.buy_asset_with_ausd("aBTC".to_string(), "60".to_string(), None, None);
+        let reserved = purchase_storage_usage(&bob(), "aBTC");
+        assert_eq!(contract.storage_usages.get(&bob()), Some(reserved));
+
+        // The reservation is released if the aUSD isn't burnt
+        testing_env!(
+            get_context(alice()),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_asset_purchased(
+            bob(),
+            "aBTC".to_string(),
+            U128(60),
+            U128(3000000000000),
+            U64(1),
+        );
+        assert_eq!(contract.storage_usages.get(&bob()), Some(0));
+
+        // Or replaced by the storage the purchase takes, which it covers
+        testing_env!(get_context(bob()));
+        contract.buy_asset_with_ausd("aBTC".to_string(), "60".to_string(), None, None);
+        testing_env!(
+            get_context(alice()),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_asset_purchased(
+            bob(),
+            "aBTC".to_string(),
+            U128(60),
+            U128(3000000000000),
+            U64(1),
+        );
+        let pending_trade_usage = contract.storage_usages.get(&bob()).unwrap();
+        let asset_usage = contract.get_account(&bob()).extra_storage_usage();
+        assert!(pending_trade_usage > 0);
+        assert_eq!(pending_trade_usage + asset_usage, reserved);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account bob.near needs")]
+    fn test_buy_asset_with_ausd_storage_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        // Only the minimum storage balance
+        context.predecessor_account_id = bob();
+        context.attached_deposit = contract.storage_balance_bounds().min.0;
+        testing_env!(context.clone());
+        contract.storage_deposit(None, None);
+        context.attached_deposit = 0;
+        testing_env!(context);
+        contract.buy_asset_with_ausd("aBTC".to_string(), "60".to_string(), None, None);
+    }
+
+    #[test]
+    fn test_total_asset_exposure_follows_prices() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        contract.submit_asset_price("aETH".to_string(), "200000000000".to_string());
+        contract.define_index_asset("aDEFI".to_string(), defi_index());
+        testing_env!(get_context("ausd".to_string()));
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 40);
+        buy_asset(&mut contract, &bob(), &"aDEFI".to_string(), 10);
+        assert_eq!(
+            contract.get_total_asset_exposure(),
+            (40 * 30000 + 10 * 19000).to_string()
+        );
+
+        // a component price revalues the index too
+        testing_env!(get_context(carol()));
+        contract.submit_asset_price("aBTC".to_string(), "4000000000000".to_string());
+        assert_eq!(
+            contract.get_total_asset_exposure(),
+            (40 * 40000 + 10 * 24000).to_string()
+        );
+        testing_env!(get_context(bob()));
+        contract.sell_asset_to_ausd("aBTC".to_string(), "40".to_string(), None, None);
+        testing_env!(
+            get_context(alice()),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_asset_sold(
+            bob(),
+            "aBTC".to_string(),
+            U128(40),
+            U128(40 * 40000),
+            U128(4000000000000),
+        );
+        assert_eq!(
+            contract.get_total_asset_exposure(),
+            (10 * 24000).to_string()
+        );
+    }
+
+    #[test]
+    fn test_sell_asset_to_ausd_returned_if_mint_fails() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 40);
+        testing_env!(get_context(bob()));
+        contract.sell_asset_to_ausd("aBTC".to_string(), "40".to_string(), None, None);
+        assert_eq!(contract.get_asset_balance(bob(), "aBTC".to_string()), "0");
+
+        testing_env!(
+            get_context(alice()),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_asset_sold(
+            bob(),
+            "aBTC".to_string(),
+            U128(40),
+            U128(40 * 30000),
+            U128(3000000000000),
+        );
+        assert_eq!(contract.get_asset_balance(bob(), "aBTC".to_string()), "40");
+        assert_eq!(
+            contract.get_total_asset_exposure(),
+            (40 * 30000).to_string()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Total asset exposure cap exceeded")]
+    fn test_total_asset_exposure_cap_exceeded() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        contract.submit_asset_price("aETH".to_string(), "200000000000".to_string());
+        contract.set_max_total_asset_exposure(Some("1000000".to_string()));
+        testing_env!(get_context("ausd".to_string()));
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 30);
+        testing_env!(get_context(bob()));
+        // 30 * 30000 + 51 * 2000 > 1000000
+        contract.buy_asset_with_ausd("aETH".to_string(), "51".to_string(), None, None);
+    }
+
+    #[test]
+    fn test_sell_order_lifecycle() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        testing_env!(get_context("ausd".to_string()));
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 100);
+
+        testing_env!(get_context(bob()));
+        let take_profit = contract.place_sell_order(
+            "aBTC".to_string(),
+            "60".to_string(),
+            "4000000000000".to_string(),
+            TriggerDirection::Above,
+        );
+        let stop_loss = contract.place_sell_order(
+            "aBTC".to_string(),
+            "40".to_string(),
+            "2000000000000".to_string(),
+            TriggerDirection::Below,
+        );
+        assert_eq!(contract.get_asset_balance(bob(), "aBTC".to_string()), "0");
+        assert_eq!(contract.get_orders(bob(), 0, 10).len(), 2);
+        assert_eq!(contract.order_counts.get(&bob()), Some(2));
+        assert!(contract.get_executable_orders(0, 10).is_empty());
+
+        testing_env!(get_context(carol()));
+        contract.submit_asset_price("aBTC".to_string(), "4500000000000".to_string());
+        let executable = contract.get_executable_orders(0, 10);
+        assert_eq!(executable.len(), 1);
+        assert_eq!(executable[0].id, take_profit);
+
+        // carol acts as keeper, the supply is reduced once bob's aUSD is minted
+        let order = contract.get_order(take_profit).unwrap();
+        contract.execute_order(take_profit);
+        assert_eq!(contract.get_number_of_orders(), 1);
+        assert_eq!(contract.get_asset_supply("aBTC".to_string()), "100");
+        testing_env!(
+            get_context(alice()),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_sell_order_paid(order, carol(), U128(270000), U128(4500000000000));
+        assert_eq!(contract.get_asset_supply("aBTC".to_string()), "40");
+        assert_eq!(contract.order_counts.get(&bob()), Some(1));
+
+        testing_env!(get_context(bob()));
+        contract.cancel_order(stop_loss);
+        assert_eq!(contract.get_asset_balance(bob(), "aBTC".to_string()), "40");
+        assert!(contract.get_orders(bob(), 0, 10).is_empty());
+        assert_eq!(contract.order_counts.get(&bob()), None);
+    }
+
+    #[test]
+    fn test_sell_order_restored_if_mint_failed() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        testing_env!(get_context("ausd".to_string()));
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 100);
+        testing_env!(get_context(bob()));
+        let order_id = contract.place_sell_order(
+            "aBTC".to_string(),
+            "100".to_string(),
+            "2000000000000".to_string(),
+            TriggerDirection::Below,
+        );
+        testing_env!(get_context(carol()));
+        contract.submit_asset_price("aBTC".to_string(), "1000000000000".to_string());
+        let order = contract.get_order(order_id).unwrap();
+        contract.execute_order(order_id);
+        assert!(contract.get_order(order_id).is_none());
+
+        testing_env!(
+            get_context(alice()),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_sell_order_paid(order, carol(), U128(100000), U128(1000000000000));
+        assert_eq!(contract.get_order(order_id).unwrap().amount.0, 100);
+        assert_eq!(contract.get_asset_supply("aBTC".to_string()), "100");
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't place orders of a delisted asset")]
+    fn test_sell_order_of_delisted_asset_fail() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        testing_env!(get_context("ausd".to_string()));
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 100);
+        testing_env!(get_context(carol()));
+        contract.delist_asset("aBTC".to_string());
+        testing_env!(get_context(bob()));
+        contract.place_sell_order(
+            "aBTC".to_string(),
+            "100".to_string(),
+            "2000000000000".to_string(),
+            TriggerDirection::Below,
+        );
+    }
+
+    #[test]
+    fn test_get_orders_paginated() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        register(&mut contract, &carol());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        testing_env!(get_context("ausd".to_string()));
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 100);
+        buy_asset(&mut contract, &carol(), &"aBTC".to_string(), 100);
+        for account_id in [bob(), carol(), bob()].iter() {
+            testing_env!(get_context(account_id.clone()));
+            contract.place_sell_order(
+                "aBTC".to_string(),
+                "10".to_string(),
+                "2000000000000".to_string(),
+                TriggerDirection::Below,
+            );
+        }
+        assert_eq!(contract.get_number_of_orders(), 3);
+        assert_eq!(contract.get_orders(bob(), 0, 2).len(), 1);
+        assert_eq!(contract.get_orders(bob(), 0, 3).len(), 2);
+        assert_eq!(contract.get_orders(bob(), 2, 10)[0].id, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Order is not triggered at current price")]
+    fn test_execute_untriggered_order_fail() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        testing_env!(get_context("ausd".to_string()));
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 100);
+        testing_env!(get_context(bob()));
+        let order_id = contract.place_sell_order(
+            "aBTC".to_string(),
+            "100".to_string(),
+            "2000000000000".to_string(),
+            TriggerDirection::Below,
+        );
+        contract.execute_order(order_id);
+    }
+
+    #[test]
+    fn test_delayed_settlement() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.set_settlement_waiting_period(60);
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        context.predecessor_account_id = "ausd".to_string();
+        testing_env!(context.clone());
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 100);
+        assert_eq!(contract.get_pending_trades(bob(), "aBTC".to_string()).len(), 1);
+
+        // price submitted within the waiting period doesn't settle the trade
+        context.predecessor_account_id = carol();
+        context.block_timestamp = 30;
+        testing_env!(context.clone());
+        contract.submit_asset_price("aBTC".to_string(), "3100000000000".to_string());
+        assert!(contract
+            .settle_asset_trades(bob(), "aBTC".to_string())
+            .is_none());
+
+        context.block_timestamp = 61;
+        testing_env!(context.clone());
+        contract.submit_asset_price("aBTC".to_string(), "3300000000000".to_string());
+        context.block_timestamp = 100;
+        testing_env!(context.clone());
+        contract.submit_asset_price("aBTC".to_string(), "3500000000000".to_string());
+        assert!(contract
+            .settle_asset_trades(bob(), "aBTC".to_string())
+            .is_some());
+        // the trade stays pending until the charge is burnt
+        assert_eq!(contract.get_pending_trades(bob(), "aBTC".to_string()).len(), 1);
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_settlement_charged(bob(), "aBTC".to_string(), U128(9));
+        assert!(contract
+            .get_pending_trades(bob(), "aBTC".to_string())
+            .is_empty());
+        assert_eq!(contract.get_asset_balance(bob(), "aBTC".to_string()), "100");
+    }
+
+    #[test]
+    fn test_settlement_charge_taken_from_asset() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.set_settlement_waiting_period(60);
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        context.predecessor_account_id = "ausd".to_string();
+        testing_env!(context.clone());
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 100);
+        context.predecessor_account_id = carol();
+        context.block_timestamp = 61;
+        testing_env!(context.clone());
+        contract.submit_asset_price("aBTC".to_string(), "3300000000000".to_string());
+        contract.settle_asset_trades(bob(), "aBTC".to_string());
+
+        // bob has no aUSD, 100 * $3000 is taken as 9 aBTC at $33000
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_settlement_charged(bob(), "aBTC".to_string(), U128(9));
+        assert!(contract
+            .get_pending_trades(bob(), "aBTC".to_string())
+            .is_empty());
+        assert_eq!(contract.get_asset_balance(bob(), "aBTC".to_string()), "91");
+        assert_eq!(contract.get_asset_supply("aBTC".to_string()), "91");
+    }
+
+    #[test]
+    fn test_settlement_rebate_retried_if_mint_fails() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.set_settlement_waiting_period(60);
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        context.predecessor_account_id = "ausd".to_string();
+        testing_env!(context.clone());
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 100);
+        context.predecessor_account_id = carol();
+        context.block_timestamp = 61;
+        testing_env!(context.clone());
+        contract.submit_asset_price("aBTC".to_string(), "2700000000000".to_string());
+        assert!(contract
+            .settle_asset_trades(bob(), "aBTC".to_string())
+            .is_some());
+
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_settlement_rebated(bob(), "aBTC".to_string());
+        assert_eq!(
+            contract.get_pending_trades(bob(), "aBTC".to_string()).len(),
+            1
+        );
+        assert!(contract
+            .settle_asset_trades(bob(), "aBTC".to_string())
+            .is_some());
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_settlement_rebated(bob(), "aBTC".to_string());
+        assert!(contract
+            .get_pending_trades(bob(), "aBTC".to_string())
+            .is_empty());
+        assert_eq!(contract.get_asset_balance(bob(), "aBTC".to_string()), "100");
+    }
+
+    #[test]
+    #[should_panic(expected = "aBTC has unsettled trades, call settle_asset_trades first")]
+    fn test_sell_while_settlement_charged_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.set_settlement_waiting_period(60);
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        context.predecessor_account_id = "ausd".to_string();
+        testing_env!(context.clone());
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 100);
+        context.predecessor_account_id = carol();
+        context.block_timestamp = 61;
+        testing_env!(context.clone());
+        contract.submit_asset_price("aBTC".to_string(), "3300000000000".to_string());
+        contract.settle_asset_trades(bob(), "aBTC".to_string());
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        contract.sell_asset_to_ausd("aBTC".to_string(), "100".to_string(), None, None);
+    }
+
+    #[test]
+    fn test_price_rounds_pruned() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.set_settlement_waiting_period(60);
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        contract.submit_asset_price("aBTC".to_string(), "3100000000000".to_string());
+        let key = ("aBTC".to_string(), 1);
+        assert!(contract.asset_round_prices.get(&key).is_some());
+
+        context.block_timestamp = MAX_PRICE_ROUND_AGE + 1;
+        testing_env!(context.clone());
+        contract.submit_asset_price("aBTC".to_string(), "3200000000000".to_string());
+        assert!(contract.asset_round_prices.get(&key).is_none());
+        assert!(contract
+            .asset_round_prices
+            .get(&("aBTC".to_string(), 2))
+            .is_none());
+        assert!(contract
+            .asset_round_prices
+            .get(&("aBTC".to_string(), 3))
+            .is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "aBTC has unsettled trades, call settle_asset_trades first")]
+    fn test_sell_unsettled_asset_fail() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.set_settlement_waiting_period(60);
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        testing_env!(get_context("ausd".to_string()));
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 100);
+        testing_env!(get_context(bob()));
+        contract.sell_asset_to_ausd("aBTC".to_string(), "100".to_string(), None, None);
+    }
+
+    fn defi_index() -> Vec<IndexComponent> {
+        vec![
+            IndexComponent {
+                asset: "aBTC".to_string(),
+                weight: 50_000_000.into(),
+            },
+            IndexComponent {
+                asset: "aETH".to_string(),
+                weight: 200_000_000.into(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_index_asset_price() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        contract.submit_asset_price("aETH".to_string(), "200000000000".to_string());
+        contract.define_index_asset("aDEFI".to_string(), defi_index());
+        // 0.5 * $30000 + 2 * $2000
+        assert_eq!(
+            contract.get_asset_price("aDEFI".to_string()),
+            "1900000000000"
+        );
+        assert_eq!(contract.get_index_assets(), vec!["aDEFI".to_string()]);
+
+        testing_env!(get_context("ausd".to_string()));
+        buy_asset(&mut contract, &bob(), &"aDEFI".to_string(), 10);
+        assert_eq!(contract.get_asset_balance(bob(), "aDEFI".to_string()), "10");
+        assert_eq!(
+            contract.get_open_interest("aDEFI".to_string()).value.0,
+            10 * 19000
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Price of aBTC is stale")]
+    fn test_index_asset_stale_price() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        context.block_timestamp = 2 * 60 * 60 * 1_000_000_000;
+        testing_env!(context.clone());
+        contract.submit_asset_price("aETH".to_string(), "200000000000".to_string());
+        contract.define_index_asset("aDEFI".to_string(), defi_index());
+        contract.get_asset_price("aDEFI".to_string());
+    }
+
+    #[test]
+    fn test_index_asset_stale_price_in_views() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        contract.submit_asset_price("aETH".to_string(), "200000000000".to_string());
+        contract.define_index_asset("aDEFI".to_string(), defi_index());
+        context.predecessor_account_id = "ausd".to_string();
+        testing_env!(context.clone());
+        buy_asset(&mut contract, &bob(), &"aDEFI".to_string(), 10);
+        buy_asset(&mut contract, &bob(), &"aETH".to_string(), 10);
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        contract.place_sell_order(
+            "aDEFI".to_string(),
+            "10".to_string(),
+            "1000000000000".to_string(),
+            TriggerDirection::Above,
+        );
+
+        context.predecessor_account_id = carol();
+        context.block_timestamp = 2 * 60 * 60 * 1_000_000_000;
+        testing_env!(context.clone());
+        contract.submit_asset_price("aETH".to_string(), "200000000000".to_string());
+        let open_interests = contract.get_open_interests();
+        let index = open_interests
+            .iter()
+            .find(|open_interest| open_interest.asset == "aDEFI")
+            .unwrap();
+        assert_eq!(index.supply.0, 10);
+        assert_eq!(index.value.0, 0);
+        assert_eq!(
+            index.price_error,
+            Some("Price of aBTC is stale".to_string())
+        );
+        // the index is valued at its last price
+        assert_eq!(
+            contract.get_total_asset_exposure(),
+            (10 * 19000 + 10 * 2000).to_string()
+        );
+        assert_eq!(contract.get_executable_orders(0, 10).len(), 0);
+    }
+
+    #[test]
+    fn test_sell_art_to_near() {
+        let mut context = get_context(carol());
+        context.account_balance = 1000 * 10u128.pow(24);
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_price("2000000000".to_string());
+        contract.submit_asset_price("aNEAR".to_string(), "500000000".to_string());
+        contract.transfer(bob(), "1000".to_string());
+
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        contract.sell_art_to_near("1000".to_string());
+        assert_eq!(contract.get_unstaked_balance(bob()), "0");
+        assert_eq!(
+            contract.get_unstaked_balance(carol()),
+            (total_supply - 1000).to_string()
+        );
+
+        // owner gets the art once the NEAR is sent
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_art_sold_for_near(bob(), U128(1000), U128(4000), U128(0));
+        assert_eq!(
+            contract.get_unstaked_balance(carol()),
+            total_supply.to_string()
+        );
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"nep141","version":"1.0.0","event":"ft_transfer","data":[{"old_owner_id":"bob.near","new_owner_id":"carol.near","amount":"1000"}]}"#,
+                r#"EVENT_JSON:{"standard":"art","version":"1.0.0","event":"exchange","data":[{"account_id":"bob.near","path":"art_to_near","amount_in":"1000","amount_out":"4000","fee":"0"}]}"#
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sell_art_to_near_refund() {
+        let mut context = get_context(carol());
+        context.account_balance = 1000 * 10u128.pow(24);
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_price("2000000000".to_string());
+        contract.submit_asset_price("aNEAR".to_string(), "500000000".to_string());
+        contract.transfer(bob(), "1000".to_string());
+
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        contract.sell_art_to_near("1000".to_string());
+        // owner spent all its art meanwhile, the refund doesn't depend on it
+        context.predecessor_account_id = carol();
+        testing_env!(context.clone());
+        contract.transfer(bob(), (total_supply - 1000).to_string());
+
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_art_sold_for_near(bob(), U128(1000), U128(4000), U128(0));
+        assert_eq!(
+            contract.get_unstaked_balance(bob()),
+            total_supply.to_string()
+        );
+        assert_eq!(contract.get_unstaked_balance(carol()), "0");
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough NEAR in reserve")]
+    fn test_sell_art_to_near_reserve_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.submit_price("2000000000".to_string());
+        contract.submit_asset_price("aNEAR".to_string(), "500000000".to_string());
+        context.account_balance = 10u128.pow(24);
+        testing_env!(context.clone());
+        contract.sell_art_to_near("1000".to_string());
+    }
+
+    #[test]
+    fn test_pool_reverted_if_near_not_sent() {
+        let mut context = get_context(carol());
+        context.account_balance = 1000 * 10u128.pow(24);
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.transfer(bob(), (10 * 10u128.pow(24)).to_string());
+
+        context.attached_deposit = 100 * 10u128.pow(24);
+        testing_env!(context.clone());
+        let shares = contract.add_liquidity((1000 * 10u128.pow(24)).to_string());
+
+        context.predecessor_account_id = bob();
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        contract.swap_art_to_near((10 * 10u128.pow(24)).to_string(), "0".to_string());
+        let near_out = 100 * 10u128.pow(24) - contract.get_pool().near_reserve.0;
+        // The swapped art isn't in the pool while the NEAR is being sent
+        assert_eq!(contract.get_pool().art_reserve.0, 1000 * 10u128.pow(24));
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_pool_near_sent(bob(), U128(10 * 10u128.pow(24)), U128(near_out), U128(0));
+        let pool = contract.get_pool();
+        assert_eq!(pool.art_reserve.0, 1000 * 10u128.pow(24));
+        assert_eq!(pool.near_reserve.0, 100 * 10u128.pow(24));
+        assert_eq!(
+            contract.get_unstaked_balance(bob()),
+            (10 * 10u128.pow(24)).to_string()
+        );
+
+        context.predecessor_account_id = carol();
+        testing_env!(context.clone());
+        let balance = contract.get_unstaked_balance(carol());
+        contract.remove_liquidity(shares.clone());
+        // The withdrawn art isn't credited while the NEAR is being sent
+        assert_eq!(contract.get_unstaked_balance(carol()), balance);
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_pool_near_sent(
+            carol(),
+            U128(1000 * 10u128.pow(24)),
+            U128(100 * 10u128.pow(24)),
+            U128(shares.parse().unwrap()),
+        );
+        let pool = contract.get_pool();
+        assert_eq!(pool.art_reserve.0, 1000 * 10u128.pow(24));
+        assert_eq!(pool.near_reserve.0, 100 * 10u128.pow(24));
+        assert_eq!(contract.get_pool_shares(carol()), shares);
+        assert_eq!(contract.get_unstaked_balance(carol()), balance);
+    }
+
+    #[test]
+    fn test_add_liquidity_exact_ratio() {
+        let mut context = get_context(carol());
+        context.account_balance = 1000 * 10u128.pow(24);
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        context.attached_deposit = 100 * 10u128.pow(24);
+        testing_env!(context.clone());
+        contract.add_liquidity((1000 * 10u128.pow(24)).to_string());
+
+        // Exactly the pool ratio, so no rounding up
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context.clone());
+        contract.add_liquidity((10 * 10u128.pow(24)).to_string());
+        assert_eq!(contract.get_pool().art_reserve.0, 1010 * 10u128.pow(24));
+    }
+
+    #[test]
+    #[should_panic(expected = "Output amount is 0")]
+    fn test_swap_near_to_art_zero_output_fail() {
+        let mut context = get_context(carol());
+        context.account_balance = 1000 * 10u128.pow(24);
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context.clone());
+        contract.add_liquidity("1".to_string());
+
+        context.attached_deposit = 1;
+        testing_env!(context.clone());
+        contract.swap_near_to_art("0".to_string());
+    }
+
+    #[test]
+    fn test_pool_liquidity_and_swap() {
+        let mut context = get_context(carol());
+        context.account_balance = 1000 * 10u128.pow(24);
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.transfer(bob(), (10 * 10u128.pow(24)).to_string());
+
+        context.attached_deposit = 100 * 10u128.pow(24);
+        testing_env!(context.clone());
+        let shares = contract.add_liquidity((1000 * 10u128.pow(24)).to_string());
+        assert_eq!(shares, (100 * 10u128.pow(24)).to_string());
+        assert_eq!(contract.get_pool_shares(carol()), shares);
+
+        context.predecessor_account_id = bob();
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        contract.swap_art_to_near((10 * 10u128.pow(24)).to_string(), "0".to_string());
+        // Less than 1 NEAR at the 0.1 NEAR spot price, due to price impact and fee
+        let near_out = 100 * 10u128.pow(24) - contract.get_pool().near_reserve.0;
+        assert!(near_out < 10u128.pow(24));
+        assert_eq!(near_out, 987158034397061298850460);
+        assert_eq!(contract.get_unstaked_balance(bob()), "0");
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_pool_near_sent(bob(), U128(10 * 10u128.pow(24)), U128(near_out), U128(0));
+        assert_eq!(contract.get_pool().art_reserve.0, 1010 * 10u128.pow(24));
+
+        context.predecessor_account_id = carol();
+        testing_env!(context.clone());
+        contract.remove_liquidity(shares.clone());
+        let pool = contract.get_pool();
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_pool_near_sent(
+            carol(),
+            U128(1010 * 10u128.pow(24)),
+            U128(100 * 10u128.pow(24) - near_out),
+            U128(shares.parse().unwrap()),
+        );
+        assert_eq!(pool.total_shares.0, 0);
+        assert_eq!(pool.art_reserve.0, 0);
+        assert_eq!(pool.near_reserve.0, 0);
+        assert_eq!(contract.get_pool_shares(carol()), "0");
+        // The art bob swapped in went to the only liquidity provider
+        assert_eq!(
+            contract.get_unstaked_balance(carol()),
+            total_supply.to_string()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Output amount is less than min_art_out")]
+    fn test_pool_swap_min_out_fail() {
+        let mut context = get_context(carol());
+        context.attached_deposit = 100 * 10u128.pow(24);
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.add_liquidity((1000 * 10u128.pow(24)).to_string());
+
+        context.predecessor_account_id = bob();
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context.clone());
+        // 10 art at the spot price, but the fee and price impact lower the output
+        contract.swap_near_to_art((10 * 10u128.pow(24)).to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Output amount is less than min_amount_out")]
+    fn test_buy_art_with_near_moved_price_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.submit_price("2000000000".to_string());
+        contract.submit_asset_price("aNEAR".to_string(), "500000000".to_string());
+        // 100 NEAR buy 25 art when the transaction is signed, then art goes up to $21
+        let min_amount_out = (25 * 10u128.pow(24)).to_string();
+        contract.submit_price("2100000000".to_string());
+
+        context.predecessor_account_id = bob();
+        context.attached_deposit = 100 * 10u128.pow(24);
+        testing_env!(context.clone());
+        contract.buy_art_with_near(Some(min_amount_out), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Input amount is more than max_ausd_amount")]
+    fn test_buy_asset_with_ausd_moved_price_fail() {
+        let context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.submit_asset_price("aBTC".to_string(), "5000000000000".to_string());
+        // 1 aBTC costs 50000 aUSD when the transaction is signed, then aBTC goes up
+        contract.submit_asset_price("aBTC".to_string(), "5100000000000".to_string());
+        contract.buy_asset_with_ausd(
+            "aBTC".to_string(),
+            "1".to_string(),
+            Some("50000".to_string()),
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Deadline has passed")]
+    fn test_exchange_art_to_ausd_deadline_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.submit_price("2000000000".to_string());
+
+        context.block_timestamp = 2_000_000_000;
+        testing_env!(context.clone());
+        contract.exchange_art_to_ausd("1000".to_string(), None, Some(1_000_000_000.into()));
+    }
+
+    #[test]
+    fn test_quotes() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_price("2000000000".to_string());
+        contract.submit_asset_price("aNEAR".to_string(), "500000000".to_string());
+        contract.submit_asset_price("aBTC".to_string(), "5000000000000".to_string());
+
+        let quote = contract.quote_stake_and_mint("1000".to_string());
+        assert_eq!(quote.amount_out.0, 4000);
+        assert_eq!(quote.price.0, 2000000000);
+        let quote = contract.quote_burn_to_unstake("1000".to_string());
+        assert_eq!(quote.amount_in.0, 4000);
+
+        let quote = contract.quote_exchange("art".to_string(), "1000".to_string());
+        assert_eq!(quote.amount_out.0, 1000 * 20 / 1000 * 997);
+        assert_eq!(quote.fee.0, 1000 * 20 / 1000 * 3);
+        let quote = contract.quote_exchange("aUSD".to_string(), "20000".to_string());
+        assert_eq!(quote.amount_out.0, 20000 / 20 / 1000 * 997);
+        assert_eq!(quote.fee.0, 20000 / 20 / 1000 * 3);
+
+        // 1 art = 4 NEAR
+        let quote = contract.quote_buy_with_near("art".to_string(), "100".to_string());
+        assert_eq!(quote.amount_out.0, 25);
+        assert_eq!(quote.price.0, 400000000);
+        let quote = contract.quote_buy_with_near("aUSD".to_string(), "100".to_string());
+        assert_eq!(quote.amount_out.0, 500);
+        let quote = contract.quote_sell_for_near("aUSD".to_string(), "5000".to_string());
+        assert_eq!(quote.amount_out.0, 997);
+        assert_eq!(quote.fee.0, 3);
+
+        let quote = contract.quote_asset_trade("aBTC".to_string(), OrderSide::Buy, "2".to_string());
+        assert_eq!(quote.amount_in.0, 100000);
+        assert_eq!(quote.price.0, 5000000000000);
+
+        // Execution gives the quoted amount
+        let quote =
+            contract.quote_buy_with_near("art".to_string(), (100 * 10u128.pow(24)).to_string());
+        context.predecessor_account_id = bob();
+        context.attached_deposit = 100 * 10u128.pow(24);
+        testing_env!(context.clone());
+        contract.buy_art_with_near(None, None);
+        assert_eq!(
+            contract.get_unstaked_balance(bob()),
+            quote.amount_out.0.to_string()
+        );
+    }
+
+    #[test]
+    fn test_reserve_conversion() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.submit_price("2000000000".to_string());
+        contract.set_conversion_mode(ConversionMode::Reserve);
+        contract.set_art_reserve_bounds("100".to_string(), Some("10000".to_string()));
+
+        // Art paid in stays in the reserve instead of going to owner
+        contract.exchange_art_to_ausd("1000".to_string(), None, None);
+        assert_eq!(
+            contract.get_unstaked_balance(carol()),
+            (total_supply - 1000).to_string()
+        );
+        let reserve = contract.get_art_reserve();
+        assert_eq!(reserve.art_reserve.0, 1000);
+        assert_eq!(reserve.available_art.0, 900);
+
+        let quote = contract.quote_exchange("aUSD".to_string(), "4000".to_string());
+        contract.exchange_ausd_to_art("4000".to_string(), None, None);
+        let taken = quote.amount_out.0 + quote.fee.0;
+        assert_eq!(contract.get_art_reserve().art_reserve.0, 1000 - taken);
+
+        // Art is credited once the aUSD is burnt, carol is also the treasury and gets the fee
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_reserve_ausd_burned(carol(), quote.amount_out, U128(4000), quote.fee);
+        assert_eq!(
+            contract.get_unstaked_balance(carol()),
+            (total_supply - 1000 + taken).to_string()
+        );
+
+        // and returned to the reserve if the burn failed
+        context.predecessor_account_id = carol();
+        testing_env!(context.clone());
+        contract.exchange_ausd_to_art("4000".to_string(), None, None);
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_reserve_ausd_burned(carol(), quote.amount_out, U128(4000), quote.fee);
+        assert_eq!(contract.get_art_reserve().art_reserve.0, 1000 - taken);
+        assert_eq!(
+            contract.get_unstaked_balance(carol()),
+            (total_supply - 1000 + taken).to_string()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough art in the reserve")]
+    fn test_reserve_conversion_below_min_fail() {
+        let context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.submit_price("2000000000".to_string());
+        contract.set_conversion_mode(ConversionMode::Reserve);
+        contract.set_art_reserve_bounds("500".to_string(), None);
+        contract.exchange_art_to_ausd("1000".to_string(), None, None);
+        // Would take about 997 art out of the reserve, but only 500 is above the minimum
+        contract.exchange_ausd_to_art("20000".to_string(), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Exchange would take the art reserve above its maximum")]
+    fn test_reserve_conversion_above_max_fail() {
+        let context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.submit_price("2000000000".to_string());
+        contract.set_conversion_mode(ConversionMode::Reserve);
+        contract.set_art_reserve_bounds("0".to_string(), Some("1000".to_string()));
+        contract.exchange_art_to_ausd("1001".to_string(), None, None);
+    }
+
+    #[test]
+    fn test_conversion_fees() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        register(&mut contract, &alice());
+        contract.submit_price("2000000000".to_string());
+        contract.set_governance(Some(alice().try_into().unwrap()));
+
+        context.predecessor_account_id = alice();
+        testing_env!(context.clone());
+        contract.set_conversion_fee_bps(ConversionPath::AusdToArt, 100);
+        contract.set_treasury(alice().try_into().unwrap());
+
+        // 20000 aUSD buys 1000 art, 1% of it goes to the treasury
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        contract.exchange_ausd_to_art("20000".to_string(), None, None);
+        assert_eq!(contract.get_unstaked_balance(bob()), "0");
+        assert_eq!(
+            contract.get_unstaked_balance(carol()),
+            (total_supply - 1000).to_string()
+        );
+        testing_env!(
+            get_context(alice()),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_exchange_settled(
+            bob(),
+            ConversionPath::AusdToArt,
+            U128(20000),
+            U128(990),
+            U128(10),
+        );
+        assert_eq!(contract.get_unstaked_balance(bob()), "990");
+        assert_eq!(contract.get_unstaked_balance(alice()), "10");
+
+        let fees = contract.get_conversion_fees();
+        let fee = fees
+            .iter()
+            .find(|fee| fee.path == ConversionPath::AusdToArt)
+            .unwrap();
+        assert_eq!(fee.fee_bps, 100);
+        assert_eq!(fee.fee_token, "art");
+        assert_eq!(fee.collected.0, 10);
+        let fee = fees
+            .iter()
+            .find(|fee| fee.path == ConversionPath::ArtToAusd)
+            .unwrap();
+        assert_eq!(fee.fee_bps, 30);
+        assert_eq!(fee.collected.0, 0);
+    }
+
+    #[test]
+    fn test_exchange_reverted_if_ausd_not_moved() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_price("2000000000".to_string());
+        contract.transfer(bob(), "1000".to_string());
+
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        contract.exchange_ausd_to_art("20000".to_string(), None, None);
+        contract.exchange_art_to_ausd("1000".to_string(), None, None);
+        assert_eq!(contract.get_unstaked_balance(bob()), "0");
+        assert_eq!(
+            contract.get_unstaked_balance(carol()),
+            (total_supply - 2000).to_string()
+        );
+
+        testing_env!(
+            get_context(alice()),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_exchange_settled(
+            bob(),
+            ConversionPath::AusdToArt,
+            U128(20000),
+            U128(997),
+            U128(3),
+        );
+        contract.on_exchange_settled(
+            bob(),
+            ConversionPath::ArtToAusd,
+            U128(1000),
+            U128(19940),
+            U128(60),
+        );
+        assert_eq!(contract.get_unstaked_balance(bob()), "1000");
+        assert_eq!(
+            contract.get_unstaked_balance(carol()),
+            (total_supply - 1000).to_string()
+        );
+        assert!(get_logs().iter().all(|log| !log.starts_with("EVENT_JSON")));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only owner or governance can call this method")]
+    fn test_set_conversion_fee_bps_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        contract.set_conversion_fee_bps(ConversionPath::ArtToAusd, 0);
+    }
+
+    #[test]
+    fn test_swap() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_price("2000000000".to_string());
+        contract.submit_asset_price("aNEAR".to_string(), "500000000".to_string());
+        contract.submit_asset_price("aBTC".to_string(), "5000000000000".to_string());
+        contract.transfer(bob(), (10u128.pow(22)).to_string());
+
+        // 1 NEAR buys 5 aUSD worth of aBTC, without fee
+        context.predecessor_account_id = bob();
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context.clone());
+        let path = vec!["NEAR".to_string(), "aUSD".to_string(), "aBTC".to_string()];
+        let quote = contract.quote_swap(path.clone(), 10u128.pow(24).to_string());
+        assert_eq!(quote.amount_out.0, 10u128.pow(20));
+        contract.swap(path, 10u128.pow(24).to_string(), "0".to_string());
+        assert_eq!(
+            contract.get_asset_balance(bob(), "aBTC".to_string()),
+            10u128.pow(20).to_string()
+        );
+
+        // 0.01 art is 0.2 aUSD, minus 0.3% fee, in aBTC
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        let path = vec!["art".to_string(), "aUSD".to_string(), "aBTC".to_string()];
+        contract.swap(path, 10u128.pow(22).to_string(), "0".to_string());
+        assert_eq!(contract.get_unstaked_balance(bob()), "0");
+        assert_eq!(
+            contract.get_asset_balance(bob(), "aBTC".to_string()),
+            (10u128.pow(20) + 3_988_000_000_000_000_000).to_string()
+        );
+        // Owner receives the art
+        assert_eq!(
+            contract.get_unstaked_balance(carol()),
+            total_supply.to_string()
+        );
+        // The aUSD fee counts as collected once it's paid
+        let collected = |contract: &Art| {
+            contract
+                .get_conversion_fees()
+                .into_iter()
+                .find(|fee| fee.path == ConversionPath::ArtToAusd)
+                .unwrap()
+                .collected
+                .0
+        };
+        assert_eq!(collected(&contract), 0);
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_fee_paid(ConversionPath::ArtToAusd, U128(6 * 10u128.pow(20)));
+        assert_eq!(collected(&contract), 6 * 10u128.pow(20));
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_fee_paid(ConversionPath::ArtToAusd, U128(6 * 10u128.pow(20)));
+        assert_eq!(collected(&contract), 6 * 10u128.pow(20));
+
+        // And back to art, the aUSD in the middle is never minted
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        let path = vec!["aBTC".to_string(), "aUSD".to_string(), "art".to_string()];
+        let quote = contract.quote_swap(path.clone(), 10u128.pow(20).to_string());
+        contract.swap(path, 10u128.pow(20).to_string(), "0".to_string());
+        assert_eq!(
+            contract.get_unstaked_balance(bob()),
+            quote.amount_out.0.to_string()
+        );
+        assert_eq!(
+            contract.get_asset_balance(bob(), "aBTC".to_string()),
+            3_988_000_000_000_000_000u128.to_string()
+        );
+    }
+
+    #[test]
+    fn test_swap_rollback() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_price("2000000000".to_string());
+        contract.transfer(bob(), 1000.to_string());
+
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        let path = vec!["art".to_string(), "aUSD".to_string()];
+        contract.swap(path.clone(), "1000".to_string(), "0".to_string());
+        assert_eq!(contract.get_unstaked_balance(bob()), "0");
+
+        // Sending the aUSD failed, bob gets the art back
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        let quote = contract.quote_swap(path.clone(), "1000".to_string());
+        let amount_out = contract.on_swap_output_sent(SwapRoute {
+            account_id: bob(),
+            path,
+            amount_in: quote.amount_in,
+            amount_out: quote.amount_out,
+            fees: quote.fees,
+            asset_price: quote.asset_price,
+            round: 0.into(),
+            uses_art_reserve: false,
+        });
+        assert_eq!(amount_out.0, 0);
+        assert_eq!(contract.get_unstaked_balance(bob()), "1000");
+        assert_eq!(
+            contract.get_unstaked_balance(carol()),
+            (total_supply - 1000).to_string()
+        );
+        assert_eq!(contract.get_conversion_fees()[0].collected.0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Supply cap of aBTC exceeded")]
+    fn test_swap_asset_supply_cap_counts_pending_swaps() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        contract.set_asset_supply_cap("aBTC".to_string(), Some("100".to_string()));
+        testing_env!(get_context(bob()));
+        let path = vec!["aUSD".to_string(), "aBTC".to_string()];
+        contract.swap(path.clone(), "1800000".to_string(), "0".to_string());
+        // The aUSD of the first swap isn't taken yet, but its aBTC still counts against the cap
+        contract.swap(path, "1230000".to_string(), "0".to_string());
+    }
+
+    #[test]
+    fn test_swap_asset_reservation_released() {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        contract.set_asset_supply_cap("aBTC".to_string(), Some("100".to_string()));
+        testing_env!(get_context(bob()));
+        let path = vec!["aUSD".to_string(), "aBTC".to_string()];
+        let quote = contract.quote_swap(path.clone(), "1800000".to_string());
+        contract.swap(path.clone(), "1800000".to_string(), "0".to_string());
+        assert_eq!(
+            contract.reserved_asset_supplies.get(&"aBTC".to_string()),
+            Some(quote.amount_out.0)
+        );
+        let route = |contract: &Art| SwapRoute {
+            account_id: bob(),
+            path: path.clone(),
+            amount_in: quote.amount_in,
+            amount_out: quote.amount_out,
+            fees: contract
+                .quote_swap(path.clone(), "1800000".to_string())
+                .fees,
+            asset_price: quote.asset_price,
+            round: 1.into(),
+            uses_art_reserve: false,
+        };
+
+        testing_env!(
+            get_context(alice()),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_swap_input_taken(route(&contract));
+        assert_eq!(
+            contract.reserved_asset_supplies.get(&"aBTC".to_string()),
+            None
+        );
+        assert_eq!(contract.reserved_asset_exposure, 0);
+
+        testing_env!(get_context(bob()));
+        contract.swap(path.clone(), "1800000".to_string(), "0".to_string());
+        testing_env!(
+            get_context(alice()),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_swap_input_taken(route(&contract));
+        assert_eq!(
+            contract.reserved_asset_supplies.get(&"aBTC".to_string()),
+            None
+        );
+        assert_eq!(contract.reserved_asset_exposure, 0);
+        assert_eq!(
+            contract.get_asset_supply("aBTC".to_string()),
+            quote.amount_out.0.to_string()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Output amount is less than min_out")]
+    fn test_swap_min_out_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.submit_price("2000000000".to_string());
+        contract.submit_asset_price("aNEAR".to_string(), "500000000".to_string());
+
+        // 1000 yoctoNEAR buys 250 art
+        context.attached_deposit = 1000;
+        testing_env!(context.clone());
+        let path = vec!["NEAR".to_string(), "art".to_string()];
+        contract.swap(path, "1000".to_string(), "251".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only aUSD can be in the middle of a swap path")]
+    fn test_swap_path_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        context.attached_deposit = 1000;
+        testing_env!(context.clone());
+        let path = vec!["NEAR".to_string(), "art".to_string(), "aBTC".to_string()];
+        contract.swap(path, "1000".to_string(), "0".to_string());
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &alice());
+        register(&mut contract, &bob());
+        contract.submit_price("2000000000".to_string());
+        let hour = 60 * 60 * 1_000_000_000u64;
+        contract.set_rate_limit(
+            ConversionPath::AusdToArt,
+            hour.into(),
+            Some(30000.into()),
+            Some(50000.into()),
+        );
+
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        contract.exchange_ausd_to_art("20000".to_string(), None, None);
+        let allowance = contract.get_conversion_allowance(ConversionPath::AusdToArt, bob());
+        assert_eq!(allowance.account_remaining.unwrap().0, 10000);
+        assert_eq!(allowance.global_remaining.unwrap().0, 30000);
+        assert_eq!(allowance.window_ends_at.0, hour);
+
+        context.predecessor_account_id = alice();
+        testing_env!(context.clone());
+        contract.exchange_ausd_to_art("30000".to_string(), None, None);
+        let allowance = contract.get_conversion_allowance(ConversionPath::AusdToArt, bob());
+        assert_eq!(allowance.account_remaining.unwrap().0, 10000);
+        assert_eq!(allowance.global_remaining.unwrap().0, 0);
+
+        // Volumes reset in the next window
+        context.predecessor_account_id = bob();
+        context.block_timestamp = hour + 1;
+        testing_env!(context.clone());
+        contract.exchange_ausd_to_art("30000".to_string(), None, None);
+        let allowance = contract.get_conversion_allowance(ConversionPath::AusdToArt, bob());
+        assert_eq!(allowance.account_remaining.unwrap().0, 0);
+        assert_eq!(allowance.global_remaining.unwrap().0, 20000);
+        assert_eq!(allowance.window_ends_at.0, 2 * hour);
+    }
+
+    #[test]
+    fn test_rate_limit_refunded_on_revert() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_price("2000000000".to_string());
+        contract.set_conversion_mode(ConversionMode::Reserve);
+        contract.exchange_art_to_ausd("100000".to_string(), None, None);
+        contract.set_rate_limit(
+            ConversionPath::AusdToArt,
+            (60 * 60 * 1_000_000_000u64).into(),
+            Some(30000.into()),
+            Some(50000.into()),
+        );
+
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        let storage_balance = contract
+            .storage_balance_of(bob().try_into().unwrap())
+            .unwrap();
+        let quote = contract.internal_quote_ausd_to_art(20000);
+        contract.exchange_ausd_to_art("20000".to_string(), None, None);
+        // The volume is charged to bob's storage balance
+        assert!(
+            contract
+                .storage_balance_of(bob().try_into().unwrap())
+                .unwrap()
+                .available
+                .0
+                < storage_balance.available.0
+        );
+
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_reserve_ausd_burned(bob(), quote.amount_out, U128(20000), quote.fee);
+        let allowance = contract.get_conversion_allowance(ConversionPath::AusdToArt, bob());
+        assert_eq!(allowance.account_remaining.unwrap().0, 30000);
+        assert_eq!(allowance.global_remaining.unwrap().0, 50000);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Volume limit of ausd_to_art per account is reached, 10000 left in this window"
+    )]
+    fn test_rate_limit_account_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_price("2000000000".to_string());
+        contract.set_rate_limit(
+            ConversionPath::AusdToArt,
+            (60 * 60 * 1_000_000_000u64).into(),
+            Some(30000.into()),
+            None,
+        );
+
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        contract.exchange_ausd_to_art("20000".to_string(), None, None);
+        contract.exchange_ausd_to_art("20000".to_string(), None, None);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Volume limit of near_to_art for all accounts is reached, 1000 left in this window"
+    )]
+    fn test_rate_limit_swap_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_price("2000000000".to_string());
+        contract.submit_asset_price("aNEAR".to_string(), "500000000".to_string());
+        contract.set_rate_limit(
+            ConversionPath::NearToArt,
+            (60 * 60 * 1_000_000_000u64).into(),
+            None,
+            Some(1000.into()),
+        );
+
+        context.predecessor_account_id = bob();
+        context.attached_deposit = 1001;
+        testing_env!(context.clone());
+        let path = vec!["NEAR".to_string(), "art".to_string()];
+        contract.swap(path, "1001".to_string(), "0".to_string());
+    }
+
+    fn carol_permit(amount: Balance, nonce: u64) -> PermitMessage {
+        PermitMessage {
+            contract_id: alice(),
+            owner_id: carol(),
+            spender_id: bob(),
+            amount,
+            nonce,
+            deadline: 1000,
+        }
+    }
+
+    /// Carol's permit key is registered, bob relays her permits.
+    fn permit_contract() -> Art {
+        testing_env!(get_context(carol()));
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &carol());
+        contract.set_permit_key(Some(permit_public_key(&permit_keypair(1))));
+        testing_env!(get_context(bob()));
+        contract
+    }
+
+    #[test]
+    fn test_permit() {
+        let mut contract = permit_contract();
+        assert_eq!(
+            contract.get_permit_key(carol()),
+            Some(permit_public_key(&permit_keypair(1)))
+        );
+        assert_eq!(contract.get_permit_nonce(carol()).0, 0);
+        let keypair = permit_keypair(1);
+        let message = carol_permit(100, 0);
+        contract.permit(
+            carol(),
+            bob(),
+            "100".to_string(),
+            U64(0),
+            U64(1000),
+            sign_permit(&keypair, &message),
+        );
+        assert_eq!(contract.get_allowance(carol(), bob()), "100");
+        assert_eq!(contract.get_permit_nonce(carol()).0, 1);
+
+        let message = carol_permit(0, 1);
+        contract.permit(
+            carol(),
+            bob(),
+            "0".to_string(),
+            U64(1),
+            U64(1000),
+            sign_permit(&keypair, &message),
+        );
+        assert_eq!(contract.get_allowance(carol(), bob()), "0");
+        assert_eq!(contract.get_permit_nonce(carol()).0, 2);
+    }
+
+    #[test]
+    fn test_set_permit_key_tracks_storage() {
+        let mut contract = permit_contract();
+        testing_env!(get_context(carol()));
+        contract.set_permit_key(None);
+        let initial_usage = contract.storage_usages.get(&carol()).unwrap();
+        contract.set_permit_key(Some(permit_public_key(&permit_keypair(1))));
+        let key_usage = contract.storage_usages.get(&carol()).unwrap() - initial_usage;
+        assert!(key_usage > 0);
+        testing_env!(get_context(bob()));
+
+        let message = carol_permit(100, 0);
+        contract.permit(
+            carol(),
+            bob(),
+            "100".to_string(),
+            U64(0),
+            U64(1000),
+            sign_permit(&permit_keypair(1), &message),
+        );
+        let usage = contract.storage_usages.get(&carol()).unwrap();
+        assert!(usage > initial_usage + key_usage);
+
+        // Only the nonce is left charged
+        testing_env!(get_context(carol()));
+        contract.set_permit_key(None);
+        assert_eq!(
+            contract.storage_usages.get(&carol()).unwrap(),
+            usage - key_usage
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The account carol.near is not registered")]
+    fn test_set_permit_key_unregistered_fail() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(bob(), total_supply.to_string(), "ausd".to_string());
+        contract.set_permit_key(Some(permit_public_key(&permit_keypair(1))));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid permit nonce")]
+    fn test_permit_replay_fail() {
+        let mut contract = permit_contract();
+        let signature = sign_permit(&permit_keypair(1), &carol_permit(100, 0));
+        contract.permit(
+            carol(),
+            bob(),
+            "100".to_string(),
+            U64(0),
+            U64(1000),
+            signature.clone(),
+        );
+        contract.permit(
+            carol(),
+            bob(),
+            "100".to_string(),
+            U64(0),
+            U64(1000),
+            signature,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid permit signature")]
+    fn test_permit_wrong_key_fail() {
+        let mut contract = permit_contract();
+        let signature = sign_permit(&permit_keypair(2), &carol_permit(100, 0));
+        contract.permit(
+            carol(),
+            bob(),
+            "100".to_string(),
+            U64(0),
+            U64(1000),
+            signature,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid permit signature")]
+    fn test_permit_changed_amount_fail() {
+        let mut contract = permit_contract();
+        let signature = sign_permit(&permit_keypair(1), &carol_permit(100, 0));
+        contract.permit(
+            carol(),
+            bob(),
+            "1000".to_string(),
+            U64(0),
+            U64(1000),
+            signature,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Deadline has passed")]
+    fn test_permit_expired_fail() {
+        let mut contract = permit_contract();
+        let mut context = get_context(bob());
+        context.block_timestamp = 1001;
+        testing_env!(context);
+        let signature = sign_permit(&permit_keypair(1), &carol_permit(100, 0));
+        contract.permit(
+            carol(),
+            bob(),
+            "100".to_string(),
+            U64(0),
+            U64(1000),
+            signature,
+        );
+    }
+
+    #[test]
+    fn test_balance_checkpoints() {
+        let mut context = get_context(carol());
+        context.block_index = 1;
+        testing_env!(context.clone());
+        let mut contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+
+        context.block_index = 5;
+        testing_env!(context.clone());
+        contract.transfer(bob(), "300".to_string());
+        contract.submit_price("2000000000".to_string());
+        contract.stake_and_mint("100".to_string());
+
+        context.block_index = 10;
+        testing_env!(context.clone());
+        assert_eq!(contract.create_snapshot("vote".to_string()).0, 10);
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        contract.transfer(carol(), "100".to_string());
+
+        // One checkpoint is kept per block
+        context.predecessor_account_id = carol();
+        context.block_index = 12;
+        testing_env!(context.clone());
+        contract.transfer(bob(), "50".to_string());
+        context.block_index = 15;
+        testing_env!(context.clone());
+        contract.transfer(bob(), "50".to_string());
+        contract.transfer(bob(), "50".to_string());
+        assert_eq!(contract.balance_checkpoint_counts.get(&carol()), Some(5));
+
+        context.block_index = 20;
+        testing_env!(context.clone());
+        assert_eq!(contract.get_balance_at(carol(), U64(0)), "0");
+        assert_eq!(contract.get_balance_at(carol(), U64(1)), "1000");
+        assert_eq!(contract.get_balance_at(carol(), U64(4)), "1000");
+        // Staking doesn't change the balance
+        assert_eq!(contract.get_balance_at(carol(), U64(5)), "700");
+        assert_eq!(contract.get_balance_at(bob(), U64(5)), "300");
+        assert_eq!(contract.get_balance_at(carol(), U64(12)), "750");
+        assert_eq!(contract.get_balance_at(carol(), U64(14)), "750");
+        assert_eq!(contract.get_balance_at(carol(), U64(15)), "650");
+        assert_eq!(contract.get_balance_at(bob(), U64(19)), "350");
+        assert_eq!(contract.get_snapshot("vote".to_string()), Some(U64(10)));
+        assert_eq!(
+            contract.get_balance_at_snapshot(carol(), "vote".to_string()),
+            "800"
+        );
+        assert_eq!(
+            contract.get_balance_at_snapshot(bob(), "vote".to_string()),
+            "200"
+        );
+        assert_eq!(contract.get_total_supply_at(U64(0)), "0");
+        assert_eq!(
+            contract.get_total_supply_at_snapshot("vote".to_string()),
+            "1000"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The block height isn't in the past yet")]
+    fn test_balance_at_current_height_fail() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        contract.get_balance_at(carol(), U64(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "The snapshot already exists")]
+    fn test_create_snapshot_twice_fail() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        contract.create_snapshot("vote".to_string());
+        contract.create_snapshot("vote".to_string());
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut accounts = UnorderedMap::new(b"a".to_vec());
+        let owner = Account {
+            balance: 900,
+            staked_balance: 100,
+            ..Default::default()
+        };
+        accounts.insert(&carol(), &owner);
+        let mut account = Account::default();
+        account.assets.insert("aBTC".to_string(), 5);
+        accounts.insert(&bob(), &account);
+        let mut asset_prices = UnorderedMap::new(b"b".to_vec());
+        asset_prices.insert(&"aBTC".to_string(), &3000000000000);
+        env::state_write(&OldArt {
+            accounts,
+            total_supply: 1000,
+            price: 2000000000,
+            owner: carol(),
+            ausd_token: "ausd".to_string(),
+            total_staked: 100,
+            asset_prices,
+            reward_paid_at: UnorderedMap::new(b"d".to_vec()),
+            staking_reward_enabled_at: 0,
+            account_storage_usage: 0,
+        });
+
+        context.predecessor_account_id = alice();
+        testing_env!(context.clone());
+        let mut contract = Art::migrate();
+        assert_eq!(contract.ft_total_supply().0, 1000);
+        assert_eq!(contract.ft_balance_of(carol().try_into().unwrap()).0, 900);
+        assert_eq!(contract.get_asset_supply("aBTC".to_string()), "0");
+        assert_eq!(contract.supply_checkpoints.len(), 1);
+
+        context.predecessor_account_id = carol();
+        testing_env!(context.clone());
+        assert!(contract.migrate_accounts(1));
+        assert_eq!(contract.balance_checkpoint_counts.get(&carol()), Some(1));
+        assert_eq!(
+            contract
+                .storage_balance_of(bob().try_into().unwrap())
+                .unwrap()
+                .total
+                .0,
+            0
+        );
+        assert_eq!(contract.get_asset_supply("aBTC".to_string()), "0");
+        assert!(!contract.migrate_accounts(1));
+        assert!(contract.account_migration.is_none());
+        assert_eq!(contract.get_asset_supply("aBTC".to_string()), "5");
+        assert_eq!(contract.get_total_asset_exposure(), (5 * 30000).to_string());
+        let min_balance = contract.storage_balance_bounds().min.0;
+        assert!(min_balance > 0);
+        assert_eq!(
+            contract
+                .storage_balance_of(bob().try_into().unwrap())
+                .unwrap()
+                .total
+                .0,
+            min_balance
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Accounts are still being migrated")]
+    fn test_asset_trade_paused_while_migrating() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut accounts = UnorderedMap::new(b"a".to_vec());
+        accounts.insert(&carol(), &Account::default());
+        env::state_write(&OldArt {
+            accounts,
+            total_supply: 0,
+            price: 2000000000,
+            owner: carol(),
+            ausd_token: "ausd".to_string(),
+            total_staked: 0,
+            asset_prices: UnorderedMap::new(b"b".to_vec()),
+            reward_paid_at: UnorderedMap::new(b"d".to_vec()),
+            staking_reward_enabled_at: 0,
+            account_storage_usage: 0,
+        });
+
+        context.predecessor_account_id = alice();
+        testing_env!(context.clone());
+        let contract = Art::migrate();
+        contract.quote_asset_trade("aBTC".to_string(), OrderSide::Sell, "1".to_string());
+    }
+
+    const DAY: u64 = 24 * 60 * 60 * 1000000000;
+
+    fn bob_vesting_grant(contract: &mut Art, revocable: bool) -> u64 {
+        contract.create_vesting_grant(
+            bob().try_into().unwrap(),
+            "1000000000000".to_string(),
+            U64(0),
+            U64(10 * DAY),
+            U64(100 * DAY),
+            revocable,
+            true,
+        )
+    }
+
+    #[test]
+    fn test_vesting_grant() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = Art::new(carol(), "10000000000000".to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        let grant_id = bob_vesting_grant(&mut contract, true);
+        assert_eq!(contract.get_unstaked_balance(carol()), "9000000000000");
+        assert_eq!(contract.get_number_of_vesting_grants(), 1);
+        assert_eq!(contract.get_vesting_grants(bob(), 0, 10)[0].id, grant_id);
+        assert!(contract.get_vesting_grants(bob(), 1, 10).is_empty());
+        assert!(contract.get_vesting_grants(carol(), 0, 10).is_empty());
+
+        context.predecessor_account_id = bob();
+        context.block_timestamp = 5 * DAY;
+        testing_env!(context.clone());
+        let grant = contract.get_vesting_grant(grant_id).unwrap();
+        assert_eq!(grant.vested.0, 0);
+        assert_eq!(grant.cliff.0, 10 * DAY);
+        contract.stake_vesting(grant_id, "500000000000".to_string());
+        assert_eq!(contract.total_staked, 500000000000);
+
+        context.block_timestamp = 50 * DAY;
+        testing_env!(context.clone());
+        assert_eq!(
+            contract.get_vesting_grant(grant_id).unwrap().vested.0,
+            500000000000
+        );
+        let claimed = u128::from_str(&contract.claim_vesting(grant_id)).unwrap();
+        // The staking reward of 45 days is claimed with the vested art
+        assert!(claimed > 500000000000);
+        assert_eq!(contract.get_unstaked_balance(bob()), claimed.to_string());
+        let grant = contract.get_vesting_grant(grant_id).unwrap();
+        assert_eq!(grant.claimed.0, 500000000000);
+        assert_eq!(grant.claimable.0, 0);
+
+        context.predecessor_account_id = carol();
+        context.block_timestamp = 60 * DAY;
+        testing_env!(context.clone());
+        contract.revoke_vesting_grant(grant_id);
+        assert_eq!(contract.get_unstaked_balance(carol()), "9400000000000");
+        let grant = contract.get_vesting_grant(grant_id).unwrap();
+        assert_eq!(grant.amount.0, 600000000000);
+        assert_eq!(grant.staked.0, 100000000000);
+        assert_eq!(contract.total_staked, 100000000000);
+        assert!(!grant.revocable);
+
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        contract.unstake_vesting(grant_id, "100000000000".to_string());
+        contract.claim_vesting(grant_id);
+        assert!(contract.get_vesting_grant(grant_id).is_none());
+        assert!(contract.get_vesting_grants(bob(), 0, 10).is_empty());
+        assert_eq!(contract.total_staked, 0);
+    }
+
+    #[test]
+    fn test_revoke_vesting_grant_before_cliff() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = Art::new(carol(), "10000000000000".to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        let grant_id = bob_vesting_grant(&mut contract, true);
+
+        // Nothing vested or left to claim, so the grant is removed
+        context.block_timestamp = 5 * DAY;
+        testing_env!(context);
+        contract.revoke_vesting_grant(grant_id);
+        assert_eq!(contract.get_unstaked_balance(carol()), "10000000000000");
+        assert!(contract.get_vesting_grant(grant_id).is_none());
+        assert!(contract.vesting_grant_counts.get(&bob()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Nothing to claim")]
+    fn test_claim_vesting_before_cliff_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = Art::new(carol(), "10000000000000".to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        let grant_id = bob_vesting_grant(&mut contract, true);
+
+        context.predecessor_account_id = bob();
+        context.block_timestamp = 9 * DAY;
+        testing_env!(context);
+        contract.claim_vesting(grant_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "The vesting grant isn't revocable")]
+    fn test_revoke_irrevocable_vesting_grant_fail() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Art::new(carol(), "10000000000000".to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        let grant_id = bob_vesting_grant(&mut contract, false);
+        contract.revoke_vesting_grant(grant_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the account of the vesting grant can call this method")]
+    fn test_claim_vesting_other_account_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = Art::new(carol(), "10000000000000".to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        let grant_id = bob_vesting_grant(&mut contract, true);
+
+        context.block_timestamp = 50 * DAY;
+        testing_env!(context);
+        contract.claim_vesting(grant_id);
+    }
+
+    #[test]
+    fn test_liquid_staking() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.submit_price("2000000000".to_string());
+        contract.set_start_token("start.near".try_into().unwrap());
+        contract.stake_and_mint_liquid("1000000000000".to_string());
+        assert_eq!(contract.get_staked_balance(carol()), "1000000000000");
+
+        // The stake is wrapped once the aUSD is minted
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_stake_minted(carol(), U128(1_000_000_000_000), true);
+        assert_eq!(contract.get_staked_balance(carol()), "0");
+        assert_eq!(contract.get_liquid_staking().staked.0, 1_000_000_000_000);
+        assert_eq!(
+            contract.get_liquid_staking().start_supply.0,
+            1_000_000_000_000
+        );
+        assert_eq!(contract.total_staked, 1_000_000_000_000);
+
+        // The stake is returned if minting stART failed
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_start_minted(carol(), U128(400_000_000_000), U128(400_000_000_000));
+        assert_eq!(contract.get_staked_balance(carol()), "400000000000");
+        assert_eq!(
+            contract.get_liquid_staking().start_supply.0,
+            600_000_000_000
+        );
+
+        // The reward of a day goes to the pool, raising the value of stART
+        context.predecessor_account_id = carol();
+        context.block_timestamp = 24 * 60 * 60 * 1_000_000_000;
+        testing_env!(context.clone());
+        assert!(contract.refresh_liquid_reward());
+        assert_eq!(contract.get_liquid_staking().staked.0, 600_156_600_000);
+        assert_eq!(
+            contract.get_total_supply(),
+            (total_supply + 156_600_000).to_string()
+        );
+
+        testing_env!(context.clone());
+        contract.unwrap_stake("300000000000".to_string());
+        assert_eq!(contract.get_liquid_staking().staked.0, 300_078_300_000);
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_start_burned(carol(), U128(300_000_000_000), U128(300_078_300_000));
+        // With the reward of a day on the returned stake
+        assert_eq!(contract.get_staked_balance(carol()), "700182700000");
+        assert_eq!(
+            contract.get_liquid_staking().start_supply.0,
+            300_000_000_000
+        );
+        assert_eq!(
+            get_logs().last().unwrap(),
+            r#"EVENT_JSON:{"standard":"art","version":"1.0.0","event":"unwrap_stake","data":[{"account_id":"carol.near","amount":"300078300000","start_amount":"300000000000"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_stake_and_mint_unstaked_if_mint_fails() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.submit_price("2000000000".to_string());
+        contract.stake_and_mint("1000000000000".to_string());
+        assert_eq!(contract.get_staked_balance(carol()), "1000000000000");
+
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_stake_minted(carol(), U128(1_000_000_000_000), false);
+        assert_eq!(contract.get_staked_balance(carol()), "0");
+        assert_eq!(
+            contract.get_unstaked_balance(carol()),
+            total_supply.to_string()
+        );
+        assert_eq!(contract.total_staked, 0);
+    }
+
+    #[test]
+    fn test_unwrap_stake_keeps_reward_period() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.submit_price("2000000000".to_string());
+        contract.set_start_token("start.near".try_into().unwrap());
+        contract.stake_and_mint("1000000000000".to_string());
+        contract.stake_and_mint_liquid("1000000000000".to_string());
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_stake_minted(carol(), U128(1_000_000_000_000), true);
+
+        context.predecessor_account_id = carol();
+        context.block_timestamp = 3 * DAY / 2;
+        testing_env!(context.clone());
+        contract.refresh_liquid_reward();
+        let amount = contract.get_liquid_staking().staked;
+        contract.unwrap_stake("1000000000000".to_string());
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_start_burned(carol(), U128(1_000_000_000_000), amount);
+        // The half day since the last reward still counts for the next one
+        assert_eq!(contract.get_reward_paid_at(carol()), DAY);
+    }
+
+    #[test]
+    #[should_panic(expected = "Liquid staking is not enabled")]
+    fn test_wrap_stake_without_start_token_fail() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.submit_price("2000000000".to_string());
+        contract.stake_and_mint("1000".to_string());
+        contract.wrap_stake("1000".to_string());
+    }
+
+    #[test]
+    fn test_ausd_transfer_burn_to_unstake() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.submit_price("2000000000".to_string());
+        contract.stake_and_mint("1000".to_string());
+        let msg = r#"{"action":"burn_to_unstake","amount":"1000"}"#;
+
+        // 4000 aUSD is burnt to unstake, the stake is kept until it is
+        context.predecessor_account_id = "ausd".to_string();
+        testing_env!(context.clone());
+        contract.ft_on_transfer(carol(), U128(5000), msg.to_string());
+        assert_eq!(contract.get_staked_balance(carol()), "1000");
+
+        // The stake stays if the burn failed, and the whole transfer is refunded
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        let action = AusdTransferAction::BurnToUnstake { amount: U128(1000) };
+        let unused = contract.on_transfer_ausd_burned(
+            carol(),
+            action.clone(),
+            U128(5000),
+            U128(4000),
+            U128(0),
+            U64(0),
+        );
+        assert_eq!(unused.0, 5000);
+        assert_eq!(contract.get_staked_balance(carol()), "1000");
+
+        context.predecessor_account_id = "ausd".to_string();
+        testing_env!(context.clone());
+        contract.ft_on_transfer(carol(), U128(5000), msg.to_string());
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        let unused = contract.on_transfer_ausd_burned(
+            carol(),
+            action.clone(),
+            U128(5000),
+            U128(4000),
+            U128(0),
+            U64(0),
+        );
+        assert_eq!(unused.0, 1000);
+        assert_eq!(contract.get_staked_balance(carol()), "0");
+        assert_eq!(
+            contract.get_unstaked_balance(carol()),
+            total_supply.to_string()
+        );
+
+        // The burnt aUSD is minted back if the stake moved before the burn
+        let unused = contract.on_transfer_ausd_burned(
+            carol(),
+            action,
+            U128(5000),
+            U128(4000),
+            U128(0),
+            U64(0),
+        );
+        assert_eq!(unused.0, 1000);
+        assert_eq!(
+            get_logs().last().unwrap(),
+            "Not enough staked tokens of carol.near, minting back 4000 aUSD"
+        );
+        assert_eq!(
+            contract.get_unstaked_balance(carol()),
+            total_supply.to_string()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough aUSD transferred for the action")]
+    fn test_ausd_transfer_buy_asset_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &carol());
+        contract.submit_asset_price("aBTC".to_string(), "5000000000000".to_string());
+
+        // 2 aBTC costs 100000 aUSD
+        context.predecessor_account_id = "ausd".to_string();
+        testing_env!(context.clone());
+        let msg = r#"{"action":"buy_asset","asset":"aBTC","asset_amount":"2"}"#;
+        contract.ft_on_transfer(carol(), U128(99999), msg.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough staked tokens")]
+    fn test_ausd_transfer_burn_to_unstake_more_than_staked_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        contract.submit_price("2000000000".to_string());
+        contract.stake_and_mint("1000".to_string());
+
+        context.predecessor_account_id = "ausd".to_string();
+        testing_env!(context.clone());
+        let msg = r#"{"action":"burn_to_unstake","amount":"1001"}"#;
+        contract.ft_on_transfer(carol(), U128(5000), msg.to_string());
+    }
+
+    #[test]
+    fn test_ausd_transfer_buy_asset_reserves_supply() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &carol());
+        contract.submit_asset_price("aBTC".to_string(), "5000000000000".to_string());
+        contract.set_asset_supply_cap("aBTC".to_string(), Some("3".to_string()));
+
+        context.predecessor_account_id = "ausd".to_string();
+        testing_env!(context.clone());
+        let msg = r#"{"action":"buy_asset","asset":"aBTC","asset_amount":"2"}"#;
+        contract.ft_on_transfer(carol(), U128(100000), msg.to_string());
+        assert_eq!(
+            contract.reserved_asset_supplies.get(&"aBTC".to_string()),
+            Some(2)
+        );
+
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        let action = AusdTransferAction::BuyAsset {
+            asset: "aBTC".to_string(),
+            asset_amount: U128(2),
+        };
+        let unused = contract.on_transfer_ausd_burned(
+            carol(),
+            action,
+            U128(100000),
+            U128(100000),
+            U128(5000000000000),
+            U64(1),
+        );
+        assert_eq!(unused.0, 0);
+        assert_eq!(
+            contract.reserved_asset_supplies.get(&"aBTC".to_string()),
+            None
+        );
+        assert_eq!(contract.get_asset_balance(carol(), "aBTC".to_string()), "2");
+    }
+
+    #[test]
+    #[should_panic(expected = "Supply cap of aBTC exceeded")]
+    fn test_ausd_transfer_buy_asset_supply_cap_counts_pending_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        register(&mut contract, &carol());
+        contract.submit_asset_price("aBTC".to_string(), "5000000000000".to_string());
+        contract.set_asset_supply_cap("aBTC".to_string(), Some("3".to_string()));
+
+        context.predecessor_account_id = "ausd".to_string();
+        testing_env!(context.clone());
+        let msg = r#"{"action":"buy_asset","asset":"aBTC","asset_amount":"2"}"#;
+        contract.ft_on_transfer(carol(), U128(100000), msg.to_string());
+        // The aUSD of the first transfer isn't burnt yet, but its aBTC counts against the cap
+        contract.ft_on_transfer(carol(), U128(100000), msg.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only aUSD can be transferred to the art contract")]
+    fn test_ausd_transfer_wrong_token_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+
+        context.predecessor_account_id = "fake-ausd".to_string();
+        testing_env!(context.clone());
+        let msg = r#"{"action":"burn_to_unstake","amount":"1000"}"#;
+        contract.ft_on_transfer(carol(), U128(5000), msg.to_string());
+    }
+
+    #[test]
+    fn test_events() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
+        assert_eq!(
+            get_logs(),
+            vec![format!(
+                r#"EVENT_JSON:{{"standard":"nep141","version":"1.0.0","event":"ft_mint","data":[{{"owner_id":"carol.near","amount":"{}"}}]}}"#,
+                total_supply
+            )]
+        );
+
+        register(&mut contract, &bob());
+        testing_env!(context.clone());
+        contract.submit_price("2000000000".to_string());
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"art","version":"1.0.0","event":"price_update","data":[{"asset":"art","price":"2000000000"}]}"#
+            ]
+        );
+
+        testing_env!(context.clone());
+        contract.stake_and_mint("1000".to_string());
+        assert_eq!(
+            get_logs().last().unwrap(),
+            r#"EVENT_JSON:{"standard":"art","version":"1.0.0","event":"stake","data":[{"account_id":"carol.near","amount":"1000"}]}"#
+        );
+
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        contract.exchange_ausd_to_art("20000".to_string(), None, None);
+        assert!(get_logs().is_empty());
+
+        // The art is transferred and the exchange is logged once the aUSD is sold
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_exchange_settled(
+            bob(),
+            ConversionPath::AusdToArt,
+            U128(20000),
+            U128(997),
+            U128(3),
+        );
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"nep141","version":"1.0.0","event":"ft_transfer","data":[{"old_owner_id":"carol.near","new_owner_id":"bob.near","amount":"997"}]}"#,
+                "Collected 3 art fee on ausd_to_art for carol.near",
+                r#"EVENT_JSON:{"standard":"art","version":"1.0.0","event":"exchange","data":[{"account_id":"bob.near","path":"ausd_to_art","amount_in":"20000","amount_out":"997","fee":"3"}]}"#
+            ]
+        );
+
+        context.predecessor_account_id = carol();
+        testing_env!(context.clone());
+        contract.submit_asset_price("aBTC".to_string(), "5000000000000".to_string());
+        context.predecessor_account_id = "ausd".to_string();
+        testing_env!(context.clone());
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 2);
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"art","version":"1.0.0","event":"asset_trade","data":[{"account_id":"bob.near","asset":"aBTC","side":"buy","asset_amount":"2","ausd_amount":"100000","price":"5000000000000"}]}"#
+            ]
+        );
+
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        contract.place_sell_order(
+            "aBTC".to_string(),
+            "2".to_string(),
+            "6000000000000".to_string(),
+            TriggerDirection::Above,
+        );
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"art","version":"1.0.0","event":"place_order","data":[{"order_id":"0","account_id":"bob.near","side":"sell","asset":"aBTC","amount":"2","trigger_price":"6000000000000"}]}"#
+            ]
+        );
+
+        context.predecessor_account_id = carol();
+        testing_env!(context.clone());
+        contract.delist_asset("aBTC".to_string());
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"art","version":"1.0.0","event":"delist_asset","data":[{"asset":"aBTC","price":"5000000000000"}]}"#
+            ]
+        );
+
+        context.attached_deposit = 100;
+        testing_env!(context.clone());
+        contract.add_liquidity("1000".to_string());
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"art","version":"1.0.0","event":"add_liquidity","data":[{"account_id":"carol.near","art_amount":"1000","near_amount":"100","shares":"100"}]}"#
+            ]
+        );
+    }
+
+    // Fungible Token Standard tests
+
+    #[test]
+    fn test_batch_transfer() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        context.storage_usage = env::storage_usage();
+        context.attached_deposit = 1000 * STORAGE_PRICE_PER_BYTE;
+        testing_env!(context.clone());
+        contract.storage_deposit(Some(bob().try_into().unwrap()), None);
+
+        context.attached_deposit = 1;
+        testing_env!(context.clone());
+        let failed = contract.batch_transfer(
+            vec![
+                (bob().try_into().unwrap(), U128(100)),
+                (alice().try_into().unwrap(), U128(200)),
+                (bob().try_into().unwrap(), U128(0)),
+                (bob().try_into().unwrap(), U128(50)),
+            ],
+            None,
+            Some(true),
+        );
+        assert_eq!(
+            failed
+                .iter()
+                .map(|leg| (leg.index, leg.reason.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, "The account alice.near is not registered"),
+                (2, "The amount should be a positive number")
+            ]
+        );
+        assert_eq!(get_logs().len(), 2);
+        assert_eq!(contract.get_unstaked_balance(carol()), "850");
+        assert_eq!(contract.get_unstaked_balance(bob()), "150");
+    }
 
-                let mut sender = self.get_account(&sender_id);
-                sender.balance += refund_amount;
-                self.accounts.insert(&sender_id, &sender);
+    #[test]
+    #[should_panic(expected = "The account alice.near is not registered")]
+    fn test_batch_transfer_unregistered_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        context.attached_deposit = 1;
+        testing_env!(context);
+        contract.batch_transfer(vec![(alice().try_into().unwrap(), U128(100))], None, None);
+    }
 
-                env::log(
-                    format!(
-                        "Refund {} from {} to {}",
-                        refund_amount, receiver_id, sender_id
-                    )
-                    .as_bytes(),
-                );
-                return (amount - refund_amount).into();
-            }
-        }
-        amount.into()
+    #[test]
+    #[should_panic(expected = "The account doesn't have enough balance")]
+    fn test_batch_transfer_total_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        context.attached_deposit = 1;
+        testing_env!(context);
+        contract.batch_transfer(
+            vec![
+                (bob().try_into().unwrap(), U128(600)),
+                (alice().try_into().unwrap(), U128(600)),
+            ],
+            None,
+            Some(true),
+        );
     }
-}
 
-#[cfg(not(target_arch = "wasm32"))]
-#[cfg(test)]
-mod tests {
-    use std::convert::TryInto;
+    #[test]
+    fn test_storage_balance_per_byte() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        let min_balance = contract.storage_balance_bounds().min.0;
+        assert!(contract.storage_balance_bounds().max.is_none());
 
-    use near_sdk::env::STORAGE_PRICE_PER_BYTE;
-    use near_sdk::MockedBlockchain;
-    use near_sdk::{testing_env, VMContext};
+        context.storage_usage = env::storage_usage();
+        context.predecessor_account_id = bob();
+        context.attached_deposit = min_balance + 100 * STORAGE_PRICE_PER_BYTE;
+        testing_env!(context.clone());
+        contract.storage_deposit(None, None);
+        let storage_balance = contract
+            .storage_balance_of(bob().try_into().unwrap())
+            .unwrap();
+        assert_eq!(
+            storage_balance.total.0,
+            min_balance + 100 * STORAGE_PRICE_PER_BYTE
+        );
+        assert_eq!(storage_balance.available.0, 100 * STORAGE_PRICE_PER_BYTE);
 
-    use super::*;
+        // The allowance takes 4 + 10 bytes of "carol.near" and 16 bytes of the amount
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        contract.set_allowance(carol(), "100".to_string());
+        let storage_balance = contract
+            .storage_balance_of(bob().try_into().unwrap())
+            .unwrap();
+        assert_eq!(storage_balance.available.0, 70 * STORAGE_PRICE_PER_BYTE);
 
-    fn alice() -> AccountId {
-        "alice.near".to_string()
-    }
-    fn bob() -> AccountId {
-        "bob.near".to_string()
-    }
-    fn carol() -> AccountId {
-        "carol.near".to_string()
-    }
+        context.attached_deposit = 1;
+        testing_env!(context.clone());
+        let storage_balance = contract.storage_withdraw(Some(U128(20 * STORAGE_PRICE_PER_BYTE)));
+        assert_eq!(storage_balance.available.0, 50 * STORAGE_PRICE_PER_BYTE);
+        let storage_balance = contract.storage_withdraw(None);
+        assert_eq!(storage_balance.available.0, 0);
+        assert_eq!(
+            storage_balance.total.0,
+            min_balance + 30 * STORAGE_PRICE_PER_BYTE
+        );
 
-    fn get_context(predecessor_account_id: AccountId) -> VMContext {
-        VMContext {
-            current_account_id: alice(),
-            signer_account_id: bob(),
-            signer_account_pk: vec![0, 1, 2],
-            predecessor_account_id,
-            input: vec![],
-            block_index: 0,
-            block_timestamp: 0,
-            account_balance: 0,
-            account_locked_balance: 0,
-            storage_usage: 10u64.pow(6),
-            attached_deposit: 0,
-            prepaid_gas: 10u64.pow(18),
-            random_seed: vec![0, 1, 2],
-            is_view: false,
-            output_data_receivers: vec![],
-            epoch_height: 0,
-        }
+        // Removing the allowance frees its storage again
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        contract.set_allowance(carol(), "0".to_string());
+        let storage_balance = contract
+            .storage_balance_of(bob().try_into().unwrap())
+            .unwrap();
+        assert_eq!(storage_balance.available.0, 30 * STORAGE_PRICE_PER_BYTE);
     }
 
     #[test]
-    fn test_new() {
-        let context = get_context(carol());
+    #[should_panic(
+        expected = "The account bob.near needs 300000000000000000000 more storage balance"
+    )]
+    fn test_allowance_without_storage_balance_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        context.storage_usage = env::storage_usage();
+        context.predecessor_account_id = bob();
+        context.attached_deposit = contract.storage_balance_bounds().min.0;
+        testing_env!(context.clone());
+        contract.storage_deposit(None, None);
+
+        context.attached_deposit = 0;
         testing_env!(context);
-        let total_supply = 1_000_000_000_000_000u128;
-        let contract = Art::new(bob(), total_supply.to_string(), "ausd".to_string());
-        assert_eq!(contract.get_total_supply(), total_supply.to_string());
+        contract.set_allowance(carol(), "100".to_string());
+    }
+
+    #[test]
+    fn test_transfer_registers_receiver() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        let min_balance = contract.storage_balance_bounds().min.0;
+
+        context.storage_usage = env::storage_usage();
+        context.attached_deposit = min_balance + 10;
+        testing_env!(context.clone());
+        contract.transfer(bob(), "100".to_string());
         assert_eq!(
-            contract.get_unstaked_balance(bob()),
-            total_supply.to_string()
+            contract
+                .storage_balance_of(bob().try_into().unwrap())
+                .unwrap()
+                .total
+                .0,
+            min_balance
         );
-        assert_eq!(contract.get_total_balance(bob()), total_supply.to_string());
+        assert_eq!(contract.get_unstaked_balance(bob()), "100");
+
+        // ft_transfer keeps 1 yoctoNEAR and registers the receiver with the rest
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+        context.attached_deposit = 1 + min_balance;
+        testing_env!(context.clone());
+        contract.ft_transfer(alice().try_into().unwrap(), U128(100), None);
+        assert!(contract
+            .storage_balance_of(alice().try_into().unwrap())
+            .is_some());
+
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+        testing_env!(context.clone());
+        contract.ft_transfer_call(
+            "dave.near".try_into().unwrap(),
+            U128(100),
+            None,
+            "".to_string(),
+        );
+        assert_eq!(
+            contract.ft_balance_of("dave.near".try_into().unwrap()).0,
+            100
+        );
+
+        // Registered receivers don't need the deposit, it's refunded
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+        testing_env!(context.clone());
+        let account_balance = env::account_balance();
+        contract.ft_transfer(bob().try_into().unwrap(), U128(100), None);
+        assert_eq!(contract.ft_balance_of(bob().try_into().unwrap()).0, 200);
+        assert_eq!(env::account_balance(), account_balance - min_balance);
     }
 
     #[test]
-    fn test_transfer() {
-        let context = get_context(carol());
+    fn test_storage_unregister() {
+        testing_env!(get_context(carol()));
+        let mut contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        let mut context = get_context(bob());
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+        context.attached_deposit = 1;
         testing_env!(context);
-        let total_supply = 1_000_000_000_000_000u128;
-        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
-        let transfer_amount = total_supply / 3;
-        contract.transfer(bob(), transfer_amount.to_string());
+        let account_balance = env::account_balance();
+        let storage_deposit = contract.internal_storage_deposit_of(&bob());
+        assert!(contract.storage_unregister(None));
+        assert!(contract
+            .storage_balance_of(bob().try_into().unwrap())
+            .is_none());
         assert_eq!(
-            contract.get_unstaked_balance(carol()),
-            (total_supply - transfer_amount).to_string()
-        );
-        assert_eq!(
-            contract.get_unstaked_balance(bob()),
-            transfer_amount.to_string()
+            env::account_balance(),
+            account_balance - storage_deposit - 1
         );
     }
 
     #[test]
-    fn test_stake_fail() {
-        let context = get_context(carol());
+    fn test_storage_unregister_force_with_stake() {
+        testing_env!(get_context(carol()));
+        let mut contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.transfer(bob(), "300".to_string());
+        let mut context = get_context(bob());
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+        testing_env!(context.clone());
+        contract.stake("100".to_string());
+        assert_eq!(contract.total_staked, 100);
+        assert!(contract.balance_checkpoint_counts.get(&bob()).is_some());
+
+        context.attached_deposit = 1;
         testing_env!(context);
-        let total_supply = 1_000_000_000_000_000u128;
+        assert!(contract.storage_unregister(Some(true)));
+        assert_eq!(contract.total_staked, 0);
+        assert_eq!(contract.total_supply, 700);
+        assert!(contract.balance_checkpoint_counts.get(&bob()).is_none());
+        assert!(contract.balance_checkpoints.get(&(bob(), 0)).is_none());
+        assert!(contract.reward_paid_at.get(&bob()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't unregister the account with pool shares")]
+    fn test_storage_unregister_with_pool_shares_fail() {
+        let mut context = get_context(carol());
+        context.account_balance = 1000 * 10u128.pow(24);
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
         let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
-        let transfer_amount = total_supply / 3;
-        let context = get_context(bob());
+        context.attached_deposit = 100 * 10u128.pow(24);
+        testing_env!(context.clone());
+        contract.add_liquidity((1000 * 10u128.pow(24)).to_string());
+        context.attached_deposit = 1;
         testing_env!(context);
-        std::panic::catch_unwind(move || {
-            contract.stake(transfer_amount.to_string());
-        })
-        .unwrap_err();
+        contract.storage_unregister(Some(true));
     }
 
     #[test]
-    fn test_self_allowance_fail() {
-        let context = get_context(carol());
+    #[should_panic(expected = "Can't unregister the account holding assets")]
+    fn test_storage_unregister_with_assets_fail() {
+        testing_env!(get_context(carol()));
+        let mut contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 100);
+        let mut context = get_context(bob());
+        context.attached_deposit = 1;
         testing_env!(context);
-        let total_supply = 1_000_000_000_000_000u128;
-        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
-        std::panic::catch_unwind(move || {
-            contract.set_allowance(carol(), format!("{}", total_supply / 2));
-        })
-        .unwrap_err();
+        contract.storage_unregister(Some(true));
     }
 
     #[test]
-    fn test_carol_escrows_to_bob_transfers_to_alice() {
-        // Acting as carol
+    #[should_panic(expected = "Can't unregister the account with open orders")]
+    fn test_storage_unregister_with_open_orders_fail() {
         testing_env!(get_context(carol()));
-        let total_supply = 1_000_000_000_000_000u128;
-        let mut contract = Art::new(carol(), total_supply.to_string(), "ausd".to_string());
-        assert_eq!(contract.get_total_supply(), total_supply.to_string());
-        let allowance = total_supply / 3;
-        let transfer_amount = allowance / 3;
-        contract.set_allowance(bob(), format!("{}", allowance));
-        assert_eq!(
-            contract.get_allowance(carol(), bob()),
-            format!("{}", allowance)
+        let mut contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        contract.submit_asset_price("aBTC".to_string(), "3000000000000".to_string());
+        buy_asset(&mut contract, &bob(), &"aBTC".to_string(), 100);
+        let mut context = get_context(bob());
+        testing_env!(context.clone());
+        contract.place_sell_order(
+            "aBTC".to_string(),
+            "100".to_string(),
+            "4000000000000".to_string(),
+            TriggerDirection::Above,
         );
-        // Acting as bob now
+        context.attached_deposit = 1;
+        testing_env!(context);
+        contract.storage_unregister(Some(true));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't unregister the account with vesting grants")]
+    fn test_storage_unregister_with_vesting_grants_fail() {
+        testing_env!(get_context(carol()));
+        let mut contract = Art::new(carol(), "10000000000000".to_string(), "ausd".to_string());
+        register(&mut contract, &bob());
+        bob_vesting_grant(&mut contract, true);
+        let mut context = get_context(bob());
+        context.attached_deposit = 1;
+        testing_env!(context);
+        contract.storage_unregister(Some(true));
+    }
+
+    #[test]
+    fn test_batch_transfer_registers_receivers() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        let min_balance = contract.storage_balance_bounds().min.0;
+        register(&mut contract, &bob());
+
+        context.storage_usage = env::storage_usage();
+        context.attached_deposit = 1 + min_balance;
+        testing_env!(context.clone());
+        let failed = contract.batch_transfer(
+            vec![
+                (bob().try_into().unwrap(), U128(100)),
+                (alice().try_into().unwrap(), U128(100)),
+                ("dave.near".try_into().unwrap(), U128(100)),
+            ],
+            None,
+            Some(true),
+        );
+        // The deposit only covers the first unregistered receiver
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].index, 2);
+        assert_eq!(contract.get_unstaked_balance(alice()), "100");
+        assert_eq!(contract.get_unstaked_balance(carol()), "800");
+    }
+
+    #[test]
+    fn test_vesting_grant_registers_account() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = Art::new(carol(), "10000000000000".to_string(), "ausd".to_string());
+        context.storage_usage = env::storage_usage();
+        context.attached_deposit = contract.storage_balance_bounds().min.0;
+        testing_env!(context.clone());
+        let grant_id = bob_vesting_grant(&mut contract, false);
+
+        context.predecessor_account_id = bob();
+        context.attached_deposit = 0;
+        context.block_timestamp = 100 * DAY;
+        testing_env!(context);
+        assert_eq!(contract.claim_vesting(grant_id), "1000000000000");
+    }
+
+    /// Returns a contract called by bob, who isn't registered.
+    fn unregistered_bob_contract() -> Art {
+        testing_env!(get_context(carol()));
+        let contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
         testing_env!(get_context(bob()));
-        contract.transfer_from(carol(), alice(), transfer_amount.to_string());
-        assert_eq!(
-            contract.get_total_balance(carol()),
-            (total_supply - transfer_amount).to_string()
+        contract
+    }
+
+    #[test]
+    #[should_panic(expected = "The account bob.near is not registered")]
+    fn test_transfer_unregistered_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        // Less than the minimum storage balance
+        context.attached_deposit = contract.storage_balance_bounds().min.0 - 1;
+        testing_env!(context);
+        contract.transfer(bob(), "100".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The account bob.near is not registered")]
+    fn test_ft_transfer_unregistered_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        context.attached_deposit = 1;
+        testing_env!(context);
+        contract.ft_transfer(bob().try_into().unwrap(), U128(100), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account bob.near is not registered")]
+    fn test_buy_asset_with_ausd_unregistered_fail() {
+        let mut contract = unregistered_bob_contract();
+        contract.buy_asset_with_ausd("aBTC".to_string(), "100".to_string(), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account bob.near is not registered")]
+    fn test_place_buy_order_unregistered_fail() {
+        let mut contract = unregistered_bob_contract();
+        contract.place_buy_order(
+            "aBTC".to_string(),
+            "100".to_string(),
+            "100".to_string(),
+            TriggerDirection::Below,
         );
-        assert_eq!(
-            contract.get_unstaked_balance(alice()),
-            transfer_amount.to_string()
+    }
+
+    #[test]
+    #[should_panic(expected = "The account bob.near is not registered")]
+    fn test_exchange_ausd_to_art_unregistered_fail() {
+        let mut contract = unregistered_bob_contract();
+        contract.exchange_ausd_to_art("100".to_string(), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account bob.near is not registered")]
+    fn test_swap_unregistered_fail() {
+        let mut contract = unregistered_bob_contract();
+        contract.swap(
+            vec!["ausd".to_string(), "art".to_string()],
+            "100".to_string(),
+            "0".to_string(),
         );
-        assert_eq!(
-            contract.get_allowance(carol(), bob()),
-            format!("{}", allowance - transfer_amount)
+    }
+
+    #[test]
+    #[should_panic(expected = "The account bob.near is not registered")]
+    fn test_unwrap_stake_unregistered_fail() {
+        let mut contract = unregistered_bob_contract();
+        contract.unwrap_stake("100".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The account bob.near is not registered")]
+    fn test_ausd_transfer_unregistered_fail() {
+        let mut contract = unregistered_bob_contract();
+        testing_env!(get_context("ausd".to_string()));
+        contract.ft_on_transfer(
+            bob(),
+            U128(100),
+            r#"{"action":"burn_to_unstake","amount":"100"}"#.to_string(),
         );
     }
 
-    // Fungible Token Standard tests
+    #[test]
+    #[should_panic(expected = "The account bob.near is not registered")]
+    fn test_set_treasury_unregistered_fail() {
+        testing_env!(get_context(carol()));
+        let mut contract = Art::new(carol(), "1000".to_string(), "ausd".to_string());
+        contract.set_treasury(bob().try_into().unwrap());
+    }
 
     #[test]
     fn test_ft_transfer() {
@@ -1290,6 +9656,13 @@ mod tests {
         testing_env!(context.clone());
         let transfer_amount = total_supply / 3;
         contract.ft_transfer(bob().try_into().unwrap(), transfer_amount.into(), None);
+        assert_eq!(
+            get_logs(),
+            vec![format!(
+                r#"EVENT_JSON:{{"standard":"nep141","version":"1.0.0","event":"ft_transfer","data":[{{"old_owner_id":"carol.near","new_owner_id":"bob.near","amount":"{}"}}]}}"#,
+                transfer_amount
+            )]
+        );
         context.storage_usage = env::storage_usage();
         context.account_balance = env::account_balance();
 
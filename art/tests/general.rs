@@ -5,17 +5,37 @@ use near_sdk_sim::{
     to_yocto, view, ContractAccount, UserAccount, DEFAULT_GAS, STORAGE_AMOUNT,
 };
 use std::convert::TryInto;
-use std::{cell::RefCell, rc::Rc};
+use std::path::Path;
+use std::process::Command;
+use std::{cell::RefCell, fs, rc::Rc};
 
 extern crate art;
-use art::ArtContract;
+use art::{ArtContract, ConversionMode, ConversionPath};
 
 extern crate ausd;
 use ausd::AUSDContract;
 
+extern crate start;
+use start::StartContract;
+
 lazy_static::lazy_static! {
-    static ref ART_WASM_BYTES: &'static [u8] = include_bytes!("../res/art.wasm").as_ref();
-    static ref AUSD_WASM_BYTES: &'static [u8] = include_bytes!("../../ausd/res/ausd.wasm").as_ref();
+    static ref ART_WASM_BYTES: Vec<u8> = build_wasm("art", "art.wasm");
+    static ref AUSD_WASM_BYTES: Vec<u8> = build_wasm("ausd", "ausd.wasm");
+    static ref START_WASM_BYTES: Vec<u8> = build_wasm("start", "start.wasm");
+}
+
+/// Builds `contract` with its build.sh, so the tests deploy its current source, and returns the
+/// wasm.
+fn build_wasm(contract: &str, wasm: &str) -> Vec<u8> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("..")
+        .join(contract);
+    let status = Command::new("bash")
+        .arg(dir.join("build.sh"))
+        .status()
+        .expect("Failed to run build.sh");
+    assert!(status.success(), "Failed to build {}", contract);
+    fs::read(dir.join("res").join(wasm)).expect("Failed to read the wasm")
 }
 
 const INIT_ART_BALANCE: &'static str = "1000000000";
@@ -48,6 +68,16 @@ fn init(
     (master_account, art, ausd)
 }
 
+/// Registers `user` with aUSD, so it can get aUSD minted or bought.
+fn register_ausd(user: &UserAccount, ausd: &ContractAccount<AUSDContract>) {
+    call!(
+        user,
+        ausd.storage_deposit(None, None),
+        deposit = STORAGE_AMOUNT / 10
+    )
+    .assert_success();
+}
+
 #[test]
 fn test_initial_issue() {
     let (master_account, art, ausd) = init(None);
@@ -60,10 +90,10 @@ fn test_initial_issue() {
     .assert_success();
     let res = call!(master_account, art.stake_and_mint(deposit_amount));
     assert!(res.is_ok());
-    let total_supply: U128 = view!(ausd.get_total_supply()).unwrap_json();
+    let total_supply: U128 = view!(ausd.ft_total_supply()).unwrap_json();
     assert_eq!(total_supply.0, (to_yocto(INIT_ART_BALANCE) / 2 * 20 / 5));
     let master_ausd_balance: U128 =
-        view!(ausd.get_balance(master_account.account_id().try_into().unwrap())).unwrap_json();
+        view!(ausd.ft_balance_of(master_account.account_id().try_into().unwrap())).unwrap_json();
     assert_eq!(total_supply, master_ausd_balance);
     let master_unstaked_art_balance: String =
         view!(art.get_unstaked_balance(master_account.account_id().try_into().unwrap()))
@@ -96,7 +126,8 @@ fn test_transfer_art() {
     let alice = master_account.create_user("alice".to_string(), to_yocto("10"));
     call!(
         master_account,
-        art.transfer(alice.account_id(), to_yocto("10000").to_string())
+        art.transfer(alice.account_id(), to_yocto("10000").to_string()),
+        deposit = STORAGE_AMOUNT / 10
     )
     .assert_success();
     let master_unstaked_art_balance: String =
@@ -125,7 +156,8 @@ fn test_transfer_art() {
     let bob = master_account.create_user("bob".to_string(), to_yocto("10"));
     call!(
         alice,
-        art.transfer(bob.account_id(), to_yocto("1000").to_string())
+        art.transfer(bob.account_id(), to_yocto("1000").to_string()),
+        deposit = STORAGE_AMOUNT / 10
     )
     .assert_success();
 
@@ -151,22 +183,24 @@ fn test_mint_ausd() {
     call!(master_account, art.stake_and_mint(stake_amount)).assert_success();
 
     let alice = master_account.create_user("alice".to_string(), to_yocto("10"));
+    register_ausd(&alice, &ausd);
     call!(
         master_account,
-        art.transfer(alice.account_id(), to_yocto("10000").to_string())
+        art.transfer(alice.account_id(), to_yocto("10000").to_string()),
+        deposit = STORAGE_AMOUNT / 10
     )
     .assert_success();
 
     let res = call!(alice, art.stake_and_mint(to_yocto("10000").to_string()));
     assert!(res.is_ok());
-    let total_supply: U128 = view!(ausd.get_total_supply()).unwrap_json();
+    let total_supply: U128 = view!(ausd.ft_total_supply()).unwrap_json();
     assert_eq!(
         total_supply.0,
         ((to_yocto(INIT_ART_BALANCE) / 2 + to_yocto("10000")) * 20 / 5)
     );
 
     let alice_ausd_balance: U128 =
-        view!(ausd.get_balance(alice.account_id().try_into().unwrap())).unwrap_json();
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
     assert_eq!(U128(to_yocto("10000") * 20 / 5), alice_ausd_balance);
     let alice_unstaked_art_balance: String =
         view!(art.get_unstaked_balance(alice.account_id().try_into().unwrap())).unwrap_json();
@@ -182,21 +216,31 @@ fn test_mint_ausd() {
     )
     .is_ok());
 
-    // Alice can use her ausd freely
+    // Alice can use her ausd freely, once bob is registered
     call!(
-        alice,
-        ausd.transfer(bob.account_id(), U128(to_yocto("30000"))),
+        bob,
+        ausd.storage_deposit(None, None),
         deposit = STORAGE_AMOUNT / 10
     )
     .assert_success();
+    call!(
+        alice,
+        ausd.ft_transfer(
+            bob.account_id().try_into().unwrap(),
+            U128(to_yocto("30000")),
+            None
+        ),
+        deposit = 1
+    )
+    .assert_success();
     let alice_ausd_balance: U128 =
-        view!(ausd.get_balance(alice.account_id().try_into().unwrap())).unwrap_json();
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
     assert_eq!(
         U128(to_yocto("10000") * 20 / 5 - to_yocto("30000")),
         alice_ausd_balance
     );
     let bob_ausd_balance: U128 =
-        view!(ausd.get_balance(bob.account_id().try_into().unwrap())).unwrap_json();
+        view!(ausd.ft_balance_of(bob.account_id().try_into().unwrap())).unwrap_json();
     assert_eq!(U128(to_yocto("30000")), bob_ausd_balance);
 }
 
@@ -213,9 +257,11 @@ fn test_burn_unstake() {
     call!(master_account, art.stake_and_mint(stake_amount)).assert_success();
 
     let alice = master_account.create_user("alice".to_string(), to_yocto("10"));
+    register_ausd(&alice, &ausd);
     call!(
         master_account,
-        art.transfer(alice.account_id(), to_yocto("10000").to_string())
+        art.transfer(alice.account_id(), to_yocto("10000").to_string()),
+        deposit = STORAGE_AMOUNT / 10
     )
     .assert_success();
 
@@ -225,7 +271,7 @@ fn test_burn_unstake() {
     r.assert_success();
 
     let alice_ausd_balance: U128 =
-        view!(ausd.get_balance(alice.account_id().try_into().unwrap())).unwrap_json();
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
     assert_eq!(U128(to_yocto("0")), alice_ausd_balance);
     let alice_unstaked_art_balance: String =
         view!(art.get_unstaked_balance(alice.account_id().try_into().unwrap())).unwrap_json();
@@ -248,16 +294,18 @@ fn test_unstake_when_price_change() {
     call!(master_account, art.stake_and_mint(stake_amount)).assert_success();
 
     let alice = master_account.create_user("alice".to_string(), to_yocto("10"));
+    register_ausd(&alice, &ausd);
     call!(
         master_account,
-        art.transfer(alice.account_id(), to_yocto("10000").to_string())
+        art.transfer(alice.account_id(), to_yocto("10000").to_string()),
+        deposit = STORAGE_AMOUNT / 10
     )
     .assert_success();
 
     call!(alice, art.stake_and_mint(to_yocto("10000").to_string())).assert_success();
 
     let alice_ausd_balance: U128 =
-        view!(ausd.get_balance(alice.account_id().try_into().unwrap())).unwrap_json();
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
     println!("{:?}", alice_ausd_balance);
 
     // price of art rise, ausd/art falls
@@ -276,7 +324,7 @@ fn test_unstake_when_price_change() {
     // alice restake her art
     call!(alice, art.stake_and_mint(to_yocto("5000").to_string())).assert_success();
     let alice_ausd_balance: U128 =
-        view!(ausd.get_balance(alice.account_id().try_into().unwrap())).unwrap_json();
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
     assert_eq!(U128(to_yocto("5000") * 40 / 5), alice_ausd_balance);
 
     // now price goes down
@@ -289,14 +337,16 @@ fn test_unstake_when_price_change() {
 
     // now bob stake and mint
     let bob = master_account.create_user("bob".to_string(), to_yocto("10"));
+    register_ausd(&bob, &ausd);
     call!(
         master_account,
-        art.transfer(bob.account_id(), to_yocto("10000").to_string())
+        art.transfer(bob.account_id(), to_yocto("10000").to_string()),
+        deposit = STORAGE_AMOUNT / 10
     )
     .assert_success();
     call!(bob, art.stake_and_mint(to_yocto("10000").to_string())).assert_success();
     let bob_ausd_balance: U128 =
-        view!(ausd.get_balance(bob.account_id().try_into().unwrap())).unwrap_json();
+        view!(ausd.ft_balance_of(bob.account_id().try_into().unwrap())).unwrap_json();
     assert_eq!(U128(to_yocto("10000") * 20 / 5), bob_ausd_balance);
 }
 
@@ -318,21 +368,30 @@ fn test_exchange_ausd_abtc() {
     call!(master_account, art.stake_and_mint(stake_amount)).assert_success();
 
     let alice = master_account.create_user("alice".to_string(), to_yocto("10"));
+    register_ausd(&alice, &ausd);
     call!(
         master_account,
-        art.transfer(alice.account_id(), to_yocto("10000").to_string())
+        art.transfer(alice.account_id(), to_yocto("10000").to_string()),
+        deposit = STORAGE_AMOUNT / 10
     )
     .assert_success();
 
     call!(alice, art.stake_and_mint(to_yocto("10000").to_string())).assert_success();
+    // The aBTC balance takes storage on top of the account
     call!(
         alice,
-        art.buy_asset_with_ausd("aBTC".to_string(), to_yocto("1").to_string())
+        art.storage_deposit(None, None),
+        deposit = STORAGE_AMOUNT / 10
+    )
+    .assert_success();
+    call!(
+        alice,
+        art.buy_asset_with_ausd("aBTC".to_string(), to_yocto("1").to_string(), None, None)
     )
     .assert_success();
 
     let alice_ausd_balance: U128 =
-        view!(ausd.get_balance(alice.account_id().try_into().unwrap())).unwrap_json();
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
     assert_eq!(U128(to_yocto("10000")), alice_ausd_balance);
     let alice_abtc_balance: String =
         view!(art.get_asset_balance(alice.account_id().try_into().unwrap(), "aBTC".to_string()))
@@ -347,12 +406,12 @@ fn test_exchange_ausd_abtc() {
 
     call!(
         alice,
-        art.sell_asset_to_ausd("aBTC".to_string(), to_yocto("1").to_string())
+        art.sell_asset_to_ausd("aBTC".to_string(), to_yocto("1").to_string(), None, None)
     )
     .assert_success();
 
     let alice_ausd_balance: U128 =
-        view!(ausd.get_balance(alice.account_id().try_into().unwrap())).unwrap_json();
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
     assert_eq!(U128(to_yocto("70000")), alice_ausd_balance);
     let alice_abtc_balance: String =
         view!(art.get_asset_balance(alice.account_id().try_into().unwrap(), "aBTC".to_string()))
@@ -378,9 +437,15 @@ fn test_buy_ausd_with_near() {
     call!(master_account, art.stake_and_mint(stake_amount)).assert_success();
 
     let alice = master_account.create_user("alice".to_string(), to_yocto("101"));
-    call!(alice, art.buy_ausd_with_near(), deposit = to_yocto("100")).assert_success();
+    register_ausd(&alice, &ausd);
+    call!(
+        alice,
+        art.buy_ausd_with_near(None, None),
+        deposit = to_yocto("100")
+    )
+    .assert_success();
     let alice_ausd_balance: U128 =
-        view!(ausd.get_balance(alice.account_id().try_into().unwrap())).unwrap_json();
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
     assert_eq!(U128(to_yocto("500")), alice_ausd_balance);
 }
 
@@ -402,7 +467,18 @@ fn test_buy_art_with_near() {
     call!(master_account, art.stake_and_mint(stake_amount)).assert_success();
 
     let alice = master_account.create_user("alice".to_string(), to_yocto("101"));
-    call!(alice, art.buy_art_with_near(), deposit = to_yocto("100")).assert_success();
+    call!(
+        alice,
+        art.storage_deposit(None, None),
+        deposit = STORAGE_AMOUNT / 10
+    )
+    .assert_success();
+    call!(
+        alice,
+        art.buy_art_with_near(None, None),
+        deposit = to_yocto("100")
+    )
+    .assert_success();
 
     let alice_unstaked_art_balance: String =
         view!(art.get_unstaked_balance(alice.account_id().try_into().unwrap())).unwrap_json();
@@ -426,22 +502,24 @@ fn test_staking_reward() {
     call!(master_account, art.stake_and_mint(stake_amount)).assert_success();
 
     let alice = master_account.create_user("alice".to_string(), to_yocto("10"));
+    register_ausd(&alice, &ausd);
     call!(
         master_account,
-        art.transfer(alice.account_id(), to_yocto("30000").to_string())
+        art.transfer(alice.account_id(), to_yocto("30000").to_string()),
+        deposit = STORAGE_AMOUNT / 10
     )
     .assert_success();
 
     let res = call!(alice, art.stake_and_mint(to_yocto("10000").to_string()));
     assert!(res.is_ok());
-    let total_supply: U128 = view!(ausd.get_total_supply()).unwrap_json();
+    let total_supply: U128 = view!(ausd.ft_total_supply()).unwrap_json();
     assert_eq!(
         total_supply.0,
         ((to_yocto(INIT_ART_BALANCE) / 2 + to_yocto("10000")) * 20 / 5)
     );
 
     let alice_ausd_balance: U128 =
-        view!(ausd.get_balance(alice.account_id().try_into().unwrap())).unwrap_json();
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
     assert_eq!(U128(to_yocto("10000") * 20 / 5), alice_ausd_balance);
     let alice_unstaked_art_balance: String =
         view!(art.get_unstaked_balance(alice.account_id().try_into().unwrap())).unwrap_json();
@@ -513,9 +591,11 @@ fn test_staking_reward() {
     assert!(reward_paid_at5 == reward_paid_at4);
 
     let bob = master_account.create_user("bob".to_string(), to_yocto("10"));
+    register_ausd(&bob, &ausd);
     call!(
         master_account,
-        art.transfer(bob.account_id(), to_yocto("10000").to_string())
+        art.transfer(bob.account_id(), to_yocto("10000").to_string()),
+        deposit = STORAGE_AMOUNT / 10
     )
     .assert_success();
 
@@ -549,22 +629,24 @@ fn test_exchange_art_ausd() {
     call!(master_account, art.stake_and_mint(stake_amount)).assert_success();
 
     let alice = master_account.create_user("alice".to_string(), to_yocto("10"));
+    register_ausd(&alice, &ausd);
     call!(
         master_account,
-        art.transfer(alice.account_id(), to_yocto("30000").to_string())
+        art.transfer(alice.account_id(), to_yocto("30000").to_string()),
+        deposit = STORAGE_AMOUNT / 10
     )
     .assert_success();
 
     call!(
         alice,
-        art.exchange_art_to_ausd(to_yocto("10000").to_string())
+        art.exchange_art_to_ausd(to_yocto("10000").to_string(), None, None)
     )
     .assert_success();
     let alice_unstaked_art_balance: String =
         view!(art.get_unstaked_balance(alice.account_id().try_into().unwrap())).unwrap_json();
     assert_eq!(alice_unstaked_art_balance, to_yocto("20000").to_string());
     let alice_ausd_balance: U128 =
-        view!(ausd.get_balance(alice.account_id().try_into().unwrap())).unwrap_json();
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
     assert_eq!(
         U128(to_yocto("10000") * 20 / 1000 * 997),
         alice_ausd_balance
@@ -572,11 +654,11 @@ fn test_exchange_art_ausd() {
 
     call!(
         alice,
-        art.exchange_ausd_to_art(to_yocto("20000").to_string())
+        art.exchange_ausd_to_art(to_yocto("20000").to_string(), None, None)
     )
     .assert_success();
     let alice_ausd_balance: U128 =
-        view!(ausd.get_balance(alice.account_id().try_into().unwrap())).unwrap_json();
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
     assert_eq!(
         U128(to_yocto("10000") * 20 / 1000 * 997 - to_yocto("20000")),
         alice_ausd_balance
@@ -588,3 +670,529 @@ fn test_exchange_art_ausd() {
         (to_yocto("20000") + to_yocto("20000") / 20 / 1000 * 997).to_string()
     );
 }
+
+#[test]
+fn test_exchange_art_ausd_reserve() {
+    let (master_account, art, ausd) = init(None);
+    call!(
+        master_account,
+        art.submit_price("2000000000".to_string()), // every art is $20
+        gas = DEFAULT_GAS
+    )
+    .assert_success();
+    call!(
+        master_account,
+        art.set_conversion_mode(ConversionMode::Reserve)
+    )
+    .assert_success();
+
+    let alice = master_account.create_user("alice".to_string(), to_yocto("10"));
+    register_ausd(&alice, &ausd);
+    call!(
+        master_account,
+        art.transfer(alice.account_id(), to_yocto("30000").to_string()),
+        deposit = STORAGE_AMOUNT / 10
+    )
+    .assert_success();
+
+    // Owner holds no aUSD, it's minted against the art paid in to the reserve
+    call!(
+        alice,
+        art.exchange_art_to_ausd(to_yocto("10000").to_string(), None, None)
+    )
+    .assert_success();
+    let alice_ausd_balance: U128 =
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(
+        U128(to_yocto("10000") * 20 / 1000 * 997),
+        alice_ausd_balance
+    );
+    let total_supply: U128 = view!(ausd.ft_total_supply()).unwrap_json();
+    assert_eq!(alice_ausd_balance, total_supply);
+    let reserve: near_sdk::serde_json::Value = view!(art.get_art_reserve()).unwrap_json();
+    assert_eq!(reserve["art_reserve"], to_yocto("10000").to_string());
+
+    // aUSD is burnt and art comes out of the reserve
+    call!(
+        alice,
+        art.exchange_ausd_to_art(to_yocto("20000").to_string(), None, None)
+    )
+    .assert_success();
+    let alice_unstaked_art_balance: String =
+        view!(art.get_unstaked_balance(alice.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(
+        alice_unstaked_art_balance,
+        (to_yocto("20000") + to_yocto("20000") / 20 / 1000 * 997).to_string()
+    );
+    let total_supply: U128 = view!(ausd.ft_total_supply()).unwrap_json();
+    assert_eq!(
+        U128(to_yocto("10000") * 20 / 1000 * 997 - to_yocto("20000")),
+        total_supply
+    );
+    let reserve: near_sdk::serde_json::Value = view!(art.get_art_reserve()).unwrap_json();
+    assert_eq!(
+        reserve["art_reserve"],
+        (to_yocto("10000") - to_yocto("20000") / 20 / 1000 * 997).to_string()
+    );
+
+    // Buying more art than the reserve holds fails before burning anything
+    let res = call!(
+        alice,
+        art.exchange_ausd_to_art(to_yocto("200000").to_string(), None, None)
+    );
+    assert!(!res.is_ok());
+    let total_supply_after: U128 = view!(ausd.ft_total_supply()).unwrap_json();
+    assert_eq!(total_supply, total_supply_after);
+}
+
+#[test]
+fn test_conversion_fee_to_treasury() {
+    let (master_account, art, ausd) = init(None);
+    call!(
+        master_account,
+        art.submit_price("2000000000".to_string()), // every art is $20
+        gas = DEFAULT_GAS
+    )
+    .assert_success();
+    call!(
+        master_account,
+        art.stake_and_mint((to_yocto(INIT_ART_BALANCE) / 2).to_string())
+    )
+    .assert_success();
+
+    let alice = master_account.create_user("alice".to_string(), to_yocto("10"));
+    register_ausd(&alice, &ausd);
+    let treasury = master_account.create_user("treasury".to_string(), to_yocto("10"));
+    register_ausd(&treasury, &ausd);
+    call!(
+        treasury,
+        art.storage_deposit(None, None),
+        deposit = STORAGE_AMOUNT / 10
+    )
+    .assert_success();
+    call!(
+        master_account,
+        art.transfer(alice.account_id(), to_yocto("30000").to_string()),
+        deposit = STORAGE_AMOUNT / 10
+    )
+    .assert_success();
+    call!(
+        master_account,
+        art.set_treasury(treasury.account_id().try_into().unwrap())
+    )
+    .assert_success();
+    call!(
+        master_account,
+        art.set_conversion_fee_bps(ConversionPath::ArtToAusd, 100)
+    )
+    .assert_success();
+
+    call!(
+        alice,
+        art.exchange_art_to_ausd(to_yocto("10000").to_string(), None, None)
+    )
+    .assert_success();
+    let alice_ausd_balance: U128 =
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(U128(to_yocto("200000") / 100 * 99), alice_ausd_balance);
+    let treasury_ausd_balance: U128 =
+        view!(ausd.ft_balance_of(treasury.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(U128(to_yocto("2000")), treasury_ausd_balance);
+
+    let fees: near_sdk::serde_json::Value = view!(art.get_conversion_fees()).unwrap_json();
+    let fee = fees
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|fee| fee["path"] == "art_to_ausd")
+        .unwrap();
+    assert_eq!(fee["fee_bps"], 100);
+    assert_eq!(fee["collected"], to_yocto("2000").to_string());
+}
+
+#[test]
+fn test_swap_near_to_abtc() {
+    let (master_account, art, ausd) = init(None);
+    call!(
+        master_account,
+        art.submit_price("2000000000".to_string()), // every art is $20
+        gas = DEFAULT_GAS
+    )
+    .assert_success();
+    call!(
+        master_account,
+        art.submit_asset_price("aNEAR".to_string(), "500000000".to_string())
+    ) // 1 NEAR = 5 ausd
+    .assert_success();
+    call!(
+        master_account,
+        art.submit_asset_price("aBTC".to_string(), "5000000000000".to_string())
+    )
+    .assert_success();
+
+    let alice = master_account.create_user("alice".to_string(), to_yocto("101"));
+    call!(
+        alice,
+        art.storage_deposit(None, None),
+        deposit = STORAGE_AMOUNT / 10
+    )
+    .assert_success();
+    let path = vec!["NEAR".to_string(), "aUSD".to_string(), "aBTC".to_string()];
+    call!(
+        alice,
+        art.swap(path, to_yocto("100").to_string(), "0".to_string()),
+        deposit = to_yocto("100")
+    )
+    .assert_success();
+    let alice_abtc_balance: String =
+        view!(art.get_asset_balance(alice.account_id().try_into().unwrap(), "aBTC".to_string()))
+            .unwrap_json();
+    assert_eq!(alice_abtc_balance, to_yocto("0.01").to_string());
+    let total_supply: U128 = view!(ausd.ft_total_supply()).unwrap_json();
+    assert_eq!(U128(0), total_supply);
+}
+
+#[test]
+fn test_swap_rolled_back() {
+    let (master_account, art, ausd) = init(None);
+    call!(
+        master_account,
+        art.submit_price("2000000000".to_string()), // every art is $20
+        gas = DEFAULT_GAS
+    )
+    .assert_success();
+
+    // alice holds no aUSD, so taking it fails and the art set aside for her goes back to owner
+    let alice = master_account.create_user("alice".to_string(), to_yocto("10"));
+    call!(
+        alice,
+        art.storage_deposit(None, None),
+        deposit = STORAGE_AMOUNT / 10
+    )
+    .assert_success();
+    let path = vec!["aUSD".to_string(), "art".to_string()];
+    call!(
+        alice,
+        art.swap(path, to_yocto("20000").to_string(), "0".to_string())
+    );
+    let alice_unstaked_art_balance: String =
+        view!(art.get_unstaked_balance(alice.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(alice_unstaked_art_balance, "0");
+    let master_unstaked_art_balance: String =
+        view!(art.get_unstaked_balance(master_account.account_id().try_into().unwrap()))
+            .unwrap_json();
+    assert_eq!(
+        master_unstaked_art_balance,
+        to_yocto(INIT_ART_BALANCE).to_string()
+    );
+    let alice_ausd_balance: U128 =
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(U128(0), alice_ausd_balance);
+}
+
+#[test]
+fn test_burn_to_unstake_with_ausd_transfer() {
+    let (master_account, art, ausd) = init(None);
+    call!(
+        master_account,
+        art.submit_price("2000000000".to_string()), // every art is $20
+        gas = DEFAULT_GAS
+    )
+    .assert_success();
+    call!(
+        master_account,
+        art.stake_and_mint(to_yocto("10000").to_string())
+    )
+    .assert_success();
+    call!(
+        master_account,
+        ausd.storage_deposit(Some("art".try_into().unwrap()), None),
+        deposit = STORAGE_AMOUNT / 10
+    )
+    .assert_success();
+
+    // Unstaking 1000 art burns 4000 aUSD, the other 1000 aUSD is refunded
+    let used: U128 = call!(
+        master_account,
+        ausd.ft_transfer_call(
+            "art".try_into().unwrap(),
+            U128(to_yocto("5000")),
+            None,
+            format!(
+                r#"{{"action":"burn_to_unstake","amount":"{}"}}"#,
+                to_yocto("1000")
+            )
+        ),
+        deposit = 1,
+        gas = DEFAULT_GAS
+    )
+    .unwrap_json();
+    assert_eq!(used, U128(to_yocto("4000")));
+    let master_ausd_balance: U128 =
+        view!(ausd.ft_balance_of(master_account.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(master_ausd_balance, U128(to_yocto("36000")));
+    let art_ausd_balance: U128 = view!(ausd.ft_balance_of("art".try_into().unwrap())).unwrap_json();
+    assert_eq!(art_ausd_balance, U128(0));
+    let master_staked_art_balance: String =
+        view!(art.get_staked_balance(master_account.account_id().try_into().unwrap()))
+            .unwrap_json();
+    assert_eq!(master_staked_art_balance, to_yocto("9000").to_string());
+}
+
+#[test]
+fn test_wrap_and_unwrap_stake() {
+    let (master_account, art, _ausd) = init(None);
+    let start = deploy! {
+        contract: StartContract,
+        contract_id: "start",
+        bytes: &START_WASM_BYTES,
+        signer_account: master_account,
+        init_method: new("art".to_string())
+    };
+    call!(
+        master_account,
+        art.set_start_token("start".try_into().unwrap())
+    )
+    .assert_success();
+    call!(
+        master_account,
+        art.submit_price("2000000000".to_string()), // every art is $20
+        gas = DEFAULT_GAS
+    )
+    .assert_success();
+    call!(
+        master_account,
+        art.stake_and_mint(to_yocto("10000").to_string())
+    )
+    .assert_success();
+
+    // stART isn't minted to an unregistered account, the stake stays with it
+    call!(
+        master_account,
+        art.wrap_stake(to_yocto("1000").to_string()),
+        gas = DEFAULT_GAS
+    );
+    let master_staked_art_balance: String =
+        view!(art.get_staked_balance(master_account.account_id().try_into().unwrap()))
+            .unwrap_json();
+    assert_eq!(master_staked_art_balance, to_yocto("10000").to_string());
+
+    call!(
+        master_account,
+        start.storage_deposit(None, None),
+        deposit = STORAGE_AMOUNT / 10
+    )
+    .assert_success();
+    call!(
+        master_account,
+        art.wrap_stake(to_yocto("1000").to_string()),
+        gas = DEFAULT_GAS
+    )
+    .assert_success();
+    let master_start_balance: U128 =
+        view!(start.ft_balance_of(master_account.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(master_start_balance, U128(to_yocto("1000")));
+    let master_staked_art_balance: String =
+        view!(art.get_staked_balance(master_account.account_id().try_into().unwrap()))
+            .unwrap_json();
+    assert_eq!(master_staked_art_balance, to_yocto("9000").to_string());
+
+    call!(
+        master_account,
+        art.unwrap_stake(to_yocto("1000").to_string()),
+        gas = DEFAULT_GAS
+    )
+    .assert_success();
+    let master_start_balance: U128 =
+        view!(start.ft_balance_of(master_account.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(master_start_balance, U128(0));
+    let start_supply: U128 = view!(start.ft_total_supply()).unwrap_json();
+    assert_eq!(start_supply, U128(0));
+    let master_staked_art_balance: String =
+        view!(art.get_staked_balance(master_account.account_id().try_into().unwrap()))
+            .unwrap_json();
+    assert_eq!(master_staked_art_balance, to_yocto("10000").to_string());
+}
+
+fn add_pool_liquidity(master_account: &UserAccount, art: &ContractAccount<ArtContract>) {
+    // 1 art = 0.1 NEAR
+    call!(
+        master_account,
+        art.add_liquidity(to_yocto("1000").to_string()),
+        deposit = to_yocto("100")
+    )
+    .assert_success();
+}
+
+fn pool_reserves(art: &ContractAccount<ArtContract>) -> (u128, u128) {
+    let pool: near_sdk::serde_json::Value = view!(art.get_pool()).unwrap_json();
+    (
+        u128::from_str(pool["art_reserve"].as_str().unwrap()).unwrap(),
+        u128::from_str(pool["near_reserve"].as_str().unwrap()).unwrap(),
+    )
+}
+
+#[test]
+fn test_pool_price_impact() {
+    let (master_account, art, _ausd) = init(None);
+    add_pool_liquidity(&master_account, &art);
+
+    let alice = master_account.create_user("alice".to_string(), to_yocto("100"));
+    call!(
+        alice,
+        art.storage_deposit(None, None),
+        deposit = STORAGE_AMOUNT / 10
+    )
+    .assert_success();
+    let first: String = call!(
+        alice,
+        art.swap_near_to_art("0".to_string()),
+        deposit = to_yocto("10")
+    )
+    .unwrap_json();
+    let first = u128::from_str(&first).unwrap();
+    let second: String = call!(
+        alice,
+        art.swap_near_to_art("0".to_string()),
+        deposit = to_yocto("10")
+    )
+    .unwrap_json();
+    let second = u128::from_str(&second).unwrap();
+
+    // 10 NEAR would buy 100 art at the initial price, each swap moves the price up
+    assert!(first < to_yocto("100"));
+    assert!(second < first);
+    let alice_unstaked_art_balance: String =
+        view!(art.get_unstaked_balance(alice.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(alice_unstaked_art_balance, (first + second).to_string());
+    assert_eq!(
+        pool_reserves(&art),
+        (to_yocto("1000") - first - second, to_yocto("120"))
+    );
+
+    // A swap that would receive less than `min_art_out` is rejected
+    let res = call!(
+        alice,
+        art.swap_near_to_art(to_yocto("100").to_string()),
+        deposit = to_yocto("10")
+    );
+    assert!(!res.is_ok());
+    assert_eq!(
+        pool_reserves(&art),
+        (to_yocto("1000") - first - second, to_yocto("120"))
+    );
+}
+
+#[test]
+fn test_pool_fee_accrual() {
+    let (master_account, art, _ausd) = init(None);
+    add_pool_liquidity(&master_account, &art);
+    let master_unstaked_art_balance: String =
+        view!(art.get_unstaked_balance(master_account.account_id().try_into().unwrap()))
+            .unwrap_json();
+
+    let alice = master_account.create_user("alice".to_string(), to_yocto("100"));
+    call!(
+        alice,
+        art.storage_deposit(None, None),
+        deposit = STORAGE_AMOUNT / 10
+    )
+    .assert_success();
+    let art_amount: String = call!(
+        alice,
+        art.swap_near_to_art("0".to_string()),
+        deposit = to_yocto("10")
+    )
+    .unwrap_json();
+    call!(
+        alice,
+        art.swap_art_to_near(art_amount, "0".to_string()),
+        gas = DEFAULT_GAS
+    )
+    .assert_success();
+
+    // The round trip leaves the fees from both swaps in the pool
+    let (art_reserve, near_reserve) = pool_reserves(&art);
+    assert_eq!(art_reserve, to_yocto("1000"));
+    assert!(near_reserve > to_yocto("100"));
+
+    let shares: String = view!(art.get_pool_shares(master_account.account_id())).unwrap_json();
+    assert_eq!(shares, to_yocto("100").to_string());
+    call!(
+        master_account,
+        art.remove_liquidity(shares),
+        gas = DEFAULT_GAS
+    )
+    .assert_success();
+    assert_eq!(pool_reserves(&art), (0, 0));
+    let master_art_after: String =
+        view!(art.get_unstaked_balance(master_account.account_id().try_into().unwrap()))
+            .unwrap_json();
+    assert_eq!(
+        u128::from_str(&master_art_after).unwrap(),
+        u128::from_str(&master_unstaked_art_balance).unwrap() + to_yocto("1000")
+    );
+}
+
+#[test]
+fn test_exchange_moved_price_rejected() {
+    let (master_account, art, ausd) = init(None);
+    let stake_amount = (to_yocto(INIT_ART_BALANCE) / 2).to_string();
+    call!(
+        master_account,
+        art.submit_price("2000000000".to_string()), // every art is $20
+        gas = DEFAULT_GAS
+    )
+    .assert_success();
+    call!(master_account, art.stake_and_mint(stake_amount)).assert_success();
+
+    let alice = master_account.create_user("alice".to_string(), to_yocto("10"));
+    register_ausd(&alice, &ausd);
+    call!(
+        master_account,
+        art.transfer(alice.account_id(), to_yocto("10000").to_string()),
+        deposit = STORAGE_AMOUNT / 10
+    )
+    .assert_success();
+
+    // alice signs at $20, but the oracle moves art to $19 before her transaction lands
+    let min_amount_out = to_yocto("10000") * 20 / 1000 * 997;
+    call!(
+        master_account,
+        art.submit_price("1900000000".to_string()),
+        gas = DEFAULT_GAS
+    )
+    .assert_success();
+    let res = call!(
+        alice,
+        art.exchange_art_to_ausd(
+            to_yocto("10000").to_string(),
+            Some(min_amount_out.to_string()),
+            None
+        )
+    );
+    assert!(!res.is_ok());
+    let alice_unstaked_art_balance: String =
+        view!(art.get_unstaked_balance(alice.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(alice_unstaked_art_balance, to_yocto("10000").to_string());
+    let alice_ausd_balance: U128 =
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(U128(0), alice_ausd_balance);
+
+    // At the new price with a matching bound the exchange goes through
+    call!(
+        alice,
+        art.exchange_art_to_ausd(
+            to_yocto("10000").to_string(),
+            Some((to_yocto("10000") * 19 / 1000 * 997).to_string()),
+            None
+        )
+    )
+    .assert_success();
+    let alice_ausd_balance: U128 =
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(
+        U128(to_yocto("10000") * 19 / 1000 * 997),
+        alice_ausd_balance
+    );
+}
@@ -2,6 +2,7 @@
 set -e
 cd "`dirname $0`"
 source ../flags.sh
+mkdir -p res
 cargo build --target wasm32-unknown-unknown --release
 cp target/wasm32-unknown-unknown/release/ausd.wasm ./res/
 
@@ -0,0 +1,40 @@
+//! NEP-297 events, see the `events` crate. Changes of aUSD balances and supply use the
+//! NEP-141 events, pool swaps are under the `ausd` standard.
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::Balance;
+
+use events::emit;
+pub(crate) use events::{ft_burn, ft_mint, ft_transfer};
+
+const AUSD_STANDARD: &str = "ausd";
+const AUSD_VERSION: &str = "1.0.0";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct Exchange<'a> {
+    account_id: &'a str,
+    path: &'a str,
+    amount_in: U128,
+    amount_out: U128,
+    fee: U128,
+}
+
+/// Swap of `amount_in` for `amount_out` in the pool, `path` is "near_to_ausd" or "ausd_to_near".
+/// The `fee` is in the input token and stays in the pool.
+pub(crate) fn exchange(
+    account_id: &str,
+    path: &str,
+    amount_in: Balance,
+    amount_out: Balance,
+    fee: Balance,
+) {
+    let data = Exchange {
+        account_id,
+        path,
+        amount_in: amount_in.into(),
+        amount_out: amount_out.into(),
+        fee: fee.into(),
+    };
+    emit(AUSD_STANDARD, AUSD_VERSION, "exchange", data);
+}
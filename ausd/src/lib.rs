@@ -1,11 +1,20 @@
 /**
 * Fungible Token implementation with JSON serialization.
 * NOTES:
+*  - Implements NEP-141 (fungible token core), NEP-145 (storage management) and NEP-148
+*    (metadata). Accounts have to be registered with `storage_deposit` before they can receive
+*    aUSD, including aUSD minted or bought through the art contract or the PSM. The sender of
+*    `ft_transfer`, `ft_transfer_call` or `batch_transfer` can register the receiver with the
+*    deposit attached beyond 1 yoctoNEAR. `storage_unregister` refunds only the NEAR deposited for
+*    the account.
+*  - The legacy NEP-21 methods (`inc_allowance`, `dec_allowance`, `transfer_from`, `transfer`,
+*    `get_total_supply`, `get_balance`, `get_allowance`) are only built with the `nep21` feature.
+*    The signed permits that set allowances (`set_permit_key`, `permit`) are always built.
 *  - The maximum balance value is limited by U128 (2**128 - 1).
 *  - JSON calls should pass U128 as a base-10 string. E.g. "100".
 *  - The contract optimizes the inner trie structure by hashing account IDs. It will prevent some
 *    abuse of deep tries. Shouldn't be an issue, once NEAR clients implement full hashing of keys.
-*  - The contract tracks the change in storage before and after the call. If the storage increases,
+*  - Outside of NEP-145, the contract tracks the change in storage before and after the call. If the storage increases,
 *    the contract requires the caller of the contract to attach enough deposit to the function call
 *    to cover the storage cost.
 *    This is done to prevent a denial of service attack on the contract by taking all available storage.
@@ -15,12 +24,26 @@
 *  - To prevent the deployed contract from being modified or deleted, it should not have any access
 *    keys on its account.
 */
+use batch_transfer::{assert_at_least_one_yocto, refund_deposit, BatchTransfer, FailedTransfer};
+use near_contract_standards::fungible_token::core::FungibleTokenCore;
+use near_contract_standards::fungible_token::metadata::{
+    FungibleTokenMetadata, FungibleTokenMetadataProvider, FT_METADATA_SPEC,
+};
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::LookupMap;
-use near_sdk::json_types::U128;
+use near_sdk::json_types::{Base58PublicKey, Base64VecU8, ValidAccountId, U128, U64};
+use near_sdk::serde::Serialize;
 use near_sdk::{
-    env, ext_contract, near_bindgen, AccountId, Balance, PanicOnDefault, Promise, StorageUsage,
+    assert_one_yocto, env, ext_contract, near_bindgen, AccountId, Balance, Gas, PanicOnDefault,
+    Promise, PromiseOrValue, PromiseResult, StorageUsage,
 };
+use num_bigint::BigInt;
+use num_traits::cast::ToPrimitive;
+
+mod event;
 
 #[cfg(target_arch = "wasm32")]
 #[global_allocator]
@@ -29,6 +52,12 @@ static ALLOC: near_sdk::wee_alloc::WeeAlloc<'_> = near_sdk::wee_alloc::WeeAlloc:
 /// Price per 1 byte of storage from mainnet genesis config.
 const STORAGE_PRICE_PER_BYTE: Balance = 100_000_000_000_000_000_000;
 
+const GAS_FOR_RESOLVE_TRANSFER: Gas = 5_000_000_000_000;
+const GAS_FOR_FT_TRANSFER_CALL: Gas = 25_000_000_000_000 + GAS_FOR_RESOLVE_TRANSFER;
+const GAS_FOR_POOL_CALLBACK: Gas = 10_000_000_000_000;
+
+const NO_DEPOSIT: Balance = 0;
+
 /// Contains balance and allowances information for one account.
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct Account {
@@ -73,10 +102,159 @@ impl Account {
     }
 }
 
+/// Constant-product (x * y = k) pool of aUSD and NEAR. Swap fees stay in the reserves, so they
+/// accrue to the liquidity providers.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Pool {
+    /// aUSD deposited to the pool, not part of any account balance
+    pub ausd_reserve: Balance,
+    /// NEAR deposited to the pool, part of the contract balance
+    pub near_reserve: Balance,
+    /// Total LP shares issued
+    pub total_shares: Balance,
+    /// sha256(AccountID) -> LP shares
+    pub shares: LookupMap<Vec<u8>, Balance>,
+    /// Swap fee in basis points
+    pub fee_bps: u32,
+}
+
+impl Pool {
+    /// Initializes an empty pool with a 0.3% swap fee.
+    pub fn new(prefix: Vec<u8>) -> Self {
+        Self {
+            ausd_reserve: 0,
+            near_reserve: 0,
+            total_shares: 0,
+            shares: LookupMap::new(prefix),
+            fee_bps: 30,
+        }
+    }
+
+    /// Returns the LP shares of `account_id`.
+    pub fn shares_of(&self, account_id: &AccountId) -> Balance {
+        self.shares
+            .get(&env::sha256(account_id.as_bytes()))
+            .unwrap_or(0)
+    }
+
+    fn set_shares(&mut self, account_id: &AccountId, shares: Balance) {
+        let account_hash = env::sha256(account_id.as_bytes());
+        if shares > 0 {
+            self.shares.insert(&account_hash, &shares);
+        } else {
+            self.shares.remove(&account_hash);
+        }
+    }
+
+    /// Returns the output of swapping `amount_in` against the reserves, after the fee.
+    fn amount_out(&self, amount_in: Balance, reserve_in: Balance, reserve_out: Balance) -> Balance {
+        if reserve_in == 0 || reserve_out == 0 {
+            env::panic(b"The pool has no liquidity");
+        }
+        let amount_in_with_fee: BigInt = BigInt::from(amount_in) * (10_000 - self.fee_bps);
+        let numerator: BigInt = &amount_in_with_fee * reserve_out;
+        let denominator: BigInt = BigInt::from(reserve_in) * 10_000 + amount_in_with_fee;
+        (numerator / denominator).to_u128().unwrap()
+    }
+}
+
+/// Reserves of the aUSD/NEAR pool.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolView {
+    pub ausd_reserve: U128,
+    pub near_reserve: U128,
+    pub total_shares: U128,
+    pub fee_bps: u32,
+}
+
+/// Peg stability module: swaps aUSD 1:1 with an external NEP-141 stablecoin, minus fees. The
+/// minted aUSD is always backed by the stablecoin reserve, fees are paid to the owner in aUSD.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Psm {
+    /// Stablecoin contract accepted by the PSM, `None` while not configured
+    pub stablecoin: Option<AccountId>,
+    /// Decimals of the stablecoin, aUSD has 24
+    pub decimals: u8,
+    /// Stablecoin held by the PSM
+    pub reserve: Balance,
+    /// Maximum aUSD backed by the reserve
+    pub debt_ceiling: Balance,
+    /// Fee of aUSD minted for the stablecoin, in basis points
+    pub mint_fee_bps: u32,
+    /// Fee of aUSD burnt to release the stablecoin, in basis points
+    pub redeem_fee_bps: u32,
+}
+
+impl Psm {
+    /// aUSD units per stablecoin unit.
+    fn scale(&self) -> Balance {
+        10u128.pow(24 - u32::from(self.decimals))
+    }
+
+    /// Returns the aUSD backed by the reserve.
+    fn debt(&self) -> Balance {
+        self.reserve * self.scale()
+    }
+}
+
+/// Configuration and reserve of the peg stability module.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PsmView {
+    pub stablecoin: Option<AccountId>,
+    pub decimals: u8,
+    pub reserve: U128,
+    pub debt: U128,
+    pub debt_ceiling: U128,
+    pub mint_fee_bps: u32,
+    pub redeem_fee_bps: u32,
+}
+
+#[ext_contract(ext_stablecoin)]
+pub trait ExtStablecoin {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+}
+
+#[ext_contract(ext_fungible_token_receiver)]
+pub trait FungibleTokenReceiver {
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128>;
+}
+
+#[ext_contract(ext_self)]
+pub trait ExtAUSDCallbacks {
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128;
+
+    fn on_psm_redeemed(
+        &mut self,
+        account_id: AccountId,
+        burn_amount: U128,
+        fee: U128,
+        stablecoin_amount: U128,
+    );
+
+    fn on_pool_near_sent(
+        &mut self,
+        account_id: AccountId,
+        ausd_amount: U128,
+        near_amount: U128,
+        shares: U128,
+    );
+}
+
 #[ext_contract(ext_gov)]
 pub trait ExtArtContract {
     fn unstake(&mut self, account_id: String, unstake_amount: u128) -> u128;
-    fn buy_asset_callback(&mut self, account_id: String, asset: String, asset_amount: u128);
 }
 
 #[near_bindgen]
@@ -93,6 +271,33 @@ pub struct AUSD {
 
     /// Who created this account, should be same as the one created Art contract
     pub owner_id: AccountId,
+
+    /// aUSD/NEAR constant-product pool
+    pub pool: Pool,
+
+    /// Peg stability module
+    pub psm: Psm,
+
+    /// Storage bytes of one registered account, see NEP-145
+    pub account_storage_usage: StorageUsage,
+
+    /// AccountID -> ed25519 key that signs its permits
+    pub permit_keys: LookupMap<AccountId, Vec<u8>>,
+
+    /// AccountID -> nonce of its next permit
+    pub permit_nonces: LookupMap<AccountId, u64>,
+
+    /// sha256(AccountID) -> NEAR deposited for its storage, refunded by `storage_unregister`
+    pub storage_deposits: LookupMap<Vec<u8>, Balance>,
+}
+
+/// State of the contract before the pool, PSM, storage deposits and permits.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct OldAUSD {
+    pub accounts: LookupMap<Vec<u8>, Account>,
+    pub total_supply: Balance,
+    pub art_token: AccountId,
+    pub owner_id: AccountId,
 }
 
 #[near_bindgen]
@@ -108,216 +313,557 @@ impl AUSD {
             total_supply,
             art_token,
             owner_id: owner_id.clone(),
+            pool: Pool::new(b"p".to_vec()),
+            psm: Psm {
+                stablecoin: None,
+                decimals: 24,
+                reserve: 0,
+                debt_ceiling: 0,
+                mint_fee_bps: 0,
+                redeem_fee_bps: 10,
+            },
+            account_storage_usage: 0,
+            permit_keys: LookupMap::new(b"k".to_vec()),
+            permit_nonces: LookupMap::new(b"n".to_vec()),
+            storage_deposits: LookupMap::new(b"d".to_vec()),
         };
-        let mut account = ft.get_account(&owner_id);
+        ft.internal_register_account(&owner_id);
+        let mut account = ft.internal_unwrap_account(&owner_id);
         account.balance = total_supply;
         ft.set_account(&owner_id, &account);
+        ft.measure_account_storage_usage();
+        if total_supply > 0 {
+            event::ft_mint(&owner_id, total_supply, None);
+        }
         ft
     }
 
-    /// Increments the `allowance` for `escrow_account_id` by `amount` on the account of the caller of this contract
-    /// (`predecessor_id`) who is the balance owner.
+    /// Migrates the state from before the pool, PSM, storage deposits and permits were added and
+    /// measures the storage of an account. Accounts registered before have no storage deposit to
+    /// refund. Only the contract itself can call it.
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "Method is private"
+        );
+        let old: OldAUSD = env::state_read().expect("Failed to read the old state");
+        let mut ft = Self {
+            accounts: old.accounts,
+            total_supply: old.total_supply,
+            art_token: old.art_token,
+            owner_id: old.owner_id,
+            pool: Pool::new(b"p".to_vec()),
+            psm: Psm {
+                stablecoin: None,
+                decimals: 24,
+                reserve: 0,
+                debt_ceiling: 0,
+                mint_fee_bps: 0,
+                redeem_fee_bps: 10,
+            },
+            account_storage_usage: 0,
+            permit_keys: LookupMap::new(b"k".to_vec()),
+            permit_nonces: LookupMap::new(b"n".to_vec()),
+            storage_deposits: LookupMap::new(b"d".to_vec()),
+        };
+        ft.measure_account_storage_usage();
+        ft
+    }
+
+    /// Deposits `near_amount` of the attached NEAR and the matching amount of aUSD, at most
+    /// `max_ausd_amount`, to the aUSD/NEAR pool. The first deposit sets the pool price. Returns the
+    /// minted LP shares.
     /// Requirements:
-    /// * Caller of the method has to attach deposit enough to cover storage difference at the
-    ///   fixed storage price defined in the contract.
+    /// * Caller of the method has to attach `near_amount` plus deposit enough to cover storage
+    ///   difference at the fixed storage price defined in the contract.
     #[payable]
-    pub fn inc_allowance(&mut self, escrow_account_id: AccountId, amount: U128) {
+    pub fn add_liquidity(&mut self, near_amount: U128, max_ausd_amount: U128) -> U128 {
         let initial_storage = env::storage_usage();
-        assert!(
-            env::is_valid_account_id(escrow_account_id.as_bytes()),
-            "Escrow account ID is invalid"
-        );
-        let owner_id = env::predecessor_account_id();
-        if escrow_account_id == owner_id {
-            env::panic(b"Can not increment allowance for yourself");
+        let near_amount: Balance = near_amount.into();
+        let max_ausd_amount: Balance = max_ausd_amount.into();
+        if near_amount == 0 {
+            env::panic(b"Can't add liquidity with 0 NEAR");
         }
+        let attached_deposit = env::attached_deposit();
+        if attached_deposit < near_amount {
+            env::panic(b"The attached deposit is less than near_amount");
+        }
+        let (ausd_amount, shares) = if self.pool.total_shares == 0 {
+            (max_ausd_amount, near_amount)
+        } else {
+            // Rounds up, so a deposit never dilutes the existing shares
+            (
+                mul_div_round_up(near_amount, self.pool.ausd_reserve, self.pool.near_reserve),
+                mul_div(near_amount, self.pool.total_shares, self.pool.near_reserve),
+            )
+        };
+        if ausd_amount == 0 {
+            env::panic(b"Can't add liquidity with 0 aUSD");
+        }
+        if ausd_amount > max_ausd_amount {
+            env::panic(b"The required aUSD amount exceeds max_ausd_amount");
+        }
+
+        let owner_id = env::predecessor_account_id();
         let mut account = self.get_account(&owner_id);
-        let current_allowance = account.get_allowance(&escrow_account_id);
-        account.set_allowance(
-            &escrow_account_id,
-            current_allowance.saturating_add(amount.0),
-        );
+        if account.balance < ausd_amount {
+            env::panic(b"Not enough balance");
+        }
+        account.balance -= ausd_amount;
         self.set_account(&owner_id, &account);
-        self.refund_storage(initial_storage);
+
+        self.pool.ausd_reserve += ausd_amount;
+        self.pool.near_reserve += near_amount;
+        self.pool.total_shares += shares;
+        let account_shares = self.pool.shares_of(&owner_id) + shares;
+        self.pool.set_shares(&owner_id, account_shares);
+        self.refund_storage_from(initial_storage, attached_deposit - near_amount);
+        shares.into()
     }
 
-    /// Decrements the `allowance` for `escrow_account_id` by `amount` on the account of the caller of this contract
-    /// (`predecessor_id`) who is the balance owner.
+    /// Burns `shares` of the caller's LP shares for their part of both pool reserves.
     /// Requirements:
     /// * Caller of the method has to attach deposit enough to cover storage difference at the
     ///   fixed storage price defined in the contract.
     #[payable]
-    pub fn dec_allowance(&mut self, escrow_account_id: AccountId, amount: U128) {
+    pub fn remove_liquidity(&mut self, shares: U128) {
         let initial_storage = env::storage_usage();
-        assert!(
-            env::is_valid_account_id(escrow_account_id.as_bytes()),
-            "Escrow account ID is invalid"
-        );
+        let shares: Balance = shares.into();
+        if shares == 0 {
+            env::panic(b"Can't remove 0 shares");
+        }
         let owner_id = env::predecessor_account_id();
-        if escrow_account_id == owner_id {
-            env::panic(b"Can not decrement allowance for yourself");
+        let account_shares = self.pool.shares_of(&owner_id);
+        if account_shares < shares {
+            env::panic(b"Not enough shares");
         }
-        let mut account = self.get_account(&owner_id);
-        let current_allowance = account.get_allowance(&escrow_account_id);
-        account.set_allowance(
-            &escrow_account_id,
-            current_allowance.saturating_sub(amount.0),
-        );
-        self.set_account(&owner_id, &account);
+        let ausd_amount = mul_div(shares, self.pool.ausd_reserve, self.pool.total_shares);
+        let near_amount = mul_div(shares, self.pool.near_reserve, self.pool.total_shares);
+        self.pool.set_shares(&owner_id, account_shares - shares);
+        self.pool.total_shares -= shares;
+        self.pool.ausd_reserve -= ausd_amount;
+        self.pool.near_reserve -= near_amount;
         self.refund_storage(initial_storage);
+
+        // The aUSD is credited once the NEAR is sent
+        self.internal_send_pool_near(owner_id, ausd_amount, near_amount, shares);
     }
 
-    /// Transfers the `amount` of tokens from `owner_id` to the `new_owner_id`.
+    /// Swaps `near_amount` of the attached NEAR for aUSD from the pool. Panics if less than
+    /// `min_ausd_out` would be received. Returns the received aUSD amount.
     /// Requirements:
-    /// * `amount` should be a positive integer.
-    /// * `owner_id` should have balance on the account greater or equal than the transfer `amount`.
-    /// * If this function is called by an escrow account (`owner_id != predecessor_account_id`),
-    ///   then the allowance of the caller of the function (`predecessor_account_id`) on
-    ///   the account of `owner_id` should be greater or equal than the transfer `amount`.
-    /// * Caller of the method has to attach deposit enough to cover storage difference at the
-    ///   fixed storage price defined in the contract.
+    /// * Caller of the method has to attach `near_amount` plus deposit enough to cover storage
+    ///   difference at the fixed storage price defined in the contract.
     #[payable]
-    pub fn transfer_from(&mut self, owner_id: AccountId, new_owner_id: AccountId, amount: U128) {
+    pub fn swap_near_to_ausd(&mut self, near_amount: U128, min_ausd_out: U128) -> U128 {
         let initial_storage = env::storage_usage();
-        assert!(
-            env::is_valid_account_id(new_owner_id.as_bytes()),
-            "New owner's account ID is invalid"
-        );
-        let amount = amount.into();
-        if amount == 0 {
-            env::panic(b"Can't transfer 0 tokens");
+        let near_amount: Balance = near_amount.into();
+        if near_amount == 0 {
+            env::panic(b"Can't swap 0 NEAR");
         }
-        assert_ne!(
-            owner_id, new_owner_id,
-            "The new owner should be different from the current owner"
+        let attached_deposit = env::attached_deposit();
+        if attached_deposit < near_amount {
+            env::panic(b"The attached deposit is less than near_amount");
+        }
+        let ausd_amount =
+            self.pool
+                .amount_out(near_amount, self.pool.near_reserve, self.pool.ausd_reserve);
+        if ausd_amount == 0 {
+            env::panic(b"Output amount is 0");
+        }
+        if ausd_amount < min_ausd_out.0 {
+            env::panic(b"Output amount is less than min_ausd_out");
+        }
+        self.pool.near_reserve += near_amount;
+        self.pool.ausd_reserve -= ausd_amount;
+
+        let owner_id = env::predecessor_account_id();
+        let mut account = self.internal_unwrap_account(&owner_id);
+        account.balance += ausd_amount;
+        self.set_account(&owner_id, &account);
+        event::exchange(
+            &owner_id,
+            "near_to_ausd",
+            near_amount,
+            ausd_amount,
+            mul_div(near_amount, self.pool.fee_bps.into(), 10_000),
         );
-        // Retrieving the account from the state.
-        let mut account = self.get_account(&owner_id);
+        self.refund_storage_from(initial_storage, attached_deposit - near_amount);
+        ausd_amount.into()
+    }
 
-        // Checking and updating unstaked balance
-        if account.balance < amount {
-            env::panic(b"Not enough balance");
+    /// Swaps `ausd_amount` of the caller's aUSD for NEAR from the pool. Panics if less than
+    /// `min_near_out` would be received. Returns the received NEAR amount.
+    #[payable]
+    pub fn swap_ausd_to_near(&mut self, ausd_amount: U128, min_near_out: U128) -> U128 {
+        let initial_storage = env::storage_usage();
+        let ausd_amount: Balance = ausd_amount.into();
+        if ausd_amount == 0 {
+            env::panic(b"Can't swap 0 tokens");
         }
-        account.balance -= amount;
-
-        // If transferring by escrow, need to check and update allowance.
-        let escrow_account_id = env::predecessor_account_id();
-        if escrow_account_id != owner_id {
-            let allowance = account.get_allowance(&escrow_account_id);
-            if allowance < amount {
-                env::panic(b"Not enough allowance");
-            }
-            account.set_allowance(&escrow_account_id, allowance - amount);
+        let near_amount =
+            self.pool
+                .amount_out(ausd_amount, self.pool.ausd_reserve, self.pool.near_reserve);
+        if near_amount == 0 {
+            env::panic(b"Output amount is 0");
+        }
+        if near_amount < min_near_out.0 {
+            env::panic(b"Output amount is less than min_near_out");
         }
 
-        // Saving the account back to the state.
+        let owner_id = env::predecessor_account_id();
+        let mut account = self.get_account(&owner_id);
+        if account.balance < ausd_amount {
+            env::panic(b"Not enough balance");
+        }
+        account.balance -= ausd_amount;
         self.set_account(&owner_id, &account);
 
-        // Deposit amount to the new owner and save the new account to the state.
-        let mut new_account = self.get_account(&new_owner_id);
-        new_account.balance += amount;
-        self.set_account(&new_owner_id, &new_account);
+        // The aUSD is added to the pool once the NEAR is sent
+        self.pool.near_reserve -= near_amount;
         self.refund_storage(initial_storage);
+        self.internal_send_pool_near(owner_id, ausd_amount, near_amount, 0);
+        near_amount.into()
     }
 
-    /// Transfer `amount` of tokens from the caller of the contract (`predecessor_id`) to
-    /// `new_owner_id`.
-    /// Act the same was as `transfer_from` with `owner_id` equal to the caller of the contract
-    /// (`predecessor_id`).
-    /// Requirements:
-    /// * Caller of the method has to attach deposit enough to cover storage difference at the
-    ///   fixed storage price defined in the contract.
-    #[payable]
-    pub fn transfer(&mut self, new_owner_id: AccountId, amount: U128) {
-        // NOTE: New owner's Account ID checked in transfer_from.
-        // Storage fees are also refunded in transfer_from.
-        self.transfer_from(env::predecessor_account_id(), new_owner_id, amount);
+    /// Completes a pool withdrawal of `shares`, or a swap if `shares` is 0, once its NEAR is sent:
+    /// the withdrawn aUSD goes to the account, or the swapped aUSD to the pool and the swap is
+    /// logged. If sending the NEAR failed, the amounts taken out are put back instead.
+    pub fn on_pool_near_sent(
+        &mut self,
+        account_id: AccountId,
+        ausd_amount: U128,
+        near_amount: U128,
+        shares: U128,
+    ) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "Method is private"
+        );
+        let success = matches!(env::promise_result(0), PromiseResult::Successful(_));
+        if !success {
+            env::log(
+                format!(
+                    "Failed to send {} NEAR from the pool to {}, reverting",
+                    near_amount.0, account_id
+                )
+                .as_bytes(),
+            );
+        }
+        if shares.0 == 0 {
+            if success {
+                self.pool.ausd_reserve += ausd_amount.0;
+                event::exchange(
+                    &account_id,
+                    "ausd_to_near",
+                    ausd_amount.0,
+                    near_amount.0,
+                    mul_div(ausd_amount.0, self.pool.fee_bps.into(), 10_000),
+                );
+            } else {
+                self.pool.near_reserve += near_amount.0;
+                let mut account = self.get_account(&account_id);
+                account.balance += ausd_amount.0;
+                self.set_account(&account_id, &account);
+            }
+        } else if success {
+            let mut account = self.get_account(&account_id);
+            account.balance += ausd_amount.0;
+            self.set_account(&account_id, &account);
+        } else {
+            self.pool.ausd_reserve += ausd_amount.0;
+            self.pool.near_reserve += near_amount.0;
+            self.pool.total_shares += shares.0;
+            let account_shares = self.pool.shares_of(&account_id) + shares.0;
+            self.pool.set_shares(&account_id, account_shares);
+        }
     }
 
-    /// Returns total supply of tokens.
-    pub fn get_total_supply(&self) -> U128 {
-        self.total_supply.into()
+    /// Sets the pool swap fee. Only the owner can set it.
+    pub fn set_pool_fee_bps(&mut self, fee_bps: u32) {
+        assert!(
+            env::predecessor_account_id() == self.owner_id,
+            "Only owner can set the pool fee"
+        );
+        assert!(fee_bps < 10_000, "Fee must be less than 10000 basis points");
+        self.pool.fee_bps = fee_bps;
     }
 
-    /// Returns balance of the `owner_id` account.
-    pub fn get_balance(&self, owner_id: AccountId) -> U128 {
-        self.get_account(&owner_id).balance.into()
+    /// Returns the aUSD/NEAR pool reserves.
+    pub fn get_pool(&self) -> PoolView {
+        PoolView {
+            ausd_reserve: self.pool.ausd_reserve.into(),
+            near_reserve: self.pool.near_reserve.into(),
+            total_shares: self.pool.total_shares.into(),
+            fee_bps: self.pool.fee_bps,
+        }
     }
 
-    /// Returns current allowance of `escrow_account_id` for the account of `owner_id`.
-    ///
-    /// NOTE: Other contracts should not rely on this information, because by the moment a contract
-    /// receives this information, the allowance may already be changed by the owner.
-    /// So this method should only be used on the front-end to see the current allowance.
-    pub fn get_allowance(&self, owner_id: AccountId, escrow_account_id: AccountId) -> U128 {
+    /// Returns the LP shares of `owner_id`.
+    pub fn get_pool_shares(&self, owner_id: AccountId) -> U128 {
+        self.pool.shares_of(&owner_id).into()
+    }
+
+    /// Returns the pool price of 1 NEAR in aUSD, per 10^8 like the oracle prices. While aUSD holds
+    /// its peg it's close to the oracle NEAR price, a lower pool price means aUSD trades above $1.
+    pub fn get_pool_price(&self) -> U128 {
+        if self.pool.near_reserve == 0 {
+            return 0.into();
+        }
+        mul_div(self.pool.ausd_reserve, 100_000_000, self.pool.near_reserve).into()
+    }
+
+    /// Sets the stablecoin accepted by the PSM and its decimals. Only the owner can set it, while
+    /// the PSM holds no reserve.
+    pub fn set_psm_stablecoin(&mut self, stablecoin: AccountId, decimals: u8) {
+        self.assert_owner();
         assert!(
-            env::is_valid_account_id(escrow_account_id.as_bytes()),
-            "Escrow account ID is invalid"
+            env::is_valid_account_id(stablecoin.as_bytes()),
+            "Stablecoin account ID is invalid"
         );
-        self.get_account(&owner_id)
-            .get_allowance(&escrow_account_id)
-            .into()
+        assert!(
+            decimals <= 24,
+            "Stablecoin can't have more than 24 decimals"
+        );
+        assert!(
+            self.psm.reserve == 0,
+            "Can't change the stablecoin while the PSM holds a reserve"
+        );
+        self.psm.stablecoin = Some(stablecoin);
+        self.psm.decimals = decimals;
     }
 
-    pub fn mint(&mut self, account_id: String, amount: u128) -> u128 {
+    /// Sets the maximum aUSD the PSM can mint. Only the owner can set it.
+    pub fn set_psm_debt_ceiling(&mut self, debt_ceiling: U128) {
+        self.assert_owner();
+        self.psm.debt_ceiling = debt_ceiling.into();
+    }
+
+    /// Sets the PSM mint and redeem fees. Only the owner can set them.
+    pub fn set_psm_fees(&mut self, mint_fee_bps: u32, redeem_fee_bps: u32) {
+        self.assert_owner();
         assert!(
-            env::predecessor_account_id() == self.art_token,
-            "Only allow mint originated from governance token"
+            mint_fee_bps < 10_000 && redeem_fee_bps < 10_000,
+            "Fee must be less than 10000 basis points"
         );
-        let mut account = self.get_account(&account_id);
-        // env::log(&amount.to_string().as_bytes());
-        account.balance += amount;
-        self.total_supply += amount;
-        env::log(&self.total_supply.to_string().as_bytes());
-        self.set_account(&account_id, &account);
-        amount
+        self.psm.mint_fee_bps = mint_fee_bps;
+        self.psm.redeem_fee_bps = redeem_fee_bps;
     }
 
-    fn burn(&mut self, account_id: String, burn_amount: u128) {
+    /// Mints aUSD 1:1 for the PSM stablecoin sent with `ft_transfer_call`, minus the mint fee.
+    /// Panicking here makes the stablecoin contract refund the transfer.
+    #[allow(unused_variables)]
+    pub fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
         assert!(
-            env::predecessor_account_id() == self.art_token,
-            "Only allow burn originated from governance token"
+            self.psm.stablecoin.as_ref() == Some(&env::predecessor_account_id()),
+            "Only the PSM stablecoin can be deposited"
         );
-        if burn_amount == 0 {
-            env::panic(b"Can't burn 0 tokens");
+        let amount: Balance = amount.into();
+        if amount == 0 {
+            env::panic(b"Can't deposit 0 tokens");
         }
-        let mut account = self.get_account(&account_id);
-        if account.balance < burn_amount {
-            env::panic(b"Not enough balance to burn");
+        let ausd_amount = amount
+            .checked_mul(self.psm.scale())
+            .expect("Deposit amount overflow");
+        if self.psm.debt() + ausd_amount > self.psm.debt_ceiling {
+            env::panic(b"The PSM debt ceiling is reached");
         }
-        account.balance -= burn_amount;
-        self.total_supply -= burn_amount;
-        self.set_account(&account_id, &account);
+        let fee = mul_div(ausd_amount, self.psm.mint_fee_bps.into(), 10_000);
+
+        let mut account = self.internal_unwrap_account(&sender_id);
+        account.balance += ausd_amount - fee;
+        self.set_account(&sender_id, &account);
+        self.internal_pay_fee(fee);
+        self.total_supply += ausd_amount;
+        self.psm.reserve += amount;
+        event::ft_mint(&sender_id, ausd_amount - fee, Some("psm"));
+        if fee > 0 {
+            event::ft_mint(&self.owner_id, fee, Some("psm fee"));
+        }
+        env::log(
+            format!(
+                "PSM minted {} aUSD to {} for {} stablecoin, fee {}",
+                ausd_amount - fee,
+                sender_id,
+                amount,
+                fee
+            )
+            .as_bytes(),
+        );
+        PromiseOrValue::Value(0.into())
     }
 
-    pub fn burn_to_unstake(
-        &mut self,
-        account_id: String,
-        burn_amount: u128,
-        unstake_amount: u128,
-    ) -> Promise {
-        self.burn(account_id.clone(), burn_amount);
-        ext_gov::unstake(
-            account_id,
-            unstake_amount,
-            &self.art_token,
-            0,
-            env::prepaid_gas() / 3,
-        )
-    }
+    /// Burns `ausd_amount` of the caller's aUSD for the same value of the PSM stablecoin, minus the
+    /// redeem fee. Amounts below the stablecoin precision stay with the caller.
+    /// Requirements:
+    /// * Caller of the method has to attach deposit enough to cover storage difference at the
+    ///   fixed storage price defined in the contract.
+    #[payable]
+    pub fn psm_redeem(&mut self, ausd_amount: U128) -> Promise {
+        let initial_storage = env::storage_usage();
+        let stablecoin = self
+            .psm
+            .stablecoin
+            .clone()
+            .unwrap_or_else(|| env::panic(b"The PSM is not configured"));
+        let ausd_amount: Balance = ausd_amount.into();
+        let fee = mul_div(ausd_amount, self.psm.redeem_fee_bps.into(), 10_000);
+        let stablecoin_amount = (ausd_amount - fee) / self.psm.scale();
+        if stablecoin_amount == 0 {
+            env::panic(b"Can't redeem 0 tokens");
+        }
+        if stablecoin_amount > self.psm.reserve {
+            env::panic(b"Not enough stablecoin in the PSM");
+        }
+        let burn_amount = stablecoin_amount * self.psm.scale();
+
+        let owner_id = env::predecessor_account_id();
+        let mut account = self.get_account(&owner_id);
+        if account.balance < burn_amount + fee {
+            env::panic(b"Not enough balance");
+        }
+        account.balance -= burn_amount + fee;
+        self.set_account(&owner_id, &account);
+        self.internal_pay_fee(fee);
+        self.total_supply -= burn_amount;
+        self.psm.reserve -= stablecoin_amount;
+        event::ft_burn(&owner_id, burn_amount, Some("psm"));
+        if fee > 0 {
+            event::ft_transfer(&owner_id, &self.owner_id, fee, Some("psm fee"));
+        }
+        self.refund_storage(initial_storage);
+
+        ext_stablecoin::ft_transfer(
+            owner_id.clone(),
+            stablecoin_amount.into(),
+            None,
+            &stablecoin,
+            1,
+            env::prepaid_gas() / 3,
+        )
+        .then(ext_self::on_psm_redeemed(
+            owner_id,
+            burn_amount.into(),
+            fee.into(),
+            stablecoin_amount.into(),
+            &env::current_account_id(),
+            0,
+            env::prepaid_gas() / 3,
+        ))
+    }
+
+    /// Reverts a PSM redeem if the stablecoin transfer failed, e.g. when the caller isn't
+    /// registered with the stablecoin contract.
+    pub fn on_psm_redeemed(
+        &mut self,
+        account_id: AccountId,
+        burn_amount: U128,
+        fee: U128,
+        stablecoin_amount: U128,
+    ) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "Method is private"
+        );
+        if let PromiseResult::Successful(_) = env::promise_result(0) {
+            return;
+        }
+        env::log(format!("Failed to send stablecoin, refunding to {}", account_id).as_bytes());
+        // The owner may have moved the fee on since, then only the burnt aUSD is refunded
+        let owner_id = self.owner_id.clone();
+        let mut owner = self.get_account(&owner_id);
+        let fee = match owner.balance.checked_sub(fee.0) {
+            Some(balance) => {
+                owner.balance = balance;
+                self.set_account(&owner_id, &owner);
+                fee.0
+            }
+            None => {
+                env::log(
+                    format!(
+                        "Error: the owner balance {} is less than the fee {}, not refunding it",
+                        owner.balance, fee.0
+                    )
+                    .as_bytes(),
+                );
+                0
+            }
+        };
+        let mut account = self.get_account(&account_id);
+        account.balance += burn_amount.0 + fee;
+        self.set_account(&account_id, &account);
+        self.total_supply += burn_amount.0;
+        self.psm.reserve += stablecoin_amount.0;
+        event::ft_mint(&account_id, burn_amount.0, Some("psm refund"));
+        if fee > 0 {
+            event::ft_transfer(&owner_id, &account_id, fee, Some("psm refund"));
+        }
+    }
+
+    /// Returns the PSM configuration and reserve.
+    pub fn get_psm(&self) -> PsmView {
+        PsmView {
+            stablecoin: self.psm.stablecoin.clone(),
+            decimals: self.psm.decimals,
+            reserve: self.psm.reserve.into(),
+            debt: self.psm.debt().into(),
+            debt_ceiling: self.psm.debt_ceiling.into(),
+            mint_fee_bps: self.psm.mint_fee_bps,
+            redeem_fee_bps: self.psm.redeem_fee_bps,
+        }
+    }
+
+    pub fn mint(&mut self, account_id: String, amount: u128) -> u128 {
+        assert!(
+            env::predecessor_account_id() == self.art_token,
+            "Only allow mint originated from governance token"
+        );
+        let mut account = self.internal_unwrap_account(&account_id);
+        account.balance += amount;
+        self.total_supply += amount;
+        self.set_account(&account_id, &account);
+        event::ft_mint(&account_id, amount, None);
+        amount
+    }
+
+    /// Burns `burn_amount` of tokens from `account_id`. Only the art contract can burn.
+    pub fn burn(&mut self, account_id: String, burn_amount: u128) {
+        assert!(
+            env::predecessor_account_id() == self.art_token,
+            "Only allow burn originated from governance token"
+        );
+        if burn_amount == 0 {
+            env::panic(b"Can't burn 0 tokens");
+        }
+        let mut account = self.get_account(&account_id);
+        if account.balance < burn_amount {
+            env::panic(b"Not enough balance to burn");
+        }
+        account.balance -= burn_amount;
+        self.total_supply -= burn_amount;
+        self.set_account(&account_id, &account);
+        event::ft_burn(&account_id, burn_amount, None);
+    }
 
-    pub fn burn_to_buy_asset(
+    pub fn burn_to_unstake(
         &mut self,
         account_id: String,
         burn_amount: u128,
-        asset: String,
-        asset_amount: u128,
+        unstake_amount: u128,
     ) -> Promise {
         self.burn(account_id.clone(), burn_amount);
-        ext_gov::buy_asset_callback(
+        ext_gov::unstake(
             account_id,
-            asset,
-            asset_amount,
+            unstake_amount,
             &self.art_token,
             0,
             env::prepaid_gas() / 3,
@@ -329,14 +875,17 @@ impl AUSD {
             env::predecessor_account_id() == self.art_token,
             "Only allow buy ausd originated from governance token"
         );
-        let mut account = self.get_account(&new_owner_id);
-        account.balance = account.balance.checked_add(amount.0).unwrap();
         let contract_owner_id = self.owner_id.clone();
         let mut owner = self.get_account(&contract_owner_id);
         owner.balance = owner.balance.checked_sub(amount.0).unwrap();
+        self.set_account(&contract_owner_id, &owner);
 
+        // Read after owner is updated, so buying for owner itself, e.g. fees paid to owner as
+        // treasury, leaves its balance unchanged
+        let mut account = self.internal_unwrap_account(&new_owner_id);
+        account.balance = account.balance.checked_add(amount.0).unwrap();
         self.set_account(&new_owner_id, &account);
-        self.set_account(&contract_owner_id, &owner);
+        event::ft_transfer(&contract_owner_id, &new_owner_id, amount.0, None);
     }
 
     pub fn sell_ausd(&mut self, seller_id: AccountId, amount: U128) {
@@ -352,354 +901,1771 @@ impl AUSD {
 
         self.set_account(&seller_id, &account);
         self.set_account(&contract_owner_id, &owner);
+        event::ft_transfer(&seller_id, &contract_owner_id, amount.0, None);
+    }
+
+    /// Transfers the amount of each leg of `transfers` from the caller to its receiver, like
+    /// `ft_transfer`, logging an `ft_transfer` event per leg. The caller's balance has to cover
+    /// the total of all legs. Unregistered receivers are registered with the attached deposit
+    /// beyond 1 yoctoNEAR, the rest is refunded. A leg fails if its receiver isn't registered and
+    /// the deposit left doesn't cover it, is the caller, or its amount is 0. If `partial` is set,
+    /// failed legs are skipped and returned, otherwise the first one reverts the whole batch.
+    #[payable]
+    pub fn batch_transfer(
+        &mut self,
+        transfers: Vec<(ValidAccountId, U128)>,
+        memo: Option<String>,
+        partial: Option<bool>,
+    ) -> Vec<FailedTransfer> {
+        let deposit = assert_at_least_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let (failed, refund) =
+            batch_transfer::batch_transfer(self, &sender_id, transfers, memo, partial, deposit);
+        refund_deposit(refund);
+        failed
     }
 }
 
+/// Legacy NEP-21 interface, kept for the existing integrations. The allowances and balances are
+/// shared with the NEP-141 interface.
+#[cfg(feature = "nep21")]
+#[near_bindgen]
 impl AUSD {
-    /// Helper method to get the account details for `owner_id`.
-    fn get_account(&self, owner_id: &AccountId) -> Account {
+    /// Increments the `allowance` for `escrow_account_id` by `amount` on the account of the caller of this contract
+    /// (`predecessor_id`) who is the balance owner.
+    /// Requirements:
+    /// * Caller of the method has to attach deposit enough to cover storage difference at the
+    ///   fixed storage price defined in the contract.
+    #[payable]
+    pub fn inc_allowance(&mut self, escrow_account_id: AccountId, amount: U128) {
+        let initial_storage = env::storage_usage();
         assert!(
-            env::is_valid_account_id(owner_id.as_bytes()),
-            "Owner's account ID is invalid"
+            env::is_valid_account_id(escrow_account_id.as_bytes()),
+            "Escrow account ID is invalid"
         );
-        let account_hash = env::sha256(owner_id.as_bytes());
-        self.accounts
-            .get(&account_hash)
-            .unwrap_or_else(|| Account::new(account_hash))
+        let owner_id = env::predecessor_account_id();
+        if escrow_account_id == owner_id {
+            env::panic(b"Can not increment allowance for yourself");
+        }
+        let mut account = self.get_account(&owner_id);
+        let current_allowance = account.get_allowance(&escrow_account_id);
+        account.set_allowance(
+            &escrow_account_id,
+            current_allowance.saturating_add(amount.0),
+        );
+        self.set_account(&owner_id, &account);
+        self.refund_storage(initial_storage);
     }
 
-    /// Helper method to set the account details for `owner_id` to the state.
-    fn set_account(&mut self, owner_id: &AccountId, account: &Account) {
-        let account_hash = env::sha256(owner_id.as_bytes());
-        if account.balance > 0 || account.num_allowances > 0 {
-            self.accounts.insert(&account_hash, &account);
-        } else {
-            self.accounts.remove(&account_hash);
+    /// Decrements the `allowance` for `escrow_account_id` by `amount` on the account of the caller of this contract
+    /// (`predecessor_id`) who is the balance owner.
+    /// Requirements:
+    /// * Caller of the method has to attach deposit enough to cover storage difference at the
+    ///   fixed storage price defined in the contract.
+    #[payable]
+    pub fn dec_allowance(&mut self, escrow_account_id: AccountId, amount: U128) {
+        let initial_storage = env::storage_usage();
+        assert!(
+            env::is_valid_account_id(escrow_account_id.as_bytes()),
+            "Escrow account ID is invalid"
+        );
+        let owner_id = env::predecessor_account_id();
+        if escrow_account_id == owner_id {
+            env::panic(b"Can not decrement allowance for yourself");
         }
+        let mut account = self.get_account(&owner_id);
+        let current_allowance = account.get_allowance(&escrow_account_id);
+        account.set_allowance(
+            &escrow_account_id,
+            current_allowance.saturating_sub(amount.0),
+        );
+        self.set_account(&owner_id, &account);
+        self.refund_storage(initial_storage);
     }
 
-    fn refund_storage(&self, initial_storage: StorageUsage) {
-        let current_storage = env::storage_usage();
-        let attached_deposit = env::attached_deposit();
-        let refund_amount = if current_storage > initial_storage {
-            let required_deposit =
-                Balance::from(current_storage - initial_storage) * STORAGE_PRICE_PER_BYTE;
-            assert!(
-                required_deposit <= attached_deposit,
-                "The required attached deposit is {}, but the given attached deposit is is {}",
-                required_deposit,
-                attached_deposit,
-            );
-            attached_deposit - required_deposit
-        } else {
-            attached_deposit
-                + Balance::from(initial_storage - current_storage) * STORAGE_PRICE_PER_BYTE
-        };
-        if refund_amount > 0 {
-            env::log(format!("Refunding {} tokens for storage", refund_amount).as_bytes());
-            Promise::new(env::predecessor_account_id()).transfer(refund_amount);
+    /// Transfers the `amount` of tokens from `owner_id` to the `new_owner_id`.
+    /// Requirements:
+    /// * `amount` should be a positive integer.
+    /// * `owner_id` should have balance on the account greater or equal than the transfer `amount`.
+    /// * If this function is called by an escrow account (`owner_id != predecessor_account_id`),
+    ///   then the allowance of the caller of the function (`predecessor_account_id`) on
+    ///   the account of `owner_id` should be greater or equal than the transfer `amount`.
+    /// * Caller of the method has to attach deposit enough to cover storage difference at the
+    ///   fixed storage price defined in the contract.
+    /// * If `new_owner_id` isn't registered, the attached deposit has to cover its minimum storage
+    ///   balance too, it's registered like with `storage_deposit`.
+    #[payable]
+    pub fn transfer_from(&mut self, owner_id: AccountId, new_owner_id: AccountId, amount: U128) {
+        assert!(
+            env::is_valid_account_id(new_owner_id.as_bytes()),
+            "New owner's account ID is invalid"
+        );
+        let attached_deposit =
+            self.internal_register_receiver(&new_owner_id, env::attached_deposit());
+        let initial_storage = env::storage_usage();
+        let amount = amount.into();
+        if amount == 0 {
+            env::panic(b"Can't transfer 0 tokens");
         }
-    }
-}
+        assert_ne!(
+            owner_id, new_owner_id,
+            "The new owner should be different from the current owner"
+        );
+        // Retrieving the account from the state.
+        let mut account = self.get_account(&owner_id);
 
-#[cfg(all(test, not(target_arch = "wasm32")))]
-mod tests {
-    use near_sdk::MockedBlockchain;
-    use near_sdk::{testing_env, VMContext};
+        // Checking and updating unstaked balance
+        if account.balance < amount {
+            env::panic(b"Not enough balance");
+        }
+        account.balance -= amount;
 
-    use super::*;
+        // If transferring by escrow, need to check and update allowance.
+        let escrow_account_id = env::predecessor_account_id();
+        if escrow_account_id != owner_id {
+            let allowance = account.get_allowance(&escrow_account_id);
+            if allowance < amount {
+                env::panic(b"Not enough allowance");
+            }
+            account.set_allowance(&escrow_account_id, allowance - amount);
+        }
 
-    fn alice() -> AccountId {
-        "alice.near".to_string()
-    }
-    fn bob() -> AccountId {
-        "bob.near".to_string()
-    }
-    fn carol() -> AccountId {
-        "carol.near".to_string()
+        // Saving the account back to the state.
+        self.set_account(&owner_id, &account);
+
+        // Deposit amount to the new owner and save the new account to the state.
+        let mut new_account = self.internal_unwrap_account(&new_owner_id);
+        new_account.balance += amount;
+        self.set_account(&new_owner_id, &new_account);
+        event::ft_transfer(&owner_id, &new_owner_id, amount, None);
+        self.refund_storage_from(initial_storage, attached_deposit);
     }
 
-    fn get_context(predecessor_account_id: AccountId) -> VMContext {
-        VMContext {
-            current_account_id: alice(),
-            signer_account_id: bob(),
-            signer_account_pk: vec![0, 1, 2],
-            predecessor_account_id,
-            input: vec![],
-            block_index: 0,
-            block_timestamp: 0,
-            account_balance: 1000 * 10u128.pow(24),
-            account_locked_balance: 0,
-            storage_usage: 10u64.pow(6),
-            attached_deposit: 0,
-            prepaid_gas: 10u64.pow(18),
-            random_seed: vec![0, 1, 2],
-            is_view: false,
-            output_data_receivers: vec![],
-            epoch_height: 0,
-        }
+    /// Transfer `amount` of tokens from the caller of the contract (`predecessor_id`) to
+    /// `new_owner_id`.
+    /// Act the same was as `transfer_from` with `owner_id` equal to the caller of the contract
+    /// (`predecessor_id`).
+    /// Requirements:
+    /// * Caller of the method has to attach deposit enough to cover storage difference at the
+    ///   fixed storage price defined in the contract.
+    #[payable]
+    pub fn transfer(&mut self, new_owner_id: AccountId, amount: U128) {
+        // NOTE: New owner's Account ID checked in transfer_from.
+        // Storage fees are also refunded in transfer_from.
+        self.transfer_from(env::predecessor_account_id(), new_owner_id, amount);
     }
 
-    #[test]
-    fn test_new() {
-        let context = get_context(carol());
-        testing_env!(context);
-        let total_supply = 1_000_000_000_000_000u128;
-        let contract = AUSD::new(bob(), total_supply.into(), "art".to_string());
-        assert_eq!(contract.get_total_supply().0, total_supply);
-        assert_eq!(contract.get_balance(bob()).0, total_supply);
+    /// Returns total supply of tokens.
+    pub fn get_total_supply(&self) -> U128 {
+        self.total_supply.into()
     }
 
-    #[test]
-    #[should_panic(expected = "The contract is not initialized")]
+    /// Returns balance of the `owner_id` account.
+    pub fn get_balance(&self, owner_id: AccountId) -> U128 {
+        self.get_account(&owner_id).balance.into()
+    }
+
+    /// Returns current allowance of `escrow_account_id` for the account of `owner_id`.
+    ///
+    /// NOTE: Other contracts should not rely on this information, because by the moment a contract
+    /// receives this information, the allowance may already be changed by the owner.
+    /// So this method should only be used on the front-end to see the current allowance.
+    pub fn get_allowance(&self, owner_id: AccountId, escrow_account_id: AccountId) -> U128 {
+        assert!(
+            env::is_valid_account_id(escrow_account_id.as_bytes()),
+            "Escrow account ID is invalid"
+        );
+        self.get_account(&owner_id)
+            .get_allowance(&escrow_account_id)
+            .into()
+    }
+}
+
+/// Signed permits, they set the allowances of the legacy NEP-21 interface without a transaction
+/// of the owner.
+#[near_bindgen]
+impl AUSD {
+    /// Registers the ed25519 key that signs the permits of the caller, or removes it with `None`.
+    /// Requirements:
+    /// * Caller of the method has to attach deposit enough to cover storage difference at the
+    ///   fixed storage price defined in the contract.
+    #[payable]
+    pub fn set_permit_key(&mut self, public_key: Option<Base58PublicKey>) {
+        let initial_storage = env::storage_usage();
+        let owner_id = env::predecessor_account_id();
+        match public_key {
+            Some(public_key) => {
+                self.permit_keys
+                    .insert(&owner_id, &permit::ed25519_key_bytes(public_key));
+            }
+            None => {
+                self.permit_keys.remove(&owner_id);
+            }
+        }
+        self.refund_storage(initial_storage);
+    }
+
+    /// Sets the allowance of `escrow_account_id` on the account of `owner_id` to `amount` with a
+    /// `PermitMessage` signed by the permit key of `owner_id`, so the owner doesn't need to send a
+    /// transaction. Anyone can submit the permit, e.g. a relayer.
+    /// Requirements:
+    /// * `nonce` should be the current permit nonce of `owner_id`, the permit increments it.
+    /// * The block timestamp should be at most `deadline`.
+    /// * Caller of the method has to attach deposit enough to cover storage difference at the
+    ///   fixed storage price defined in the contract.
+    #[payable]
+    pub fn permit(
+        &mut self,
+        owner_id: AccountId,
+        escrow_account_id: AccountId,
+        amount: U128,
+        nonce: U64,
+        deadline: U64,
+        signature: Base64VecU8,
+    ) {
+        let initial_storage = env::storage_usage();
+        assert!(
+            env::is_valid_account_id(escrow_account_id.as_bytes()),
+            "Escrow account ID is invalid"
+        );
+        if escrow_account_id == owner_id {
+            env::panic(b"Can not set allowance for yourself");
+        }
+        if env::block_timestamp() > deadline.0 {
+            env::panic(b"Deadline has passed");
+        }
+        let public_key = self
+            .permit_keys
+            .get(&owner_id)
+            .unwrap_or_else(|| env::panic(b"The owner has no permit key"));
+        let current_nonce = self.permit_nonces.get(&owner_id).unwrap_or(0);
+        if nonce.0 != current_nonce {
+            env::panic(b"Invalid permit nonce");
+        }
+        let message = permit::PermitMessage {
+            contract_id: env::current_account_id(),
+            owner_id: owner_id.clone(),
+            spender_id: escrow_account_id.clone(),
+            amount: amount.0,
+            nonce: nonce.0,
+            deadline: deadline.0,
+        };
+        message.assert_signed(&public_key, &signature.0);
+        self.permit_nonces.insert(&owner_id, &(current_nonce + 1));
+
+        let mut account = self.get_account(&owner_id);
+        account.set_allowance(&escrow_account_id, amount.0);
+        self.set_account(&owner_id, &account);
+        self.refund_storage(initial_storage);
+    }
+
+    pub fn get_permit_key(&self, owner_id: AccountId) -> Option<Base58PublicKey> {
+        self.permit_keys
+            .get(&owner_id)
+            .map(permit::ed25519_public_key)
+    }
+
+    /// Returns the nonce the next permit of `owner_id` has to be signed with.
+    pub fn get_permit_nonce(&self, owner_id: AccountId) -> U64 {
+        self.permit_nonces.get(&owner_id).unwrap_or(0).into()
+    }
+}
+
+impl AUSD {
+    fn assert_owner(&self) {
+        assert!(
+            env::predecessor_account_id() == self.owner_id,
+            "Only owner can call this method"
+        );
+    }
+
+    /// Pays `fee` of aUSD to the owner.
+    fn internal_pay_fee(&mut self, fee: Balance) {
+        if fee > 0 {
+            let owner_id = self.owner_id.clone();
+            let mut owner = self.get_account(&owner_id);
+            owner.balance += fee;
+            self.set_account(&owner_id, &owner);
+        }
+    }
+
+    /// Helper method to get the account details for `owner_id`.
+    fn get_account(&self, owner_id: &AccountId) -> Account {
+        assert!(
+            env::is_valid_account_id(owner_id.as_bytes()),
+            "Owner's account ID is invalid"
+        );
+        let account_hash = env::sha256(owner_id.as_bytes());
+        self.accounts
+            .get(&account_hash)
+            .unwrap_or_else(|| Account::new(account_hash))
+    }
+
+    /// Helper method to set the account details for `owner_id` to the state. The account stays
+    /// registered with 0 balance, until it's removed with `storage_unregister`.
+    fn set_account(&mut self, owner_id: &AccountId, account: &Account) {
+        let account_hash = env::sha256(owner_id.as_bytes());
+        self.accounts.insert(&account_hash, &account);
+    }
+
+    /// Measures the storage of an empty account, including its storage deposit.
+    fn measure_account_storage_usage(&mut self) {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_hash = env::sha256("a".repeat(64).as_bytes());
+        self.accounts
+            .insert(&tmp_account_hash, &Account::new(tmp_account_hash.clone()));
+        self.storage_deposits.insert(&tmp_account_hash, &0);
+        self.account_storage_usage = env::storage_usage() - initial_storage_usage;
+        self.accounts.remove(&tmp_account_hash);
+        self.storage_deposits.remove(&tmp_account_hash);
+    }
+
+    fn internal_register_account(&mut self, account_id: &AccountId) {
+        let account_hash = env::sha256(account_id.as_bytes());
+        if self
+            .accounts
+            .insert(&account_hash, &Account::new(account_hash.clone()))
+            .is_some()
+        {
+            env::panic(b"The account is already registered");
+        }
+    }
+
+    /// Registers `account_id` with the minimum storage balance paid from `deposit`, unless it's
+    /// registered already. Returns what's left of `deposit`.
+    fn internal_register_receiver(&mut self, account_id: &AccountId, deposit: Balance) -> Balance {
+        if self.internal_storage_balance_of(account_id).is_some() {
+            return deposit;
+        }
+        let min_balance = self.storage_balance_bounds().min.0;
+        if deposit < min_balance {
+            env::panic(format!("The account {} is not registered", account_id).as_bytes());
+        }
+        self.internal_register_account(account_id);
+        self.storage_deposits
+            .insert(&env::sha256(account_id.as_bytes()), &min_balance);
+        deposit - min_balance
+    }
+
+    /// Returns the account details for `account_id`, panics if it's not registered.
+    fn internal_unwrap_account(&self, account_id: &AccountId) -> Account {
+        match self.accounts.get(&env::sha256(account_id.as_bytes())) {
+            Some(account) => account,
+            None => env::panic(format!("The account {} is not registered", account_id).as_bytes()),
+        }
+    }
+
+    /// The total storage balance is what was deposited for the account, nothing is available.
+    fn internal_storage_balance_of(&self, account_id: &AccountId) -> Option<StorageBalance> {
+        let account_hash = env::sha256(account_id.as_bytes());
+        if self.accounts.contains_key(&account_hash) {
+            Some(StorageBalance {
+                total: self.storage_deposits.get(&account_hash).unwrap_or(0).into(),
+                available: 0.into(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Transfers `amount` between two registered accounts.
+    fn internal_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        memo: Option<String>,
+    ) {
+        assert_ne!(
+            sender_id, receiver_id,
+            "Sender and receiver should be different"
+        );
+        assert!(amount > 0, "The amount should be a positive number");
+        let mut sender = self.internal_unwrap_account(sender_id);
+        let mut receiver = self.internal_unwrap_account(receiver_id);
+        if sender.balance < amount {
+            env::panic(b"The account doesn't have enough balance");
+        }
+        sender.balance -= amount;
+        receiver.balance = receiver
+            .balance
+            .checked_add(amount)
+            .unwrap_or_else(|| env::panic(b"Balance overflow"));
+        self.set_account(sender_id, &sender);
+        self.set_account(receiver_id, &receiver);
+        event::ft_transfer(sender_id, receiver_id, amount, memo.as_deref());
+    }
+
+    /// Sends `near_amount` from the pool to `account_id`, `on_pool_near_sent` completes or reverts
+    /// the withdrawal or swap.
+    fn internal_send_pool_near(
+        &mut self,
+        account_id: AccountId,
+        ausd_amount: Balance,
+        near_amount: Balance,
+        shares: Balance,
+    ) {
+        Promise::new(account_id.clone())
+            .transfer(near_amount)
+            .then(ext_self::on_pool_near_sent(
+                account_id,
+                ausd_amount.into(),
+                near_amount.into(),
+                shares.into(),
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_POOL_CALLBACK,
+            ));
+    }
+
+    fn refund_storage(&self, initial_storage: StorageUsage) {
+        self.refund_storage_from(initial_storage, env::attached_deposit());
+    }
+
+    /// Same as `refund_storage`, when only `attached_deposit` of the attached deposit is left to
+    /// cover the storage.
+    fn refund_storage_from(&self, initial_storage: StorageUsage, attached_deposit: Balance) {
+        let current_storage = env::storage_usage();
+        let refund_amount = if current_storage > initial_storage {
+            let required_deposit =
+                Balance::from(current_storage - initial_storage) * STORAGE_PRICE_PER_BYTE;
+            assert!(
+                required_deposit <= attached_deposit,
+                "The required attached deposit is {}, but the given attached deposit is is {}",
+                required_deposit,
+                attached_deposit,
+            );
+            attached_deposit - required_deposit
+        } else {
+            attached_deposit
+                + Balance::from(initial_storage - current_storage) * STORAGE_PRICE_PER_BYTE
+        };
+        if refund_amount > 0 {
+            env::log(format!("Refunding {} tokens for storage", refund_amount).as_bytes());
+            Promise::new(env::predecessor_account_id()).transfer(refund_amount);
+        }
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenCore for AUSD {
+    /// Unregistered receivers are registered with the attached deposit beyond 1 yoctoNEAR, the
+    /// rest is refunded.
+    #[payable]
+    fn ft_transfer(&mut self, receiver_id: ValidAccountId, amount: U128, memo: Option<String>) {
+        let deposit = assert_at_least_one_yocto();
+        let refund = self.internal_register_receiver(receiver_id.as_ref(), deposit);
+        let sender_id = env::predecessor_account_id();
+        self.internal_transfer(&sender_id, receiver_id.as_ref(), amount.into(), memo);
+        refund_deposit(refund);
+    }
+
+    #[payable]
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: ValidAccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        let deposit = assert_at_least_one_yocto();
+        let refund = self.internal_register_receiver(receiver_id.as_ref(), deposit);
+        let sender_id = env::predecessor_account_id();
+        self.internal_transfer(&sender_id, receiver_id.as_ref(), amount.into(), memo);
+        refund_deposit(refund);
+        ext_fungible_token_receiver::ft_on_transfer(
+            sender_id.clone(),
+            amount,
+            msg,
+            receiver_id.as_ref(),
+            NO_DEPOSIT,
+            env::prepaid_gas() - GAS_FOR_FT_TRANSFER_CALL,
+        )
+        .then(ext_self::ft_resolve_transfer(
+            sender_id,
+            receiver_id.into(),
+            amount,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+        .into()
+    }
+
+    fn ft_total_supply(&self) -> U128 {
+        self.total_supply.into()
+    }
+
+    fn ft_balance_of(&self, account_id: ValidAccountId) -> U128 {
+        self.get_account(account_id.as_ref()).balance.into()
+    }
+}
+
+trait FungibleTokenResolver {
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128;
+}
+
+#[near_bindgen]
+impl FungibleTokenResolver for AUSD {
+    /// Refunds the amount the receiver didn't use in `ft_on_transfer`, as much as the receiver
+    /// still holds. Returns the amount the receiver kept. The refund is burnt if the sender
+    /// unregistered in the meantime.
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "Method is private"
+        );
+        let amount: Balance = amount.into();
+
+        // Get the unused amount from the `ft_on_transfer` call result.
+        let unused_amount = match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(value) => {
+                if let Ok(unused_amount) = near_sdk::serde_json::from_slice::<U128>(&value) {
+                    std::cmp::min(amount, unused_amount.0)
+                } else {
+                    amount
+                }
+            }
+            PromiseResult::Failed => amount,
+        };
+
+        if unused_amount > 0 {
+            let mut receiver = self.get_account(&receiver_id);
+            if receiver.balance > 0 {
+                let refund_amount = std::cmp::min(receiver.balance, unused_amount);
+                receiver.balance -= refund_amount;
+                self.set_account(&receiver_id, &receiver);
+
+                let sender_hash = env::sha256(sender_id.as_bytes());
+                if let Some(mut sender) = self.accounts.get(&sender_hash) {
+                    sender.balance += refund_amount;
+                    self.accounts.insert(&sender_hash, &sender);
+                    event::ft_transfer(&receiver_id, &sender_id, refund_amount, Some("refund"));
+                } else {
+                    self.total_supply -= refund_amount;
+                    env::log(b"The account of the sender was deleted");
+                    event::ft_burn(&receiver_id, refund_amount, Some("refund"));
+                }
+                return (amount - refund_amount).into();
+            }
+        }
+        amount.into()
+    }
+}
+
+#[near_bindgen]
+impl StorageManagement for AUSD {
+    // `registration_only` doesn't affect the implementation, the storage balance is fixed.
+    #[allow(unused_variables)]
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<ValidAccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let amount: Balance = env::attached_deposit();
+        let account_id = account_id
+            .map(|a| a.into())
+            .unwrap_or_else(env::predecessor_account_id);
+        if self.internal_storage_balance_of(&account_id).is_some() {
+            env::log(b"The account is already registered, refunding the deposit");
+            if amount > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(amount);
+            }
+        } else {
+            let min_balance = self.storage_balance_bounds().min.0;
+            if amount < min_balance {
+                env::panic(b"The attached deposit is less than the minimum storage balance");
+            }
+
+            self.internal_register_account(&account_id);
+            self.storage_deposits
+                .insert(&env::sha256(account_id.as_bytes()), &min_balance);
+            let refund = amount - min_balance;
+            if refund > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(refund);
+            }
+        }
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+
+    /// The storage balance is fixed to `storage_balance_bounds().min`, so nothing is available to
+    /// withdraw. Panics if `amount > 0`, otherwise returns the storage balance of the caller.
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let predecessor_account_id = env::predecessor_account_id();
+        if let Some(storage_balance) = self.internal_storage_balance_of(&predecessor_account_id) {
+            match amount {
+                Some(amount) if amount.0 > 0 => {
+                    env::panic(b"The amount is greater than the available storage balance");
+                }
+                _ => storage_balance,
+            }
+        } else {
+            env::panic(
+                format!("The account {} is not registered", &predecessor_account_id).as_bytes(),
+            );
+        }
+    }
+
+    /// Removes the caller's account and refunds its storage deposit. With `force` the remaining
+    /// aUSD balance is burnt, otherwise the balance must be 0. The account's allowances have to
+    /// be set to 0 first, they can't be removed with it.
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let account_hash = env::sha256(account_id.as_bytes());
+        if let Some(account) = self.accounts.get(&account_hash) {
+            if account.num_allowances > 0 {
+                env::panic(b"Can't unregister the account with allowances, set them to 0 first");
+            }
+            if account.balance == 0 || force.unwrap_or(false) {
+                self.accounts.remove(&account_hash);
+                let storage_deposit = self.storage_deposits.remove(&account_hash).unwrap_or(0);
+                self.total_supply -= account.balance;
+                if account.balance > 0 {
+                    event::ft_burn(&account_id, account.balance, Some("storage unregister"));
+                }
+                Promise::new(account_id).transfer(storage_deposit + 1);
+                true
+            } else {
+                env::panic(b"Can't unregister the account with the positive balance without force")
+            }
+        } else {
+            env::log(format!("The account {} is not registered", &account_id).as_bytes());
+            false
+        }
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        let required_storage_balance =
+            Balance::from(self.account_storage_usage) * STORAGE_PRICE_PER_BYTE;
+        StorageBalanceBounds {
+            min: required_storage_balance.into(),
+            max: Some(required_storage_balance.into()),
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance> {
+        self.internal_storage_balance_of(account_id.as_ref())
+    }
+}
+
+impl BatchTransfer for AUSD {
+    fn transferable_balance(&self, account_id: &AccountId) -> Balance {
+        self.internal_unwrap_account(account_id).balance
+    }
+
+    fn register_receiver(&mut self, account_id: &AccountId, deposit: Balance) -> Balance {
+        self.internal_register_receiver(account_id, deposit)
+    }
+
+    fn transfer_leg(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        memo: Option<String>,
+    ) {
+        self.internal_transfer(sender_id, receiver_id, amount, memo);
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenMetadataProvider for AUSD {
+    fn ft_metadata(&self) -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: FT_METADATA_SPEC.to_string(),
+            name: "Artificial USD".to_string(),
+            symbol: "aUSD".to_string(),
+            icon: None,
+            decimals: 24,
+            reference: None,
+            reference_hash: None,
+        }
+    }
+}
+
+/// Returns `a * b / c` rounded down.
+fn mul_div(a: u128, b: u128, c: u128) -> u128 {
+    (BigInt::from(a) * b / c).to_u128().unwrap()
+}
+
+/// Returns `a * b / c` rounded up.
+fn mul_div_round_up(a: u128, b: u128, c: u128) -> u128 {
+    ((BigInt::from(a) * b + c - 1u32) / c).to_u128().unwrap()
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::convert::TryInto;
+
+    use near_sdk::test_utils::get_logs;
+    use near_sdk::MockedBlockchain;
+    use near_sdk::{testing_env, VMContext};
+    use permit::test_utils::{permit_keypair, permit_public_key, sign_permit};
+    use permit::PermitMessage;
+
+    use super::*;
+
+    fn alice() -> AccountId {
+        "alice.near".to_string()
+    }
+    fn bob() -> AccountId {
+        "bob.near".to_string()
+    }
+    fn carol() -> AccountId {
+        "carol.near".to_string()
+    }
+
+    fn get_context(predecessor_account_id: AccountId) -> VMContext {
+        VMContext {
+            current_account_id: alice(),
+            signer_account_id: bob(),
+            signer_account_pk: vec![0, 1, 2],
+            predecessor_account_id,
+            input: vec![],
+            block_index: 0,
+            block_timestamp: 0,
+            account_balance: 1000 * 10u128.pow(24),
+            account_locked_balance: 0,
+            storage_usage: 10u64.pow(6),
+            attached_deposit: 0,
+            prepaid_gas: 10u64.pow(18),
+            random_seed: vec![0, 1, 2],
+            is_view: false,
+            output_data_receivers: vec![],
+            epoch_height: 0,
+        }
+    }
+
+    /// Registers `account_id` with `storage_deposit`, then restores `context`.
+    fn register(contract: &mut AUSD, context: &VMContext, account_id: &AccountId) {
+        let mut deposit_context = context.clone();
+        deposit_context.attached_deposit = contract.storage_balance_bounds().min.0;
+        testing_env!(deposit_context);
+        contract.storage_deposit(Some(account_id.clone().try_into().unwrap()), None);
+        testing_env!(context.clone());
+    }
+
+    #[test]
+    fn test_new() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let total_supply = 1_000_000_000_000_000u128;
+        let contract = AUSD::new(bob(), total_supply.into(), "art".to_string());
+        assert_eq!(contract.ft_total_supply().0, total_supply);
+        assert_eq!(
+            contract.ft_balance_of(bob().try_into().unwrap()).0,
+            total_supply
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The contract is not initialized")]
     fn test_default() {
         let context = get_context(carol());
         testing_env!(context);
-        let _contract = AUSD::default();
+        let _contract = AUSD::default();
+    }
+
+    #[cfg(feature = "nep21")]
+    #[test]
+    fn test_transfer() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
+        context.storage_usage = env::storage_usage();
+
+        context.attached_deposit = 1000 * STORAGE_PRICE_PER_BYTE;
+        testing_env!(context.clone());
+        let transfer_amount = total_supply / 3;
+        contract.transfer(bob(), transfer_amount.into());
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+
+        context.is_view = true;
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        assert_eq!(
+            contract.get_balance(carol()).0,
+            (total_supply - transfer_amount)
+        );
+        assert_eq!(contract.get_balance(bob()).0, transfer_amount);
+    }
+
+    #[cfg(feature = "nep21")]
+    #[test]
+    #[should_panic(expected = "The new owner should be different from the current owner")]
+    fn test_transfer_fail_self() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
+        context.storage_usage = env::storage_usage();
+
+        context.attached_deposit = 1000 * STORAGE_PRICE_PER_BYTE;
+        testing_env!(context.clone());
+        let transfer_amount = total_supply / 3;
+        contract.transfer(carol(), transfer_amount.into());
+    }
+
+    #[cfg(feature = "nep21")]
+    #[test]
+    #[should_panic(expected = "Can not increment allowance for yourself")]
+    fn test_self_inc_allowance_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
+        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        testing_env!(context.clone());
+        contract.inc_allowance(carol(), (total_supply / 2).into());
+    }
+
+    #[cfg(feature = "nep21")]
+    #[test]
+    #[should_panic(expected = "Can not decrement allowance for yourself")]
+    fn test_self_dec_allowance_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
+        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        testing_env!(context.clone());
+        contract.dec_allowance(carol(), (total_supply / 2).into());
+    }
+
+    #[cfg(feature = "nep21")]
+    #[test]
+    fn test_saturating_dec_allowance() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
+        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        testing_env!(context.clone());
+        contract.dec_allowance(bob(), (total_supply / 2).into());
+        assert_eq!(contract.get_allowance(carol(), bob()), 0.into())
+    }
+
+    #[cfg(feature = "nep21")]
+    #[test]
+    #[should_panic(expected = "Can't unregister the account with allowances, set them to 0 first")]
+    fn test_storage_unregister_with_allowances_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = AUSD::new(carol(), 0.into(), "art".to_string());
+        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        testing_env!(context.clone());
+        contract.inc_allowance(bob(), 1000.into());
+        context.attached_deposit = 1;
+        testing_env!(context.clone());
+        contract.storage_unregister(None);
+    }
+
+    #[cfg(feature = "nep21")]
+    #[test]
+    fn test_saturating_inc_allowance() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = std::u128::MAX;
+        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
+        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        testing_env!(context.clone());
+        contract.inc_allowance(bob(), total_supply.into());
+        contract.inc_allowance(bob(), total_supply.into());
+        assert_eq!(
+            contract.get_allowance(carol(), bob()),
+            std::u128::MAX.into()
+        )
+    }
+
+    #[cfg(feature = "nep21")]
+    #[test]
+    #[should_panic(
+        expected = "The required attached deposit is 12400000000000000000000, but the given attached deposit is is 0"
+    )]
+    fn test_self_allowance_fail_no_deposit() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        contract.inc_allowance(bob(), (total_supply / 2).into());
+    }
+
+    #[cfg(feature = "nep21")]
+    #[test]
+    fn test_carol_escrows_to_bob_transfers_to_alice() {
+        // Acting as carol
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
+        context.storage_usage = env::storage_usage();
+
+        context.is_view = true;
+        testing_env!(context.clone());
+        assert_eq!(contract.get_total_supply().0, total_supply);
+
+        let allowance = total_supply / 3;
+        let transfer_amount = allowance / 3;
+        context.is_view = false;
+        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        testing_env!(context.clone());
+        contract.inc_allowance(bob(), allowance.into());
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+
+        context.is_view = true;
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        assert_eq!(contract.get_allowance(carol(), bob()).0, allowance);
+
+        // Acting as bob now
+        context.is_view = false;
+        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        contract.transfer_from(carol(), alice(), transfer_amount.into());
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+
+        context.is_view = true;
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        assert_eq!(
+            contract.get_balance(carol()).0,
+            total_supply - transfer_amount
+        );
+        assert_eq!(contract.get_balance(alice()).0, transfer_amount);
+        assert_eq!(
+            contract.get_allowance(carol(), bob()).0,
+            allowance - transfer_amount
+        );
+    }
+
+    #[cfg(feature = "nep21")]
+    #[test]
+    fn test_carol_escrows_to_bob_stakes_and_transfers_to_alice() {
+        // Acting as carol
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
+        context.storage_usage = env::storage_usage();
+
+        context.is_view = true;
+        testing_env!(context.clone());
+        assert_eq!(contract.get_total_supply().0, total_supply);
+
+        let allowance = total_supply / 3;
+        let transfer_amount = allowance / 3;
+        context.is_view = false;
+        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        testing_env!(context.clone());
+        contract.inc_allowance(bob(), allowance.into());
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+
+        context.is_view = true;
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        assert_eq!(contract.get_allowance(carol(), bob()).0, allowance);
+        assert_eq!(contract.get_balance(carol()).0, total_supply);
+
+        // Acting as bob now
+        context.is_view = false;
+        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        context.predecessor_account_id = bob();
+        testing_env!(context.clone());
+        contract.transfer_from(carol(), alice(), transfer_amount.into());
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+
+        context.is_view = true;
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        assert_eq!(
+            contract.get_balance(carol()).0,
+            (total_supply - transfer_amount)
+        );
+        assert_eq!(contract.get_balance(alice()).0, transfer_amount);
+        assert_eq!(
+            contract.get_allowance(carol(), bob()).0,
+            allowance - transfer_amount
+        );
+    }
+
+    #[cfg(feature = "nep21")]
+    #[test]
+    fn test_self_allowance_set_for_refund() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
+        context.storage_usage = env::storage_usage();
+
+        let initial_balance = context.account_balance;
+        let initial_storage = context.storage_usage;
+        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        testing_env!(context.clone());
+        contract.inc_allowance(bob(), (total_supply / 2).into());
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+        assert_eq!(
+            context.account_balance,
+            initial_balance
+                + Balance::from(context.storage_usage - initial_storage) * STORAGE_PRICE_PER_BYTE
+        );
+
+        let initial_balance = context.account_balance;
+        let initial_storage = context.storage_usage;
+        testing_env!(context.clone());
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        contract.dec_allowance(bob(), (total_supply / 2).into());
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+        assert!(context.storage_usage < initial_storage);
+        assert!(context.account_balance < initial_balance);
+        assert_eq!(
+            context.account_balance,
+            initial_balance
+                - Balance::from(initial_storage - context.storage_usage) * STORAGE_PRICE_PER_BYTE
+        );
+    }
+
+    fn carol_permit(amount: Balance, nonce: u64) -> PermitMessage {
+        PermitMessage {
+            contract_id: alice(),
+            owner_id: carol(),
+            spender_id: bob(),
+            amount,
+            nonce,
+            deadline: 1000,
+        }
+    }
+
+    /// Carol's permit key is registered, bob relays her permits.
+    fn permit_contract() -> AUSD {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = AUSD::new(carol(), 1000.into(), "art".to_string());
+        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        testing_env!(context.clone());
+        contract.set_permit_key(Some(permit_public_key(&permit_keypair(1))));
+        context.predecessor_account_id = bob();
+        testing_env!(context);
+        contract
+    }
+
+    #[test]
+    fn test_permit() {
+        let mut contract = permit_contract();
+        let keypair = permit_keypair(1);
+        assert_eq!(
+            contract.get_permit_key(carol()),
+            Some(permit_public_key(&keypair))
+        );
+        let signature = sign_permit(&keypair, &carol_permit(500, 0));
+        contract.permit(carol(), bob(), 500.into(), U64(0), U64(1000), signature);
+        assert_eq!(contract.get_account(&carol()).get_allowance(&bob()), 500);
+        assert_eq!(contract.get_permit_nonce(carol()).0, 1);
+    }
+
+    #[cfg(feature = "nep21")]
+    #[test]
+    fn test_permit_transfer_from() {
+        let mut contract = permit_contract();
+        let signature = sign_permit(&permit_keypair(1), &carol_permit(500, 0));
+        contract.permit(carol(), bob(), 500.into(), U64(0), U64(1000), signature);
+        assert_eq!(contract.get_allowance(carol(), bob()).0, 500);
+
+        // The allowance can be spent by bob like any other
+        contract.transfer_from(carol(), alice(), 200.into());
+        assert_eq!(contract.get_allowance(carol(), bob()).0, 300);
+        assert_eq!(contract.get_balance(alice()).0, 200);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid permit nonce")]
+    fn test_permit_replay_fail() {
+        let mut contract = permit_contract();
+        let signature = sign_permit(&permit_keypair(1), &carol_permit(500, 0));
+        contract.permit(
+            carol(),
+            bob(),
+            500.into(),
+            U64(0),
+            U64(1000),
+            signature.clone(),
+        );
+        contract.permit(carol(), bob(), 500.into(), U64(0), U64(1000), signature);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid permit signature")]
+    fn test_permit_wrong_key_fail() {
+        let mut contract = permit_contract();
+        let signature = sign_permit(&permit_keypair(2), &carol_permit(500, 0));
+        contract.permit(carol(), bob(), 500.into(), U64(0), U64(1000), signature);
+    }
+
+    #[test]
+    fn test_pool_liquidity_and_swap() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 10u128.pow(30);
+        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
+        context.storage_usage = env::storage_usage();
+
+        // 1 NEAR = 5 aUSD
+        let near_amount = 100 * 10u128.pow(24);
+        let ausd_amount = 500 * 10u128.pow(24);
+        context.attached_deposit = near_amount + STORAGE_PRICE_PER_BYTE * 1000;
+        testing_env!(context.clone());
+        let shares = contract.add_liquidity(near_amount.into(), ausd_amount.into());
+        assert_eq!(shares.0, near_amount);
+        assert_eq!(contract.get_pool_shares(carol()), shares);
+        assert_eq!(contract.get_pool_price().0, 500_000_000);
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+
+        // Acting as bob now
+        context.predecessor_account_id = bob();
+        context.attached_deposit = 10 * 10u128.pow(24) + STORAGE_PRICE_PER_BYTE * 1000;
+        testing_env!(context.clone());
+        register(&mut contract, &context, &bob());
+        let ausd_out = contract.swap_near_to_ausd((10 * 10u128.pow(24)).into(), 0.into());
+        // Less than 50 aUSD at the initial price, due to price impact and fee
+        assert!(ausd_out.0 < 50 * 10u128.pow(24));
+        assert_eq!(contract.ft_balance_of(bob().try_into().unwrap()), ausd_out);
+        assert!(contract.get_pool_price().0 < 500_000_000);
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        let near_out = contract.swap_ausd_to_near(ausd_out, 0.into());
+        assert!(near_out.0 < 10 * 10u128.pow(24));
+        assert_eq!(contract.ft_balance_of(bob().try_into().unwrap()).0, 0);
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_pool_near_sent(bob(), ausd_out, near_out, 0.into());
+        assert_eq!(
+            get_logs(),
+            vec![format!(
+                r#"EVENT_JSON:{{"standard":"ausd","version":"1.0.0","event":"exchange","data":[{{"account_id":"bob.near","path":"ausd_to_near","amount_in":"{}","amount_out":"{}","fee":"{}"}}]}}"#,
+                ausd_out.0,
+                near_out.0,
+                ausd_out.0 * 3 / 1000
+            )]
+        );
+        // The round trip leaves the fees from both swaps in the pool
+        let pool = contract.get_pool();
+        assert_eq!(pool.ausd_reserve.0, ausd_amount);
+        assert!(pool.near_reserve.0 > near_amount);
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+
+        // Acting as carol
+        context.predecessor_account_id = carol();
+        testing_env!(context.clone());
+        let pool = contract.get_pool();
+        contract.remove_liquidity(shares);
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_pool_near_sent(carol(), pool.ausd_reserve, pool.near_reserve, shares);
+        let pool = contract.get_pool();
+        assert_eq!(pool.total_shares.0, 0);
+        assert_eq!(pool.near_reserve.0, 0);
+        assert_eq!(
+            contract.ft_balance_of(carol().try_into().unwrap()).0,
+            total_supply
+        );
+        assert_eq!(contract.get_pool_price().0, 0);
     }
 
     #[test]
-    fn test_transfer() {
+    fn test_pool_reverted_if_near_not_sent() {
         let mut context = get_context(carol());
         testing_env!(context.clone());
-        let total_supply = 1_000_000_000_000_000u128;
+        let total_supply = 10u128.pow(30);
         let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
         context.storage_usage = env::storage_usage();
 
-        context.attached_deposit = 1000 * STORAGE_PRICE_PER_BYTE;
+        let near_amount = 100 * 10u128.pow(24);
+        let ausd_amount = 500 * 10u128.pow(24);
+        context.attached_deposit = near_amount + STORAGE_PRICE_PER_BYTE * 1000;
         testing_env!(context.clone());
-        let transfer_amount = total_supply / 3;
-        contract.transfer(bob(), transfer_amount.into());
+        let shares = contract.add_liquidity(near_amount.into(), ausd_amount.into());
         context.storage_usage = env::storage_usage();
         context.account_balance = env::account_balance();
 
-        context.is_view = true;
         context.attached_deposit = 0;
         testing_env!(context.clone());
-        assert_eq!(
-            contract.get_balance(carol()).0,
-            (total_supply - transfer_amount)
+        let balance = contract.ft_balance_of(carol().try_into().unwrap());
+        let near_out = contract.swap_ausd_to_near((5 * 10u128.pow(24)).into(), 0.into());
+        // The swapped aUSD isn't in the pool while the NEAR is being sent
+        assert_eq!(contract.get_pool().ausd_reserve.0, ausd_amount);
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
         );
-        assert_eq!(contract.get_balance(bob()).0, transfer_amount);
+        contract.on_pool_near_sent(carol(), (5 * 10u128.pow(24)).into(), near_out, 0.into());
+        let pool = contract.get_pool();
+        assert_eq!(pool.ausd_reserve.0, ausd_amount);
+        assert_eq!(pool.near_reserve.0, near_amount);
+        assert_eq!(contract.ft_balance_of(carol().try_into().unwrap()), balance);
+
+        context.predecessor_account_id = carol();
+        testing_env!(context.clone());
+        contract.remove_liquidity(shares);
+        // The withdrawn aUSD isn't credited while the NEAR is being sent
+        assert_eq!(contract.ft_balance_of(carol().try_into().unwrap()), balance);
+        context.predecessor_account_id = alice();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_pool_near_sent(carol(), ausd_amount.into(), near_amount.into(), shares);
+        let pool = contract.get_pool();
+        assert_eq!(pool.ausd_reserve.0, ausd_amount);
+        assert_eq!(pool.near_reserve.0, near_amount);
+        assert_eq!(contract.get_pool_shares(carol()), shares);
+        assert_eq!(contract.ft_balance_of(carol().try_into().unwrap()), balance);
     }
 
     #[test]
-    #[should_panic(expected = "The new owner should be different from the current owner")]
-    fn test_transfer_fail_self() {
+    #[should_panic(expected = "Output amount is less than min_near_out")]
+    fn test_pool_swap_min_out_fail() {
         let mut context = get_context(carol());
         testing_env!(context.clone());
-        let total_supply = 1_000_000_000_000_000u128;
+        let total_supply = 10u128.pow(30);
         let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
         context.storage_usage = env::storage_usage();
 
-        context.attached_deposit = 1000 * STORAGE_PRICE_PER_BYTE;
+        context.attached_deposit = 101 * 10u128.pow(24);
         testing_env!(context.clone());
-        let transfer_amount = total_supply / 3;
-        contract.transfer(carol(), transfer_amount.into());
+        contract.add_liquidity((100 * 10u128.pow(24)).into(), (500 * 10u128.pow(24)).into());
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        // 1 NEAR at the pool price, but the fee and price impact lower the output
+        contract.swap_ausd_to_near((5 * 10u128.pow(24)).into(), 10u128.pow(24).into());
     }
 
     #[test]
-    #[should_panic(expected = "Can not increment allowance for yourself")]
-    fn test_self_inc_allowance_fail() {
+    fn test_buy_ausd_for_owner() {
         let mut context = get_context(carol());
         testing_env!(context.clone());
         let total_supply = 1_000_000_000_000_000u128;
         let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
-        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        context.predecessor_account_id = "art".to_string();
         testing_env!(context.clone());
-        contract.inc_allowance(carol(), (total_supply / 2).into());
+        register(&mut contract, &context, &bob());
+        contract.buy_ausd(bob(), 1000.into());
+        contract.buy_ausd(carol(), 1000.into());
+        assert_eq!(contract.ft_balance_of(bob().try_into().unwrap()).0, 1000);
+        assert_eq!(
+            contract.ft_balance_of(carol().try_into().unwrap()).0,
+            total_supply - 1000
+        );
     }
 
     #[test]
-    #[should_panic(expected = "Can not decrement allowance for yourself")]
-    fn test_self_dec_allowance_fail() {
+    fn test_ft_transfer() {
         let mut context = get_context(carol());
         testing_env!(context.clone());
         let total_supply = 1_000_000_000_000_000u128;
         let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
-        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        context.storage_usage = env::storage_usage();
+
+        let min_balance = contract.storage_balance_bounds().min;
+        context.predecessor_account_id = bob();
+        context.attached_deposit = min_balance.0;
         testing_env!(context.clone());
-        contract.dec_allowance(carol(), (total_supply / 2).into());
+        contract.storage_deposit(None, None);
+        assert_eq!(
+            contract
+                .storage_balance_of(bob().try_into().unwrap())
+                .unwrap()
+                .total,
+            min_balance
+        );
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+
+        context.predecessor_account_id = carol();
+        context.attached_deposit = 1;
+        testing_env!(context.clone());
+        let transfer_amount = total_supply / 3;
+        contract.ft_transfer(bob().try_into().unwrap(), transfer_amount.into(), None);
+        assert_eq!(
+            contract.ft_balance_of(carol().try_into().unwrap()).0,
+            total_supply - transfer_amount
+        );
+        assert_eq!(
+            contract.ft_balance_of(bob().try_into().unwrap()).0,
+            transfer_amount
+        );
+        assert_eq!(contract.ft_total_supply().0, total_supply);
+        assert_eq!(contract.ft_metadata().symbol, "aUSD");
     }
 
     #[test]
-    fn test_saturating_dec_allowance() {
+    #[should_panic(expected = "The account bob.near is not registered")]
+    fn test_ft_transfer_unregistered_fail() {
         let mut context = get_context(carol());
         testing_env!(context.clone());
         let total_supply = 1_000_000_000_000_000u128;
         let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
-        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        context.attached_deposit = 1;
         testing_env!(context.clone());
-        contract.dec_allowance(bob(), (total_supply / 2).into());
-        assert_eq!(contract.get_allowance(carol(), bob()), 0.into())
+        contract.ft_transfer(bob().try_into().unwrap(), 1000.into(), None);
     }
 
     #[test]
-    fn test_saturating_inc_allowance() {
+    fn test_ft_transfer_registers_receiver() {
         let mut context = get_context(carol());
         testing_env!(context.clone());
-        let total_supply = std::u128::MAX;
-        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
-        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        let mut contract = AUSD::new(carol(), 1000.into(), "art".to_string());
+        let min_balance = contract.storage_balance_bounds().min.0;
+        context.storage_usage = env::storage_usage();
+        context.attached_deposit = 1 + min_balance;
         testing_env!(context.clone());
-        contract.inc_allowance(bob(), total_supply.into());
-        contract.inc_allowance(bob(), total_supply.into());
+        contract.ft_transfer(bob().try_into().unwrap(), 100.into(), None);
+        assert_eq!(contract.ft_balance_of(bob().try_into().unwrap()).0, 100);
         assert_eq!(
-            contract.get_allowance(carol(), bob()),
-            std::u128::MAX.into()
-        )
+            contract
+                .storage_balance_of(bob().try_into().unwrap())
+                .unwrap()
+                .total
+                .0,
+            min_balance
+        );
     }
 
     #[test]
-    #[should_panic(
-        expected = "The required attached deposit is 12400000000000000000000, but the given attached deposit is is 0"
-    )]
-    fn test_self_allowance_fail_no_deposit() {
+    fn test_batch_transfer_registers_receivers() {
         let mut context = get_context(carol());
         testing_env!(context.clone());
-        let total_supply = 1_000_000_000_000_000u128;
-        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
-        context.attached_deposit = 0;
+        let mut contract = AUSD::new(carol(), 1000.into(), "art".to_string());
+        let min_balance = contract.storage_balance_bounds().min.0;
+        register(&mut contract, &context, &bob());
+
+        context.storage_usage = env::storage_usage();
+        context.attached_deposit = 1 + min_balance;
         testing_env!(context.clone());
-        contract.inc_allowance(bob(), (total_supply / 2).into());
+        let failed = contract.batch_transfer(
+            vec![
+                (bob().try_into().unwrap(), 100.into()),
+                (alice().try_into().unwrap(), 100.into()),
+                ("dave.near".try_into().unwrap(), 100.into()),
+            ],
+            None,
+            Some(true),
+        );
+        // The deposit only covers the first unregistered receiver
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].index, 2);
+        assert_eq!(contract.ft_balance_of(alice().try_into().unwrap()).0, 100);
+        assert_eq!(contract.ft_balance_of(carol().try_into().unwrap()).0, 800);
     }
 
     #[test]
-    fn test_carol_escrows_to_bob_transfers_to_alice() {
-        // Acting as carol
+    fn test_batch_transfer() {
         let mut context = get_context(carol());
         testing_env!(context.clone());
-        let total_supply = 1_000_000_000_000_000u128;
-        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
+        let mut contract = AUSD::new(carol(), 1000.into(), "art".to_string());
         context.storage_usage = env::storage_usage();
-
-        context.is_view = true;
+        context.attached_deposit = contract.storage_balance_bounds().min.0;
         testing_env!(context.clone());
-        assert_eq!(contract.get_total_supply().0, total_supply);
+        contract.storage_deposit(Some(bob().try_into().unwrap()), None);
 
-        let allowance = total_supply / 3;
-        let transfer_amount = allowance / 3;
-        context.is_view = false;
-        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        context.attached_deposit = 1;
         testing_env!(context.clone());
-        contract.inc_allowance(bob(), allowance.into());
-        context.storage_usage = env::storage_usage();
-        context.account_balance = env::account_balance();
+        let failed = contract.batch_transfer(
+            vec![
+                (bob().try_into().unwrap(), 100.into()),
+                (carol().try_into().unwrap(), 200.into()),
+                (alice().try_into().unwrap(), 300.into()),
+            ],
+            Some("airdrop".to_string()),
+            Some(true),
+        );
+        assert_eq!(failed.len(), 2);
+        assert_eq!(failed[0].reason, "Sender and receiver should be different");
+        assert_eq!(failed[1].index, 2);
+        assert_eq!(failed[1].reason, "The account alice.near is not registered");
+        assert_eq!(get_logs().len(), 1);
+        assert_eq!(contract.ft_balance_of(carol().try_into().unwrap()).0, 900);
+        assert_eq!(contract.ft_balance_of(bob().try_into().unwrap()).0, 100);
+    }
 
-        context.is_view = true;
-        context.attached_deposit = 0;
+    #[test]
+    #[should_panic(expected = "The account alice.near is not registered")]
+    fn test_batch_transfer_unregistered_fail() {
+        let mut context = get_context(carol());
         testing_env!(context.clone());
-        assert_eq!(contract.get_allowance(carol(), bob()).0, allowance);
+        let mut contract = AUSD::new(carol(), 1000.into(), "art".to_string());
+        context.attached_deposit = 1;
+        testing_env!(context);
+        contract.batch_transfer(vec![(alice().try_into().unwrap(), 100.into())], None, None);
+    }
 
-        // Acting as bob now
-        context.is_view = false;
-        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+    #[test]
+    fn test_ft_resolve_transfer() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
         context.predecessor_account_id = bob();
+        context.attached_deposit = contract.storage_balance_bounds().min.0;
         testing_env!(context.clone());
-        contract.transfer_from(carol(), alice(), transfer_amount.into());
-        context.storage_usage = env::storage_usage();
-        context.account_balance = env::account_balance();
+        contract.storage_deposit(None, None);
 
-        context.is_view = true;
-        context.attached_deposit = 0;
+        context.predecessor_account_id = carol();
+        context.attached_deposit = 1;
         testing_env!(context.clone());
+        contract.ft_transfer_call(bob().try_into().unwrap(), 1000.into(), None, "".to_string());
+
+        // The receiver used 400 and returned 600
+        context.predecessor_account_id = alice();
+        context.attached_deposit = 0;
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"600\"".to_vec())]
+        );
+        let used = contract.ft_resolve_transfer(carol(), bob(), 1000.into());
+        assert_eq!(used.0, 400);
         assert_eq!(
-            contract.get_balance(carol()).0,
-            total_supply - transfer_amount
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"nep141","version":"1.0.0","event":"ft_transfer","data":[{"old_owner_id":"bob.near","new_owner_id":"carol.near","amount":"600","memo":"refund"}]}"#
+            ]
         );
-        assert_eq!(contract.get_balance(alice()).0, transfer_amount);
+        assert_eq!(contract.ft_balance_of(bob().try_into().unwrap()).0, 400);
         assert_eq!(
-            contract.get_allowance(carol(), bob()).0,
-            allowance - transfer_amount
+            contract.ft_balance_of(carol().try_into().unwrap()).0,
+            total_supply - 400
         );
     }
 
     #[test]
-    fn test_carol_escrows_to_bob_stakes_and_transfers_to_alice() {
-        // Acting as carol
+    fn test_storage_unregister() {
         let mut context = get_context(carol());
         testing_env!(context.clone());
         let total_supply = 1_000_000_000_000_000u128;
         let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
-        context.storage_usage = env::storage_usage();
+        context.predecessor_account_id = bob();
+        context.attached_deposit = contract.storage_balance_bounds().min.0;
+        testing_env!(context.clone());
+        contract.storage_deposit(None, None);
 
-        context.is_view = true;
+        context.predecessor_account_id = carol();
+        context.attached_deposit = 1;
         testing_env!(context.clone());
-        assert_eq!(contract.get_total_supply().0, total_supply);
+        contract.ft_transfer(bob().try_into().unwrap(), 1000.into(), None);
 
-        let allowance = total_supply / 3;
-        let transfer_amount = allowance / 3;
-        context.is_view = false;
-        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        // Force burns the remaining balance
+        context.predecessor_account_id = bob();
         testing_env!(context.clone());
-        contract.inc_allowance(bob(), allowance.into());
-        context.storage_usage = env::storage_usage();
-        context.account_balance = env::account_balance();
+        assert!(contract.storage_unregister(Some(true)));
+        assert!(contract
+            .storage_balance_of(bob().try_into().unwrap())
+            .is_none());
+        assert_eq!(contract.ft_total_supply().0, total_supply - 1000);
+        assert!(!contract.storage_unregister(None));
+    }
 
-        context.is_view = true;
-        context.attached_deposit = 0;
+    #[test]
+    fn test_storage_unregister_refunds_deposit() {
+        let mut context = get_context(carol());
         testing_env!(context.clone());
-        assert_eq!(contract.get_allowance(carol(), bob()).0, allowance);
-        assert_eq!(contract.get_balance(carol()).0, total_supply);
+        let total_supply = 1_000_000_000_000_000u128;
+        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
+        let min_balance = contract.storage_balance_bounds().min.0;
+        register(&mut contract, &context, &bob());
+        assert_eq!(
+            contract
+                .storage_balance_of(bob().try_into().unwrap())
+                .unwrap()
+                .total
+                .0,
+            min_balance
+        );
+        context.account_balance = env::account_balance();
 
-        // Acting as bob now
-        context.is_view = false;
-        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
         context.predecessor_account_id = bob();
+        context.attached_deposit = 1;
         testing_env!(context.clone());
-        contract.transfer_from(carol(), alice(), transfer_amount.into());
-        context.storage_usage = env::storage_usage();
+        let balance = env::account_balance();
+        assert!(contract.storage_unregister(None));
+        assert_eq!(balance - env::account_balance(), min_balance + 1);
         context.account_balance = env::account_balance();
 
-        context.is_view = true;
-        context.attached_deposit = 0;
+        // Owner was registered by `new` without a deposit
+        context.predecessor_account_id = carol();
+        testing_env!(context.clone());
+        let balance = env::account_balance();
+        assert!(contract.storage_unregister(Some(true)));
+        assert_eq!(balance - env::account_balance(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account bob.near is not registered")]
+    fn test_mint_unregistered_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = AUSD::new(carol(), 0.into(), "art".to_string());
+        context.predecessor_account_id = "art".to_string();
+        testing_env!(context.clone());
+        contract.mint(bob(), 1000);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut accounts = LookupMap::new(b"a".to_vec());
+        let owner_hash = env::sha256(carol().as_bytes());
+        let mut owner = Account::new(owner_hash.clone());
+        owner.balance = 1000;
+        accounts.insert(&owner_hash, &owner);
+        env::state_write(&OldAUSD {
+            accounts,
+            total_supply: 1000,
+            art_token: "art".to_string(),
+            owner_id: carol(),
+        });
+
+        context.predecessor_account_id = alice();
         testing_env!(context.clone());
+        let contract = AUSD::migrate();
+        assert_eq!(contract.ft_total_supply().0, 1000);
+        assert_eq!(contract.ft_balance_of(carol().try_into().unwrap()).0, 1000);
+        assert!(contract.storage_balance_bounds().min.0 > 0);
         assert_eq!(
-            contract.get_balance(carol()).0,
-            (total_supply - transfer_amount)
+            contract
+                .storage_balance_of(carol().try_into().unwrap())
+                .unwrap()
+                .total
+                .0,
+            0
         );
-        assert_eq!(contract.get_balance(alice()).0, transfer_amount);
+    }
+
+    #[test]
+    fn test_events() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = AUSD::new(carol(), 0.into(), "art".to_string());
+        assert!(get_logs().is_empty());
+
+        context.predecessor_account_id = "art".to_string();
+        testing_env!(context.clone());
+        register(&mut contract, &context, &bob());
+        contract.mint(bob(), 1000);
         assert_eq!(
-            contract.get_allowance(carol(), bob()).0,
-            allowance - transfer_amount
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"nep141","version":"1.0.0","event":"ft_mint","data":[{"owner_id":"bob.near","amount":"1000"}]}"#
+            ]
+        );
+
+        testing_env!(context.clone());
+        contract.burn(bob(), 400);
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"nep141","version":"1.0.0","event":"ft_burn","data":[{"owner_id":"bob.near","amount":"400"}]}"#
+            ]
+        );
+
+        context.predecessor_account_id = bob();
+        context.attached_deposit = 1;
+        testing_env!(context.clone());
+        contract.storage_deposit(Some(carol().try_into().unwrap()), None);
+        testing_env!(context.clone());
+        contract.ft_transfer(
+            carol().try_into().unwrap(),
+            100.into(),
+            Some("rent".to_string()),
+        );
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"nep141","version":"1.0.0","event":"ft_transfer","data":[{"old_owner_id":"bob.near","new_owner_id":"carol.near","amount":"100","memo":"rent"}]}"#
+            ]
         );
     }
 
+    fn psm_contract(context: &VMContext) -> AUSD {
+        let mut contract = AUSD::new(carol(), 0.into(), "art".to_string());
+        register(&mut contract, context, &bob());
+        contract.set_psm_stablecoin("usdc".to_string(), 6);
+        contract.set_psm_debt_ceiling((1000 * 10u128.pow(24)).into());
+        contract.set_psm_fees(10, 10);
+        contract
+    }
+
     #[test]
-    fn test_self_allowance_set_for_refund() {
+    fn test_psm_mint_and_redeem() {
         let mut context = get_context(carol());
         testing_env!(context.clone());
-        let total_supply = 1_000_000_000_000_000u128;
-        let mut contract = AUSD::new(carol(), total_supply.into(), "art".to_string());
-        context.storage_usage = env::storage_usage();
+        let mut contract = psm_contract(&context);
 
-        let initial_balance = context.account_balance;
-        let initial_storage = context.storage_usage;
-        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        // 100 USDC with 6 decimals
+        context.predecessor_account_id = "usdc".to_string();
         testing_env!(context.clone());
-        contract.inc_allowance(bob(), (total_supply / 2).into());
+        contract.ft_on_transfer(bob(), (100 * 10u128.pow(6)).into(), "".to_string());
+        assert_eq!(
+            contract.ft_balance_of(bob().try_into().unwrap()).0,
+            99_900 * 10u128.pow(21)
+        );
+        assert_eq!(
+            contract.ft_balance_of(carol().try_into().unwrap()).0,
+            100 * 10u128.pow(21)
+        );
+        assert_eq!(contract.ft_total_supply().0, 100 * 10u128.pow(24));
+        assert_eq!(contract.get_psm().debt.0, 100 * 10u128.pow(24));
         context.storage_usage = env::storage_usage();
         context.account_balance = env::account_balance();
+
+        context.predecessor_account_id = bob();
+        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        testing_env!(context.clone());
+        contract.psm_redeem((50 * 10u128.pow(24)).into());
+        // 0.05 aUSD fee, 49.95 aUSD burnt for 49.95 USDC
         assert_eq!(
-            context.account_balance,
-            initial_balance
-                + Balance::from(context.storage_usage - initial_storage) * STORAGE_PRICE_PER_BYTE
+            contract.ft_balance_of(bob().try_into().unwrap()).0,
+            49_900 * 10u128.pow(21)
+        );
+        assert_eq!(
+            contract.ft_balance_of(carol().try_into().unwrap()).0,
+            150 * 10u128.pow(21)
         );
+        assert_eq!(contract.get_psm().reserve.0, 50_050_000);
+        assert_eq!(contract.ft_total_supply().0, contract.get_psm().debt.0);
+    }
 
-        let initial_balance = context.account_balance;
-        let initial_storage = context.storage_usage;
+    #[test]
+    fn test_psm_redeem_refunded_without_fee() {
+        let mut context = get_context(carol());
         testing_env!(context.clone());
-        context.attached_deposit = 0;
+        let mut contract = psm_contract(&context);
+
+        context.predecessor_account_id = "usdc".to_string();
         testing_env!(context.clone());
-        contract.dec_allowance(bob(), (total_supply / 2).into());
+        contract.ft_on_transfer(bob(), (100 * 10u128.pow(6)).into(), "".to_string());
         context.storage_usage = env::storage_usage();
         context.account_balance = env::account_balance();
-        assert!(context.storage_usage < initial_storage);
-        assert!(context.account_balance < initial_balance);
+
+        context.predecessor_account_id = bob();
+        context.attached_deposit = STORAGE_PRICE_PER_BYTE * 1000;
+        testing_env!(context.clone());
+        contract.psm_redeem((50 * 10u128.pow(24)).into());
+        context.storage_usage = env::storage_usage();
+        context.account_balance = env::account_balance();
+
+        // The owner moves the fees on before the stablecoin transfer fails
+        context.predecessor_account_id = carol();
+        context.attached_deposit = 1;
+        testing_env!(context.clone());
+        contract.ft_transfer(
+            bob().try_into().unwrap(),
+            (150 * 10u128.pow(21)).into(),
+            None,
+        );
+
+        context.predecessor_account_id = alice();
+        context.attached_deposit = 0;
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_psm_redeemed(
+            bob(),
+            (49_950 * 10u128.pow(21)).into(),
+            (50 * 10u128.pow(21)).into(),
+            49_950_000.into(),
+        );
+        // 49.9 aUSD left, the 0.15 aUSD fees from carol and the 49.95 aUSD burnt
         assert_eq!(
-            context.account_balance,
-            initial_balance
-                - Balance::from(initial_storage - context.storage_usage) * STORAGE_PRICE_PER_BYTE
+            contract.ft_balance_of(bob().try_into().unwrap()).0,
+            100 * 10u128.pow(24)
         );
+        assert_eq!(contract.ft_balance_of(carol().try_into().unwrap()).0, 0);
+        assert_eq!(contract.get_psm().reserve.0, 100 * 10u128.pow(6));
+    }
+
+    #[test]
+    #[should_panic(expected = "The PSM debt ceiling is reached")]
+    fn test_psm_debt_ceiling_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = psm_contract(&context);
+
+        context.predecessor_account_id = "usdc".to_string();
+        testing_env!(context.clone());
+        contract.ft_on_transfer(bob(), (1001 * 10u128.pow(6)).into(), "".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the PSM stablecoin can be deposited")]
+    fn test_psm_wrong_token_fail() {
+        let mut context = get_context(carol());
+        testing_env!(context.clone());
+        let mut contract = psm_contract(&context);
+
+        context.predecessor_account_id = "fake-usdc".to_string();
+        testing_env!(context.clone());
+        contract.ft_on_transfer(bob(), (100 * 10u128.pow(6)).into(), "".to_string());
     }
 }
@@ -0,0 +1,189 @@
+use near_sdk::json_types::U128;
+use near_sdk_sim::{
+    call, deploy, init_simulator, to_yocto, view, ContractAccount, UserAccount, DEFAULT_GAS,
+};
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+extern crate ausd;
+use ausd::AUSDContract;
+
+extern crate mock_stablecoin;
+use mock_stablecoin::MockStablecoinContract;
+
+lazy_static::lazy_static! {
+    static ref AUSD_WASM_BYTES: Vec<u8> = build_wasm("ausd", "ausd.wasm");
+    static ref STABLECOIN_WASM_BYTES: Vec<u8> =
+        build_wasm("mock-stablecoin", "mock_stablecoin.wasm");
+}
+
+/// Builds `contract` with its build.sh, so the tests deploy its current source, and returns the
+/// wasm.
+fn build_wasm(contract: &str, wasm: &str) -> Vec<u8> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("..")
+        .join(contract);
+    let status = Command::new("bash")
+        .arg(dir.join("build.sh"))
+        .status()
+        .expect("Failed to run build.sh");
+    assert!(status.success(), "Failed to build {}", contract);
+    fs::read(dir.join("res").join(wasm)).expect("Failed to read the wasm")
+}
+
+/// 1 USDC with 6 decimals.
+const ONE_USDC: u128 = 1_000_000;
+
+fn init() -> (
+    UserAccount,
+    ContractAccount<AUSDContract>,
+    ContractAccount<MockStablecoinContract>,
+    UserAccount,
+) {
+    let master_account = init_simulator(None);
+    let ausd = deploy! {
+        contract: AUSDContract,
+        contract_id: "ausd",
+        bytes: &AUSD_WASM_BYTES,
+        signer_account: master_account,
+        init_method: new(master_account.account_id(), 0u128.into(), "art".to_string())
+    };
+    let usdc = deploy! {
+        contract: MockStablecoinContract,
+        contract_id: "usdc",
+        bytes: &STABLECOIN_WASM_BYTES,
+        signer_account: master_account,
+        init_method: new(6)
+    };
+    call!(
+        master_account,
+        ausd.set_psm_stablecoin("usdc".to_string(), 6)
+    )
+    .assert_success();
+    call!(
+        master_account,
+        ausd.set_psm_debt_ceiling(U128(to_yocto("1000")))
+    )
+    .assert_success();
+    call!(master_account, ausd.set_psm_fees(10, 10)).assert_success();
+    call!(
+        master_account,
+        usdc.storage_deposit(Some("ausd".to_string()), None)
+    )
+    .assert_success();
+
+    let alice = master_account.create_user("alice".to_string(), to_yocto("10"));
+    call!(
+        master_account,
+        usdc.mint(alice.account_id(), U128(2000 * ONE_USDC))
+    )
+    .assert_success();
+    call!(
+        alice,
+        ausd.storage_deposit(None, None),
+        deposit = to_yocto("1")
+    )
+    .assert_success();
+    (master_account, ausd, usdc, alice)
+}
+
+fn deposit_usdc(
+    alice: &UserAccount,
+    usdc: &ContractAccount<MockStablecoinContract>,
+    amount: u128,
+) -> U128 {
+    call!(
+        alice,
+        usdc.ft_transfer_call("ausd".to_string(), U128(amount), None, "".to_string()),
+        deposit = 1,
+        gas = DEFAULT_GAS
+    )
+    .unwrap_json()
+}
+
+#[test]
+fn test_psm_mint_and_redeem() {
+    let (master_account, ausd, usdc, alice) = init();
+
+    let used = deposit_usdc(&alice, &usdc, 100 * ONE_USDC);
+    assert_eq!(used, U128(100 * ONE_USDC));
+    // 0.1% mint fee paid to the owner
+    let alice_ausd_balance: U128 =
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(alice_ausd_balance, U128(to_yocto("99.9")));
+    let owner_ausd_balance: U128 =
+        view!(ausd.ft_balance_of(master_account.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(owner_ausd_balance, U128(to_yocto("0.1")));
+    let ausd_usdc_balance: U128 = view!(usdc.ft_balance_of("ausd".to_string())).unwrap_json();
+    assert_eq!(ausd_usdc_balance, U128(100 * ONE_USDC));
+
+    call!(
+        alice,
+        ausd.psm_redeem(U128(to_yocto("50"))),
+        deposit = to_yocto("1"),
+        gas = DEFAULT_GAS
+    )
+    .assert_success();
+    let alice_ausd_balance: U128 =
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(alice_ausd_balance, U128(to_yocto("49.9")));
+    let alice_usdc_balance: U128 = view!(usdc.ft_balance_of(alice.account_id())).unwrap_json();
+    assert_eq!(alice_usdc_balance, U128(1900 * ONE_USDC + 49_950_000));
+    let total_supply: U128 = view!(ausd.ft_total_supply()).unwrap_json();
+    assert_eq!(total_supply, U128(to_yocto("50.05")));
+}
+
+#[test]
+fn test_psm_debt_ceiling_refund() {
+    let (_master_account, ausd, usdc, alice) = init();
+
+    deposit_usdc(&alice, &usdc, 900 * ONE_USDC);
+    // Over the 1000 aUSD debt ceiling, the stablecoin contract refunds the whole transfer
+    let used = deposit_usdc(&alice, &usdc, 200 * ONE_USDC);
+    assert_eq!(used, U128(0));
+    let alice_usdc_balance: U128 = view!(usdc.ft_balance_of(alice.account_id())).unwrap_json();
+    assert_eq!(alice_usdc_balance, U128(1100 * ONE_USDC));
+    let alice_ausd_balance: U128 =
+        view!(ausd.ft_balance_of(alice.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(alice_ausd_balance, U128(to_yocto("899.1")));
+}
+
+#[test]
+fn test_psm_redeem_unregistered_rollback() {
+    let (master_account, ausd, usdc, alice) = init();
+    deposit_usdc(&alice, &usdc, 100 * ONE_USDC);
+    let bob = master_account.create_user("bob".to_string(), to_yocto("10"));
+    call!(
+        bob,
+        ausd.storage_deposit(None, None),
+        deposit = to_yocto("1")
+    )
+    .assert_success();
+    call!(
+        alice,
+        ausd.ft_transfer(
+            bob.account_id().try_into().unwrap(),
+            U128(to_yocto("10")),
+            None
+        ),
+        deposit = 1
+    )
+    .assert_success();
+
+    // bob isn't registered with the stablecoin, so the transfer fails and the redeem is reverted
+    call!(
+        bob,
+        ausd.psm_redeem(U128(to_yocto("10"))),
+        deposit = to_yocto("1"),
+        gas = DEFAULT_GAS
+    );
+    let bob_ausd_balance: U128 =
+        view!(ausd.ft_balance_of(bob.account_id().try_into().unwrap())).unwrap_json();
+    assert_eq!(bob_ausd_balance, U128(to_yocto("10")));
+    let total_supply: U128 = view!(ausd.ft_total_supply()).unwrap_json();
+    assert_eq!(total_supply, U128(to_yocto("100")));
+    let psm: near_sdk::serde_json::Value = view!(ausd.get_psm()).unwrap_json();
+    assert_eq!(psm["reserve"], (100 * ONE_USDC).to_string());
+}
@@ -0,0 +1,109 @@
+//! `batch_transfer` shared by the art and aUSD contracts, and the handling of the deposit attached
+//! to transfers, which pays for registering their receivers.
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::Serialize;
+use near_sdk::{env, AccountId, Balance, Promise};
+
+/// Leg of `batch_transfer` that wasn't transferred.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FailedTransfer {
+    /// Index of the leg in `transfers`
+    pub index: u32,
+    pub receiver_id: AccountId,
+    pub amount: U128,
+    pub reason: String,
+}
+
+/// Token the legs of a `batch_transfer` move.
+pub trait BatchTransfer: StorageManagement {
+    /// Returns the balance `account_id` can transfer, panics if it's not registered.
+    fn transferable_balance(&self, account_id: &AccountId) -> Balance;
+
+    /// Registers `account_id` with the minimum storage balance paid from `deposit`, unless it's
+    /// registered already. Returns what's left of `deposit`.
+    fn register_receiver(&mut self, account_id: &AccountId, deposit: Balance) -> Balance;
+
+    /// Transfers `amount` from `sender_id` to the registered `receiver_id`, logging an
+    /// `ft_transfer` event.
+    fn transfer_leg(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        memo: Option<String>,
+    );
+}
+
+/// Transfers the amount of each leg of `transfers` from `sender_id` to its receiver. The balance
+/// of `sender_id` has to cover the total of all legs. Unregistered receivers are registered with
+/// `deposit`. A leg fails if its receiver isn't registered and the deposit left doesn't cover it,
+/// is `sender_id`, or its amount is 0. If `partial` is set, failed legs are skipped and returned,
+/// otherwise the first one panics. Returns the failed legs and what's left of `deposit`.
+pub fn batch_transfer<T: BatchTransfer>(
+    token: &mut T,
+    sender_id: &AccountId,
+    transfers: Vec<(ValidAccountId, U128)>,
+    memo: Option<String>,
+    partial: Option<bool>,
+    mut deposit: Balance,
+) -> (Vec<FailedTransfer>, Balance) {
+    let total = transfers.iter().fold(0u128, |total, (_, amount)| {
+        total
+            .checked_add(amount.0)
+            .unwrap_or_else(|| env::panic(b"Total amount overflow"))
+    });
+    if token.transferable_balance(sender_id) < total {
+        env::panic(b"The account doesn't have enough balance");
+    }
+    let mut failed = vec![];
+    for (index, (receiver_id, amount)) in transfers.into_iter().enumerate() {
+        let reason = if receiver_id.as_ref() == sender_id {
+            Some("Sender and receiver should be different".to_string())
+        } else if amount.0 == 0 {
+            Some("The amount should be a positive number".to_string())
+        } else if token.storage_balance_of(receiver_id.clone()).is_none()
+            && deposit < token.storage_balance_bounds().min.0
+        {
+            Some(format!(
+                "The account {} is not registered",
+                receiver_id.as_ref()
+            ))
+        } else {
+            None
+        };
+        let receiver_id: AccountId = receiver_id.into();
+        match reason {
+            None => {
+                deposit = token.register_receiver(&receiver_id, deposit);
+                token.transfer_leg(sender_id, &receiver_id, amount.0, memo.clone());
+            }
+            Some(reason) if partial.unwrap_or(false) => failed.push(FailedTransfer {
+                index: index as u32,
+                receiver_id,
+                amount,
+                reason,
+            }),
+            Some(reason) => env::panic(reason.as_bytes()),
+        }
+    }
+    (failed, deposit)
+}
+
+/// Asserts at least 1 yoctoNEAR is attached, like `assert_one_yocto`, and returns the rest of the
+/// attached deposit, which can pay for registering receivers.
+pub fn assert_at_least_one_yocto() -> Balance {
+    let deposit = env::attached_deposit();
+    if deposit < 1 {
+        env::panic(b"Requires attached deposit of at least 1 yoctoNEAR");
+    }
+    deposit - 1
+}
+
+/// Refunds `amount` of the attached deposit that wasn't used to the caller.
+pub fn refund_deposit(amount: Balance) {
+    if amount > 0 {
+        Promise::new(env::predecessor_account_id()).transfer(amount);
+    }
+}
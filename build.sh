@@ -0,0 +1,7 @@
+#!/bin/bash
+set -e
+cd "`dirname $0`"
+art/build.sh
+ausd/build.sh
+start/build.sh
+mock-stablecoin/build.sh
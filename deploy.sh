@@ -3,3 +3,4 @@
 
 near deploy --accountId art.artcoin.testnet --wasmFile ./art/res/art.wasm --initFunction new --initArgs '{"owner_id": "art.artcoin.testnet", "total_supply": "1000000000000000000000000000000000", "ausd_token": "ausd.artcoin.testnet"}'
 
+#near deploy --accountId start.artcoin.testnet --wasmFile ./start/res/start.wasm --initFunction new --initArgs '{"art_token": "art.artcoin.testnet"}'
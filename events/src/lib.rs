@@ -0,0 +1,78 @@
+//! NEP-297 events shared by the art, aUSD and stART contracts. Each event is logged as
+//! `EVENT_JSON:` followed by `{"standard":...,"version":...,"event":...,"data":[...]}`, so
+//! indexers don't have to parse the other logs. Changes of token balances and supply use the
+//! NEP-141 events below, the contracts log their other operations with `emit`.
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json;
+use near_sdk::{env, Balance};
+
+const NEP141_STANDARD: &str = "nep141";
+const NEP141_VERSION: &str = "1.0.0";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a, T: Serialize> {
+    standard: &'a str,
+    version: &'a str,
+    event: &'a str,
+    data: [T; 1],
+}
+
+/// Logs `event` of `standard` at `version` with `data`.
+pub fn emit<T: Serialize>(standard: &str, version: &str, event: &str, data: T) {
+    let log = EventLog {
+        standard,
+        version,
+        event,
+        data: [data],
+    };
+    env::log(format!("EVENT_JSON:{}", serde_json::to_string(&log).unwrap()).as_bytes());
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct FtMint<'a> {
+    owner_id: &'a str,
+    amount: U128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memo: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct FtTransfer<'a> {
+    old_owner_id: &'a str,
+    new_owner_id: &'a str,
+    amount: U128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memo: Option<&'a str>,
+}
+
+pub fn ft_mint(owner_id: &str, amount: Balance, memo: Option<&str>) {
+    let data = FtMint {
+        owner_id,
+        amount: amount.into(),
+        memo,
+    };
+    emit(NEP141_STANDARD, NEP141_VERSION, "ft_mint", data);
+}
+
+pub fn ft_burn(owner_id: &str, amount: Balance, memo: Option<&str>) {
+    let data = FtMint {
+        owner_id,
+        amount: amount.into(),
+        memo,
+    };
+    emit(NEP141_STANDARD, NEP141_VERSION, "ft_burn", data);
+}
+
+pub fn ft_transfer(old_owner_id: &str, new_owner_id: &str, amount: Balance, memo: Option<&str>) {
+    let data = FtTransfer {
+        old_owner_id,
+        new_owner_id,
+        amount: amount.into(),
+        memo,
+    };
+    emit(NEP141_STANDARD, NEP141_VERSION, "ft_transfer", data);
+}
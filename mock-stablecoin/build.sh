@@ -0,0 +1,7 @@
+#!/bin/bash
+set -e
+cd "`dirname $0`"
+source ../flags.sh
+mkdir -p res
+cargo build --target wasm32-unknown-unknown --release
+cp target/wasm32-unknown-unknown/release/mock_stablecoin.wasm ./res/
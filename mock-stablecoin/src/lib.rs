@@ -0,0 +1,196 @@
+/**
+* Minimal NEP-141 stablecoin for testing the aUSD peg stability module.
+* NOTES:
+*  - Anyone can mint, it must never be deployed outside of tests.
+*  - Accounts have to be registered with `storage_deposit` (or by receiving minted tokens) before
+*    they can receive transfers, like with real NEP-141 tokens.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::U128;
+use near_sdk::{
+    assert_one_yocto, env, ext_contract, near_bindgen, AccountId, Balance, Gas, PanicOnDefault,
+    PromiseOrValue, PromiseResult,
+};
+
+#[cfg(target_arch = "wasm32")]
+#[global_allocator]
+static ALLOC: near_sdk::wee_alloc::WeeAlloc<'_> = near_sdk::wee_alloc::WeeAlloc::INIT;
+
+const GAS_FOR_RESOLVE_TRANSFER: Gas = 5_000_000_000_000;
+const GAS_FOR_FT_TRANSFER_CALL: Gas = 25_000_000_000_000 + GAS_FOR_RESOLVE_TRANSFER;
+
+#[ext_contract(ext_fungible_token_receiver)]
+pub trait FungibleTokenReceiver {
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128>;
+}
+
+#[ext_contract(ext_self)]
+pub trait FungibleTokenResolver {
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128;
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct MockStablecoin {
+    /// AccountID -> balance, registered accounts only.
+    pub balances: LookupMap<AccountId, Balance>,
+
+    /// Total supply of the token.
+    pub total_supply: Balance,
+
+    pub decimals: u8,
+}
+
+#[near_bindgen]
+impl MockStablecoin {
+    #[init]
+    pub fn new(decimals: u8) -> Self {
+        assert!(!env::state_exists(), "Already initialized");
+        Self {
+            balances: LookupMap::new(b"b".to_vec()),
+            total_supply: 0,
+            decimals,
+        }
+    }
+
+    /// Mints `amount` to `account_id`, registering it if needed.
+    pub fn mint(&mut self, account_id: AccountId, amount: U128) {
+        let balance = self.balances.get(&account_id).unwrap_or(0);
+        self.balances.insert(&account_id, &(balance + amount.0));
+        self.total_supply += amount.0;
+    }
+
+    /// Registers `account_id`, or the caller. Storage is free in the mock.
+    #[allow(unused_variables)]
+    #[payable]
+    pub fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        if !self.balances.contains_key(&account_id) {
+            self.balances.insert(&account_id, &0);
+        }
+    }
+
+    #[payable]
+    pub fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>) {
+        assert_one_yocto();
+        self.internal_transfer(&env::predecessor_account_id(), &receiver_id, amount.0, memo);
+    }
+
+    #[payable]
+    pub fn ft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        self.internal_transfer(&sender_id, &receiver_id, amount.0, memo);
+        ext_fungible_token_receiver::ft_on_transfer(
+            sender_id.clone(),
+            amount,
+            msg,
+            &receiver_id,
+            0,
+            env::prepaid_gas() - GAS_FOR_FT_TRANSFER_CALL,
+        )
+        .then(ext_self::ft_resolve_transfer(
+            sender_id,
+            receiver_id,
+            amount,
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+        .into()
+    }
+
+    /// Refunds the amount unused by the receiver. Returns the used amount.
+    pub fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "Method is private"
+        );
+        let unused_amount = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                match near_sdk::serde_json::from_slice::<U128>(&value) {
+                    Ok(unused_amount) => std::cmp::min(amount.0, unused_amount.0),
+                    Err(_) => amount.0,
+                }
+            }
+            _ => amount.0,
+        };
+        let receiver_balance = self.balances.get(&receiver_id).unwrap_or(0);
+        let refund_amount = std::cmp::min(unused_amount, receiver_balance);
+        if refund_amount > 0 {
+            self.balances
+                .insert(&receiver_id, &(receiver_balance - refund_amount));
+            let sender_balance = self.balances.get(&sender_id).unwrap_or(0);
+            self.balances
+                .insert(&sender_id, &(sender_balance + refund_amount));
+        }
+        (amount.0 - refund_amount).into()
+    }
+
+    pub fn ft_total_supply(&self) -> U128 {
+        self.total_supply.into()
+    }
+
+    pub fn ft_balance_of(&self, account_id: AccountId) -> U128 {
+        self.balances.get(&account_id).unwrap_or(0).into()
+    }
+}
+
+impl MockStablecoin {
+    fn internal_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        memo: Option<String>,
+    ) {
+        assert_ne!(
+            sender_id, receiver_id,
+            "Sender and receiver should be different"
+        );
+        assert!(amount > 0, "The amount should be a positive number");
+        let sender_balance = self.balances.get(sender_id).unwrap_or_else(|| {
+            env::panic(format!("The account {} is not registered", sender_id).as_bytes())
+        });
+        assert!(
+            sender_balance >= amount,
+            "The account doesn't have enough balance"
+        );
+        let receiver_balance = self.balances.get(receiver_id).unwrap_or_else(|| {
+            env::panic(format!("The account {} is not registered", receiver_id).as_bytes())
+        });
+        self.balances.insert(sender_id, &(sender_balance - amount));
+        self.balances
+            .insert(receiver_id, &(receiver_balance + amount));
+        if let Some(memo) = memo {
+            env::log(format!("Memo: {}", memo).as_bytes());
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Signed permits shared by the art and aUSD contracts. The owner of a balance signs a
+//! `PermitMessage` with the ed25519 key it registered with `set_permit_key`, and anyone can submit
+//! it with `permit` to set the allowance, so the owner doesn't need to send a transaction.
+use std::convert::TryFrom;
+
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use near_sdk::borsh::{self, BorshSerialize};
+use near_sdk::json_types::Base58PublicKey;
+use near_sdk::{env, AccountId, Balance};
+
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+
+/// Message signed for `permit`, the signature is over its borsh serialization.
+#[derive(BorshSerialize)]
+pub struct PermitMessage {
+    /// Contract the permit is for, so it can't be replayed on another token
+    pub contract_id: AccountId,
+    pub owner_id: AccountId,
+    pub spender_id: AccountId,
+    /// Allowance of `spender_id` after the permit
+    pub amount: Balance,
+    /// Permit nonce of `owner_id`, see `get_permit_nonce`
+    pub nonce: u64,
+    /// Timestamp in nanoseconds the permit can't be used after
+    pub deadline: u64,
+}
+
+impl PermitMessage {
+    /// Panics unless `signature` is the signature of the message by `public_key`.
+    pub fn assert_signed(&self, public_key: &[u8], signature: &[u8]) {
+        let public_key =
+            PublicKey::from_bytes(public_key).unwrap_or_else(|_| env::panic(b"Invalid permit key"));
+        let signature = Signature::try_from(signature)
+            .unwrap_or_else(|_| env::panic(b"Invalid permit signature"));
+        if public_key
+            .verify(&self.try_to_vec().unwrap(), &signature)
+            .is_err()
+        {
+            env::panic(b"Invalid permit signature");
+        }
+    }
+}
+
+/// Returns the ed25519 key bytes of `public_key`, permits can't be signed with other keys.
+pub fn ed25519_key_bytes(public_key: Base58PublicKey) -> Vec<u8> {
+    let bytes: Vec<u8> = public_key.into();
+    if bytes.len() != 33 || bytes[0] != 0 {
+        env::panic(b"Only ed25519 keys can sign permits");
+    }
+    bytes[1..].to_vec()
+}
+
+/// Returns `key_bytes` of an ed25519 key in the JSON format.
+pub fn ed25519_public_key(key_bytes: Vec<u8>) -> Base58PublicKey {
+    let mut bytes = vec![0];
+    bytes.extend(key_bytes);
+    Base58PublicKey::try_from(bytes).unwrap()
+}
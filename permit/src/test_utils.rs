@@ -0,0 +1,24 @@
+//! Keys and signatures for the permit tests of the contracts.
+use ed25519_dalek::{Keypair, SecretKey, Signer};
+use near_sdk::borsh::BorshSerialize;
+use near_sdk::json_types::{Base58PublicKey, Base64VecU8};
+
+use crate::PermitMessage;
+
+/// Returns the ed25519 keypair with the secret key of 32 `seed` bytes.
+pub fn permit_keypair(seed: u8) -> Keypair {
+    let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+    let public = (&secret).into();
+    Keypair { secret, public }
+}
+
+/// Returns the public key of `keypair` in the JSON format `set_permit_key` takes.
+pub fn permit_public_key(keypair: &Keypair) -> Base58PublicKey {
+    crate::ed25519_public_key(keypair.public.to_bytes().to_vec())
+}
+
+/// Returns the signature of `message` by `keypair` in the JSON format `permit` takes.
+pub fn sign_permit(keypair: &Keypair, message: &PermitMessage) -> Base64VecU8 {
+    let signature = keypair.sign(&message.try_to_vec().unwrap());
+    signature.to_bytes().to_vec().into()
+}
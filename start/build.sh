@@ -0,0 +1,8 @@
+#!/bin/bash
+set -e
+cd "`dirname $0`"
+source ../flags.sh
+mkdir -p res
+cargo build --target wasm32-unknown-unknown --release
+cp target/wasm32-unknown-unknown/release/start.wasm ./res/
+
@@ -0,0 +1,3 @@
+//! NEP-297 events, see the `events` crate. All changes of stART balances and supply use the
+//! NEP-141 events.
+pub(crate) use events::{ft_burn, ft_mint, ft_transfer};
@@ -0,0 +1,585 @@
+/**
+* stART, the liquid staking token of art.
+* NOTES:
+*  - Only the art contract mints and burns stART. It's minted for art staked to the liquid staking
+*    pool of the art contract and burnt when it's redeemed. One stART is a share of the pool, so
+*    its value in art grows with the staking rewards of the pool, see `get_liquid_staking` of art.
+*  - Implements NEP-141 (fungible token core), NEP-145 (storage management) and NEP-148
+*    (metadata). Accounts have to be registered with `storage_deposit` before they can receive
+*    stART, also to get it minted by the art contract.
+*  - stART is only burnt by the art contract, so the stART supply always matches the shares of the
+*    pool. Accounts holding stART can't unregister, and a refund of `ft_transfer_call` to an
+*    unregistered sender stays with the receiver.
+*  - The maximum balance value is limited by U128 (2**128 - 1).
+*  - JSON calls should pass U128 as a base-10 string. E.g. "100".
+*  - To prevent the deployed contract from being modified or deleted, it should not have any access
+*    keys on its account.
+*/
+use near_contract_standards::fungible_token::core::FungibleTokenCore;
+use near_contract_standards::fungible_token::metadata::{
+    FungibleTokenMetadata, FungibleTokenMetadataProvider, FT_METADATA_SPEC,
+};
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::{
+    assert_one_yocto, env, ext_contract, near_bindgen, AccountId, Balance, Gas, PanicOnDefault,
+    Promise, PromiseOrValue, PromiseResult, StorageUsage,
+};
+
+mod event;
+
+#[cfg(target_arch = "wasm32")]
+#[global_allocator]
+static ALLOC: near_sdk::wee_alloc::WeeAlloc<'_> = near_sdk::wee_alloc::WeeAlloc::INIT;
+
+/// Price per 1 byte of storage from mainnet genesis config.
+const STORAGE_PRICE_PER_BYTE: Balance = 100_000_000_000_000_000_000;
+
+const GAS_FOR_RESOLVE_TRANSFER: Gas = 5_000_000_000_000;
+const GAS_FOR_FT_TRANSFER_CALL: Gas = 25_000_000_000_000 + GAS_FOR_RESOLVE_TRANSFER;
+
+const NO_DEPOSIT: Balance = 0;
+
+#[ext_contract(ext_fungible_token_receiver)]
+pub trait FungibleTokenReceiver {
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128>;
+}
+
+#[ext_contract(ext_self)]
+pub trait ExtStartCallbacks {
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128;
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Start {
+    /// AccountID -> balance, registered accounts only.
+    pub balances: LookupMap<AccountId, Balance>,
+
+    /// Total supply of the token.
+    pub total_supply: Balance,
+
+    /// Art contract, the only one that can mint and burn
+    pub art_token: AccountId,
+
+    /// Storage bytes of one registered account, see NEP-145
+    pub account_storage_usage: StorageUsage,
+}
+
+#[near_bindgen]
+impl Start {
+    #[init]
+    pub fn new(art_token: AccountId) -> Self {
+        assert!(!env::state_exists(), "Already initialized");
+        let mut ft = Self {
+            balances: LookupMap::new(b"b".to_vec()),
+            total_supply: 0,
+            art_token,
+            account_storage_usage: 0,
+        };
+        ft.measure_account_storage_usage();
+        ft
+    }
+
+    /// Mints `amount` to the registered `account_id`. Only the art contract can mint.
+    pub fn mint(&mut self, account_id: AccountId, amount: U128) {
+        self.assert_art();
+        let amount: Balance = amount.into();
+        let balance = self.internal_unwrap_balance(&account_id);
+        self.balances.insert(&account_id, &(balance + amount));
+        self.total_supply += amount;
+        event::ft_mint(&account_id, amount, None);
+    }
+
+    /// Burns `amount` from `account_id`. Only the art contract can burn.
+    pub fn burn(&mut self, account_id: AccountId, amount: U128) {
+        self.assert_art();
+        let amount: Balance = amount.into();
+        if amount == 0 {
+            env::panic(b"Can't burn 0 tokens");
+        }
+        let balance = self.internal_unwrap_balance(&account_id);
+        if balance < amount {
+            env::panic(b"Not enough balance to burn");
+        }
+        self.balances.insert(&account_id, &(balance - amount));
+        self.total_supply -= amount;
+        event::ft_burn(&account_id, amount, None);
+    }
+
+    pub fn get_art_token(&self) -> AccountId {
+        self.art_token.clone()
+    }
+}
+
+impl Start {
+    fn assert_art(&self) {
+        assert!(
+            env::predecessor_account_id() == self.art_token,
+            "Only the art contract can mint or burn stART"
+        );
+    }
+
+    fn measure_account_storage_usage(&mut self) {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = "a".repeat(64);
+        self.balances.insert(&tmp_account_id, &0);
+        self.account_storage_usage = env::storage_usage() - initial_storage_usage;
+        self.balances.remove(&tmp_account_id);
+    }
+
+    /// Returns the balance of `account_id`, panics if it's not registered.
+    fn internal_unwrap_balance(&self, account_id: &AccountId) -> Balance {
+        match self.balances.get(account_id) {
+            Some(balance) => balance,
+            None => env::panic(format!("The account {} is not registered", account_id).as_bytes()),
+        }
+    }
+
+    /// Transfers `amount` between two registered accounts.
+    fn internal_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        memo: Option<String>,
+    ) {
+        assert_ne!(
+            sender_id, receiver_id,
+            "Sender and receiver should be different"
+        );
+        assert!(amount > 0, "The amount should be a positive number");
+        let sender_balance = self.internal_unwrap_balance(sender_id);
+        let receiver_balance = self.internal_unwrap_balance(receiver_id);
+        if sender_balance < amount {
+            env::panic(b"The account doesn't have enough balance");
+        }
+        self.balances.insert(sender_id, &(sender_balance - amount));
+        self.balances.insert(
+            receiver_id,
+            &receiver_balance
+                .checked_add(amount)
+                .unwrap_or_else(|| env::panic(b"Balance overflow")),
+        );
+        event::ft_transfer(sender_id, receiver_id, amount, memo.as_deref());
+    }
+
+    fn internal_storage_balance_of(&self, account_id: &AccountId) -> Option<StorageBalance> {
+        if self.balances.contains_key(account_id) {
+            Some(StorageBalance {
+                total: self.storage_balance_bounds().min,
+                available: 0.into(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenCore for Start {
+    #[payable]
+    fn ft_transfer(&mut self, receiver_id: ValidAccountId, amount: U128, memo: Option<String>) {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        self.internal_transfer(&sender_id, receiver_id.as_ref(), amount.into(), memo);
+    }
+
+    #[payable]
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: ValidAccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        self.internal_transfer(&sender_id, receiver_id.as_ref(), amount.into(), memo);
+        ext_fungible_token_receiver::ft_on_transfer(
+            sender_id.clone(),
+            amount,
+            msg,
+            receiver_id.as_ref(),
+            NO_DEPOSIT,
+            env::prepaid_gas() - GAS_FOR_FT_TRANSFER_CALL,
+        )
+        .then(ext_self::ft_resolve_transfer(
+            sender_id,
+            receiver_id.into(),
+            amount,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+        .into()
+    }
+
+    fn ft_total_supply(&self) -> U128 {
+        self.total_supply.into()
+    }
+
+    fn ft_balance_of(&self, account_id: ValidAccountId) -> U128 {
+        self.balances.get(account_id.as_ref()).unwrap_or(0).into()
+    }
+}
+
+trait FungibleTokenResolver {
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128;
+}
+
+#[near_bindgen]
+impl FungibleTokenResolver for Start {
+    /// Refunds the amount the receiver didn't use in `ft_on_transfer`, as much as the receiver
+    /// still holds. Returns the amount the receiver kept. If the sender unregistered in the
+    /// meantime the receiver keeps all of it, burning it would leave its share of the pool
+    /// behind in the art contract.
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "Method is private"
+        );
+        let amount: Balance = amount.into();
+
+        // Get the unused amount from the `ft_on_transfer` call result.
+        let unused_amount = match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(value) => {
+                if let Ok(unused_amount) = near_sdk::serde_json::from_slice::<U128>(&value) {
+                    std::cmp::min(amount, unused_amount.0)
+                } else {
+                    amount
+                }
+            }
+            PromiseResult::Failed => amount,
+        };
+
+        if unused_amount > 0 {
+            let receiver_balance = self.balances.get(&receiver_id).unwrap_or(0);
+            if receiver_balance > 0 {
+                if let Some(sender_balance) = self.balances.get(&sender_id) {
+                    let refund_amount = std::cmp::min(receiver_balance, unused_amount);
+                    self.balances
+                        .insert(&receiver_id, &(receiver_balance - refund_amount));
+                    self.balances
+                        .insert(&sender_id, &(sender_balance + refund_amount));
+                    event::ft_transfer(&receiver_id, &sender_id, refund_amount, Some("refund"));
+                    return (amount - refund_amount).into();
+                }
+                env::log(b"The account of the sender was deleted, the receiver keeps the refund");
+            }
+        }
+        amount.into()
+    }
+}
+
+#[near_bindgen]
+impl StorageManagement for Start {
+    // `registration_only` doesn't affect the implementation, the storage balance is fixed.
+    #[allow(unused_variables)]
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<ValidAccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let amount: Balance = env::attached_deposit();
+        let account_id = account_id
+            .map(|a| a.into())
+            .unwrap_or_else(env::predecessor_account_id);
+        if self.internal_storage_balance_of(&account_id).is_some() {
+            env::log(b"The account is already registered, refunding the deposit");
+            if amount > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(amount);
+            }
+        } else {
+            let min_balance = self.storage_balance_bounds().min.0;
+            if amount < min_balance {
+                env::panic(b"The attached deposit is less than the minimum storage balance");
+            }
+
+            self.balances.insert(&account_id, &0);
+            let refund = amount - min_balance;
+            if refund > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(refund);
+            }
+        }
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+
+    /// The storage balance is fixed to `storage_balance_bounds().min`, so nothing is available to
+    /// withdraw. Panics if `amount > 0`, otherwise returns the storage balance of the caller.
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let predecessor_account_id = env::predecessor_account_id();
+        if let Some(storage_balance) = self.internal_storage_balance_of(&predecessor_account_id) {
+            match amount {
+                Some(amount) if amount.0 > 0 => {
+                    env::panic(b"The amount is greater than the available storage balance");
+                }
+                _ => storage_balance,
+            }
+        } else {
+            env::panic(
+                format!("The account {} is not registered", &predecessor_account_id).as_bytes(),
+            );
+        }
+    }
+
+    /// Removes the caller's account and refunds its storage balance. The balance must be 0 even
+    /// with `force`, as burning stART here would leave its share of the pool behind in the art
+    /// contract. Unwrap it with the art contract first.
+    #[allow(unused_variables)]
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        if let Some(balance) = self.balances.get(&account_id) {
+            if balance == 0 {
+                self.balances.remove(&account_id);
+                Promise::new(account_id).transfer(self.storage_balance_bounds().min.0 + 1);
+                true
+            } else {
+                env::panic(
+                    b"Can't unregister the account with the positive balance, unwrap it first",
+                )
+            }
+        } else {
+            env::log(format!("The account {} is not registered", &account_id).as_bytes());
+            false
+        }
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        let required_storage_balance =
+            Balance::from(self.account_storage_usage) * STORAGE_PRICE_PER_BYTE;
+        StorageBalanceBounds {
+            min: required_storage_balance.into(),
+            max: Some(required_storage_balance.into()),
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance> {
+        self.internal_storage_balance_of(account_id.as_ref())
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenMetadataProvider for Start {
+    fn ft_metadata(&self) -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: FT_METADATA_SPEC.to_string(),
+            name: "Staked art".to_string(),
+            symbol: "stART".to_string(),
+            icon: None,
+            decimals: 24,
+            reference: None,
+            reference_hash: None,
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::convert::TryInto;
+
+    use near_sdk::MockedBlockchain;
+    use near_sdk::{testing_env, VMContext};
+
+    use super::*;
+
+    fn art() -> AccountId {
+        "art.near".to_string()
+    }
+    fn bob() -> AccountId {
+        "bob.near".to_string()
+    }
+    fn carol() -> AccountId {
+        "carol.near".to_string()
+    }
+
+    fn get_context(predecessor_account_id: AccountId) -> VMContext {
+        VMContext {
+            current_account_id: "start.near".to_string(),
+            signer_account_id: bob(),
+            signer_account_pk: vec![0, 1, 2],
+            predecessor_account_id,
+            input: vec![],
+            block_index: 0,
+            block_timestamp: 0,
+            account_balance: 1000 * 10u128.pow(24),
+            account_locked_balance: 0,
+            storage_usage: 10u64.pow(6),
+            attached_deposit: 0,
+            prepaid_gas: 10u64.pow(18),
+            random_seed: vec![0, 1, 2],
+            is_view: false,
+            output_data_receivers: vec![],
+            epoch_height: 0,
+        }
+    }
+
+    fn register(contract: &mut Start, context: &VMContext, account_id: &AccountId) {
+        let mut deposit_context = context.clone();
+        deposit_context.attached_deposit = contract.storage_balance_bounds().min.0;
+        testing_env!(deposit_context);
+        contract.storage_deposit(Some(account_id.clone().try_into().unwrap()), None);
+        testing_env!(context.clone());
+    }
+
+    #[test]
+    fn test_mint_transfer_burn() {
+        let mut context = get_context(art());
+        testing_env!(context.clone());
+        let mut contract = Start::new(art());
+        register(&mut contract, &context, &bob());
+        contract.mint(bob(), 1000.into());
+        assert_eq!(contract.ft_balance_of(bob().try_into().unwrap()).0, 1000);
+        assert_eq!(contract.ft_total_supply().0, 1000);
+
+        context.predecessor_account_id = bob();
+        context.attached_deposit = contract.storage_balance_bounds().min.0;
+        testing_env!(context.clone());
+        contract.storage_deposit(Some(carol().try_into().unwrap()), None);
+        context.attached_deposit = 1;
+        testing_env!(context.clone());
+        contract.ft_transfer(carol().try_into().unwrap(), 400.into(), None);
+        assert_eq!(contract.ft_balance_of(bob().try_into().unwrap()).0, 600);
+        assert_eq!(contract.ft_balance_of(carol().try_into().unwrap()).0, 400);
+
+        context.predecessor_account_id = art();
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        contract.burn(carol(), 400.into());
+        assert_eq!(contract.ft_balance_of(carol().try_into().unwrap()).0, 0);
+        assert_eq!(contract.ft_total_supply().0, 600);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the art contract can mint or burn stART")]
+    fn test_mint_not_art_fail() {
+        let context = get_context(art());
+        testing_env!(context.clone());
+        let mut contract = Start::new(art());
+        testing_env!(get_context(bob()));
+        contract.mint(bob(), 1000.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "The account bob.near is not registered")]
+    fn test_mint_unregistered_fail() {
+        let context = get_context(art());
+        testing_env!(context.clone());
+        let mut contract = Start::new(art());
+        contract.mint(bob(), 1000.into());
+    }
+
+    #[test]
+    fn test_storage_unregister() {
+        let mut context = get_context(art());
+        testing_env!(context.clone());
+        let mut contract = Start::new(art());
+        let min_balance = contract.storage_balance_bounds().min.0;
+        register(&mut contract, &context, &bob());
+        context.account_balance = env::account_balance();
+
+        context.predecessor_account_id = bob();
+        context.attached_deposit = 1;
+        testing_env!(context.clone());
+        let balance = env::account_balance();
+        assert!(contract.storage_unregister(None));
+        assert_eq!(balance - env::account_balance(), min_balance + 1);
+        assert!(contract
+            .storage_balance_of(bob().try_into().unwrap())
+            .is_none());
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Can't unregister the account with the positive balance, unwrap it first"
+    )]
+    fn test_storage_unregister_with_balance_fail() {
+        let mut context = get_context(art());
+        testing_env!(context.clone());
+        let mut contract = Start::new(art());
+        register(&mut contract, &context, &bob());
+        contract.mint(bob(), 1000.into());
+
+        context.predecessor_account_id = bob();
+        context.attached_deposit = 1;
+        testing_env!(context.clone());
+        contract.storage_unregister(Some(true));
+    }
+
+    #[test]
+    fn test_resolve_transfer_to_unregistered_sender() {
+        let mut context = get_context(art());
+        testing_env!(context.clone());
+        let mut contract = Start::new(art());
+        register(&mut contract, &context, &bob());
+        register(&mut contract, &context, &carol());
+        contract.mint(bob(), 1000.into());
+
+        context.predecessor_account_id = bob();
+        context.attached_deposit = 1;
+        testing_env!(context.clone());
+        contract.ft_transfer_call(
+            carol().try_into().unwrap(),
+            1000.into(),
+            None,
+            "".to_string(),
+        );
+        contract.storage_unregister(None);
+
+        // The receiver keeps the refund, so the supply still matches the pool shares
+        context.predecessor_account_id = "start.near".to_string();
+        testing_env!(
+            context.clone(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"1000\"".to_vec())]
+        );
+        let used = contract.ft_resolve_transfer(bob(), carol(), 1000.into());
+        assert_eq!(used.0, 1000);
+        assert_eq!(contract.ft_balance_of(carol().try_into().unwrap()).0, 1000);
+        assert_eq!(contract.ft_total_supply().0, 1000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough balance to burn")]
+    fn test_burn_more_than_balance_fail() {
+        let context = get_context(art());
+        testing_env!(context.clone());
+        let mut contract = Start::new(art());
+        register(&mut contract, &context, &bob());
+        contract.mint(bob(), 1000.into());
+        contract.burn(bob(), 1001.into());
+    }
+}